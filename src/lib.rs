@@ -9,8 +9,10 @@
 //! ## Features
 //!
 //! - Parse SMF Format 0 and Format 1 files
+//! - Write parsed (or edited) events back to SMF Format 0 or Format 1
 //! - Convert MIDI notes to YM2151 KC/KF values
 //! - Handle tempo changes
+//! - Stream live MIDI input to YM2151 register writes one message at a time
 //! - Output JSON format compatible with [ym2151-zig-cc](https://github.com/cat2151/ym2151-zig-cc)
 //! - Time values in seconds (f64) for simplicity and WebAudio API compatibility
 //!
@@ -96,6 +98,22 @@ pub struct ProgramAttachment {
         default = "default_change_to_next_tone_time"
     )]
     pub change_to_next_tone_time: f64,
+    /// Optional velocity-to-carrier-level scaling for this program
+    #[serde(rename = "VelocitySensitivity", default)]
+    pub velocity_sensitivity: Option<VelocitySensitivity>,
+    /// Optional routing of channel and key pressure for this program
+    #[serde(rename = "Aftertouch", default)]
+    pub aftertouch: Option<AftertouchRouting>,
+    /// Noise frequency (NFRQ, 0-31); notes of this program play on YM2151
+    /// channel 7 with the noise generator
+    #[serde(rename = "Noise", default)]
+    pub noise: Option<u8>,
+    /// Hardware LFO phase modulation sensitivity (PMS, 0-7) for this program
+    #[serde(rename = "Pms", default)]
+    pub pms: Option<u8>,
+    /// Hardware LFO amplitude modulation sensitivity (AMS, 0-3) for this program
+    #[serde(rename = "Ams", default)]
+    pub ams: Option<u8>,
 }
 
 /// Optional conversion options supplied via attachment JSON
@@ -116,6 +134,37 @@ pub struct ConversionOptions {
     /// Optional YM2151 tone definitions keyed by MIDI program number
     #[serde(rename = "Tones", default)]
     pub tones: HashMap<u8, ToneDefinition>,
+    /// Optional velocity-to-carrier-level scaling applied to all programs
+    #[serde(rename = "VelocitySensitivity", default)]
+    pub velocity_sensitivity: Option<VelocitySensitivity>,
+    /// Distance of MIDI pan (CC10) from center at which a channel is hard-panned.
+    /// Defaults to 32 (0-32 left only, 96-127 right only).
+    #[serde(rename = "PanThreshold", default)]
+    pub pan_threshold: Option<u8>,
+    /// Optional modulation wheel (CC1) vibrato applied to all channels
+    #[serde(rename = "ModulationWheel", default)]
+    pub modulation_wheel: Option<ModulationWheel>,
+    /// Optional routing of channel and key pressure applied to all programs
+    #[serde(rename = "Aftertouch", default)]
+    pub aftertouch: Option<AftertouchRouting>,
+    /// Optional CC/NRPN to register-field mappings
+    #[serde(rename = "ControllerMappings", default)]
+    pub controller_mappings: Vec<ControllerMapping>,
+    /// Optional loop unrolling for songs with loop markers
+    #[serde(rename = "Loop", default)]
+    pub loop_options: Option<LoopOptions>,
+    /// Optional dynamic voice allocation replacing the polyphony-based split
+    #[serde(rename = "VoiceAllocation", default)]
+    pub voice_allocation: Option<VoiceAllocation>,
+    /// Optional per-MIDI-channel pins, voice limits and steal priorities
+    #[serde(rename = "ChannelAssignment", default)]
+    pub channel_assignment: HashMap<u8, ChannelAssignment>,
+    /// Optional per-note tones and pitches for the drum channel, keyed by MIDI note
+    #[serde(rename = "DrumMap", default)]
+    pub drum_map: HashMap<u8, DrumNote>,
+    /// Optional setup of the chip's own LFO
+    #[serde(rename = "HardwareLfo", default)]
+    pub hardware_lfo: Option<HardwareLfo>,
     /// Per-program attachment entries (new array format).
     /// Populated when the attachment JSON is an array of `ProgramAttachment` objects.
     #[serde(skip)]
@@ -123,12 +172,23 @@ pub struct ConversionOptions {
 }
 
 /// Defines a software LFO targeting a YM2151 tone register (per channel/operator)
+/// or the note's pitch
+///
+/// ```json
+/// { "Target": "pitch", "Depth": 30, "RateHz": 5.5, "DelaySeconds": 0.3, "Waveform": "sine" }
+/// ```
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct RegisterLfoDefinition {
-    /// Base register address (channel 0 / operator base, e.g. "0x60")
+    /// What the LFO modulates (default `register`)
+    #[serde(default)]
+    pub target: LfoTarget,
+    /// Base register address (channel 0 / operator base, e.g. "0x60");
+    /// required for the `register` target
+    #[serde(default)]
     pub base_register: String,
-    /// Peak modulation amount applied around the base register value
+    /// Peak modulation amount applied around the base register value,
+    /// or in cents around the note for the `pitch` target
     #[serde(default)]
     pub depth: f64,
     /// Oscillation rate in Hz
@@ -146,6 +206,12 @@ pub struct RegisterLfoDefinition {
     /// Waveform shape
     #[serde(default = "default_lfo_waveform")]
     pub waveform: LfoWaveform,
+    /// Fraction of each period a `square` wave spends high (0.0-1.0 exclusive, default 0.5)
+    #[serde(default = "default_lfo_duty")]
+    pub duty: f64,
+    /// Seed of the `sample_and_hold` random sequence; equal seeds repeat the same steps
+    #[serde(default)]
+    pub seed: u64,
     /// When true (default), the LFO phase and attack reset on each note-on (key-on sync).
     /// When false, the LFO is triggered once at the start of the song and runs continuously
     /// across all notes without resetting.
@@ -178,17 +244,466 @@ pub struct PopNoiseEnvelope {
     pub registers: Vec<RegisterOverride>,
 }
 
+/// Velocity sensitivity of the carrier operators' Total Level
+///
+/// At note-on the carrier TL registers are set to the tone's own TL plus an
+/// attenuation of `depth * (1 - curve(velocity / 127))` TL steps (0.75 dB each),
+/// so velocity 127 plays the tone as defined and lower velocities play softer.
+///
+/// # Example
+/// ```json
+/// { "Curve": "quadratic", "Depth": 40 }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct VelocitySensitivity {
+    /// Shape of the velocity response
+    #[serde(default = "default_velocity_curve")]
+    pub curve: VelocityCurve,
+    /// Attenuation in TL steps applied at the lowest velocity
+    #[serde(default = "default_velocity_depth")]
+    pub depth: f64,
+}
+
+/// Velocity response curves
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VelocityCurve {
+    /// Level follows velocity linearly
+    Linear,
+    /// Soft notes fall off faster (velocity squared)
+    Quadratic,
+    /// Levels stay close to full for most velocities (square root of velocity)
+    SquareRoot,
+}
+
+/// Vibrato controlled by the modulation wheel (CC1)
+///
+/// The vibrato depth follows CC1 in real time, reaching `max_depth` at 127.
+///
+/// # Example
+/// ```json
+/// { "Mode": "hardware", "MaxDepth": 5 }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ModulationWheel {
+    /// How the vibrato is produced
+    #[serde(default = "default_modulation_wheel_mode")]
+    pub mode: ModulationWheelMode,
+    /// Depth at CC1 = 127: cents in software mode (default 100),
+    /// PMS (0-7) in hardware mode (default 7)
+    #[serde(default)]
+    pub max_depth: Option<f64>,
+}
+
+impl ModulationWheel {
+    /// Depth at CC1 = 127, falling back to the mode's default
+    pub fn max_depth(&self) -> f64 {
+        self.max_depth.unwrap_or(match self.mode {
+            ModulationWheelMode::Software => 100.0,
+            ModulationWheelMode::Hardware => 7.0,
+        })
+    }
+}
+
+/// The chip's own LFO, programmed once at the start of the song
+///
+/// `Rate` is LFRQ (0-255), `Pmd` and `Amd` the phase and amplitude
+/// modulation depths (0-127). How much of it each channel gets is set by
+/// the PMS/AMS of its tone or the `Pms`/`Ams` of a program attachment entry.
+/// `ResetOnKeyOn` restarts the LFO (test register 0x01 bit 1) at every key-on,
+/// so each note's vibrato starts from the same phase. This replaces the
+/// fixed vibrato LFO of hardware modulation wheel mode.
+///
+/// # Example
+/// ```json
+/// { "Rate": 200, "Waveform": "triangle", "Pmd": 20, "Amd": 0, "ResetOnKeyOn": true }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct HardwareLfo {
+    /// LFO frequency (LFRQ)
+    #[serde(default)]
+    pub rate: u8,
+    /// LFO waveform
+    #[serde(default)]
+    pub waveform: HardwareLfoWaveform,
+    /// Phase modulation depth (PMD)
+    #[serde(default)]
+    pub pmd: u8,
+    /// Amplitude modulation depth (AMD)
+    #[serde(default)]
+    pub amd: u8,
+    /// Restart the LFO at every key-on
+    #[serde(default)]
+    pub reset_on_key_on: bool,
+}
+
+/// Waveforms of the chip's LFO (register 0x1B bits 0-1)
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HardwareLfoWaveform {
+    /// Sawtooth wave (W = 0)
+    Saw,
+    /// Square wave (W = 1)
+    Square,
+    /// Triangle wave (W = 2)
+    #[default]
+    Triangle,
+    /// Random levels from the noise generator (W = 3)
+    Noise,
+}
+
+impl HardwareLfoWaveform {
+    /// Value of the W field
+    pub fn register_value(self) -> u8 {
+        match self {
+            HardwareLfoWaveform::Saw => 0,
+            HardwareLfoWaveform::Square => 1,
+            HardwareLfoWaveform::Triangle => 2,
+            HardwareLfoWaveform::Noise => 3,
+        }
+    }
+}
+
+/// Modulation wheel vibrato implementations
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModulationWheelMode {
+    /// Scale the software vibrato curve (KC/KF writes)
+    Software,
+    /// Use the chip LFO: PMD on register 0x19 and per-channel PMS
+    Hardware,
+}
+
+/// Routing of aftertouch (channel pressure and polyphonic key pressure)
+///
+/// Each voice follows the larger of its MIDI channel's pressure and its own
+/// key pressure. Key pressure starts at 0 on every note-on and only reaches
+/// the YM2151 channel playing that exact note.
+///
+/// # Example
+/// ```json
+/// { "Target": "swell", "Depth": 24 }
+/// { "Target": "field", "Field": "AR", "Operators": [2, 3], "Range": [12, 31] }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AftertouchRouting {
+    /// What the pressure drives
+    pub target: AftertouchTarget,
+    /// Swell: carrier attenuation in TL steps at zero pressure (default 32).
+    /// Vibrato: PMS (0-7) at full pressure (default 7).
+    #[serde(default)]
+    pub depth: Option<f64>,
+    /// Register field written by the `field` target
+    #[serde(default)]
+    pub field: Option<RegisterField>,
+    /// Operators for an operator field, in register order (0 = M1, 1 = M2, 2 = C1, 3 = C2).
+    /// All four when empty; ignored for channel fields.
+    #[serde(default)]
+    pub operators: Vec<u8>,
+    /// Field values at zero and full pressure.
+    /// Defaults to the full field range.
+    #[serde(default)]
+    pub range: Option<[u8; 2]>,
+}
+
+impl AftertouchRouting {
+    /// Depth of the swell or vibrato, falling back to the target's default
+    pub fn depth(&self) -> f64 {
+        self.depth.unwrap_or(match self.target {
+            AftertouchTarget::Swell => 32.0,
+            AftertouchTarget::Vibrato => 7.0,
+            AftertouchTarget::Field => 0.0,
+        })
+    }
+}
+
+/// Aftertouch routing targets
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AftertouchTarget {
+    /// Carrier TL: the voice gets louder with pressure, up to the tone's level
+    Swell,
+    /// Chip LFO vibrato depth: PMS follows pressure
+    Vibrato,
+    /// A chosen register field, scaled over `Range`
+    Field,
+}
+
+/// Most loop passes `LoopOptions::count` may ask for
+pub const MAX_LOOP_COUNT: u32 = 256;
+
+/// Loop unrolling for songs with loop markers ("loopStart"/"loopEnd" or CC111)
+///
+/// # Example
+/// ```json
+/// { "Count": 2, "FadeOutSeconds": 8.0 }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LoopOptions {
+    /// How many times the loop body is played (1 keeps the song as written,
+    /// at most [`MAX_LOOP_COUNT`])
+    #[serde(default = "default_loop_count")]
+    pub count: u32,
+    /// Fade out over this many seconds at the end of the last pass.
+    /// The song then ends at the loop end; anything after it is dropped.
+    #[serde(default)]
+    pub fade_out_seconds: Option<f64>,
+}
+
+/// Dynamic voice allocation
+///
+/// Instead of splitting the YM2151 channels between MIDI channels from the
+/// song-wide peak polyphony, every note-on takes a free voice and, when none
+/// is free, steals one by `Stealing`. `Reserve` keeps a minimum number of
+/// voices (MIDI channel to voice count) for a MIDI channel alone; the rest
+/// are shared. The `lowest_priority` policy uses the priorities set in
+/// [`ChannelAssignment`].
+///
+/// # Example
+/// ```json
+/// { "Stealing": "lowest_priority", "Reserve": { "9": 2 } }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct VoiceAllocation {
+    /// Which sounding voice a note-on takes when no voice is free
+    #[serde(default)]
+    pub stealing: StealPolicy,
+    /// Voices kept for one MIDI channel (or multi-port part, see
+    /// [`ChannelAssignment`]) only
+    #[serde(default)]
+    pub reserve: HashMap<u8, usize>,
+}
+
+/// Explicit YM2151 channel assignment for one MIDI channel
+///
+/// `Ym2151Channels` pins the MIDI channel to exactly these YM2151 channels;
+/// no other part may use them. `MaxVoices` caps how many voices the part
+/// gets (or, with dynamic allocation, keeps sounding at once). `Priority`
+/// decides which parts get voices first when there are not enough, and which
+/// notes the `lowest_priority` stealing policy keeps; higher wins.
+///
+/// Keys are MIDI channels. In files that use several MIDI ports, parts on
+/// the second port and up are numbered on from 16 (channel + 16 × port
+/// rank), and are assigned by those numbers.
+///
+/// # Example
+/// ```json
+/// { "0": { "Priority": 10 }, "1": { "Ym2151Channels": [1] }, "9": { "Ym2151Channels": [6, 7] } }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ChannelAssignment {
+    /// YM2151 channels (0-7) this MIDI channel always plays on
+    #[serde(rename = "Ym2151Channels", default)]
+    pub ym2151_channels: Vec<u8>,
+    /// Most voices this MIDI channel uses
+    #[serde(default)]
+    pub max_voices: Option<usize>,
+    /// Allocation and steal priority (default 0)
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// Drum map entry for one note of the drum channel (MIDI channel 9)
+///
+/// `Tone` replaces the channel's program tone for this note and `Kc`/`Kf`
+/// play it at a fixed pitch, untouched by pitch bend and vibrato.
+/// `KeyOnMask` keys on only some operators (bit 0 M1, bit 1 C1, bit 2 M2,
+/// bit 3 C2, as in the key-on register). Notes sharing a `Group` always play
+/// on the same voice, so e.g. an open hi-hat is cut by the closed one.
+/// `Noise` plays the note on YM2151 channel 7 with the noise generator at
+/// this noise frequency.
+///
+/// # Example
+/// ```json
+/// {
+///   "36": { "Tone": { "events": [{ "time": 0, "addr": "0x20", "data": "0xC4" }] }, "Kc": 16 },
+///   "42": { "Kc": 94, "KeyOnMask": 8, "Group": 1 },
+///   "46": { "Kc": 94, "Group": 1 }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DrumNote {
+    /// Tone loaded onto the voice for this note
+    #[serde(default)]
+    pub tone: Option<ToneDefinition>,
+    /// Fixed key code (0x00-0x7F); the note's own pitch when absent
+    #[serde(default)]
+    pub kc: Option<u8>,
+    /// Key fraction (0-63) used with `Kc`
+    #[serde(default)]
+    pub kf: u8,
+    /// Operators keyed on (0x1-0xF); all four when absent
+    #[serde(default)]
+    pub key_on_mask: Option<u8>,
+    /// Voice group; notes of one group share a voice
+    #[serde(default)]
+    pub group: Option<u8>,
+    /// Noise frequency (NFRQ, 0-31)
+    #[serde(default)]
+    pub noise: Option<u8>,
+}
+
+impl DrumNote {
+    /// Fixed (KC, KF) of the note, if it has one
+    pub fn fixed_pitch(&self) -> Option<(u8, u8)> {
+        self.kc.map(|kc| (kc, self.kf))
+    }
+}
+
+/// Voice stealing policies for [`VoiceAllocation`]
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StealPolicy {
+    /// The note that started first
+    #[default]
+    Oldest,
+    /// The note with the lowest velocity after channel volume and expression
+    Quietest,
+    /// A note only kept sounding by a pedal, else the oldest
+    ReleasedFirst,
+    /// The oldest note of the lowest-priority MIDI channel, never one whose
+    /// channel outranks the new note's
+    LowestPriority,
+}
+
+/// Maps a MIDI controller (CC or NRPN) onto a YM2151 register field
+///
+/// Each matching controller message becomes a write of the field on every
+/// YM2151 channel allocated to the MIDI channel, at the message's time.
+///
+/// # Example
+/// ```json
+/// { "Source": { "Cc": 74 }, "Field": "TL", "Operators": [0, 1], "Range": [40, 10] }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ControllerMapping {
+    /// Controller that drives the field
+    pub source: ControllerSource,
+    /// Only react on this MIDI channel, or part in multi-port files (see
+    /// [`ChannelAssignment`]); all channels when omitted
+    #[serde(default)]
+    pub channel: Option<u8>,
+    /// Register field to write
+    pub field: RegisterField,
+    /// Operators for operator fields, in register order (0 = M1, 1 = M2, 2 = C1, 3 = C2).
+    /// All four when empty; ignored for channel fields.
+    #[serde(default)]
+    pub operators: Vec<u8>,
+    /// Field values at the controller's minimum and maximum.
+    /// Defaults to the full field range.
+    #[serde(default)]
+    pub range: Option<[u8; 2]>,
+}
+
+/// MIDI controller sources for [`ControllerMapping`]
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub enum ControllerSource {
+    /// Control change number (0-127), 7-bit value
+    Cc(u8),
+    /// NRPN number (MSB << 7 | LSB), 14-bit data entry value
+    Nrpn(u16),
+}
+
+/// YM2151 tone parameters that can be addressed by name
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RegisterField {
+    /// Speaker enable bits (0x20-0x27)
+    Rl,
+    /// Feedback (0x20-0x27)
+    Fb,
+    /// Connection algorithm (0x20-0x27)
+    Con,
+    /// Phase modulation sensitivity (0x38-0x3F)
+    Pms,
+    /// Amplitude modulation sensitivity (0x38-0x3F)
+    Ams,
+    /// Detune 1 (0x40-0x5F)
+    Dt1,
+    /// Frequency multiplier (0x40-0x5F)
+    Mul,
+    /// Total level (0x60-0x7F)
+    Tl,
+    /// Key scale (0x80-0x9F)
+    Ks,
+    /// Attack rate (0x80-0x9F)
+    Ar,
+    /// Amplitude modulation enable (0xA0-0xBF)
+    Amsen,
+    /// First decay rate (0xA0-0xBF)
+    D1r,
+    /// Detune 2 (0xC0-0xDF)
+    Dt2,
+    /// Second decay rate (0xC0-0xDF)
+    D2r,
+    /// First decay level (0xE0-0xFF)
+    D1l,
+    /// Release rate (0xE0-0xFF)
+    Rr,
+}
+
+/// Destinations of a software LFO
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LfoTarget {
+    /// The register named by `BaseRegister`, resolved per channel
+    #[default]
+    Register,
+    /// The note's KC/KF, on top of pitch bend, portamento and vibrato
+    Pitch,
+}
+
 /// Supported software LFO waveforms
 #[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "snake_case")]
 pub enum LfoWaveform {
+    /// Rises and falls linearly across the period
     Triangle,
+    /// Smooth sine wave
+    Sine,
+    /// Rises across the period, then drops back at its start
+    RampUp,
+    /// Falls across the period, then jumps back at its start
+    RampDown,
+    /// High for `Duty` of each period, low for the rest
+    Square,
+    /// A new random level at the start of each period, seeded by `Seed`
+    SampleAndHold,
 }
 
 fn default_lfo_waveform() -> LfoWaveform {
     LfoWaveform::Triangle
 }
 
+fn default_loop_count() -> u32 {
+    2
+}
+
+fn default_modulation_wheel_mode() -> ModulationWheelMode {
+    ModulationWheelMode::Software
+}
+
+fn default_velocity_curve() -> VelocityCurve {
+    VelocityCurve::Linear
+}
+
+fn default_velocity_depth() -> f64 {
+    32.0
+}
+
+fn default_lfo_duty() -> f64 {
+    0.5
+}
+
 fn default_key_on_sync() -> bool {
     true
 }
@@ -228,16 +743,264 @@ impl ConversionOptions {
                         }
                     }
                     options.program_attachments = attachments;
+                    options.validate_controller_mappings()?;
+                    options.validate_aftertouch()?;
+                    options.validate_software_lfo()?;
+                    options.validate_noise()?;
+                    options.validate_hardware_lfo()?;
                     Ok(options)
                 } else {
                     // Legacy flat object format
                     let options: ConversionOptions = serde_json::from_value(value)?;
+                    options.validate_controller_mappings()?;
+                    options.validate_aftertouch()?;
+                    options.validate_software_lfo()?;
+                    options.validate_voice_allocation()?;
+                    crate::ym2151::validate_channel_assignment(&options.channel_assignment)?;
+                    options.validate_drum_map()?;
+                    options.validate_noise()?;
+                    options.validate_hardware_lfo()?;
+                    options.validate_loop()?;
                     Ok(options)
                 }
             }
             _ => Ok(ConversionOptions::default()),
         }
     }
+
+    /// Velocity sensitivity for a program.
+    ///
+    /// A per-program attachment entry takes precedence over the global setting.
+    pub fn velocity_sensitivity_for(&self, program: u8) -> Option<&VelocitySensitivity> {
+        self.program_attachments
+            .iter()
+            .find(|pa| pa.program_change == program)
+            .and_then(|pa| pa.velocity_sensitivity.as_ref())
+            .or(self.velocity_sensitivity.as_ref())
+    }
+
+    /// Aftertouch routing for a program.
+    ///
+    /// A per-program attachment entry takes precedence over the global setting.
+    pub fn aftertouch_for(&self, program: u8) -> Option<&AftertouchRouting> {
+        self.program_attachments
+            .iter()
+            .find(|pa| pa.program_change == program)
+            .and_then(|pa| pa.aftertouch.as_ref())
+            .or(self.aftertouch.as_ref())
+    }
+
+    /// Drum map entry for a note, if `channel` is the drum channel
+    ///
+    /// Every port's drum channel counts; see [`crate::ym2151::is_drum_channel`].
+    pub fn drum_note(&self, channel: u8, note: u8) -> Option<&DrumNote> {
+        if !crate::ym2151::is_drum_channel(channel) {
+            return None;
+        }
+        self.drum_map.get(&note)
+    }
+
+    /// Noise frequency for a note, if it is played with the noise generator
+    ///
+    /// A drum map entry takes precedence over the program's attachment entry.
+    pub fn noise_for(&self, channel: u8, program: u8, note: u8) -> Option<u8> {
+        self.drum_note(channel, note)
+            .and_then(|drum| drum.noise)
+            .or_else(|| {
+                self.program_attachments
+                    .iter()
+                    .find(|pa| pa.program_change == program)
+                    .and_then(|pa| pa.noise)
+            })
+    }
+
+    /// Hardware LFO sensitivity (PMS, AMS) a program's attachment entry sets
+    pub fn lfo_sensitivity_for(&self, program: u8) -> (Option<u8>, Option<u8>) {
+        self.program_attachments
+            .iter()
+            .find(|pa| pa.program_change == program)
+            .map_or((None, None), |pa| (pa.pms, pa.ams))
+    }
+
+    /// Whether any program or drum map entry uses the noise generator
+    pub fn uses_noise(&self) -> bool {
+        self.program_attachments.iter().any(|pa| pa.noise.is_some())
+            || self.drum_map.values().any(|drum| drum.noise.is_some())
+    }
+
+    /// Whether any program routes aftertouch to the chip LFO vibrato
+    pub fn has_aftertouch_vibrato(&self) -> bool {
+        self.program_attachments
+            .iter()
+            .filter_map(|pa| pa.aftertouch.as_ref())
+            .chain(self.aftertouch.as_ref())
+            .any(|routing| routing.target == AftertouchTarget::Vibrato)
+    }
+
+    /// Reject field routings without a field or with operators that do not exist.
+    fn validate_aftertouch(&self) -> Result<()> {
+        let routings = self
+            .program_attachments
+            .iter()
+            .filter_map(|pa| pa.aftertouch.as_ref())
+            .chain(self.aftertouch.as_ref());
+        for routing in routings {
+            if routing.target == AftertouchTarget::Field && routing.field.is_none() {
+                return Err(Error::InvalidParameter(
+                    "aftertouch field target requires a Field".to_string(),
+                ));
+            }
+            if let Some(op) = routing.operators.iter().find(|&&op| op > 3) {
+                return Err(Error::InvalidParameter(format!(
+                    "aftertouch operator {op} is out of range (0-3)"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject register LFOs without a register and square duty cycles
+    /// that leave no high or no low part.
+    fn validate_software_lfo(&self) -> Result<()> {
+        let definitions = self
+            .program_attachments
+            .iter()
+            .flat_map(|pa| &pa.software_lfo)
+            .chain(&self.software_lfo);
+        for def in definitions {
+            if def.target == LfoTarget::Register && def.base_register.is_empty() {
+                return Err(Error::InvalidParameter(
+                    "software LFO register target requires a BaseRegister".to_string(),
+                ));
+            }
+            if !(def.duty > 0.0 && def.duty < 1.0) {
+                return Err(Error::InvalidParameter(format!(
+                    "software LFO duty {} is out of range (between 0.0 and 1.0)",
+                    def.duty
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject voice reservations beyond the chip's eight voices.
+    fn validate_voice_allocation(&self) -> Result<()> {
+        let Some(allocation) = &self.voice_allocation else {
+            return Ok(());
+        };
+        let reserved: usize = allocation.reserve.values().sum();
+        if reserved > crate::ym2151::YM2151_VOICES {
+            return Err(Error::InvalidParameter(format!(
+                "{reserved} voices reserved; the YM2151 has {}",
+                crate::ym2151::YM2151_VOICES
+            )));
+        }
+        Ok(())
+    }
+
+    /// Reject LFO depths and sensitivities beyond their register fields.
+    fn validate_hardware_lfo(&self) -> Result<()> {
+        if let Some(lfo) = &self.hardware_lfo {
+            if lfo.pmd > 127 || lfo.amd > 127 {
+                return Err(Error::InvalidParameter(
+                    "hardware LFO depths are out of range (PMD/AMD 0-127)".to_string(),
+                ));
+            }
+        }
+        for pa in &self.program_attachments {
+            if pa.pms.is_some_and(|pms| pms > 7) || pa.ams.is_some_and(|ams| ams > 3) {
+                return Err(Error::InvalidParameter(format!(
+                    "program {} LFO sensitivity is out of range (PMS 0-7, AMS 0-3)",
+                    pa.program_change
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject noise frequencies beyond the 5-bit NFRQ field.
+    fn validate_noise(&self) -> Result<()> {
+        let frequencies = self
+            .program_attachments
+            .iter()
+            .filter_map(|pa| pa.noise)
+            .chain(self.drum_map.values().filter_map(|drum| drum.noise));
+        for frequency in frequencies {
+            if frequency > 31 {
+                return Err(Error::InvalidParameter(format!(
+                    "noise frequency {frequency} is out of range (0-31)"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject drum map pitches and key-on masks the chip cannot take.
+    fn validate_drum_map(&self) -> Result<()> {
+        let mut notes: Vec<(&u8, &DrumNote)> = self.drum_map.iter().collect();
+        notes.sort_unstable_by_key(|(note, _)| **note);
+        for (note, drum) in notes {
+            if drum.kc.is_some_and(|kc| kc > 0x7F) || drum.kf > 63 {
+                return Err(Error::InvalidParameter(format!(
+                    "drum note {note} pitch is out of range (KC 0x00-0x7F, KF 0-63)"
+                )));
+            }
+            if drum
+                .key_on_mask
+                .is_some_and(|mask| mask == 0 || mask > 0x0F)
+            {
+                return Err(Error::InvalidParameter(format!(
+                    "drum note {note} key-on mask must key on 1-4 operators (0x1-0xF)"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject loop counts beyond [`MAX_LOOP_COUNT`].
+    fn validate_loop(&self) -> Result<()> {
+        match &self.loop_options {
+            Some(loop_options) if loop_options.count > MAX_LOOP_COUNT => {
+                Err(Error::InvalidParameter(format!(
+                    "loop count {} is out of range (at most {MAX_LOOP_COUNT})",
+                    loop_options.count
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reject controller mappings that address controllers or operators that do not exist.
+    fn validate_controller_mappings(&self) -> Result<()> {
+        for mapping in &self.controller_mappings {
+            if let ControllerSource::Cc(cc) = mapping.source {
+                if cc > 127 {
+                    return Err(Error::InvalidParameter(format!(
+                        "controller mapping CC {cc} is out of range (0-127)"
+                    )));
+                }
+            }
+            if let ControllerSource::Nrpn(nrpn) = mapping.source {
+                if nrpn > 0x3FFF {
+                    return Err(Error::InvalidParameter(format!(
+                        "controller mapping NRPN {nrpn} is out of range (0-16383)"
+                    )));
+                }
+            }
+            if let Some(op) = mapping.operators.iter().find(|&&op| op > 3) {
+                return Err(Error::InvalidParameter(format!(
+                    "controller mapping operator {op} is out of range (0-3)"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Pan threshold for hard left/right, falling back to the default.
+    pub fn pan_threshold(&self) -> u8 {
+        self.pan_threshold
+            .unwrap_or(crate::ym2151::DEFAULT_PAN_THRESHOLD)
+    }
 }
 
 /// Convert Standard MIDI File data to YM2151 register log JSON
@@ -282,9 +1045,34 @@ pub fn convert_smf_to_ym2151_log(smf_data: &[u8]) -> Result<String> {
 pub fn convert_smf_to_ym2151_log_with_options(
     smf_data: &[u8],
     attachment_json: Option<&[u8]>,
+) -> Result<String> {
+    convert_smf_to_ym2151_log_with_playback(
+        smf_data,
+        attachment_json,
+        midi::Format2Playback::Sequential,
+    )
+}
+
+/// Convert SMF data to YM2151 register log JSON, choosing how Format 2 patterns are played
+///
+/// # Example
+/// ```no_run
+/// use smf_to_ym2151log::convert_smf_to_ym2151_log_with_playback;
+/// use smf_to_ym2151log::midi::Format2Playback;
+///
+/// let smf_bytes = std::fs::read("patterns.mid").unwrap();
+/// let second_pattern =
+///     convert_smf_to_ym2151_log_with_playback(&smf_bytes, None, Format2Playback::Pattern(1))
+///         .unwrap();
+/// println!("{}", second_pattern);
+/// ```
+pub fn convert_smf_to_ym2151_log_with_playback(
+    smf_data: &[u8],
+    attachment_json: Option<&[u8]>,
+    playback: midi::Format2Playback,
 ) -> Result<String> {
     // Pass A: Parse MIDI data from bytes
-    let midi_data = midi::parse_midi_from_bytes(smf_data)?;
+    let midi_data = midi::parse_midi_from_bytes_with_playback(smf_data, playback)?;
 
     // Parse optional conversion options
     let options = ConversionOptions::from_attachment_bytes(attachment_json)?;
@@ -298,6 +1086,23 @@ pub fn convert_smf_to_ym2151_log_with_options(
     Ok(json)
 }
 
+/// Convert every SMF Format 2 pattern to its own YM2151 register log JSON
+///
+/// Format 0 and 1 files yield a single log.
+pub fn convert_smf_patterns_to_ym2151_logs(
+    smf_data: &[u8],
+    attachment_json: Option<&[u8]>,
+) -> Result<Vec<String>> {
+    let options = ConversionOptions::from_attachment_bytes(attachment_json)?;
+    midi::parse_midi_patterns_from_bytes(smf_data)?
+        .iter()
+        .map(|midi_data| {
+            let ym2151_log = ym2151::convert_to_ym2151_log_with_options(midi_data, &options)?;
+            Ok(serde_json::to_string_pretty(&ym2151_log)?)
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -366,6 +1171,75 @@ mod tests {
         assert!(opts.program_attachments.is_empty());
     }
 
+    #[test]
+    fn test_from_attachment_bytes_velocity_sensitivity() {
+        let json = br#"[
+          { "ProgramChange": 0, "VelocitySensitivity": { "Curve": "square_root", "Depth": 20 } },
+          { "ProgramChange": 1 }
+        ]"#;
+        let opts = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+        let sens = opts.velocity_sensitivity_for(0).unwrap();
+        assert_eq!(sens.curve, VelocityCurve::SquareRoot);
+        assert!((sens.depth - 20.0).abs() < 1e-9);
+        assert!(opts.velocity_sensitivity_for(1).is_none());
+    }
+
+    #[test]
+    fn test_from_attachment_bytes_modulation_wheel() {
+        let json = br#"{ "ModulationWheel": { "Mode": "hardware" } }"#;
+        let opts = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+        let wheel = opts.modulation_wheel.unwrap();
+        assert_eq!(wheel.mode, ModulationWheelMode::Hardware);
+        assert_eq!(wheel.max_depth(), 7.0);
+
+        let json = br#"{ "ModulationWheel": { "MaxDepth": 50 } }"#;
+        let opts = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+        let wheel = opts.modulation_wheel.unwrap();
+        assert_eq!(wheel.mode, ModulationWheelMode::Software);
+        assert_eq!(wheel.max_depth(), 50.0);
+    }
+
+    #[test]
+    fn test_from_attachment_bytes_controller_mappings() {
+        let json = br#"{
+          "ControllerMappings": [
+            { "Source": { "Nrpn": 16 }, "Field": "TL", "Operators": [0] },
+            { "Source": { "Cc": 74 }, "Channel": 1, "Field": "TL", "Operators": [0, 1], "Range": [40, 10] }
+          ]
+        }"#;
+        let opts = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+        assert_eq!(opts.controller_mappings.len(), 2);
+        assert_eq!(
+            opts.controller_mappings[0].source,
+            ControllerSource::Nrpn(16)
+        );
+        assert_eq!(opts.controller_mappings[1].field, RegisterField::Tl);
+        assert_eq!(opts.controller_mappings[1].range, Some([40, 10]));
+
+        let bad_operator = br#"{
+          "ControllerMappings": [{ "Source": { "Cc": 1 }, "Field": "AR", "Operators": [4] }]
+        }"#;
+        assert!(ConversionOptions::from_attachment_bytes(Some(bad_operator)).is_err());
+    }
+
+    #[test]
+    fn test_from_attachment_bytes_loop() {
+        let json = br#"{ "Loop": { "FadeOutSeconds": 4.0 } }"#;
+        let opts = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+        let loop_options = opts.loop_options.unwrap();
+        assert_eq!(loop_options.count, 2);
+        assert_eq!(loop_options.fade_out_seconds, Some(4.0));
+
+        let json = br#"{ "Loop": { "Count": 3 } }"#;
+        let opts = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+        let loop_options = opts.loop_options.unwrap();
+        assert_eq!(loop_options.count, 3);
+        assert_eq!(loop_options.fade_out_seconds, None);
+
+        let json = br#"{ "Loop": { "Count": 4294967295 } }"#;
+        assert!(ConversionOptions::from_attachment_bytes(Some(json)).is_err());
+    }
+
     #[test]
     fn test_from_attachment_bytes_change_to_next_tone_fields() {
         let json = br#"[
@@ -1,26 +1,77 @@
 //! SMF to YM2151 Log Converter
 //!
 //! Converts Standard MIDI Files to YM2151 register write log in JSON format.
+//! An events JSON saved by Pass A can be given instead to run only Pass B.
 //!
 //! Usage:
-//!     smf-to-ym2151log-rust <midi_file>
+//!     smf-to-ym2151log-rust <midi_file|events_json> [--format2 <mode>]
 
-use smf_to_ym2151log::midi::{parse_midi_file, save_midi_events_json};
+use smf_to_ym2151log::midi::{
+    load_midi_events_json, parse_midi_from_bytes_with_playback, parse_midi_patterns_from_bytes,
+    save_midi_events_json, Format2Playback, MidiData, MidiTiming,
+};
 use smf_to_ym2151log::ym2151::{convert_to_ym2151_log, save_ym2151_log};
 use std::env;
+use std::fs;
 use std::path::Path;
 use std::process;
 
+/// What to do with the patterns of an SMF Format 2 file
+enum Format2Mode {
+    /// Produce one log, playing the patterns as chosen
+    Play(Format2Playback),
+    /// Produce one log per pattern
+    Separate,
+}
+
+fn parse_format2_mode(value: &str) -> Option<Format2Mode> {
+    match value {
+        "sequential" => Some(Format2Mode::Play(Format2Playback::Sequential)),
+        "separate" => Some(Format2Mode::Separate),
+        _ => value
+            .strip_prefix("pattern:")
+            .and_then(|index| index.parse().ok())
+            .map(|index| Format2Mode::Play(Format2Playback::Pattern(index))),
+    }
+}
+
+fn print_usage() {
+    eprintln!("Usage: smf-to-ym2151log-rust <midi_file|events_json> [--format2 <mode>]");
+    eprintln!("  <midi_file>: Path to Standard MIDI File");
+    eprintln!("  <events_json>: Path to an events JSON (*.json) to run only Pass B");
+    eprintln!("  --format2 <mode>: How to play SMF Format 2 patterns");
+    eprintln!("      sequential  Play the patterns one after another (default)");
+    eprintln!("      pattern:<N> Play only pattern N (0-based)");
+    eprintln!("      separate    Write one log per pattern");
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        eprintln!("Usage: smf-to-ym2151log-rust <midi_file>");
-        eprintln!("  <midi_file>: Path to Standard MIDI File");
-        process::exit(1);
+    let mut midi_filename = None;
+    let mut format2_mode = Format2Mode::Play(Format2Playback::Sequential);
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        if arg == "--format2" {
+            match rest.next().and_then(|value| parse_format2_mode(value)) {
+                Some(mode) => format2_mode = mode,
+                None => {
+                    print_usage();
+                    process::exit(1);
+                }
+            }
+        } else if midi_filename.is_none() {
+            midi_filename = Some(arg);
+        } else {
+            print_usage();
+            process::exit(1);
+        }
     }
 
-    let midi_filename = &args[1];
+    let Some(midi_filename) = midi_filename else {
+        print_usage();
+        process::exit(1);
+    };
 
     println!("smf-to-ym2151log-rust");
     println!("Processing: {}", midi_filename);
@@ -30,18 +81,61 @@ fn main() {
     let path = Path::new(midi_filename);
     let base_name = path.file_stem().unwrap_or_default().to_string_lossy();
     let output_dir = path.parent().unwrap_or_else(|| Path::new("."));
-    let events_json_path = output_dir.join(format!("{}_events.json", base_name));
-    let ym2151_json_path = output_dir.join(format!("{}_ym2151.json", base_name));
+
+    // An events JSON skips Pass A: song_events.json -> song_ym2151.json
+    let is_events_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    if is_events_json {
+        println!("Loading events JSON...");
+        let midi_data = match load_midi_events_json(midi_filename) {
+            Ok(data) => {
+                println!("  ✓ Successfully loaded events JSON");
+                data
+            }
+            Err(e) => {
+                eprintln!("Error loading events JSON: {}", e);
+                process::exit(1);
+            }
+        };
+        let base_name = base_name.strip_suffix("_events").unwrap_or(&base_name);
+        let ym2151_json_path = output_dir.join(format!("{}_ym2151.json", base_name));
+        convert_midi_data(&midi_data, None, &ym2151_json_path);
+
+        println!();
+        println!("=== CONVERSION COMPLETE ===");
+        println!();
+        println!("Summary:");
+        println!("  Input file:  {}", midi_filename);
+        println!("  YM2151 log:  {}", ym2151_json_path.display());
+        return;
+    }
+    let output_paths = |suffix: &str| {
+        (
+            output_dir.join(format!("{}{}_events.json", base_name, suffix)),
+            output_dir.join(format!("{}{}_ym2151.json", base_name, suffix)),
+        )
+    };
 
     // Pass A: Parse MIDI file
     println!("Pass A: Parsing MIDI file...");
-    let midi_data = match parse_midi_file(midi_filename) {
-        Ok(data) => {
+    let smf_data = match fs::read(midi_filename) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error reading MIDI file: {}", e);
+            process::exit(1);
+        }
+    };
+    let parsed = match format2_mode {
+        Format2Mode::Play(playback) => {
+            parse_midi_from_bytes_with_playback(&smf_data, playback).map(|data| vec![data])
+        }
+        Format2Mode::Separate => parse_midi_patterns_from_bytes(&smf_data),
+    };
+    let patterns = match parsed {
+        Ok(patterns) => {
             println!("  ✓ Successfully parsed MIDI file");
-            println!("  - Ticks per beat: {}", data.ticks_per_beat);
-            println!("  - Initial tempo: {:.2} BPM", data.tempo_bpm);
-            println!("  - Total events: {}", data.events.len());
-            data
+            patterns
         }
         Err(e) => {
             eprintln!("Error parsing MIDI file: {}", e);
@@ -49,19 +143,76 @@ fn main() {
         }
     };
 
-    // Save intermediate JSON
+    let separate = patterns.len() > 1;
+    let mut outputs = Vec::new();
+    for (index, midi_data) in patterns.iter().enumerate() {
+        let suffix = if separate {
+            println!();
+            println!("Pattern {}:", index);
+            format!("_pattern{}", index)
+        } else {
+            String::new()
+        };
+        let (events_json_path, ym2151_json_path) = output_paths(&suffix);
+        convert_midi_data(midi_data, Some(&events_json_path), &ym2151_json_path);
+        outputs.push((events_json_path, ym2151_json_path));
+    }
+
     println!();
-    println!("Saving intermediate events JSON...");
-    if let Err(e) = save_midi_events_json(&midi_data, events_json_path.to_str().unwrap()) {
-        eprintln!("Error saving events JSON: {}", e);
-        process::exit(1);
+    println!("=== CONVERSION COMPLETE ===");
+    println!();
+    println!("Summary:");
+    println!("  Input file:  {}", midi_filename);
+    for (events_json_path, ym2151_json_path) in &outputs {
+        println!("  Events JSON: {}", events_json_path.display());
+        println!("  YM2151 log:  {}", ym2151_json_path.display());
+    }
+    println!();
+    println!("Implementation Status:");
+    println!("  Phase 1-3: Foundation & MIDI Parser (COMPLETED)");
+    println!("  Phase 4: YM2151 Converter (COMPLETED)");
+    println!("  Phase 5: Main Program Integration (COMPLETED)");
+    println!("  Phase 6: Documentation and Polish (COMPLETED)");
+}
+
+/// Save the events JSON (if a path is given), run Pass B and save the YM2151
+/// log for one song or pattern
+fn convert_midi_data(
+    midi_data: &MidiData,
+    events_json_path: Option<&Path>,
+    ym2151_json_path: &Path,
+) {
+    match midi_data.timing {
+        MidiTiming::Metrical { ticks_per_beat } => {
+            println!("  - Ticks per beat: {}", ticks_per_beat)
+        }
+        MidiTiming::Timecode {
+            fps,
+            ticks_per_frame,
+        } => println!(
+            "  - Timecode: {:.2} fps, {} ticks per frame",
+            fps.frames_per_second(),
+            ticks_per_frame
+        ),
+    }
+    println!("  - Initial tempo: {:.2} BPM", midi_data.tempo_bpm);
+    println!("  - Total events: {}", midi_data.events.len());
+
+    // Save intermediate JSON
+    if let Some(events_json_path) = events_json_path {
+        println!();
+        println!("Saving intermediate events JSON...");
+        if let Err(e) = save_midi_events_json(midi_data, events_json_path.to_str().unwrap()) {
+            eprintln!("Error saving events JSON: {}", e);
+            process::exit(1);
+        }
+        println!("  ✓ Saved: {}", events_json_path.display());
     }
-    println!("  ✓ Saved: {}", events_json_path.display());
 
     // Pass B: Convert to YM2151 log
     println!();
     println!("Pass B: Converting to YM2151 register log...");
-    let ym2151_log = match convert_to_ym2151_log(&midi_data) {
+    let ym2151_log = match convert_to_ym2151_log(midi_data) {
         Ok(log) => {
             println!("  ✓ Successfully converted to YM2151 log");
             println!("  - Total YM2151 events: {}", log.event_count);
@@ -81,18 +232,4 @@ fn main() {
         process::exit(1);
     }
     println!("  ✓ Saved: {}", ym2151_json_path.display());
-
-    println!();
-    println!("=== CONVERSION COMPLETE ===");
-    println!();
-    println!("Summary:");
-    println!("  Input file:  {}", midi_filename);
-    println!("  Events JSON: {}", events_json_path.display());
-    println!("  YM2151 log:  {}", ym2151_json_path.display());
-    println!();
-    println!("Implementation Status:");
-    println!("  Phase 1-3: Foundation & MIDI Parser (COMPLETED)");
-    println!("  Phase 4: YM2151 Converter (COMPLETED)");
-    println!("  Phase 5: Main Program Integration (COMPLETED)");
-    println!("  Phase 6: Documentation and Polish (COMPLETED)");
 }
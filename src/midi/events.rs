@@ -3,6 +3,10 @@
 use serde::{Deserialize, Serialize};
 
 /// Represents a parsed MIDI event
+///
+/// Every event records where it came from: `track` is the index of its
+/// track in the file and `port` the MIDI port set by the track's port prefix
+/// meta event (0x21), if any.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum MidiEvent {
@@ -12,26 +16,410 @@ pub enum MidiEvent {
         channel: u8,
         note: u8,
         velocity: u8,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
     },
     /// Note Off event
-    NoteOff { ticks: u32, channel: u8, note: u8 },
+    NoteOff {
+        ticks: u32,
+        channel: u8,
+        note: u8,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
     /// Tempo change event
-    Tempo { ticks: u32, tempo_bpm: f64 },
+    Tempo {
+        ticks: u32,
+        tempo_bpm: f64,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
     /// Program change event (for future use)
     ProgramChange {
         ticks: u32,
         channel: u8,
         program: u8,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
+    /// Control change event (CC0-127)
+    ControlChange {
+        ticks: u32,
+        channel: u8,
+        controller: u8,
+        value: u8,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
+    /// Channel pressure (channel aftertouch) event
+    ChannelPressure {
+        ticks: u32,
+        channel: u8,
+        pressure: u8,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
+    /// Polyphonic key pressure (poly aftertouch) event for one note
+    PolyPressure {
+        ticks: u32,
+        channel: u8,
+        note: u8,
+        pressure: u8,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
+    /// Pitch bend event
+    ///
+    /// `value` is signed and centered on 0 (-8192 to 8191).
+    PitchBend {
+        ticks: u32,
+        channel: u8,
+        value: i16,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
+    /// Pitch bend sensitivity set via RPN 0 (CC101/100 = 0/0, then CC6/38)
+    PitchBendRange {
+        ticks: u32,
+        channel: u8,
+        semitones: u8,
+        cents: u8,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
+    /// NRPN data entry (CC99/98 select the parameter, then CC6/38)
+    ///
+    /// `parameter` and `value` are 14-bit (MSB << 7 | LSB).
+    Nrpn {
+        ticks: u32,
+        channel: u8,
+        parameter: u16,
+        value: u16,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
+    /// Raw YM2151 register write carried by a register write SysEx
+    RegisterWrite {
+        ticks: u32,
+        addr: u8,
+        data: u8,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
+    /// Loop start point ("loopStart" marker/cue point, or CC111)
+    LoopStart {
+        ticks: u32,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
+    },
+    /// Loop end point ("loopEnd" marker/cue point)
+    LoopEnd {
+        ticks: u32,
+        #[serde(default)]
+        track: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u8>,
     },
 }
 
+impl MidiEvent {
+    /// Absolute tick position of this event
+    pub fn ticks(&self) -> u32 {
+        match self {
+            MidiEvent::NoteOn { ticks, .. }
+            | MidiEvent::NoteOff { ticks, .. }
+            | MidiEvent::Tempo { ticks, .. }
+            | MidiEvent::ProgramChange { ticks, .. }
+            | MidiEvent::ControlChange { ticks, .. }
+            | MidiEvent::ChannelPressure { ticks, .. }
+            | MidiEvent::PolyPressure { ticks, .. }
+            | MidiEvent::PitchBend { ticks, .. }
+            | MidiEvent::PitchBendRange { ticks, .. }
+            | MidiEvent::Nrpn { ticks, .. }
+            | MidiEvent::RegisterWrite { ticks, .. }
+            | MidiEvent::LoopStart { ticks, .. }
+            | MidiEvent::LoopEnd { ticks, .. } => *ticks,
+        }
+    }
+
+    /// Mutable absolute tick position of this event
+    pub fn ticks_mut(&mut self) -> &mut u32 {
+        match self {
+            MidiEvent::NoteOn { ticks, .. }
+            | MidiEvent::NoteOff { ticks, .. }
+            | MidiEvent::Tempo { ticks, .. }
+            | MidiEvent::ProgramChange { ticks, .. }
+            | MidiEvent::ControlChange { ticks, .. }
+            | MidiEvent::ChannelPressure { ticks, .. }
+            | MidiEvent::PolyPressure { ticks, .. }
+            | MidiEvent::PitchBend { ticks, .. }
+            | MidiEvent::PitchBendRange { ticks, .. }
+            | MidiEvent::Nrpn { ticks, .. }
+            | MidiEvent::RegisterWrite { ticks, .. }
+            | MidiEvent::LoopStart { ticks, .. }
+            | MidiEvent::LoopEnd { ticks, .. } => ticks,
+        }
+    }
+
+    /// Index of the track this event came from
+    pub fn track(&self) -> u16 {
+        match self {
+            MidiEvent::NoteOn { track, .. }
+            | MidiEvent::NoteOff { track, .. }
+            | MidiEvent::Tempo { track, .. }
+            | MidiEvent::ProgramChange { track, .. }
+            | MidiEvent::ControlChange { track, .. }
+            | MidiEvent::ChannelPressure { track, .. }
+            | MidiEvent::PolyPressure { track, .. }
+            | MidiEvent::PitchBend { track, .. }
+            | MidiEvent::PitchBendRange { track, .. }
+            | MidiEvent::Nrpn { track, .. }
+            | MidiEvent::RegisterWrite { track, .. }
+            | MidiEvent::LoopStart { track, .. }
+            | MidiEvent::LoopEnd { track, .. } => *track,
+        }
+    }
+
+    /// MIDI port this event was sent on, if the track set one
+    pub fn port(&self) -> Option<u8> {
+        match self {
+            MidiEvent::NoteOn { port, .. }
+            | MidiEvent::NoteOff { port, .. }
+            | MidiEvent::Tempo { port, .. }
+            | MidiEvent::ProgramChange { port, .. }
+            | MidiEvent::ControlChange { port, .. }
+            | MidiEvent::ChannelPressure { port, .. }
+            | MidiEvent::PolyPressure { port, .. }
+            | MidiEvent::PitchBend { port, .. }
+            | MidiEvent::PitchBendRange { port, .. }
+            | MidiEvent::Nrpn { port, .. }
+            | MidiEvent::RegisterWrite { port, .. }
+            | MidiEvent::LoopStart { port, .. }
+            | MidiEvent::LoopEnd { port, .. } => *port,
+        }
+    }
+
+    /// MIDI channel of a channel event
+    pub fn channel(&self) -> Option<u8> {
+        match self {
+            MidiEvent::NoteOn { channel, .. }
+            | MidiEvent::NoteOff { channel, .. }
+            | MidiEvent::ProgramChange { channel, .. }
+            | MidiEvent::ControlChange { channel, .. }
+            | MidiEvent::ChannelPressure { channel, .. }
+            | MidiEvent::PolyPressure { channel, .. }
+            | MidiEvent::PitchBend { channel, .. }
+            | MidiEvent::PitchBendRange { channel, .. }
+            | MidiEvent::Nrpn { channel, .. } => Some(*channel),
+            MidiEvent::Tempo { .. }
+            | MidiEvent::RegisterWrite { .. }
+            | MidiEvent::LoopStart { .. }
+            | MidiEvent::LoopEnd { .. } => None,
+        }
+    }
+
+    /// Mutable MIDI channel of a channel event
+    pub fn channel_mut(&mut self) -> Option<&mut u8> {
+        match self {
+            MidiEvent::NoteOn { channel, .. }
+            | MidiEvent::NoteOff { channel, .. }
+            | MidiEvent::ProgramChange { channel, .. }
+            | MidiEvent::ControlChange { channel, .. }
+            | MidiEvent::ChannelPressure { channel, .. }
+            | MidiEvent::PolyPressure { channel, .. }
+            | MidiEvent::PitchBend { channel, .. }
+            | MidiEvent::PitchBendRange { channel, .. }
+            | MidiEvent::Nrpn { channel, .. } => Some(channel),
+            MidiEvent::Tempo { .. }
+            | MidiEvent::RegisterWrite { .. }
+            | MidiEvent::LoopStart { .. }
+            | MidiEvent::LoopEnd { .. } => None,
+        }
+    }
+}
+
+/// SMPTE frame rate of a timecode-timed file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmpteFps {
+    Fps24,
+    Fps25,
+    /// 29.97 fps drop-frame (stored as -29 in the SMF header)
+    Fps29DropFrame,
+    Fps30,
+}
+
+impl SmpteFps {
+    /// Real frame rate in frames per second
+    ///
+    /// # Example
+    /// ```
+    /// use smf_to_ym2151log::midi::SmpteFps;
+    /// assert_eq!(SmpteFps::Fps25.frames_per_second(), 25.0);
+    /// assert!((SmpteFps::Fps29DropFrame.frames_per_second() - 29.97).abs() < 0.001);
+    /// ```
+    pub fn frames_per_second(self) -> f64 {
+        match self {
+            SmpteFps::Fps24 => 24.0,
+            SmpteFps::Fps25 => 25.0,
+            SmpteFps::Fps29DropFrame => 30000.0 / 1001.0,
+            SmpteFps::Fps30 => 30.0,
+        }
+    }
+}
+
+/// Meaning of event ticks, from the SMF header division field
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "timing", rename_all = "snake_case")]
+pub enum MidiTiming {
+    /// Musical time: ticks per quarter note, scaled by the tempo map
+    Metrical { ticks_per_beat: u16 },
+    /// Absolute time: ticks per SMPTE frame; tempo events do not apply
+    Timecode { fps: SmpteFps, ticks_per_frame: u8 },
+}
+
+impl MidiTiming {
+    /// Ticks per quarter note for metrical timing
+    pub fn ticks_per_beat(self) -> Option<u16> {
+        match self {
+            MidiTiming::Metrical { ticks_per_beat } => Some(ticks_per_beat),
+            MidiTiming::Timecode { .. } => None,
+        }
+    }
+
+    /// Fixed tick rate for timecode timing
+    pub fn ticks_per_second(self) -> Option<f64> {
+        match self {
+            MidiTiming::Metrical { .. } => None,
+            MidiTiming::Timecode {
+                fps,
+                ticks_per_frame,
+            } => Some(fps.frames_per_second() * ticks_per_frame as f64),
+        }
+    }
+}
+
+impl From<u16> for MidiTiming {
+    fn from(ticks_per_beat: u16) -> Self {
+        MidiTiming::Metrical { ticks_per_beat }
+    }
+}
+
 /// Parsed MIDI data container
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MidiData {
-    /// Ticks per quarter note
-    pub ticks_per_beat: u16,
+    /// Tick timing model, flattened so metrical files keep `ticks_per_beat`
+    #[serde(flatten)]
+    pub timing: MidiTiming,
     /// Initial tempo in BPM
     pub tempo_bpm: f64,
     /// List of MIDI events
     pub events: Vec<MidiEvent>,
+    /// Song title, track names, signatures and text from meta events
+    #[serde(default)]
+    pub metadata: MidiMetadata,
+}
+
+/// Song metadata collected from meta events
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct MidiMetadata {
+    /// Song title: the name of the first track
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// First copyright notice (meta 0x02)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copyright: Option<String>,
+    /// Track names (meta 0x03) indexed by track; `None` for unnamed tracks
+    #[serde(default)]
+    pub track_names: Vec<Option<String>>,
+    /// Time signature changes (meta 0x58) in tick order
+    #[serde(default)]
+    pub time_signatures: Vec<TimeSignature>,
+    /// Key signature changes (meta 0x59) in tick order
+    #[serde(default)]
+    pub key_signatures: Vec<KeySignature>,
+    /// Text, copyright, track name and lyric events in tick order
+    #[serde(default)]
+    pub texts: Vec<TextEvent>,
+}
+
+impl MidiMetadata {
+    /// True when the file had none of the metadata meta events
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.copyright.is_none()
+            && self.track_names.iter().all(Option::is_none)
+            && self.time_signatures.is_empty()
+            && self.key_signatures.is_empty()
+            && self.texts.is_empty()
+    }
+}
+
+/// Time signature change
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub ticks: u32,
+    pub numerator: u8,
+    /// Note value of one beat (4 = quarter note), not the SMF power of two
+    pub denominator: u8,
+    /// MIDI clocks per metronome click
+    pub clocks_per_click: u8,
+    /// Notated 32nd notes per MIDI quarter note (normally 8)
+    pub thirty_seconds_per_quarter: u8,
+}
+
+/// Key signature change
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeySignature {
+    pub ticks: u32,
+    /// Sharps (positive) or flats (negative), -7 to 7
+    pub sharps: i8,
+    pub minor: bool,
+}
+
+/// Kind of a text meta event
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TextKind {
+    /// Text (meta 0x01)
+    Text,
+    /// Copyright notice (meta 0x02)
+    Copyright,
+    /// Track name (meta 0x03)
+    TrackName,
+    /// Lyric (meta 0x05)
+    Lyric,
+}
+
+/// Text meta event
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TextEvent {
+    pub ticks: u32,
+    pub track: u16,
+    pub kind: TextKind,
+    pub text: String,
 }
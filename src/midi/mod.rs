@@ -4,8 +4,12 @@
 
 pub mod events;
 pub mod parser;
+pub mod sysex;
 pub mod utils;
+pub mod writer;
 
 pub use events::*;
 pub use parser::*;
+pub use sysex::*;
 pub use utils::*;
+pub use writer::*;
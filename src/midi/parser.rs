@@ -3,18 +3,138 @@
 //! This module parses Standard MIDI Files and extracts relevant events.
 
 use crate::error::{Error, Result};
-use crate::midi::events::{MidiData, MidiEvent};
-use midly::{MidiMessage, Smf, TrackEventKind};
+use crate::midi::events::{
+    KeySignature, MidiData, MidiEvent, MidiMetadata, MidiTiming, SmpteFps, TextEvent, TextKind,
+    TimeSignature,
+};
+use crate::midi::sysex::decode_register_write_sysex;
+use midly::{Format, MidiMessage, Smf, TrackEvent, TrackEventKind};
 use std::fs;
 
 /// Default tempo in microseconds per quarter note (120 BPM)
-const DEFAULT_TEMPO_USPQN: u32 = 500_000;
+pub(super) const DEFAULT_TEMPO_USPQN: u32 = 500_000;
 
 /// Microseconds per minute (for tempo conversion)
-const MICROSECONDS_PER_MINUTE: f64 = 60_000_000.0;
+pub(super) const MICROSECONDS_PER_MINUTE: f64 = 60_000_000.0;
+
+/// Controller numbers used for RPN/NRPN parameter selection and data entry
+pub(super) const CC_DATA_ENTRY_MSB: u8 = 6;
+pub(super) const CC_DATA_ENTRY_LSB: u8 = 38;
+pub(super) const CC_NRPN_LSB: u8 = 98;
+pub(super) const CC_NRPN_MSB: u8 = 99;
+pub(super) const CC_RPN_LSB: u8 = 100;
+pub(super) const CC_RPN_MSB: u8 = 101;
+
+/// Loop start controller (RPG Maker style)
+pub(super) const CC_LOOP_START: u8 = 111;
+
+/// Default pitch bend sensitivity (GM: ±2 semitones)
+const DEFAULT_PITCH_BEND_RANGE_SEMITONES: u8 = 2;
+
+/// Parameter number currently selected for data entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SelectedParameter {
+    None,
+    /// Registered parameter (MSB, LSB)
+    Rpn(Option<u8>, Option<u8>),
+    /// Non-registered parameter (MSB, LSB)
+    Nrpn(Option<u8>, Option<u8>),
+}
+
+/// Result of feeding a control change into [`ParameterState`]
+pub(super) enum ParameterUpdate {
+    None,
+    /// Pitch bend sensitivity (RPN 0) changed
+    PitchBendRange,
+    /// Data entry for a fully selected NRPN
+    Nrpn {
+        parameter: u16,
+        value: u16,
+    },
+}
+
+/// Per-channel RPN/NRPN selection, data entry and pitch bend sensitivity tracked while parsing
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ParameterState {
+    selected: SelectedParameter,
+    /// Latest data entry value (14-bit, MSB << 7 | LSB) for the selected NRPN
+    nrpn_value: u16,
+    pub(super) bend_range_semitones: u8,
+    pub(super) bend_range_cents: u8,
+}
+
+impl Default for ParameterState {
+    fn default() -> Self {
+        Self {
+            selected: SelectedParameter::None,
+            nrpn_value: 0,
+            bend_range_semitones: DEFAULT_PITCH_BEND_RANGE_SEMITONES,
+            bend_range_cents: 0,
+        }
+    }
+}
+
+impl ParameterState {
+    /// Update the state with a control change.
+    pub(super) fn apply_controller(&mut self, controller: u8, value: u8) -> ParameterUpdate {
+        use SelectedParameter::{Nrpn, Rpn};
+        match (controller, self.selected) {
+            (CC_RPN_MSB, Rpn(_, lsb)) => self.selected = Rpn(Some(value), lsb),
+            (CC_RPN_MSB, _) => self.selected = Rpn(Some(value), None),
+            (CC_RPN_LSB, Rpn(msb, _)) => self.selected = Rpn(msb, Some(value)),
+            (CC_RPN_LSB, _) => self.selected = Rpn(None, Some(value)),
+            (CC_NRPN_MSB, Nrpn(_, lsb)) => self.select_nrpn(Some(value), lsb),
+            (CC_NRPN_MSB, _) => self.select_nrpn(Some(value), None),
+            (CC_NRPN_LSB, Nrpn(msb, _)) => self.select_nrpn(msb, Some(value)),
+            (CC_NRPN_LSB, _) => self.select_nrpn(None, Some(value)),
+            (CC_DATA_ENTRY_MSB, Rpn(Some(0), Some(0))) => {
+                self.bend_range_semitones = value;
+                return ParameterUpdate::PitchBendRange;
+            }
+            (CC_DATA_ENTRY_LSB, Rpn(Some(0), Some(0))) => {
+                self.bend_range_cents = value;
+                return ParameterUpdate::PitchBendRange;
+            }
+            (CC_DATA_ENTRY_MSB | CC_DATA_ENTRY_LSB, Nrpn(Some(msb), Some(lsb))) => {
+                // A new MSB starts a new value; the LSB refines the current one
+                self.nrpn_value = if controller == CC_DATA_ENTRY_MSB {
+                    (value as u16) << 7
+                } else {
+                    (self.nrpn_value & 0x3F80) | value as u16
+                };
+                return ParameterUpdate::Nrpn {
+                    parameter: ((msb as u16) << 7) | lsb as u16,
+                    value: self.nrpn_value,
+                };
+            }
+            _ => {}
+        }
+        ParameterUpdate::None
+    }
+
+    fn select_nrpn(&mut self, msb: Option<u8>, lsb: Option<u8>) {
+        self.selected = SelectedParameter::Nrpn(msb, lsb);
+        self.nrpn_value = 0;
+    }
+}
+
+/// How to play the patterns of an SMF Format 2 file
+///
+/// Format 2 tracks are independent patterns rather than simultaneous parts.
+/// Format 0 and 1 files always merge their tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format2Playback {
+    /// Play the patterns one after another
+    #[default]
+    Sequential,
+    /// Play only the pattern at this track index
+    Pattern(usize),
+}
 
 /// Parse MIDI data from bytes and extract events
 ///
+/// Format 2 patterns are played one after another.
+///
 /// # Arguments
 /// * `data` - Raw MIDI file data as bytes
 ///
@@ -24,113 +144,413 @@ const MICROSECONDS_PER_MINUTE: f64 = 60_000_000.0;
 /// # Errors
 /// Returns an error if the data cannot be parsed
 pub fn parse_midi_from_bytes(data: &[u8]) -> Result<MidiData> {
-    // Parse with midly
-    let smf = Smf::parse(data)
-        .map_err(|e| Error::MidiParse(format!("Failed to parse MIDI file: {}", e)))?;
-
-    // Get ticks per beat from the timing
-    let ticks_per_beat = match smf.header.timing {
-        midly::Timing::Metrical(ticks) => ticks.as_int(),
-        midly::Timing::Timecode(fps, subframe) => {
-            // For timecode, we'll convert to a reasonable default
-            // This is a simplified conversion
-            let frames_per_second = match fps {
-                midly::Fps::Fps24 => 24,
-                midly::Fps::Fps25 => 25,
-                midly::Fps::Fps29 => 29,
-                midly::Fps::Fps30 => 30,
-            };
-            (frames_per_second as u16) * (subframe as u16)
-        }
-    };
-
-    // Extract events from all tracks
+    parse_midi_from_bytes_with_playback(data, Format2Playback::Sequential)
+}
+
+/// Parse MIDI data from bytes, choosing how Format 2 patterns are played
+///
+/// # Errors
+/// Returns an error if the data cannot be parsed or the pattern index is out of range
+pub fn parse_midi_from_bytes_with_playback(
+    data: &[u8],
+    playback: Format2Playback,
+) -> Result<MidiData> {
+    let smf = parse_smf(data)?;
+    let timing = midi_timing(smf.header.timing);
+
+    // RPN state is tracked per MIDI channel so that RPN 0 data entry becomes
+    // an explicit pitch bend range event
+    let mut parameter_states = [ParameterState::default(); 16];
     let mut events = Vec::new();
+    let mut metadata = MidiMetadata::default();
+
+    match (smf.header.format, playback) {
+        (Format::Sequential, Format2Playback::Sequential) => {
+            // Each pattern starts where the previous one ends
+            let mut start_ticks = 0;
+            for (index, track) in smf.tracks.iter().enumerate() {
+                start_ticks = parse_track(
+                    track,
+                    index as u16,
+                    start_ticks,
+                    timing,
+                    &mut parameter_states,
+                    &mut events,
+                    &mut metadata,
+                );
+            }
+        }
+        (Format::Sequential, Format2Playback::Pattern(index)) => {
+            let track = smf.tracks.get(index).ok_or_else(|| {
+                Error::InvalidParameter(format!(
+                    "Pattern {} does not exist (file has {} patterns)",
+                    index,
+                    smf.tracks.len()
+                ))
+            })?;
+            parse_track(
+                track,
+                index as u16,
+                0,
+                timing,
+                &mut parameter_states,
+                &mut events,
+                &mut metadata,
+            );
+        }
+        _ => {
+            // Format 0/1: all tracks play at the same time and are merged
+            for (index, track) in smf.tracks.iter().enumerate() {
+                parse_track(
+                    track,
+                    index as u16,
+                    0,
+                    timing,
+                    &mut parameter_states,
+                    &mut events,
+                    &mut metadata,
+                );
+            }
+        }
+    }
+
+    Ok(midi_data(&smf, events, metadata))
+}
+
+/// Parse each pattern of an SMF Format 2 file into its own MIDI data
+///
+/// Format 0 and 1 files yield a single entry with all tracks merged.
+///
+/// # Errors
+/// Returns an error if the data cannot be parsed
+pub fn parse_midi_patterns_from_bytes(data: &[u8]) -> Result<Vec<MidiData>> {
+    let smf = parse_smf(data)?;
+    if smf.header.format != Format::Sequential {
+        return parse_midi_from_bytes(data).map(|midi_data| vec![midi_data]);
+    }
+
+    let timing = midi_timing(smf.header.timing);
+    Ok(smf
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(index, track)| {
+            let mut parameter_states = [ParameterState::default(); 16];
+            let mut events = Vec::new();
+            let mut metadata = MidiMetadata::default();
+            parse_track(
+                track,
+                index as u16,
+                0,
+                timing,
+                &mut parameter_states,
+                &mut events,
+                &mut metadata,
+            );
+            midi_data(&smf, events, metadata)
+        })
+        .collect())
+}
+
+fn parse_smf(data: &[u8]) -> Result<Smf<'_>> {
+    Smf::parse(data).map_err(|e| Error::MidiParse(format!("Failed to parse MIDI file: {}", e)))
+}
+
+fn midi_timing(timing: midly::Timing) -> MidiTiming {
+    match timing {
+        midly::Timing::Metrical(ticks) => MidiTiming::Metrical {
+            ticks_per_beat: ticks.as_int(),
+        },
+        midly::Timing::Timecode(fps, subframe) => MidiTiming::Timecode {
+            fps: match fps {
+                midly::Fps::Fps24 => SmpteFps::Fps24,
+                midly::Fps::Fps25 => SmpteFps::Fps25,
+                midly::Fps::Fps29 => SmpteFps::Fps29DropFrame,
+                midly::Fps::Fps30 => SmpteFps::Fps30,
+            },
+            ticks_per_frame: subframe,
+        },
+    }
+}
+
+fn midi_data(smf: &Smf<'_>, mut events: Vec<MidiEvent>, mut metadata: MidiMetadata) -> MidiData {
+    // Sort events by ticks
+    events.sort_by_key(|e| e.ticks());
+    metadata.time_signatures.sort_by_key(|t| t.ticks);
+    metadata.key_signatures.sort_by_key(|k| k.ticks);
+    metadata.texts.sort_by_key(|t| t.ticks);
+
+    metadata.track_names = track_names(smf);
+    metadata.title = metadata.track_names.first().cloned().flatten();
+    metadata.copyright = metadata
+        .texts
+        .iter()
+        .find(|t| t.kind == TextKind::Copyright)
+        .map(|t| t.text.clone());
+
+    // Calculate initial tempo in BPM
+    let initial_tempo_bpm = MICROSECONDS_PER_MINUTE / DEFAULT_TEMPO_USPQN as f64;
+
+    MidiData {
+        timing: midi_timing(smf.header.timing),
+        tempo_bpm: initial_tempo_bpm,
+        events,
+        metadata,
+    }
+}
+
+/// First track name meta event (0x03) of every track
+fn track_names(smf: &Smf<'_>) -> Vec<Option<String>> {
+    smf.tracks
+        .iter()
+        .map(|track| {
+            track.iter().find_map(|event| match event.kind {
+                TrackEventKind::Meta(midly::MetaMessage::TrackName(name)) => {
+                    Some(String::from_utf8_lossy(name).into_owned())
+                }
+                _ => None,
+            })
+        })
+        .collect()
+}
 
-    // Process all tracks and merge events
-    for track in smf.tracks.iter() {
-        let mut absolute_ticks: u32 = 0;
+/// Parse the events of one track starting at `start_ticks` into `events`,
+/// collecting signatures and text into `metadata`.
+///
+/// Returns the absolute tick of the end of the track.
+fn parse_track(
+    events_in_track: &[TrackEvent<'_>],
+    track: u16,
+    start_ticks: u32,
+    timing: MidiTiming,
+    parameter_states: &mut [ParameterState; 16],
+    events: &mut Vec<MidiEvent>,
+    metadata: &mut MidiMetadata,
+) -> u32 {
+    let mut absolute_ticks = start_ticks;
+    // Set by the port prefix meta event (0x21) and kept for the rest of the track
+    let mut port = None;
 
-        for event in track {
-            // Convert delta time to absolute ticks
-            absolute_ticks = absolute_ticks.saturating_add(event.delta.as_int());
+    for event in events_in_track {
+        // Convert delta time to absolute ticks
+        absolute_ticks = absolute_ticks.saturating_add(event.delta.as_int());
 
-            match &event.kind {
-                TrackEventKind::Midi { channel, message } => {
-                    let ch = channel.as_int();
+        match &event.kind {
+            TrackEventKind::Midi { channel, message } => {
+                let ch = channel.as_int();
 
-                    match message {
-                        MidiMessage::NoteOn { key, vel } => {
-                            let velocity = vel.as_int();
-                            // Note: velocity 0 is treated as Note Off by MIDI spec
-                            if velocity == 0 {
-                                events.push(MidiEvent::NoteOff {
+                match message {
+                    MidiMessage::NoteOn { key, vel } => {
+                        let velocity = vel.as_int();
+                        // Note: velocity 0 is treated as Note Off by MIDI spec
+                        if velocity == 0 {
+                            events.push(MidiEvent::NoteOff {
+                                ticks: absolute_ticks,
+                                channel: ch,
+                                note: key.as_int(),
+                                track,
+                                port,
+                            });
+                        } else {
+                            events.push(MidiEvent::NoteOn {
+                                ticks: absolute_ticks,
+                                channel: ch,
+                                note: key.as_int(),
+                                velocity,
+                                track,
+                                port,
+                            });
+                        }
+                    }
+                    MidiMessage::NoteOff { key, vel: _ } => {
+                        events.push(MidiEvent::NoteOff {
+                            ticks: absolute_ticks,
+                            channel: ch,
+                            note: key.as_int(),
+                            track,
+                            port,
+                        });
+                    }
+                    MidiMessage::ProgramChange { program } => {
+                        events.push(MidiEvent::ProgramChange {
+                            ticks: absolute_ticks,
+                            channel: ch,
+                            program: program.as_int(),
+                            track,
+                            port,
+                        });
+                    }
+                    MidiMessage::PitchBend { bend } => {
+                        events.push(MidiEvent::PitchBend {
+                            ticks: absolute_ticks,
+                            channel: ch,
+                            value: bend.as_int(),
+                            track,
+                            port,
+                        });
+                    }
+                    MidiMessage::ChannelAftertouch { vel } => {
+                        events.push(MidiEvent::ChannelPressure {
+                            ticks: absolute_ticks,
+                            channel: ch,
+                            pressure: vel.as_int(),
+                            track,
+                            port,
+                        });
+                    }
+                    MidiMessage::Aftertouch { key, vel } => {
+                        events.push(MidiEvent::PolyPressure {
+                            ticks: absolute_ticks,
+                            channel: ch,
+                            note: key.as_int(),
+                            pressure: vel.as_int(),
+                            track,
+                            port,
+                        });
+                    }
+                    MidiMessage::Controller { controller, value } => {
+                        let controller = controller.as_int();
+                        let value = value.as_int();
+                        events.push(MidiEvent::ControlChange {
+                            ticks: absolute_ticks,
+                            channel: ch,
+                            controller,
+                            value,
+                            track,
+                            port,
+                        });
+                        let state = &mut parameter_states[ch as usize];
+                        match state.apply_controller(controller, value) {
+                            ParameterUpdate::PitchBendRange => {
+                                events.push(MidiEvent::PitchBendRange {
                                     ticks: absolute_ticks,
                                     channel: ch,
-                                    note: key.as_int(),
+                                    semitones: state.bend_range_semitones,
+                                    cents: state.bend_range_cents,
+                                    track,
+                                    port,
                                 });
-                            } else {
-                                events.push(MidiEvent::NoteOn {
+                            }
+                            ParameterUpdate::Nrpn { parameter, value } => {
+                                events.push(MidiEvent::Nrpn {
                                     ticks: absolute_ticks,
                                     channel: ch,
-                                    note: key.as_int(),
-                                    velocity,
+                                    parameter,
+                                    value,
+                                    track,
+                                    port,
                                 });
                             }
+                            ParameterUpdate::None => {}
                         }
-                        MidiMessage::NoteOff { key, vel: _ } => {
-                            events.push(MidiEvent::NoteOff {
+                        if controller == CC_LOOP_START {
+                            events.push(MidiEvent::LoopStart {
                                 ticks: absolute_ticks,
-                                channel: ch,
-                                note: key.as_int(),
-                            });
-                        }
-                        MidiMessage::ProgramChange { program } => {
-                            events.push(MidiEvent::ProgramChange {
-                                ticks: absolute_ticks,
-                                channel: ch,
-                                program: program.as_int(),
+                                track,
+                                port,
                             });
                         }
-                        _ => {
-                            // Ignore other MIDI messages for now
-                        }
                     }
                 }
-                TrackEventKind::Meta(midly::MetaMessage::Tempo(tempo)) => {
-                    let tempo_uspqn = tempo.as_int();
-                    let tempo_bpm = MICROSECONDS_PER_MINUTE / tempo_uspqn as f64;
-                    events.push(MidiEvent::Tempo {
+            }
+            TrackEventKind::Meta(midly::MetaMessage::Tempo(tempo)) => {
+                // Timecode files run in absolute time; the spec says tempo is ignored
+                if matches!(timing, MidiTiming::Timecode { .. }) {
+                    continue;
+                }
+                let tempo_uspqn = tempo.as_int();
+                let tempo_bpm = MICROSECONDS_PER_MINUTE / tempo_uspqn as f64;
+                events.push(MidiEvent::Tempo {
+                    ticks: absolute_ticks,
+                    tempo_bpm,
+                    track,
+                    port,
+                });
+            }
+            TrackEventKind::Meta(
+                midly::MetaMessage::Marker(text) | midly::MetaMessage::CuePoint(text),
+            ) => {
+                let text = String::from_utf8_lossy(text);
+                let text = text.trim();
+                if text.eq_ignore_ascii_case("loopStart") {
+                    events.push(MidiEvent::LoopStart {
                         ticks: absolute_ticks,
-                        tempo_bpm,
+                        track,
+                        port,
+                    });
+                } else if text.eq_ignore_ascii_case("loopEnd") {
+                    events.push(MidiEvent::LoopEnd {
+                        ticks: absolute_ticks,
+                        track,
+                        port,
                     });
                 }
-                TrackEventKind::Meta(_) => {
-                    // Ignore other meta messages for now
-                }
-                _ => {
-                    // Ignore other event types
+            }
+            TrackEventKind::Meta(midly::MetaMessage::MidiPort(midi_port)) => {
+                port = Some(midi_port.as_int());
+            }
+            TrackEventKind::Meta(midly::MetaMessage::TimeSignature(
+                numerator,
+                denominator_power,
+                clocks_per_click,
+                thirty_seconds_per_quarter,
+            )) => {
+                metadata.time_signatures.push(TimeSignature {
+                    ticks: absolute_ticks,
+                    numerator: *numerator,
+                    denominator: 1u8.checked_shl(*denominator_power as u32).unwrap_or(0),
+                    clocks_per_click: *clocks_per_click,
+                    thirty_seconds_per_quarter: *thirty_seconds_per_quarter,
+                });
+            }
+            TrackEventKind::Meta(midly::MetaMessage::KeySignature(sharps, minor)) => {
+                metadata.key_signatures.push(KeySignature {
+                    ticks: absolute_ticks,
+                    sharps: *sharps,
+                    minor: *minor,
+                });
+            }
+            TrackEventKind::Meta(
+                message @ (midly::MetaMessage::Text(text)
+                | midly::MetaMessage::Copyright(text)
+                | midly::MetaMessage::TrackName(text)
+                | midly::MetaMessage::Lyric(text)),
+            ) => {
+                let kind = match message {
+                    midly::MetaMessage::Copyright(_) => TextKind::Copyright,
+                    midly::MetaMessage::TrackName(_) => TextKind::TrackName,
+                    midly::MetaMessage::Lyric(_) => TextKind::Lyric,
+                    _ => TextKind::Text,
+                };
+                metadata.texts.push(TextEvent {
+                    ticks: absolute_ticks,
+                    track,
+                    kind,
+                    text: String::from_utf8_lossy(text).into_owned(),
+                });
+            }
+            TrackEventKind::Meta(_) => {
+                // Ignore other meta messages for now
+            }
+            TrackEventKind::SysEx(body) => {
+                // Other SysEx messages (GM reset etc.) are ignored
+                for (addr, data) in decode_register_write_sysex(body).unwrap_or_default() {
+                    events.push(MidiEvent::RegisterWrite {
+                        ticks: absolute_ticks,
+                        addr,
+                        data,
+                        track,
+                        port,
+                    });
                 }
             }
+            _ => {
+                // Ignore other event types
+            }
         }
     }
 
-    // Sort events by ticks
-    events.sort_by_key(|e| match e {
-        MidiEvent::NoteOn { ticks, .. } => *ticks,
-        MidiEvent::NoteOff { ticks, .. } => *ticks,
-        MidiEvent::Tempo { ticks, .. } => *ticks,
-        MidiEvent::ProgramChange { ticks, .. } => *ticks,
-    });
-
-    // Calculate initial tempo in BPM
-    let initial_tempo_bpm = MICROSECONDS_PER_MINUTE / DEFAULT_TEMPO_USPQN as f64;
-
-    Ok(MidiData {
-        ticks_per_beat,
-        tempo_bpm: initial_tempo_bpm,
-        events,
-    })
+    absolute_ticks
 }
 
 /// Parse a MIDI file and extract events
@@ -165,14 +585,751 @@ pub fn save_midi_events_json(midi_data: &MidiData, output_path: &str) -> Result<
     Ok(())
 }
 
+/// Load MIDI data from an events JSON file written by [`save_midi_events_json`]
+///
+/// The data is checked with [`validate_midi_data`], so hand-edited files
+/// fail here instead of producing a broken log.
+///
+/// # Arguments
+/// * `path` - Path to the events JSON file
+///
+/// # Returns
+/// MIDI data ready for Pass B
+///
+/// # Errors
+/// Returns an error if the file cannot be read, is not valid events JSON or
+/// fails validation
+pub fn load_midi_events_json(path: &str) -> Result<MidiData> {
+    let json = fs::read_to_string(path)?;
+    midi_data_from_json(&json)
+}
+
+/// Parse and validate MIDI data from events JSON text
+///
+/// # Errors
+/// Returns an error if the text is not valid events JSON or fails validation
+pub fn midi_data_from_json(json: &str) -> Result<MidiData> {
+    let mut value: serde_json::Value = serde_json::from_str(json)?;
+    // Files written before timecode support only have `ticks_per_beat`
+    if let Some(object) = value.as_object_mut() {
+        object
+            .entry("timing")
+            .or_insert_with(|| serde_json::Value::from("metrical"));
+    }
+    let midi_data: MidiData = serde_json::from_value(value)?;
+    validate_midi_data(&midi_data)?;
+    Ok(midi_data)
+}
+
+/// Check that MIDI data could have come from a MIDI file
+///
+/// Events must be sorted by ticks, channels must be 0-15, 7-bit values
+/// (notes, velocities, programs, controllers, pressure) 0-127 and tempos
+/// positive.
+///
+/// # Errors
+/// Returns `Error::InvalidParameter` naming the first offending event
+pub fn validate_midi_data(midi_data: &MidiData) -> Result<()> {
+    match midi_data.timing {
+        MidiTiming::Metrical { ticks_per_beat }
+            if ticks_per_beat == 0 || ticks_per_beat > 0x7FFF =>
+        {
+            return Err(Error::InvalidParameter(format!(
+                "ticks per beat {ticks_per_beat} is out of range (1-32767)"
+            )));
+        }
+        MidiTiming::Timecode {
+            ticks_per_frame: 0, ..
+        } => {
+            return Err(Error::InvalidParameter(
+                "ticks per frame must be positive".to_string(),
+            ));
+        }
+        _ => {}
+    }
+    if !(midi_data.tempo_bpm.is_finite() && midi_data.tempo_bpm > 0.0) {
+        return Err(Error::InvalidParameter(format!(
+            "tempo {} BPM must be positive",
+            midi_data.tempo_bpm
+        )));
+    }
+
+    let mut previous_ticks = 0;
+    for (index, event) in midi_data.events.iter().enumerate() {
+        let invalid = |message: String| {
+            Err(Error::InvalidParameter(format!(
+                "event {index} at tick {}: {message}",
+                event.ticks()
+            )))
+        };
+        if event.ticks() < previous_ticks {
+            return invalid(format!("ticks go back from {previous_ticks}"));
+        }
+        previous_ticks = event.ticks();
+
+        if let Some(channel) = event.channel().filter(|&channel| channel > 15) {
+            return invalid(format!("channel {channel} is out of range (0-15)"));
+        }
+        if let Some(port) = event.port().filter(|&port| port > 127) {
+            return invalid(format!("port {port} is out of range (0-127)"));
+        }
+        let seven_bit: &[(&str, u8)] = match *event {
+            MidiEvent::NoteOn { note, velocity, .. } => &[("note", note), ("velocity", velocity)],
+            MidiEvent::NoteOff { note, .. } => &[("note", note)],
+            MidiEvent::ProgramChange { program, .. } => &[("program", program)],
+            MidiEvent::ControlChange {
+                controller, value, ..
+            } => &[("controller", controller), ("value", value)],
+            MidiEvent::ChannelPressure { pressure, .. } => &[("pressure", pressure)],
+            MidiEvent::PolyPressure { note, pressure, .. } => {
+                &[("note", note), ("pressure", pressure)]
+            }
+            MidiEvent::PitchBendRange {
+                semitones, cents, ..
+            } => &[("semitones", semitones), ("cents", cents)],
+            _ => &[],
+        };
+        if let Some((name, value)) = seven_bit.iter().find(|(_, value)| *value > 127) {
+            return invalid(format!("{name} {value} is out of range (0-127)"));
+        }
+        match *event {
+            MidiEvent::Tempo { tempo_bpm, .. } if !(tempo_bpm.is_finite() && tempo_bpm > 0.0) => {
+                return invalid(format!("tempo {tempo_bpm} BPM must be positive"));
+            }
+            MidiEvent::PitchBend { value, .. } if !(-8192..=8191).contains(&value) => {
+                return invalid(format!(
+                    "pitch bend {value} is out of range (-8192 to 8191)"
+                ));
+            }
+            MidiEvent::Nrpn {
+                parameter, value, ..
+            } if parameter > 0x3FFF || value > 0x3FFF => {
+                return invalid(format!(
+                    "NRPN {parameter} = {value} is out of range (0-16383)"
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::midi::sysex::encode_register_write_sysex;
+    use midly::num::{u14, u28, u4, u7};
+    use midly::{Format, Header, Timing, TrackEvent};
+
+    /// Build a single-track SMF (480 ticks per beat) from `(delta, kind)` pairs
+    fn build_smf(events: Vec<(u32, TrackEventKind<'_>)>) -> Vec<u8> {
+        build_smf_with_timing(Timing::Metrical(midly::num::u15::new(480)), events)
+    }
+
+    fn build_smf_with_timing(timing: Timing, events: Vec<(u32, TrackEventKind<'_>)>) -> Vec<u8> {
+        build_multi_track_smf(Format::SingleTrack, timing, vec![events])
+    }
+
+    fn build_multi_track_smf(
+        format: Format,
+        timing: Timing,
+        tracks: Vec<Vec<(u32, TrackEventKind<'_>)>>,
+    ) -> Vec<u8> {
+        let mut smf = Smf::new(Header::new(format, timing));
+        for events in tracks {
+            let mut track: Vec<TrackEvent<'_>> = events
+                .into_iter()
+                .map(|(delta, kind)| TrackEvent {
+                    delta: u28::new(delta),
+                    kind,
+                })
+                .collect();
+            track.push(TrackEvent {
+                delta: u28::new(0),
+                kind: TrackEventKind::Meta(midly::MetaMessage::EndOfTrack),
+            });
+            smf.tracks.push(track);
+        }
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn midi(channel: u8, message: MidiMessage) -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: u4::new(channel),
+            message,
+        }
+    }
+
+    fn cc(channel: u8, controller: u8, value: u8) -> TrackEventKind<'static> {
+        midi(
+            channel,
+            MidiMessage::Controller {
+                controller: u7::new(controller),
+                value: u7::new(value),
+            },
+        )
+    }
+
     #[test]
     fn test_default_tempo_conversion() {
         // 120 BPM = 500,000 microseconds per quarter note
         let bpm = MICROSECONDS_PER_MINUTE / DEFAULT_TEMPO_USPQN as f64;
         assert_eq!(bpm, 120.0);
     }
+
+    #[test]
+    fn test_parse_pitch_bend() {
+        let bytes = build_smf(vec![
+            (
+                0,
+                midi(
+                    2,
+                    MidiMessage::PitchBend {
+                        bend: midly::PitchBend(u14::new(0x3FFF)),
+                    },
+                ),
+            ),
+            (
+                240,
+                midi(
+                    2,
+                    MidiMessage::PitchBend {
+                        bend: midly::PitchBend(u14::new(0)),
+                    },
+                ),
+            ),
+        ]);
+
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        assert_eq!(
+            midi_data.events,
+            vec![
+                MidiEvent::PitchBend {
+                    ticks: 0,
+                    channel: 2,
+                    value: 8191,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::PitchBend {
+                    ticks: 240,
+                    channel: 2,
+                    value: -8192,
+                    track: 0,
+                    port: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_aftertouch() {
+        let bytes = build_smf(vec![
+            (
+                0,
+                midi(1, MidiMessage::ChannelAftertouch { vel: u7::new(90) }),
+            ),
+            (
+                60,
+                midi(
+                    1,
+                    MidiMessage::Aftertouch {
+                        key: u7::new(64),
+                        vel: u7::new(30),
+                    },
+                ),
+            ),
+        ]);
+
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        assert_eq!(
+            midi_data.events,
+            vec![
+                MidiEvent::ChannelPressure {
+                    ticks: 0,
+                    channel: 1,
+                    pressure: 90,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::PolyPressure {
+                    ticks: 60,
+                    channel: 1,
+                    note: 64,
+                    pressure: 30,
+                    track: 0,
+                    port: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_rpn_pitch_bend_range() {
+        let bytes = build_smf(vec![
+            (0, cc(0, CC_RPN_MSB, 0)),
+            (0, cc(0, CC_RPN_LSB, 0)),
+            (0, cc(0, CC_DATA_ENTRY_MSB, 12)),
+            (0, cc(0, CC_DATA_ENTRY_LSB, 50)),
+            // Selecting an NRPN must stop data entry from affecting the bend range
+            (10, cc(0, CC_NRPN_MSB, 1)),
+            (0, cc(0, CC_NRPN_LSB, 8)),
+            (0, cc(0, CC_DATA_ENTRY_MSB, 64)),
+        ]);
+
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        let ranges: Vec<_> = midi_data
+            .events
+            .into_iter()
+            .filter(|e| matches!(e, MidiEvent::PitchBendRange { .. }))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                MidiEvent::PitchBendRange {
+                    ticks: 0,
+                    channel: 0,
+                    semitones: 12,
+                    cents: 0,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::PitchBendRange {
+                    ticks: 0,
+                    channel: 0,
+                    semitones: 12,
+                    cents: 50,
+                    track: 0,
+                    port: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_data_entry_for_other_rpn_is_ignored() {
+        // RPN 1 (fine tuning) data entry is not a pitch bend range change
+        let bytes = build_smf(vec![
+            (0, cc(0, CC_RPN_MSB, 0)),
+            (0, cc(0, CC_RPN_LSB, 1)),
+            (0, cc(0, CC_DATA_ENTRY_MSB, 64)),
+        ]);
+
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        assert!(!midi_data
+            .events
+            .iter()
+            .any(|e| matches!(e, MidiEvent::PitchBendRange { .. })));
+    }
+
+    #[test]
+    fn test_parse_nrpn_data_entry() {
+        let bytes = build_smf(vec![
+            // Data entry before an NRPN is fully selected is ignored
+            (0, cc(2, CC_NRPN_MSB, 0)),
+            (0, cc(2, CC_DATA_ENTRY_MSB, 5)),
+            (0, cc(2, CC_NRPN_LSB, 0x10)),
+            (10, cc(2, CC_DATA_ENTRY_MSB, 64)),
+            (0, cc(2, CC_DATA_ENTRY_LSB, 3)),
+            // Switching to an RPN ends NRPN data entry
+            (10, cc(2, CC_RPN_MSB, 0)),
+            (0, cc(2, CC_RPN_LSB, 1)),
+            (0, cc(2, CC_DATA_ENTRY_MSB, 64)),
+        ]);
+
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        let nrpns: Vec<_> = midi_data
+            .events
+            .into_iter()
+            .filter(|e| matches!(e, MidiEvent::Nrpn { .. }))
+            .collect();
+        assert_eq!(
+            nrpns,
+            vec![
+                MidiEvent::Nrpn {
+                    ticks: 10,
+                    channel: 2,
+                    parameter: 0x10,
+                    value: 64 << 7,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::Nrpn {
+                    ticks: 10,
+                    channel: 2,
+                    parameter: 0x10,
+                    value: (64 << 7) | 3,
+                    track: 0,
+                    port: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_register_write_sysex() {
+        let body = encode_register_write_sysex(&[(0x0F, 0x80), (0x01, 0x02)]);
+        let bytes = build_smf(vec![
+            (0, TrackEventKind::SysEx(&[0x7E, 0x7F, 0x09, 0x01, 0xF7])),
+            (240, TrackEventKind::SysEx(&body)),
+        ]);
+
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        assert_eq!(
+            midi_data.events,
+            vec![
+                MidiEvent::RegisterWrite {
+                    ticks: 240,
+                    addr: 0x0F,
+                    data: 0x80,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::RegisterWrite {
+                    ticks: 240,
+                    addr: 0x01,
+                    data: 0x02,
+                    track: 0,
+                    port: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_track_and_port_provenance() {
+        let note_on = |channel: u8| {
+            midi(
+                channel,
+                MidiMessage::NoteOn {
+                    key: u7::new(60),
+                    vel: u7::new(100),
+                },
+            )
+        };
+        let bytes = build_multi_track_smf(
+            Format::Parallel,
+            Timing::Metrical(midly::num::u15::new(480)),
+            vec![
+                vec![
+                    (
+                        0,
+                        TrackEventKind::Meta(midly::MetaMessage::TrackName(b"Lead")),
+                    ),
+                    (0, note_on(0)),
+                ],
+                vec![
+                    (
+                        0,
+                        TrackEventKind::Meta(midly::MetaMessage::MidiPort(u7::new(1))),
+                    ),
+                    (240, note_on(0)),
+                ],
+            ],
+        );
+
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        assert_eq!(
+            midi_data.metadata.track_names,
+            vec![Some("Lead".to_string()), None]
+        );
+        let sources: Vec<_> = midi_data
+            .events
+            .iter()
+            .map(|e| (e.ticks(), e.track(), e.port()))
+            .collect();
+        assert_eq!(sources, vec![(0, 0, None), (240, 1, Some(1))]);
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let meta = |message| TrackEventKind::Meta(message);
+        let bytes = build_smf(vec![
+            (0, meta(midly::MetaMessage::TrackName(b"Song"))),
+            (0, meta(midly::MetaMessage::Copyright(b"(c) 2026"))),
+            (0, meta(midly::MetaMessage::TimeSignature(6, 3, 24, 8))),
+            (0, meta(midly::MetaMessage::KeySignature(-3, true))),
+            (480, meta(midly::MetaMessage::Lyric(b"la"))),
+            (0, meta(midly::MetaMessage::Text(b"verse"))),
+        ]);
+
+        let metadata = parse_midi_from_bytes(&bytes).unwrap().metadata;
+        assert_eq!(metadata.title.as_deref(), Some("Song"));
+        assert_eq!(metadata.copyright.as_deref(), Some("(c) 2026"));
+        assert_eq!(
+            metadata.time_signatures,
+            vec![TimeSignature {
+                ticks: 0,
+                numerator: 6,
+                denominator: 8,
+                clocks_per_click: 24,
+                thirty_seconds_per_quarter: 8,
+            }]
+        );
+        assert_eq!(
+            metadata.key_signatures,
+            vec![KeySignature {
+                ticks: 0,
+                sharps: -3,
+                minor: true,
+            }]
+        );
+        let texts: Vec<_> = metadata
+            .texts
+            .iter()
+            .map(|t| (t.ticks, t.kind, t.text.as_str()))
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                (0, TextKind::TrackName, "Song"),
+                (0, TextKind::Copyright, "(c) 2026"),
+                (480, TextKind::Lyric, "la"),
+                (480, TextKind::Text, "verse"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_loop_markers() {
+        let bytes = build_smf(vec![
+            (
+                0,
+                TrackEventKind::Meta(midly::MetaMessage::Marker(b"Intro")),
+            ),
+            (
+                480,
+                TrackEventKind::Meta(midly::MetaMessage::Marker(b"loopStart")),
+            ),
+            (
+                480,
+                TrackEventKind::Meta(midly::MetaMessage::CuePoint(b"LOOPEND")),
+            ),
+            (0, cc(0, CC_LOOP_START, 0)),
+        ]);
+
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        let loop_events: Vec<_> = midi_data
+            .events
+            .into_iter()
+            .filter(|e| !matches!(e, MidiEvent::ControlChange { .. }))
+            .collect();
+        assert_eq!(
+            loop_events,
+            vec![
+                MidiEvent::LoopStart {
+                    ticks: 480,
+                    track: 0,
+                    port: None
+                },
+                MidiEvent::LoopEnd {
+                    ticks: 960,
+                    track: 0,
+                    port: None
+                },
+                MidiEvent::LoopStart {
+                    ticks: 960,
+                    track: 0,
+                    port: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_timecode_timing_ignores_tempo() {
+        let bytes = build_smf_with_timing(
+            Timing::Timecode(midly::Fps::Fps29, 80),
+            vec![
+                (
+                    0,
+                    TrackEventKind::Meta(midly::MetaMessage::Tempo(midly::num::u24::new(
+                        1_000_000,
+                    ))),
+                ),
+                (0, cc(0, 7, 100)),
+            ],
+        );
+
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        assert_eq!(
+            midi_data.timing,
+            MidiTiming::Timecode {
+                fps: SmpteFps::Fps29DropFrame,
+                ticks_per_frame: 80,
+            }
+        );
+        assert!(midi_data
+            .events
+            .iter()
+            .all(|e| !matches!(e, MidiEvent::Tempo { .. })));
+
+        let json = serde_json::to_value(&midi_data).unwrap();
+        assert_eq!(json["timing"], "timecode");
+        assert_eq!(json["fps"], "fps29_drop_frame");
+        let round_trip: MidiData = serde_json::from_value(json).unwrap();
+        assert_eq!(round_trip.timing, midi_data.timing);
+    }
+
+    /// Two Format 2 patterns of 960 ticks with one program change each
+    fn format2_patterns(format: Format) -> Vec<u8> {
+        let pattern = |program: u8| {
+            vec![
+                (
+                    0,
+                    midi(
+                        0,
+                        MidiMessage::ProgramChange {
+                            program: u7::new(program),
+                        },
+                    ),
+                ),
+                (960, cc(0, 7, 100)),
+            ]
+        };
+        build_multi_track_smf(
+            format,
+            Timing::Metrical(midly::num::u15::new(480)),
+            vec![pattern(1), pattern(2)],
+        )
+    }
+
+    fn program_changes(midi_data: &MidiData) -> Vec<(u32, u8)> {
+        midi_data
+            .events
+            .iter()
+            .filter_map(|e| match *e {
+                MidiEvent::ProgramChange { ticks, program, .. } => Some((ticks, program)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_format2_patterns_play_sequentially() {
+        let bytes = format2_patterns(Format::Sequential);
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        assert_eq!(program_changes(&midi_data), vec![(0, 1), (960, 2)]);
+    }
+
+    #[test]
+    fn test_format2_single_pattern() {
+        let bytes = format2_patterns(Format::Sequential);
+        let midi_data =
+            parse_midi_from_bytes_with_playback(&bytes, Format2Playback::Pattern(1)).unwrap();
+        assert_eq!(program_changes(&midi_data), vec![(0, 2)]);
+
+        let missing = parse_midi_from_bytes_with_playback(&bytes, Format2Playback::Pattern(2));
+        assert!(matches!(missing, Err(Error::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn test_format2_separate_patterns() {
+        let bytes = format2_patterns(Format::Sequential);
+        let patterns = parse_midi_patterns_from_bytes(&bytes).unwrap();
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(program_changes(&patterns[0]), vec![(0, 1)]);
+        assert_eq!(program_changes(&patterns[1]), vec![(0, 2)]);
+    }
+
+    #[test]
+    fn test_format1_tracks_are_merged() {
+        let bytes = format2_patterns(Format::Parallel);
+        let midi_data =
+            parse_midi_from_bytes_with_playback(&bytes, Format2Playback::Pattern(1)).unwrap();
+        assert_eq!(program_changes(&midi_data), vec![(0, 1), (0, 2)]);
+        assert_eq!(parse_midi_patterns_from_bytes(&bytes).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_control_change() {
+        let bytes = build_smf(vec![(0, cc(3, 7, 100)), (120, cc(3, 64, 127))]);
+
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        assert_eq!(
+            midi_data.events,
+            vec![
+                MidiEvent::ControlChange {
+                    ticks: 0,
+                    channel: 3,
+                    controller: 7,
+                    value: 100,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::ControlChange {
+                    ticks: 120,
+                    channel: 3,
+                    controller: 64,
+                    value: 127,
+                    track: 0,
+                    port: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_control_change_events_json_round_trip() {
+        let bytes = build_smf(vec![(0, cc(0, 10, 0)), (0, cc(0, 1, 64))]);
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+
+        let output_path = std::env::temp_dir().join("test_control_change_events.json");
+        let output_path_str = output_path.to_str().unwrap();
+        save_midi_events_json(&midi_data, output_path_str).unwrap();
+
+        let json = fs::read_to_string(&output_path).unwrap();
+        let _ = fs::remove_file(&output_path);
+        assert!(json.contains("\"type\": \"control_change\""));
+
+        let restored: MidiData = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.events, midi_data.events);
+    }
+
+    #[test]
+    fn test_load_midi_events_json() {
+        let bytes = build_smf(vec![(0, cc(0, 7, 100)), (240, cc(1, 10, 0))]);
+        let midi_data = parse_midi_from_bytes(&bytes).unwrap();
+
+        let output_path = std::env::temp_dir().join("test_load_midi_events.json");
+        let output_path_str = output_path.to_str().unwrap();
+        save_midi_events_json(&midi_data, output_path_str).unwrap();
+        let loaded = load_midi_events_json(output_path_str);
+        let _ = fs::remove_file(&output_path);
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.events, midi_data.events);
+        assert_eq!(loaded.timing, midi_data.timing);
+    }
+
+    #[test]
+    fn test_midi_data_from_json_rejects_invalid_events() {
+        let with_events = |events: &str| {
+            format!(r#"{{"ticks_per_beat": 480, "tempo_bpm": 120.0, "events": [{events}]}}"#)
+        };
+        let note = |ticks: u32, channel: u8, note: u8| {
+            format!(
+                r#"{{"type": "note_on", "ticks": {ticks}, "channel": {channel}, "note": {note}, "velocity": 100}}"#
+            )
+        };
+
+        assert!(midi_data_from_json(&with_events(&note(0, 15, 127))).is_ok());
+        let invalid = [
+            with_events(&format!("{}, {}", note(480, 0, 60), note(0, 0, 62))),
+            with_events(&note(0, 16, 60)),
+            with_events(&note(0, 0, 128)),
+            with_events(r#"{"type": "tempo", "ticks": 0, "tempo_bpm": 0.0}"#),
+            r#"{"ticks_per_beat": 480, "tempo_bpm": -1.0, "events": []}"#.to_string(),
+            r#"{"ticks_per_beat": 0, "tempo_bpm": 120.0, "events": []}"#.to_string(),
+        ];
+        for json in invalid {
+            assert!(
+                matches!(midi_data_from_json(&json), Err(Error::InvalidParameter(_))),
+                "{json}"
+            );
+        }
+    }
 }
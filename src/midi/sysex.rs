@@ -0,0 +1,88 @@
+//! YM2151 register write SysEx format
+//!
+//! Lets a MIDI file carry raw YM2151 register writes at exact song positions.
+//!
+//! ```text
+//! F0 7D 59 4D  aH aL dH dL  [aH aL dH dL ...]  F7
+//! ```
+//!
+//! - `7D` is the non-commercial manufacturer ID, followed by the ASCII tag `"YM"`.
+//! - Each register write is an address and a data byte, each split into
+//!   high and low nibbles so every payload byte stays within 7 bits.
+
+/// Non-commercial SysEx manufacturer ID
+pub const SYSEX_MANUFACTURER_ID: u8 = 0x7D;
+
+/// Tag following the manufacturer ID that marks a register write message
+pub const SYSEX_REGISTER_WRITE_TAG: [u8; 2] = [b'Y', b'M'];
+
+/// End of exclusive status byte
+const SYSEX_END: u8 = 0xF7;
+
+/// Decode a register write SysEx body (the bytes after `F0`)
+///
+/// Returns `None` when the message is not a register write message or is malformed.
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::midi::decode_register_write_sysex;
+/// let body = [0x7D, b'Y', b'M', 0x0, 0x8, 0x0, 0x0, 0xF7];
+/// assert_eq!(decode_register_write_sysex(&body), Some(vec![(0x08, 0x00)]));
+/// ```
+pub fn decode_register_write_sysex(body: &[u8]) -> Option<Vec<(u8, u8)>> {
+    let body = body.strip_suffix(&[SYSEX_END]).unwrap_or(body);
+    let payload = body
+        .strip_prefix(&[SYSEX_MANUFACTURER_ID])?
+        .strip_prefix(&SYSEX_REGISTER_WRITE_TAG)?;
+    if payload.is_empty() || payload.len() % 4 != 0 || payload.iter().any(|&b| b > 0x0F) {
+        return None;
+    }
+    Some(
+        payload
+            .chunks_exact(4)
+            .map(|c| ((c[0] << 4) | c[1], (c[2] << 4) | c[3]))
+            .collect(),
+    )
+}
+
+/// Encode register writes as a SysEx body (the bytes after `F0`, including `F7`)
+pub fn encode_register_write_sysex(writes: &[(u8, u8)]) -> Vec<u8> {
+    let mut body = vec![SYSEX_MANUFACTURER_ID];
+    body.extend_from_slice(&SYSEX_REGISTER_WRITE_TAG);
+    for &(addr, data) in writes {
+        body.extend_from_slice(&[addr >> 4, addr & 0x0F, data >> 4, data & 0x0F]);
+    }
+    body.push(SYSEX_END);
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_write_sysex_round_trip() {
+        let writes = vec![(0x0F, 0x80), (0x19, 0xFF)];
+        let body = encode_register_write_sysex(&writes);
+        assert!(body.iter().all(|&b| b < 0x80 || b == SYSEX_END));
+        assert_eq!(decode_register_write_sysex(&body), Some(writes));
+    }
+
+    #[test]
+    fn test_decode_rejects_other_sysex() {
+        // GM system on (universal non-realtime)
+        assert_eq!(
+            decode_register_write_sysex(&[0x7E, 0x7F, 0x09, 0x01, 0xF7]),
+            None
+        );
+        // Truncated pair and out-of-range nibble
+        assert_eq!(
+            decode_register_write_sysex(&[0x7D, b'Y', b'M', 0x1, 0xF7]),
+            None
+        );
+        assert_eq!(
+            decode_register_write_sysex(&[0x7D, b'Y', b'M', 0x1, 0x10, 0x0, 0x0, 0xF7]),
+            None
+        );
+    }
+}
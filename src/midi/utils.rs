@@ -2,6 +2,7 @@
 //!
 //! Provides conversion functions for MIDI to YM2151 parameters.
 
+use super::events::MidiTiming;
 use crate::ym2151::note_table::NOTE_TABLE;
 
 /// Convert MIDI note number to frequency in Hz (A4 = 440 Hz)
@@ -137,7 +138,7 @@ pub struct TempoChange {
 ///
 /// # Arguments
 /// * `target_tick` - The tick to convert to sample time
-/// * `ticks_per_beat` - Ticks per quarter note (from MIDI file)
+/// * `timing` - Timing model of the file (a bare `u16` is ticks per quarter note)
 /// * `tempo_map` - Sorted list of tempo changes (by tick)
 ///
 /// # Returns
@@ -156,9 +157,18 @@ pub struct TempoChange {
 /// ```
 pub fn ticks_to_samples_with_tempo_map(
     target_tick: u32,
-    ticks_per_beat: u16,
+    timing: impl Into<MidiTiming>,
     tempo_map: &[TempoChange],
 ) -> u32 {
+    let ticks_per_beat = match timing.into() {
+        MidiTiming::Metrical { ticks_per_beat } => ticks_per_beat,
+        timecode => {
+            // Timecode ticks are absolute; the tempo map does not apply
+            let ticks_per_second = timecode.ticks_per_second().unwrap_or(1.0).max(f64::EPSILON);
+            return seconds_to_samples(target_tick as f64 / ticks_per_second);
+        }
+    };
+
     if tempo_map.is_empty() {
         // No tempo changes - use default 120 BPM
         return ticks_to_samples(target_tick, ticks_per_beat, 120.0);
@@ -206,11 +216,12 @@ pub fn ticks_to_samples_with_tempo_map(
 /// Convert MIDI ticks to seconds with tempo changes
 ///
 /// This function correctly handles tempo changes by calculating accumulated time
-/// across different tempo segments.
+/// across different tempo segments. Timecode ticks are converted at their fixed
+/// rate and ignore the tempo map.
 ///
 /// # Arguments
 /// * `target_tick` - The tick to convert to seconds
-/// * `ticks_per_beat` - Ticks per quarter note (from MIDI file)
+/// * `timing` - Timing model of the file (a bare `u16` is ticks per quarter note)
 /// * `tempo_map` - Sorted list of tempo changes (by tick)
 ///
 /// # Returns
@@ -229,9 +240,18 @@ pub fn ticks_to_samples_with_tempo_map(
 /// ```
 pub fn ticks_to_seconds_with_tempo_map(
     target_tick: u32,
-    ticks_per_beat: u16,
+    timing: impl Into<MidiTiming>,
     tempo_map: &[TempoChange],
 ) -> f64 {
+    let ticks_per_beat = match timing.into() {
+        MidiTiming::Metrical { ticks_per_beat } => ticks_per_beat,
+        timecode => {
+            // Timecode ticks are absolute; the tempo map does not apply
+            let ticks_per_second = timecode.ticks_per_second().unwrap_or(1.0).max(f64::EPSILON);
+            return target_tick as f64 / ticks_per_second;
+        }
+    };
+
     if tempo_map.is_empty() {
         // No tempo changes - use default 120 BPM
         return ticks_to_seconds(target_tick, ticks_per_beat, 120.0);
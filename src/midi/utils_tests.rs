@@ -1,5 +1,6 @@
 //! Tests for MIDI utility functions
 use super::*;
+use crate::midi::SmpteFps;
 
 #[test]
 fn test_midi_to_kc_kf_middle_c() {
@@ -388,3 +389,33 @@ fn test_ticks_to_seconds_with_tempo_map_before_first_change() {
     let seconds = ticks_to_seconds_with_tempo_map(240, 480, &tempo_map);
     assert!((seconds - 0.5).abs() < 0.001);
 }
+
+#[test]
+fn test_ticks_to_seconds_timecode_ignores_tempo_map() {
+    // 25 fps x 40 ticks per frame = 1000 ticks per second (millisecond ticks)
+    let timing = MidiTiming::Timecode {
+        fps: SmpteFps::Fps25,
+        ticks_per_frame: 40,
+    };
+    let tempo_map = vec![TempoChange {
+        tick: 0,
+        tempo_bpm: 60.0,
+    }];
+    let seconds = ticks_to_seconds_with_tempo_map(1500, timing, &tempo_map);
+    assert!((seconds - 1.5).abs() < 1e-12);
+    assert_eq!(
+        ticks_to_samples_with_tempo_map(1000, timing, &tempo_map),
+        55930
+    );
+}
+
+#[test]
+fn test_ticks_to_seconds_timecode_drop_frame() {
+    // 29.97 fps is 30000/1001 frames per second, not 29
+    let timing = MidiTiming::Timecode {
+        fps: SmpteFps::Fps29DropFrame,
+        ticks_per_frame: 100,
+    };
+    let seconds = ticks_to_seconds_with_tempo_map(3000, timing, &[]);
+    assert!((seconds - 1.001).abs() < 1e-12);
+}
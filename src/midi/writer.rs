@@ -0,0 +1,861 @@
+//! Standard MIDI File writer
+//!
+//! Turns `MidiData` back into an SMF so edited event lists can be used by
+//! other tools. Parsing the written file gives back the same event list.
+
+use crate::error::{Error, Result};
+use crate::midi::events::{MidiData, MidiEvent, MidiTiming, SmpteFps, TextKind};
+use crate::midi::parser::{
+    ParameterState, ParameterUpdate, CC_DATA_ENTRY_LSB, CC_DATA_ENTRY_MSB, CC_LOOP_START,
+    CC_NRPN_LSB, CC_NRPN_MSB, CC_RPN_LSB, CC_RPN_MSB, DEFAULT_TEMPO_USPQN, MICROSECONDS_PER_MINUTE,
+};
+use crate::midi::sysex::encode_register_write_sysex;
+use midly::num::{u14, u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Track layout of a written SMF
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SmfFormat {
+    /// Format 0: every event in a single track
+    SingleTrack,
+    /// Format 1: one track per `track` index of the events
+    #[default]
+    MultiTrack,
+}
+
+/// Owned content of one track event, before it is encoded
+enum Item {
+    Midi { channel: u4, message: MidiMessage },
+    Tempo(u24),
+    MidiPort(u7),
+    TimeSignature(u8, u8, u8, u8),
+    KeySignature(i8, bool),
+    Text(TextKind, Vec<u8>),
+    Marker(&'static [u8]),
+    RegisterWrites(Vec<(u8, u8)>),
+}
+
+/// Write MIDI data to Standard MIDI File bytes
+///
+/// Events derived by the parser from controllers (pitch bend range and NRPN
+/// from data entry, loop start from CC111) are not written again when their
+/// controller directly precedes them and produces the same values; otherwise
+/// they are written as the controllers or markers that produce them, so
+/// edited values are kept. Without a tempo event, `tempo_bpm` is written as
+/// the initial tempo unless it is the SMF default of 120 BPM. Signatures go
+/// to the first track and text events to their own track.
+///
+/// # Arguments
+/// * `midi_data` - MIDI data to write
+/// * `format` - Track layout of the file
+///
+/// # Returns
+/// SMF bytes
+///
+/// # Errors
+/// Returns an error if an event value does not fit in a MIDI message or two
+/// events of a track are 2^28 ticks or more apart
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::midi::{
+///     parse_midi_from_bytes, write_midi_to_bytes, MidiData, MidiEvent, MidiMetadata, MidiTiming,
+///     SmfFormat,
+/// };
+///
+/// let midi_data = MidiData {
+///     timing: MidiTiming::Metrical { ticks_per_beat: 480 },
+///     tempo_bpm: 120.0,
+///     events: vec![MidiEvent::NoteOn {
+///         ticks: 0,
+///         channel: 0,
+///         note: 60,
+///         velocity: 100,
+///         track: 0,
+///         port: None,
+///     }],
+///     metadata: MidiMetadata::default(),
+/// };
+/// let bytes = write_midi_to_bytes(&midi_data, SmfFormat::MultiTrack).unwrap();
+/// assert_eq!(parse_midi_from_bytes(&bytes).unwrap().events, midi_data.events);
+/// ```
+pub fn write_midi_to_bytes(midi_data: &MidiData, format: SmfFormat) -> Result<Vec<u8>> {
+    let metadata = &midi_data.metadata;
+    let track_index = |track: u16| match format {
+        SmfFormat::SingleTrack => 0,
+        SmfFormat::MultiTrack => track as usize,
+    };
+    let track_count = match format {
+        SmfFormat::SingleTrack => 1,
+        SmfFormat::MultiTrack => midi_data
+            .events
+            .iter()
+            .map(|event| event.track() as usize + 1)
+            .chain(metadata.texts.iter().map(|text| text.track as usize + 1))
+            .chain([metadata.track_names.len(), 1])
+            .max()
+            .unwrap_or(1),
+    };
+    let mut tracks: Vec<Vec<(u32, Item)>> = (0..track_count).map(|_| Vec::new()).collect();
+
+    // Metadata first, so it comes before the events at the same tick
+    let has_tempo = midi_data
+        .events
+        .iter()
+        .any(|event| matches!(event, MidiEvent::Tempo { .. }));
+    if !has_tempo && matches!(midi_data.timing, MidiTiming::Metrical { .. }) {
+        let tempo = tempo_uspqn(midi_data.tempo_bpm)?;
+        if tempo.as_int() != DEFAULT_TEMPO_USPQN {
+            tracks[0].push((0, Item::Tempo(tempo)));
+        }
+    }
+    for signature in &metadata.time_signatures {
+        let denominator = signature.denominator;
+        if !denominator.is_power_of_two() {
+            return Err(Error::InvalidParameter(format!(
+                "time signature denominator {denominator} is not a power of two"
+            )));
+        }
+        tracks[0].push((
+            signature.ticks,
+            Item::TimeSignature(
+                signature.numerator,
+                denominator.trailing_zeros() as u8,
+                signature.clocks_per_click,
+                signature.thirty_seconds_per_quarter,
+            ),
+        ));
+    }
+    for signature in &metadata.key_signatures {
+        tracks[0].push((
+            signature.ticks,
+            Item::KeySignature(signature.sharps, signature.minor),
+        ));
+    }
+    for text in &metadata.texts {
+        tracks[track_index(text.track)].push((
+            text.ticks,
+            Item::Text(text.kind, text.text.clone().into_bytes()),
+        ));
+    }
+    // Names and copyright set without a matching text event
+    let has_text = |track: usize, kind: TextKind| {
+        metadata
+            .texts
+            .iter()
+            .any(|text| track_index(text.track) == track && text.kind == kind)
+    };
+    let mut names: BTreeMap<usize, &String> = BTreeMap::new();
+    for (track, name) in metadata.track_names.iter().enumerate() {
+        if let Some(name) = name {
+            names.entry(track_index(track as u16)).or_insert(name);
+        }
+    }
+    if let Some(title) = &metadata.title {
+        names.entry(0).or_insert(title);
+    }
+    for (track, name) in names {
+        if !has_text(track, TextKind::TrackName) {
+            tracks[track].push((
+                0,
+                Item::Text(TextKind::TrackName, name.clone().into_bytes()),
+            ));
+        }
+    }
+    if let Some(copyright) = &metadata.copyright {
+        if !has_text(0, TextKind::Copyright) {
+            tracks[0].push((
+                0,
+                Item::Text(TextKind::Copyright, copyright.clone().into_bytes()),
+            ));
+        }
+    }
+
+    let mut events: Vec<&MidiEvent> = midi_data.events.iter().collect();
+    events.sort_by_key(|event| event.ticks());
+    let mut ports: Vec<Option<u8>> = vec![None; track_count];
+    // Data entry state per channel as a parser reading the written file sees it
+    let mut parameter_states = [ParameterState::default(); 16];
+    let mut derived: Option<MidiEvent> = None;
+    for event in events {
+        let track = track_index(event.track());
+        let ticks = event.ticks();
+        if let Some(port) = event.port().filter(|&port| ports[track] != Some(port)) {
+            ports[track] = Some(port);
+            tracks[track].push((ticks, Item::MidiPort(to_u7(port, "port")?)));
+        }
+        if derived.as_ref() != Some(event) {
+            push_event(event, &mut tracks[track])?;
+        }
+        derived = None;
+        let written_controllers = match *event {
+            MidiEvent::ControlChange {
+                controller, value, ..
+            } => vec![(controller, value)],
+            MidiEvent::PitchBendRange { .. } | MidiEvent::Nrpn { .. } => {
+                parameter_controllers(event)?
+            }
+            _ => Vec::new(),
+        };
+        let Some(state) = event
+            .channel()
+            .and_then(|channel| parameter_states.get_mut(channel as usize))
+        else {
+            continue;
+        };
+        for (controller, value) in written_controllers {
+            derived = derived_event(event, state, controller, value);
+        }
+    }
+
+    let header = Header::new(
+        match format {
+            SmfFormat::SingleTrack => Format::SingleTrack,
+            SmfFormat::MultiTrack => Format::Parallel,
+        },
+        smf_timing(midi_data.timing)?,
+    );
+    let mut smf = Smf::new(header);
+    let sysex_bodies: Vec<Vec<Vec<u8>>> = tracks
+        .iter()
+        .map(|items| {
+            items
+                .iter()
+                .map(|(_, item)| match item {
+                    Item::RegisterWrites(writes) => encode_register_write_sysex(writes),
+                    _ => Vec::new(),
+                })
+                .collect()
+        })
+        .collect();
+    for (items, bodies) in tracks.iter().zip(&sysex_bodies) {
+        let mut order: Vec<usize> = (0..items.len()).collect();
+        order.sort_by_key(|&index| items[index].0);
+        let mut track = Vec::with_capacity(items.len() + 1);
+        let mut last_ticks = 0;
+        for index in order {
+            let (ticks, item) = &items[index];
+            let delta = ticks - last_ticks;
+            let delta = u28::try_from(delta).ok_or_else(|| {
+                Error::InvalidParameter(format!(
+                    "{delta} ticks between two events do not fit in an SMF delta time"
+                ))
+            })?;
+            track.push(TrackEvent {
+                delta,
+                kind: track_event_kind(item, &bodies[index]),
+            });
+            last_ticks = *ticks;
+        }
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        smf.tracks.push(track);
+    }
+
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Write MIDI data to a Standard MIDI File
+///
+/// # Arguments
+/// * `midi_data` - MIDI data to write
+/// * `output_path` - Path to output SMF
+/// * `format` - Track layout of the file
+///
+/// # Errors
+/// Returns an error if an event value does not fit in a MIDI message or the
+/// file cannot be written
+pub fn write_midi_file(midi_data: &MidiData, output_path: &str, format: SmfFormat) -> Result<()> {
+    let bytes = write_midi_to_bytes(midi_data, format)?;
+    fs::write(output_path, bytes)?;
+    Ok(())
+}
+
+/// Event the parser derives from `controller` written for `event`
+///
+/// `state` is the data entry state of the event's channel and is updated.
+fn derived_event(
+    event: &MidiEvent,
+    state: &mut ParameterState,
+    controller: u8,
+    value: u8,
+) -> Option<MidiEvent> {
+    let (ticks, track, port) = (event.ticks(), event.track(), event.port());
+    let channel = event.channel()?;
+    if controller == CC_LOOP_START {
+        return Some(MidiEvent::LoopStart { ticks, track, port });
+    }
+    match state.apply_controller(controller, value) {
+        ParameterUpdate::PitchBendRange => Some(MidiEvent::PitchBendRange {
+            ticks,
+            channel,
+            semitones: state.bend_range_semitones,
+            cents: state.bend_range_cents,
+            track,
+            port,
+        }),
+        ParameterUpdate::Nrpn { parameter, value } => Some(MidiEvent::Nrpn {
+            ticks,
+            channel,
+            parameter,
+            value,
+            track,
+            port,
+        }),
+        ParameterUpdate::None => None,
+    }
+}
+
+/// Controllers (number, value) that write a pitch bend range or NRPN event
+fn parameter_controllers(event: &MidiEvent) -> Result<Vec<(u8, u8)>> {
+    Ok(match *event {
+        MidiEvent::PitchBendRange {
+            semitones, cents, ..
+        } => vec![
+            (CC_RPN_MSB, 0),
+            (CC_RPN_LSB, 0),
+            (CC_DATA_ENTRY_MSB, semitones),
+            (CC_DATA_ENTRY_LSB, cents),
+        ],
+        MidiEvent::Nrpn {
+            parameter, value, ..
+        } => {
+            if parameter > 0x3FFF || value > 0x3FFF {
+                return Err(Error::InvalidParameter(format!(
+                    "NRPN {parameter} = {value} is out of range (0-16383)"
+                )));
+            }
+            vec![
+                (CC_NRPN_MSB, (parameter >> 7) as u8),
+                (CC_NRPN_LSB, (parameter & 0x7F) as u8),
+                (CC_DATA_ENTRY_MSB, (value >> 7) as u8),
+                (CC_DATA_ENTRY_LSB, (value & 0x7F) as u8),
+            ]
+        }
+        _ => Vec::new(),
+    })
+}
+
+/// Append the items that write `event` to a track
+fn push_event(event: &MidiEvent, items: &mut Vec<(u32, Item)>) -> Result<()> {
+    let ticks = event.ticks();
+    let midi = |channel: u8, message: MidiMessage| -> Result<(u32, Item)> {
+        if channel > 15 {
+            return Err(Error::InvalidParameter(format!(
+                "MIDI channel {channel} is out of range (0-15)"
+            )));
+        }
+        Ok((
+            ticks,
+            Item::Midi {
+                channel: u4::new(channel),
+                message,
+            },
+        ))
+    };
+    let cc = |channel: u8, controller: u8, value: u8| -> Result<(u32, Item)> {
+        midi(
+            channel,
+            MidiMessage::Controller {
+                controller: to_u7(controller, "controller")?,
+                value: to_u7(value, "controller value")?,
+            },
+        )
+    };
+
+    match *event {
+        MidiEvent::NoteOn {
+            channel,
+            note,
+            velocity,
+            ..
+        } => items.push(midi(
+            channel,
+            MidiMessage::NoteOn {
+                key: to_u7(note, "note")?,
+                vel: to_u7(velocity, "velocity")?,
+            },
+        )?),
+        MidiEvent::NoteOff { channel, note, .. } => items.push(midi(
+            channel,
+            MidiMessage::NoteOff {
+                key: to_u7(note, "note")?,
+                vel: u7::new(64),
+            },
+        )?),
+        MidiEvent::Tempo { tempo_bpm, .. } => {
+            items.push((ticks, Item::Tempo(tempo_uspqn(tempo_bpm)?)));
+        }
+        MidiEvent::ProgramChange {
+            channel, program, ..
+        } => items.push(midi(
+            channel,
+            MidiMessage::ProgramChange {
+                program: to_u7(program, "program")?,
+            },
+        )?),
+        MidiEvent::ControlChange {
+            channel,
+            controller,
+            value,
+            ..
+        } => items.push(cc(channel, controller, value)?),
+        MidiEvent::ChannelPressure {
+            channel, pressure, ..
+        } => items.push(midi(
+            channel,
+            MidiMessage::ChannelAftertouch {
+                vel: to_u7(pressure, "pressure")?,
+            },
+        )?),
+        MidiEvent::PolyPressure {
+            channel,
+            note,
+            pressure,
+            ..
+        } => items.push(midi(
+            channel,
+            MidiMessage::Aftertouch {
+                key: to_u7(note, "note")?,
+                vel: to_u7(pressure, "pressure")?,
+            },
+        )?),
+        MidiEvent::PitchBend { channel, value, .. } => {
+            if !(-8192..=8191).contains(&value) {
+                return Err(Error::InvalidParameter(format!(
+                    "pitch bend {value} is out of range (-8192 to 8191)"
+                )));
+            }
+            items.push(midi(
+                channel,
+                MidiMessage::PitchBend {
+                    bend: midly::PitchBend(u14::new((value + 0x2000) as u16)),
+                },
+            )?)
+        }
+        MidiEvent::PitchBendRange { channel, .. } | MidiEvent::Nrpn { channel, .. } => {
+            for (controller, value) in parameter_controllers(event)? {
+                items.push(cc(channel, controller, value)?);
+            }
+        }
+        MidiEvent::RegisterWrite { addr, data, .. } => {
+            // Writes at the same tick share one SysEx message
+            match items.last_mut() {
+                Some((last_ticks, Item::RegisterWrites(writes))) if *last_ticks == ticks => {
+                    writes.push((addr, data))
+                }
+                _ => items.push((ticks, Item::RegisterWrites(vec![(addr, data)]))),
+            }
+        }
+        MidiEvent::LoopStart { .. } => items.push((ticks, Item::Marker(b"loopStart"))),
+        MidiEvent::LoopEnd { .. } => items.push((ticks, Item::Marker(b"loopEnd"))),
+    }
+    Ok(())
+}
+
+/// Encode an item; `sysex_body` is the encoded body of a register write item
+fn track_event_kind<'a>(item: &'a Item, sysex_body: &'a [u8]) -> TrackEventKind<'a> {
+    match item {
+        Item::Midi { channel, message } => TrackEventKind::Midi {
+            channel: *channel,
+            message: *message,
+        },
+        Item::Tempo(tempo) => TrackEventKind::Meta(MetaMessage::Tempo(*tempo)),
+        Item::MidiPort(port) => TrackEventKind::Meta(MetaMessage::MidiPort(*port)),
+        &Item::TimeSignature(numerator, denominator_power, clocks_per_click, thirty_seconds) => {
+            TrackEventKind::Meta(MetaMessage::TimeSignature(
+                numerator,
+                denominator_power,
+                clocks_per_click,
+                thirty_seconds,
+            ))
+        }
+        &Item::KeySignature(sharps, minor) => {
+            TrackEventKind::Meta(MetaMessage::KeySignature(sharps, minor))
+        }
+        Item::Text(kind, text) => TrackEventKind::Meta(match kind {
+            TextKind::Text => MetaMessage::Text(text),
+            TextKind::Copyright => MetaMessage::Copyright(text),
+            TextKind::TrackName => MetaMessage::TrackName(text),
+            TextKind::Lyric => MetaMessage::Lyric(text),
+        }),
+        Item::Marker(text) => TrackEventKind::Meta(MetaMessage::Marker(text)),
+        Item::RegisterWrites(_) => TrackEventKind::SysEx(sysex_body),
+    }
+}
+
+/// Tempo meta value (microseconds per quarter note) of `tempo_bpm`
+fn tempo_uspqn(tempo_bpm: f64) -> Result<u24> {
+    let microseconds = (MICROSECONDS_PER_MINUTE / tempo_bpm).round();
+    if !(1.0..=u24::max_value().as_int() as f64).contains(&microseconds) {
+        return Err(Error::InvalidParameter(format!(
+            "tempo {tempo_bpm} BPM cannot be written"
+        )));
+    }
+    Ok(u24::new(microseconds as u32))
+}
+
+fn smf_timing(timing: MidiTiming) -> Result<Timing> {
+    Ok(match timing {
+        MidiTiming::Metrical { ticks_per_beat } => {
+            Timing::Metrical(u15::try_from(ticks_per_beat).ok_or_else(|| {
+                Error::InvalidParameter(format!(
+                    "ticks per beat {ticks_per_beat} is out of range (0-32767)"
+                ))
+            })?)
+        }
+        MidiTiming::Timecode {
+            fps,
+            ticks_per_frame,
+        } => Timing::Timecode(
+            match fps {
+                SmpteFps::Fps24 => midly::Fps::Fps24,
+                SmpteFps::Fps25 => midly::Fps::Fps25,
+                SmpteFps::Fps29DropFrame => midly::Fps::Fps29,
+                SmpteFps::Fps30 => midly::Fps::Fps30,
+            },
+            ticks_per_frame,
+        ),
+    })
+}
+
+fn to_u7(value: u8, what: &str) -> Result<u7> {
+    u7::try_from(value)
+        .ok_or_else(|| Error::InvalidParameter(format!("{what} {value} is out of range (0-127)")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::events::MidiMetadata;
+    use crate::midi::parser::parse_midi_from_bytes;
+
+    fn meta(message: MetaMessage<'static>) -> TrackEventKind<'static> {
+        TrackEventKind::Meta(message)
+    }
+
+    fn midi(channel: u8, message: MidiMessage) -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: u4::new(channel),
+            message,
+        }
+    }
+
+    fn cc(channel: u8, controller: u8, value: u8) -> TrackEventKind<'static> {
+        midi(
+            channel,
+            MidiMessage::Controller {
+                controller: u7::new(controller),
+                value: u7::new(value),
+            },
+        )
+    }
+
+    fn note(on: bool, channel: u8, key: u8) -> TrackEventKind<'static> {
+        let (key, vel) = (u7::new(key), u7::new(if on { 100 } else { 0 }));
+        midi(channel, MidiMessage::NoteOn { key, vel })
+    }
+
+    fn build_smf(format: Format, tracks: Vec<Vec<(u32, TrackEventKind<'_>)>>) -> Vec<u8> {
+        let mut smf = Smf::new(Header::new(format, Timing::Metrical(u15::new(480))));
+        for events in tracks {
+            let mut track: Vec<TrackEvent<'_>> = events
+                .into_iter()
+                .map(|(delta, kind)| TrackEvent {
+                    delta: u28::new(delta),
+                    kind,
+                })
+                .collect();
+            track.push(TrackEvent {
+                delta: u28::new(0),
+                kind: meta(MetaMessage::EndOfTrack),
+            });
+            smf.tracks.push(track);
+        }
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn assert_round_trip(bytes: &[u8], format: SmfFormat) -> MidiData {
+        let parsed = parse_midi_from_bytes(bytes).unwrap();
+        let written = write_midi_to_bytes(&parsed, format).unwrap();
+        let reparsed = parse_midi_from_bytes(&written).unwrap();
+        assert_eq!(reparsed.events, parsed.events);
+        assert_eq!(reparsed.metadata, parsed.metadata);
+        assert_eq!(reparsed.timing, parsed.timing);
+        parsed
+    }
+
+    #[test]
+    fn test_round_trip_format1() {
+        let register_write = encode_register_write_sysex(&[(0x0F, 0x80), (0x19, 0x7F)]);
+        let conductor = vec![
+            (0, meta(MetaMessage::TrackName(b"Song"))),
+            (0, meta(MetaMessage::Copyright(b"(c) 2026"))),
+            (0, meta(MetaMessage::TimeSignature(3, 2, 24, 8))),
+            (0, meta(MetaMessage::KeySignature(2, false))),
+            (0, meta(MetaMessage::Tempo(u24::new(400_000)))),
+            (960, meta(MetaMessage::Marker(b"loopStart"))),
+            (960, meta(MetaMessage::Tempo(u24::new(600_000)))),
+            (960, meta(MetaMessage::Marker(b"loopEnd"))),
+        ];
+        let lead = vec![
+            (0, meta(MetaMessage::TrackName(b"Lead"))),
+            (0, meta(MetaMessage::MidiPort(u7::new(1)))),
+            (
+                0,
+                midi(
+                    0,
+                    MidiMessage::ProgramChange {
+                        program: u7::new(5),
+                    },
+                ),
+            ),
+            // RPN 0 pitch bend range, then an NRPN
+            (0, cc(0, 101, 0)),
+            (0, cc(0, 100, 0)),
+            (0, cc(0, 6, 12)),
+            (0, cc(0, 99, 1)),
+            (0, cc(0, 98, 8)),
+            (0, cc(0, 6, 64)),
+            (0, cc(0, 38, 3)),
+            (0, note(true, 0, 60)),
+            (0, TrackEventKind::SysEx(&register_write)),
+            (
+                120,
+                midi(0, MidiMessage::ChannelAftertouch { vel: u7::new(90) }),
+            ),
+            (
+                0,
+                midi(
+                    0,
+                    MidiMessage::Aftertouch {
+                        key: u7::new(60),
+                        vel: u7::new(40),
+                    },
+                ),
+            ),
+            (
+                120,
+                midi(
+                    0,
+                    MidiMessage::PitchBend {
+                        bend: midly::PitchBend(u14::new(0)),
+                    },
+                ),
+            ),
+            (0, meta(MetaMessage::Lyric(b"la"))),
+            (240, note(false, 0, 60)),
+            (0, cc(0, 111, 0)),
+        ];
+        let bass = vec![
+            (0, note(true, 1, 36)),
+            (
+                480,
+                midi(
+                    1,
+                    MidiMessage::NoteOff {
+                        key: u7::new(36),
+                        vel: u7::new(0),
+                    },
+                ),
+            ),
+        ];
+        let bytes = build_smf(Format::Parallel, vec![conductor, lead, bass]);
+
+        let parsed = assert_round_trip(&bytes, SmfFormat::MultiTrack);
+        // Every kind of event is covered
+        for kind in [
+            "pitch_bend_range",
+            "nrpn",
+            "register_write",
+            "channel_pressure",
+            "poly_pressure",
+            "loop_start",
+            "loop_end",
+            "tempo",
+        ] {
+            let json = serde_json::to_string(&parsed.events).unwrap();
+            assert!(json.contains(&format!("\"type\":\"{kind}\"")), "{kind}");
+        }
+        assert_eq!(
+            parsed.events.iter().filter_map(MidiEvent::port).max(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_round_trip_format0() {
+        let bytes = build_smf(
+            Format::SingleTrack,
+            vec![vec![
+                (0, meta(MetaMessage::TrackName(b"Solo"))),
+                (0, note(true, 0, 60)),
+                (0, note(true, 9, 36)),
+                (240, note(false, 9, 36)),
+                (240, note(false, 0, 60)),
+            ]],
+        );
+        assert_round_trip(&bytes, SmfFormat::SingleTrack);
+    }
+
+    #[test]
+    fn test_standalone_derived_events_are_written_as_controllers() {
+        let midi_data = MidiData {
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
+            tempo_bpm: 120.0,
+            events: vec![
+                MidiEvent::PitchBendRange {
+                    ticks: 0,
+                    channel: 2,
+                    semitones: 7,
+                    cents: 0,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::LoopStart {
+                    ticks: 480,
+                    track: 0,
+                    port: None,
+                },
+            ],
+            metadata: MidiMetadata {
+                title: Some("Edited".to_string()),
+                ..MidiMetadata::default()
+            },
+        };
+
+        let bytes = write_midi_to_bytes(&midi_data, SmfFormat::MultiTrack).unwrap();
+        let reparsed = parse_midi_from_bytes(&bytes).unwrap();
+        assert!(reparsed.events.contains(&MidiEvent::PitchBendRange {
+            ticks: 0,
+            channel: 2,
+            semitones: 7,
+            cents: 0,
+            track: 0,
+            port: None,
+        }));
+        assert!(reparsed.events.contains(&MidiEvent::LoopStart {
+            ticks: 480,
+            track: 0,
+            port: None,
+        }));
+        assert_eq!(reparsed.metadata.title.as_deref(), Some("Edited"));
+    }
+
+    #[test]
+    fn test_edited_derived_events_are_kept() {
+        let bytes = build_smf(
+            Format::SingleTrack,
+            vec![vec![
+                (0, cc(0, 101, 0)),
+                (0, cc(0, 100, 0)),
+                (0, cc(0, 6, 12)),
+                (0, cc(0, 99, 1)),
+                (0, cc(0, 98, 8)),
+                (0, cc(0, 6, 64)),
+            ]],
+        );
+        let mut midi_data = parse_midi_from_bytes(&bytes).unwrap();
+        for event in &mut midi_data.events {
+            match event {
+                MidiEvent::PitchBendRange { semitones, .. } => *semitones = 24,
+                MidiEvent::Nrpn { value, .. } => *value = 100,
+                _ => {}
+            }
+        }
+
+        let written = write_midi_to_bytes(&midi_data, SmfFormat::SingleTrack).unwrap();
+        let reparsed = parse_midi_from_bytes(&written).unwrap();
+        let last_range = reparsed.events.iter().rev().find_map(|event| match event {
+            MidiEvent::PitchBendRange { semitones, .. } => Some(*semitones),
+            _ => None,
+        });
+        let last_nrpn = reparsed.events.iter().rev().find_map(|event| match event {
+            MidiEvent::Nrpn { value, .. } => Some(*value),
+            _ => None,
+        });
+        assert_eq!(last_range, Some(24));
+        assert_eq!(last_nrpn, Some(100));
+    }
+
+    #[test]
+    fn test_initial_tempo_is_written_without_tempo_events() {
+        let midi_data = MidiData {
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
+            tempo_bpm: 150.0,
+            events: Vec::new(),
+            metadata: MidiMetadata::default(),
+        };
+
+        let bytes = write_midi_to_bytes(&midi_data, SmfFormat::MultiTrack).unwrap();
+        let reparsed = parse_midi_from_bytes(&bytes).unwrap();
+        assert_eq!(
+            reparsed.events,
+            [MidiEvent::Tempo {
+                ticks: 0,
+                tempo_bpm: 150.0,
+                track: 0,
+                port: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_too_long_delta_time_is_rejected() {
+        let note_on = |ticks| MidiEvent::NoteOn {
+            ticks,
+            channel: 0,
+            note: 60,
+            velocity: 100,
+            track: 0,
+            port: None,
+        };
+        let midi_data = MidiData {
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
+            tempo_bpm: 120.0,
+            events: vec![note_on(0), note_on(1 << 28)],
+            metadata: MidiMetadata::default(),
+        };
+        assert!(matches!(
+            write_midi_to_bytes(&midi_data, SmfFormat::MultiTrack),
+            Err(Error::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn test_out_of_range_values_are_rejected() {
+        let midi_data = MidiData {
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
+            tempo_bpm: 120.0,
+            events: vec![MidiEvent::NoteOn {
+                ticks: 0,
+                channel: 16,
+                note: 60,
+                velocity: 100,
+                track: 0,
+                port: None,
+            }],
+            metadata: MidiMetadata::default(),
+        };
+        assert!(matches!(
+            write_midi_to_bytes(&midi_data, SmfFormat::MultiTrack),
+            Err(Error::InvalidParameter(_))
+        ));
+    }
+}
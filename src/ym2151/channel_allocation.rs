@@ -2,22 +2,333 @@
 //!
 //! Handles allocation of YM2151 channels based on MIDI polyphony requirements.
 
+use crate::error::{Error, Result};
 use crate::midi::{MidiData, MidiEvent};
-use std::collections::{HashMap, HashSet};
+use crate::ym2151::{CC_SOSTENUTO, CC_SUSTAIN};
+use crate::{ChannelAssignment, ConversionOptions, StealPolicy, VoiceAllocation};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Channels per MIDI port
+const CHANNELS_PER_PORT: usize = 16;
+
+/// Number of YM2151 channels (voices)
+pub const YM2151_VOICES: usize = 8;
+
+/// General MIDI drum channel (channel 10, numbered from 0)
+pub const DRUM_CHANNEL: u8 = 9;
+
+/// YM2151 channel whose operator 4 (C2) can play the noise generator
+pub const NOISE_CHANNEL: u8 = 7;
+
+/// Whether a MIDI channel is the drum channel of its port
+///
+/// Channels of a multi-port file are renumbered into parts (see
+/// [`assign_port_parts`]), so the drum channel of every port counts.
+pub fn is_drum_channel(channel: u8) -> bool {
+    channel as usize % CHANNELS_PER_PORT == DRUM_CHANNEL as usize
+}
 
 /// Channel allocation information
 #[derive(Debug, Clone)]
 pub struct ChannelAllocation {
-    /// Maps MIDI channel to list of allocated YM2151 channels
+    /// Maps MIDI channel to list of allocated YM2151 channels.
+    /// With dynamic allocation, the channels each MIDI channel currently owns.
     pub midi_to_ym2151: HashMap<u8, Vec<u8>>,
     /// Tracks which YM2151 channels are currently in use for each MIDI channel
     pub current_voice: HashMap<u8, usize>,
+    /// Voice pool when voices are picked at each note-on instead
+    pub dynamic: Option<DynamicVoices>,
+    /// YM2151 channel 7 is kept for notes played with the noise generator
+    pub noise: bool,
+}
+
+impl ChannelAllocation {
+    /// Every YM2151 channel allocated to some MIDI channel, in ascending order
+    ///
+    /// With dynamic allocation every channel of the chip is in use.
+    pub fn ym2151_channels(&self) -> Vec<u8> {
+        if self.dynamic.is_some() {
+            return (0..YM2151_VOICES as u8).collect();
+        }
+        let channels: BTreeSet<u8> = self.midi_to_ym2151.values().flatten().copied().collect();
+        channels.into_iter().collect()
+    }
+
+    /// YM2151 channels playing MIDI `channel` at `ticks`
+    ///
+    /// Static allocations never change; dynamic voices belong to the MIDI
+    /// channel of the latest note they started at or before `ticks`.
+    pub fn ym2151_channels_at(&self, channel: u8, ticks: u32) -> Vec<u8> {
+        match &self.dynamic {
+            Some(dynamic) => (0..YM2151_VOICES as u8)
+                .filter(|&ym_ch| dynamic.owner_at(ym_ch, ticks) == Some(channel))
+                .collect(),
+            None => self
+                .midi_to_ym2151
+                .get(&channel)
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Note last started on a dynamically allocated YM2151 channel
+#[derive(Debug, Clone, Copy)]
+pub struct VoiceState {
+    /// MIDI channel that played the note
+    pub channel: u8,
+    /// MIDI note number
+    pub note: u8,
+    /// Note-on velocity
+    pub velocity: u8,
+    /// Tick of the note-on
+    pub started: u32,
+    /// Tick of the key-off, once the voice is free again
+    pub released: Option<u32>,
+}
+
+/// Voice pool for dynamic allocation
+#[derive(Debug, Clone)]
+pub struct DynamicVoices {
+    /// How a voice is taken when none is free
+    pub stealing: StealPolicy,
+    /// MIDI channel each reserved YM2151 channel is kept for
+    pub reserved: HashMap<u8, u8>,
+    /// YM2151 channels a MIDI channel is pinned to; it plays on no others
+    pub pinned: HashMap<u8, Vec<u8>>,
+    /// Most voices a MIDI channel keeps sounding at once
+    pub max_voices: HashMap<u8, usize>,
+    /// Steal priority per MIDI channel (default 0)
+    pub priorities: HashMap<u8, i32>,
+    /// Latest note per YM2151 channel
+    pub voices: HashMap<u8, VoiceState>,
+    /// Owner changes per YM2151 channel as (tick, MIDI channel), in tick order
+    pub owners: HashMap<u8, Vec<(u32, u8)>>,
+}
+
+impl DynamicVoices {
+    /// MIDI channel owning a YM2151 channel at `ticks`
+    pub fn owner_at(&self, ym2151_channel: u8, ticks: u32) -> Option<u8> {
+        let changes = self.owners.get(&ym2151_channel)?;
+        let idx = changes.partition_point(|&(t, _)| t <= ticks);
+        idx.checked_sub(1).map(|idx| changes[idx].1)
+    }
+
+    /// Steal priority of a MIDI channel
+    pub fn priority(&self, channel: u8) -> i32 {
+        self.priorities.get(&channel).copied().unwrap_or(0)
+    }
+
+    /// Pick the YM2151 channel for a note-on on MIDI `channel`
+    ///
+    /// A free voice is preferred, unused or released longest ago first.
+    /// Otherwise, or when the channel already sounds its maximum number of
+    /// voices, a sounding voice is stolen by the stealing policy;
+    /// `sounding` lists the voices still keyed on, `held` those only a pedal
+    /// keeps on and `loudness` rates a voice for the quietest policy. With
+    /// `noise` set, YM2151 channel 7 is kept for noise notes and only picked
+    /// for a MIDI channel pinned to it. Returns `None` when every voice
+    /// outranks the new note.
+    pub fn pick(
+        &self,
+        channel: u8,
+        noise: bool,
+        sounding: &HashSet<u8>,
+        held: &HashSet<u8>,
+        loudness: impl Fn(&VoiceState) -> f64,
+    ) -> Option<u8> {
+        let candidates: Vec<u8> = match self.pinned.get(&channel) {
+            Some(pins) => pins.clone(),
+            None => (0..YM2151_VOICES as u8)
+                .filter(|&ym_ch| !(noise && ym_ch == NOISE_CHANNEL))
+                .filter(|ym_ch| {
+                    self.reserved
+                        .get(ym_ch)
+                        .is_none_or(|&owner| owner == channel)
+                })
+                .collect(),
+        };
+        let own: Vec<u8> = candidates
+            .iter()
+            .copied()
+            .filter(|ym_ch| {
+                sounding.contains(ym_ch)
+                    && self
+                        .voices
+                        .get(ym_ch)
+                        .is_some_and(|voice| voice.channel == channel)
+            })
+            .collect();
+        let at_limit = self
+            .max_voices
+            .get(&channel)
+            .is_some_and(|&max| own.len() >= max);
+        let candidates = if at_limit { own } else { candidates };
+
+        let free = candidates
+            .iter()
+            .copied()
+            .filter(|ym_ch| !sounding.contains(ym_ch))
+            .min_by_key(|ym_ch| {
+                self.voices
+                    .get(ym_ch)
+                    .map_or((0, 0), |voice| (1, voice.released.unwrap_or(0)))
+            });
+        if free.is_some() && !at_limit {
+            return free;
+        }
+
+        let voices = candidates
+            .into_iter()
+            .filter_map(|ym_ch| self.voices.get(&ym_ch).map(|voice| (ym_ch, voice)));
+        match self.stealing {
+            StealPolicy::Oldest => voices
+                .min_by_key(|(ym_ch, voice)| (voice.started, *ym_ch))
+                .map(|(ym_ch, _)| ym_ch),
+            StealPolicy::Quietest => voices
+                .min_by(|(a_ch, a), (b_ch, b)| {
+                    loudness(a)
+                        .total_cmp(&loudness(b))
+                        .then((a.started, a_ch).cmp(&(b.started, b_ch)))
+                })
+                .map(|(ym_ch, _)| ym_ch),
+            StealPolicy::ReleasedFirst => voices
+                .min_by_key(|(ym_ch, voice)| (!held.contains(ym_ch), voice.started, *ym_ch))
+                .map(|(ym_ch, _)| ym_ch),
+            StealPolicy::LowestPriority => voices
+                .filter(|(_, voice)| self.priority(voice.channel) <= self.priority(channel))
+                .min_by_key(|(ym_ch, voice)| (self.priority(voice.channel), voice.started, *ym_ch))
+                .map(|(ym_ch, _)| ym_ch),
+        }
+    }
+
+    /// Record a note-on, returning the previous owner when the voice changes hands
+    pub fn start(
+        &mut self,
+        ym2151_channel: u8,
+        channel: u8,
+        note: u8,
+        velocity: u8,
+        ticks: u32,
+    ) -> Option<Option<u8>> {
+        let previous = self.voices.get(&ym2151_channel).map(|voice| voice.channel);
+        self.voices.insert(
+            ym2151_channel,
+            VoiceState {
+                channel,
+                note,
+                velocity,
+                started: ticks,
+                released: None,
+            },
+        );
+        if previous == Some(channel) {
+            return None;
+        }
+        self.owners
+            .entry(ym2151_channel)
+            .or_default()
+            .push((ticks, channel));
+        Some(previous)
+    }
+
+    /// Record that the note on a YM2151 channel was keyed off
+    pub fn release(&mut self, ym2151_channel: u8, note: u8, ticks: u32) {
+        if let Some(voice) = self
+            .voices
+            .get_mut(&ym2151_channel)
+            .filter(|voice| voice.note == note)
+        {
+            voice.released = Some(ticks);
+        }
+    }
+}
+
+/// Pedal state used while measuring polyphony for one MIDI channel
+#[derive(Default)]
+struct PedalTracker {
+    sustain: bool,
+    sostenuto: bool,
+    sostenuto_notes: HashSet<u8>,
+    /// Notes released while a pedal still holds them
+    held_notes: HashSet<u8>,
+}
+
+/// Renumber channels of a multi-port file into parts
+///
+/// Allocation and all per-channel state are keyed by channel number, so each
+/// port in use gets its own block of 16 parts: the n-th port (in port order,
+/// with no port prefix meaning port 0) maps channel `c` to part `n * 16 + c`. "Port 2 channel 1" and "port 1
+/// channel 1" then become different parts. Files using a single port keep
+/// their channel numbers and return `None`.
+///
+/// # Errors
+/// Returns an error if the file uses more than 16 ports
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::midi::{MidiData, MidiEvent, MidiMetadata, MidiTiming};
+/// use smf_to_ym2151log::ym2151::assign_port_parts;
+///
+/// let note_on = |port| MidiEvent::NoteOn {
+///     ticks: 0,
+///     channel: 0,
+///     note: 60,
+///     velocity: 100,
+///     track: 0,
+///     port: Some(port),
+/// };
+/// let midi_data = MidiData {
+///     timing: MidiTiming::Metrical { ticks_per_beat: 480 },
+///     tempo_bpm: 120.0,
+///     events: vec![note_on(0), note_on(1)],
+///     metadata: MidiMetadata::default(),
+/// };
+/// let parted = assign_port_parts(&midi_data).unwrap().unwrap();
+/// assert!(matches!(parted.events[1], MidiEvent::NoteOn { channel: 16, .. }));
+/// ```
+pub fn assign_port_parts(midi_data: &MidiData) -> Result<Option<MidiData>> {
+    // Events without a port prefix are on the default port 0
+    let ports: BTreeSet<u8> = midi_data
+        .events
+        .iter()
+        .filter(|event| event.channel().is_some())
+        .map(|event| event.port().unwrap_or(0))
+        .collect();
+    if ports.len() <= 1 {
+        return Ok(None);
+    }
+    if ports.len() * CHANNELS_PER_PORT > u8::MAX as usize + 1 {
+        return Err(Error::InvalidParameter(format!(
+            "{} MIDI ports in use; at most {} are supported",
+            ports.len(),
+            (u8::MAX as usize + 1) / CHANNELS_PER_PORT
+        )));
+    }
+
+    let port_rank: HashMap<u8, u8> = ports
+        .into_iter()
+        .enumerate()
+        .map(|(rank, port)| (port, rank as u8))
+        .collect();
+    let mut parted = midi_data.clone();
+    for event in &mut parted.events {
+        let rank = port_rank
+            .get(&event.port().unwrap_or(0))
+            .copied()
+            .unwrap_or(0);
+        if let Some(channel) = event.channel_mut() {
+            *channel += rank * CHANNELS_PER_PORT as u8;
+        }
+    }
+    Ok(Some(parted))
 }
 
 /// Analyze polyphony requirements for each MIDI channel
 ///
 /// Measures the maximum number of simultaneous notes per MIDI channel
-/// by tracking note on/off events.
+/// by tracking note on/off events. Notes released while the damper (CC64)
+/// or sostenuto (CC66) pedal holds them still count as sounding.
 ///
 /// # Arguments
 /// * `midi_data` - MIDI data containing events to analyze
@@ -27,18 +338,20 @@ pub struct ChannelAllocation {
 ///
 /// # Example
 /// ```
-/// use smf_to_ym2151log::midi::MidiData;
+/// use smf_to_ym2151log::midi::{MidiData, MidiMetadata, MidiTiming};
 /// use smf_to_ym2151log::ym2151::analyze_polyphony;
 ///
 /// let midi_data = MidiData {
-///     ticks_per_beat: 480,
+///     timing: MidiTiming::Metrical { ticks_per_beat: 480 },
 ///     tempo_bpm: 120.0,
 ///     events: vec![],
+///     metadata: MidiMetadata::default(),
 /// };
 /// let polyphony = analyze_polyphony(&midi_data);
 /// ```
 pub fn analyze_polyphony(midi_data: &MidiData) -> HashMap<u8, usize> {
     let mut active_notes: HashMap<u8, HashSet<u8>> = HashMap::new();
+    let mut pedals: HashMap<u8, PedalTracker> = HashMap::new();
     let mut max_polyphony: HashMap<u8, usize> = HashMap::new();
 
     for event in &midi_data.events {
@@ -48,21 +361,58 @@ pub fn analyze_polyphony(midi_data: &MidiData) -> HashMap<u8, usize> {
                 note,
                 velocity,
                 ..
-            } => {
-                if *velocity > 0 {
-                    active_notes.entry(*channel).or_default().insert(*note);
-                    let current_poly = active_notes[channel].len();
-                    max_polyphony
-                        .entry(*channel)
-                        .and_modify(|max| *max = (*max).max(current_poly))
-                        .or_insert(current_poly);
+            } if *velocity > 0 => {
+                active_notes.entry(*channel).or_default().insert(*note);
+                if let Some(pedal) = pedals.get_mut(channel) {
+                    pedal.held_notes.remove(note);
                 }
+                let current_poly = active_notes[channel].len();
+                max_polyphony
+                    .entry(*channel)
+                    .and_modify(|max| *max = (*max).max(current_poly))
+                    .or_insert(current_poly);
             }
             MidiEvent::NoteOff { channel, note, .. } => {
-                if let Some(notes) = active_notes.get_mut(channel) {
+                let pedal = pedals.entry(*channel).or_default();
+                if pedal.sustain || pedal.sostenuto_notes.contains(note) {
+                    pedal.held_notes.insert(*note);
+                } else if let Some(notes) = active_notes.get_mut(channel) {
                     notes.remove(note);
                 }
             }
+            MidiEvent::ControlChange {
+                channel,
+                controller,
+                value,
+                ..
+            } if *controller == CC_SUSTAIN || *controller == CC_SOSTENUTO => {
+                let pedal = pedals.entry(*channel).or_default();
+                let pressed = *value >= 64;
+                if *controller == CC_SUSTAIN {
+                    pedal.sustain = pressed;
+                } else {
+                    if pressed && !pedal.sostenuto {
+                        pedal.sostenuto_notes =
+                            active_notes.get(channel).cloned().unwrap_or_default();
+                    } else if !pressed {
+                        pedal.sostenuto_notes.clear();
+                    }
+                    pedal.sostenuto = pressed;
+                }
+
+                let released: Vec<u8> = pedal
+                    .held_notes
+                    .iter()
+                    .copied()
+                    .filter(|note| !pedal.sustain && !pedal.sostenuto_notes.contains(note))
+                    .collect();
+                for note in released {
+                    pedal.held_notes.remove(&note);
+                    if let Some(notes) = active_notes.get_mut(channel) {
+                        notes.remove(&note);
+                    }
+                }
+            }
             _ => {}
         }
     }
@@ -70,6 +420,68 @@ pub fn analyze_polyphony(midi_data: &MidiData) -> HashMap<u8, usize> {
     max_polyphony
 }
 
+/// Check explicit channel assignments for conflicts
+///
+/// Pins must name YM2151 channels 0-7, no YM2151 channel may be pinned by
+/// two MIDI channels and `MaxVoices` must leave room for at least one voice
+/// and every pin.
+///
+/// # Errors
+/// Returns an error describing the first conflict found
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::ym2151::validate_channel_assignment;
+/// use smf_to_ym2151log::ChannelAssignment;
+/// use std::collections::HashMap;
+///
+/// let pin = |channels: Vec<u8>| ChannelAssignment {
+///     ym2151_channels: channels,
+///     ..ChannelAssignment::default()
+/// };
+/// let assignments = HashMap::from([(0, pin(vec![1])), (1, pin(vec![1, 2]))]);
+/// assert!(validate_channel_assignment(&assignments).is_err());
+/// ```
+pub fn validate_channel_assignment(assignments: &HashMap<u8, ChannelAssignment>) -> Result<()> {
+    let mut channels: Vec<(&u8, &ChannelAssignment)> = assignments.iter().collect();
+    channels.sort_unstable_by_key(|(channel, _)| **channel);
+
+    let mut pinned_by: HashMap<u8, u8> = HashMap::new();
+    for (&channel, assignment) in channels {
+        for &ym_ch in &assignment.ym2151_channels {
+            if ym_ch as usize >= YM2151_VOICES {
+                return Err(Error::InvalidParameter(format!(
+                    "MIDI channel {channel} is pinned to YM2151 channel {ym_ch}; channels are 0-7"
+                )));
+            }
+            if let Some(other) = pinned_by.insert(ym_ch, channel) {
+                if other == channel {
+                    return Err(Error::InvalidParameter(format!(
+                        "MIDI channel {channel} pins YM2151 channel {ym_ch} twice"
+                    )));
+                }
+                return Err(Error::InvalidParameter(format!(
+                    "YM2151 channel {ym_ch} is pinned by both MIDI channel {other} and {channel}"
+                )));
+            }
+        }
+        if let Some(max) = assignment.max_voices {
+            if max == 0 {
+                return Err(Error::InvalidParameter(format!(
+                    "MIDI channel {channel} is limited to 0 voices"
+                )));
+            }
+            if max < assignment.ym2151_channels.len() {
+                return Err(Error::InvalidParameter(format!(
+                    "MIDI channel {channel} is pinned to {} YM2151 channels but limited to {max} voices",
+                    assignment.ym2151_channels.len()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Allocate YM2151 channels based on polyphony requirements with drum channel priority
 ///
 /// 1. First allocates channels based on polyphony requirements
@@ -91,23 +503,101 @@ pub fn analyze_polyphony(midi_data: &MidiData) -> HashMap<u8, usize> {
 /// let allocation = allocate_channels(&polyphony);
 /// ```
 pub fn allocate_channels(polyphony: &HashMap<u8, usize>) -> ChannelAllocation {
+    allocate_channels_with_assignments(polyphony, &HashMap::new())
+        .expect("allocation without assignments cannot fail")
+}
+
+/// Allocate YM2151 channels like [`allocate_channels`], honouring explicit assignments
+///
+/// This function implements the allocation strategy:
+///
+/// 1. MIDI channels pinned by `assignments` get exactly their pinned channels
+/// 2. The remaining YM2151 channels go to the other MIDI channels by
+///    priority, then polyphony requirement, capped by `MaxVoices`
+/// 3. Then reorders to prioritize drum channel (MIDI ch 9) to YM2151 ch 0,
+///    unless either of them is pinned
+///
+/// # Arguments
+/// * `polyphony` - HashMap mapping MIDI channel to its polyphony requirement
+/// * `assignments` - Explicit pins, voice limits and priorities per MIDI channel
+///
+/// # Returns
+/// ChannelAllocation with MIDI to YM2151 channel mappings
+///
+/// # Errors
+/// Returns an error if the assignments conflict (see
+/// [`validate_channel_assignment`]) or pins leave no YM2151 channel for a
+/// MIDI channel that plays notes
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::ym2151::allocate_channels_with_assignments;
+/// use smf_to_ym2151log::ChannelAssignment;
+/// use std::collections::HashMap;
+///
+/// let mut polyphony = HashMap::new();
+/// polyphony.insert(0, 2);  // MIDI channel 0 needs 2 voices
+/// polyphony.insert(1, 1);
+/// let assignments = HashMap::from([(1, ChannelAssignment {
+///     ym2151_channels: vec![0],
+///     ..ChannelAssignment::default()
+/// })]);
+/// let allocation = allocate_channels_with_assignments(&polyphony, &assignments).unwrap();
+/// assert_eq!(allocation.midi_to_ym2151[&0], [1, 2]);
+/// ```
+pub fn allocate_channels_with_assignments(
+    polyphony: &HashMap<u8, usize>,
+    assignments: &HashMap<u8, ChannelAssignment>,
+) -> Result<ChannelAllocation> {
+    validate_channel_assignment(assignments)?;
+
     let mut allocation = HashMap::new();
-    let mut next_ym2151_channel = 0u8;
+    let pins = |channel: &u8| {
+        assignments
+            .get(channel)
+            .map(|assignment| assignment.ym2151_channels.as_slice())
+            .filter(|pins| !pins.is_empty())
+    };
+    let pinned: HashSet<u8> = assignments
+        .values()
+        .flat_map(|assignment| assignment.ym2151_channels.iter().copied())
+        .collect();
+    let pool: Vec<u8> = (0..YM2151_VOICES as u8)
+        .filter(|ym_ch| !pinned.contains(ym_ch))
+        .collect();
+    let mut next_in_pool = 0;
 
-    // Sort MIDI channels by polyphony requirement (descending) for initial allocation
-    let mut channels: Vec<(u8, usize)> = polyphony.iter().map(|(k, v)| (*k, *v)).collect();
-    channels.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    // Sort MIDI channels by priority, then polyphony requirement (descending)
+    let mut channels: Vec<(u8, usize, i32)> = Vec::new();
+    for (&midi_ch, &poly) in polyphony {
+        if let Some(pins) = pins(&midi_ch) {
+            allocation.insert(midi_ch, pins.to_vec());
+            continue;
+        }
+        let assignment = assignments.get(&midi_ch);
+        let poly = assignment
+            .and_then(|assignment| assignment.max_voices)
+            .map_or(poly, |max| poly.min(max));
+        let priority = assignment.map_or(0, |assignment| assignment.priority);
+        channels.push((midi_ch, poly, priority));
+    }
+    channels.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)).then(a.0.cmp(&b.0)));
 
-    // Allocate YM2151 channels based on polyphony
-    for (midi_ch, poly) in channels {
+    // Allocate the unpinned YM2151 channels based on polyphony
+    for (midi_ch, poly, _) in channels {
+        let Some(&last) = pool.last() else {
+            return Err(Error::InvalidParameter(format!(
+                "MIDI channel {midi_ch} plays notes but every YM2151 channel is pinned"
+            )));
+        };
         let mut ym2151_channels = Vec::new();
         for _ in 0..poly {
-            if next_ym2151_channel < 8 {
-                ym2151_channels.push(next_ym2151_channel);
-                next_ym2151_channel += 1;
+            if next_in_pool < pool.len() {
+                ym2151_channels.push(pool[next_in_pool]);
+                next_in_pool += 1;
             } else {
                 // Overflow - reuse last channel
-                ym2151_channels.push(7);
+                ym2151_channels.push(last);
                 break;
             }
         }
@@ -116,14 +606,15 @@ pub fn allocate_channels(polyphony: &HashMap<u8, usize>) -> ChannelAllocation {
 
     // Apply drum channel priority reordering
     // If MIDI channel 9 (drums) is allocated, ensure it uses YM2151 channel 0
-    if let Some(drum_channels) = allocation.get(&9) {
+    let swappable = pins(&DRUM_CHANNEL).is_none() && !pinned.contains(&0);
+    if let Some(drum_channels) = allocation.get(&DRUM_CHANNEL).filter(|_| swappable) {
         if !drum_channels.is_empty() && drum_channels[0] != 0 {
             // Need to reorder - swap the channel that has YM2151 ch 0 with drums
             let drum_first = drum_channels[0];
 
             // Find which MIDI channel has YM2151 ch 0
             for (midi_ch, ym_channels) in allocation.iter_mut() {
-                if *midi_ch != 9 {
+                if *midi_ch != DRUM_CHANNEL {
                     for ym_ch in ym_channels.iter_mut() {
                         if *ym_ch == 0 {
                             *ym_ch = drum_first;
@@ -133,26 +624,239 @@ pub fn allocate_channels(polyphony: &HashMap<u8, usize>) -> ChannelAllocation {
             }
 
             // Update drum channel to use YM2151 ch 0
-            if let Some(drum_channels) = allocation.get_mut(&9) {
+            if let Some(drum_channels) = allocation.get_mut(&DRUM_CHANNEL) {
                 drum_channels[0] = 0;
             }
         }
     }
 
-    ChannelAllocation {
+    Ok(ChannelAllocation {
         midi_to_ym2151: allocation,
         current_voice: HashMap::new(),
+        dynamic: None,
+        noise: false,
+    })
+}
+
+/// Set up dynamic voice allocation
+///
+/// No YM2151 channel belongs to a MIDI channel up front; each note-on picks
+/// one (see [`DynamicVoices::pick`]). Channels pinned by `assignments` are
+/// kept for their MIDI channel, which plays on nothing else. Reservations
+/// then take the lowest remaining YM2151 channels in MIDI channel order.
+///
+/// # Errors
+/// Returns an error if the assignments conflict (see
+/// [`validate_channel_assignment`]) or pins and reservations together need
+/// more than the chip's eight voices
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::ym2151::allocate_dynamic_voices;
+/// use smf_to_ym2151log::VoiceAllocation;
+/// use std::collections::HashMap;
+///
+/// let config = VoiceAllocation {
+///     reserve: HashMap::from([(9, 2)]),
+///     ..VoiceAllocation::default()
+/// };
+/// let allocation = allocate_dynamic_voices(&config, &HashMap::new()).unwrap();
+/// let dynamic = allocation.dynamic.unwrap();
+/// assert_eq!(dynamic.reserved.get(&1), Some(&9));
+/// assert_eq!(dynamic.reserved.get(&2), None);
+/// ```
+pub fn allocate_dynamic_voices(
+    config: &VoiceAllocation,
+    assignments: &HashMap<u8, ChannelAssignment>,
+) -> Result<ChannelAllocation> {
+    validate_channel_assignment(assignments)?;
+
+    let mut reserved = HashMap::new();
+    let mut pinned = HashMap::new();
+    for (&channel, assignment) in assignments {
+        if assignment.ym2151_channels.is_empty() {
+            continue;
+        }
+        for &ym_ch in &assignment.ym2151_channels {
+            reserved.insert(ym_ch, channel);
+        }
+        pinned.insert(channel, assignment.ym2151_channels.clone());
+    }
+
+    // Pinned channels already have all the voices they may use
+    let mut reservations: Vec<(u8, usize)> = config
+        .reserve
+        .iter()
+        .filter(|(channel, _)| !pinned.contains_key(channel))
+        .map(|(&channel, &count)| (channel, count))
+        .collect();
+    reservations.sort_unstable();
+    let wanted = reserved.len() + reservations.iter().map(|(_, count)| count).sum::<usize>();
+    if wanted > YM2151_VOICES {
+        return Err(Error::InvalidParameter(format!(
+            "{wanted} voices pinned or reserved; the YM2151 has {YM2151_VOICES}"
+        )));
+    }
+
+    let mut free_channels = (0..YM2151_VOICES as u8).filter(|ym_ch| !reserved.contains_key(ym_ch));
+    let mut reservations_by_channel = HashMap::new();
+    for (channel, count) in reservations {
+        for ym_ch in free_channels.by_ref().take(count) {
+            reservations_by_channel.insert(ym_ch, channel);
+        }
+    }
+    reserved.extend(reservations_by_channel);
+
+    Ok(ChannelAllocation {
+        midi_to_ym2151: HashMap::new(),
+        current_voice: HashMap::new(),
+        dynamic: Some(DynamicVoices {
+            stealing: config.stealing,
+            reserved,
+            pinned,
+            max_voices: assignments
+                .iter()
+                .filter_map(|(&channel, assignment)| Some((channel, assignment.max_voices?)))
+                .collect(),
+            priorities: assignments
+                .iter()
+                .map(|(&channel, assignment)| (channel, assignment.priority))
+                .collect(),
+            voices: HashMap::new(),
+            owners: HashMap::new(),
+        }),
+        noise: false,
+    })
+}
+
+/// Find the MIDI channels that play notes with the noise generator
+///
+/// Follows program changes so that each note-on is checked against the
+/// program it plays (see [`ConversionOptions::noise_for`]).
+pub fn analyze_noise_parts(midi_data: &MidiData, options: &ConversionOptions) -> BTreeSet<u8> {
+    let mut programs: HashMap<u8, u8> = HashMap::new();
+    let mut parts = BTreeSet::new();
+    for event in &midi_data.events {
+        match event {
+            MidiEvent::ProgramChange {
+                channel, program, ..
+            } => {
+                programs.insert(*channel, *program);
+            }
+            MidiEvent::NoteOn {
+                channel,
+                note,
+                velocity,
+                ..
+            } if *velocity > 0 => {
+                let program = programs.get(channel).copied().unwrap_or(0);
+                if options.noise_for(*channel, program, *note).is_some() {
+                    parts.insert(*channel);
+                }
+            }
+            _ => {}
+        }
     }
+    parts
+}
+
+/// Keep YM2151 channel 7 for the noise notes of `noise_parts`
+///
+/// The noise generator only exists on channel 7, so noise notes always play
+/// there. With static allocation each noise part gets channel 7 in addition
+/// to its own voices and other parts move off it; a part left without a
+/// voice shares the highest channel that is not pinned, as on overflow.
+/// With dynamic allocation tonal notes simply never pick channel 7.
+///
+/// # Errors
+/// Returns an error if channel 7 is pinned or reserved for a MIDI channel
+/// that does not play noise
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::ym2151::{allocate_channels, reserve_noise_voice};
+/// use std::collections::{BTreeSet, HashMap};
+///
+/// let polyphony = HashMap::from([(0, 8), (9, 1)]);
+/// let mut allocation = allocate_channels(&polyphony);
+/// reserve_noise_voice(&mut allocation, &BTreeSet::from([9]), &HashMap::new()).unwrap();
+/// assert!(!allocation.midi_to_ym2151[&0].contains(&7));
+/// assert_eq!(allocation.midi_to_ym2151[&9], [0, 7]);
+/// ```
+pub fn reserve_noise_voice(
+    allocation: &mut ChannelAllocation,
+    noise_parts: &BTreeSet<u8>,
+    assignments: &HashMap<u8, ChannelAssignment>,
+) -> Result<()> {
+    if noise_parts.is_empty() {
+        return Ok(());
+    }
+    let conflict = |channel: u8, how: &str| {
+        Error::InvalidParameter(format!(
+            "YM2151 channel {NOISE_CHANNEL} is {how} MIDI channel {channel}, but the noise \
+             generator of MIDI channel(s) {noise_parts:?} only exists there"
+        ))
+    };
+    let mut pinned_by: Vec<u8> = assignments
+        .iter()
+        .filter(|(_, assignment)| assignment.ym2151_channels.contains(&NOISE_CHANNEL))
+        .map(|(&channel, _)| channel)
+        .collect();
+    pinned_by.sort_unstable();
+    if let Some(&channel) = pinned_by.iter().find(|ch| !noise_parts.contains(ch)) {
+        return Err(conflict(channel, "pinned to"));
+    }
+
+    allocation.noise = true;
+    if let Some(dynamic) = &allocation.dynamic {
+        if let Some(&owner) = dynamic
+            .reserved
+            .get(&NOISE_CHANNEL)
+            .filter(|owner| !noise_parts.contains(owner))
+        {
+            return Err(conflict(owner, "reserved for"));
+        }
+        return Ok(());
+    }
+
+    let pinned: HashSet<u8> = assignments
+        .values()
+        .flat_map(|assignment| assignment.ym2151_channels.iter().copied())
+        .collect();
+    let shared = (0..NOISE_CHANNEL)
+        .rev()
+        .find(|ym_ch| !pinned.contains(ym_ch));
+    for (midi_ch, ym_channels) in allocation.midi_to_ym2151.iter_mut() {
+        if noise_parts.contains(midi_ch) {
+            if !ym_channels.contains(&NOISE_CHANNEL) {
+                ym_channels.push(NOISE_CHANNEL);
+            }
+            continue;
+        }
+        ym_channels.retain(|&ym_ch| ym_ch != NOISE_CHANNEL);
+        if ym_channels.is_empty() {
+            let Some(shared) = shared else {
+                return Err(Error::InvalidParameter(format!(
+                    "MIDI channel {midi_ch} has no YM2151 channel left besides the noise channel"
+                )));
+            };
+            ym_channels.push(shared);
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::midi::{MidiMetadata, MidiTiming};
 
     #[test]
     fn test_analyze_polyphony_single_note() {
         let midi_data = MidiData {
-            ticks_per_beat: 480,
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
             tempo_bpm: 120.0,
             events: vec![
                 MidiEvent::NoteOn {
@@ -160,13 +864,18 @@ mod tests {
                     channel: 0,
                     note: 60,
                     velocity: 100,
+                    track: 0,
+                    port: None,
                 },
                 MidiEvent::NoteOff {
                     ticks: 480,
                     channel: 0,
                     note: 60,
+                    track: 0,
+                    port: None,
                 },
             ],
+            metadata: MidiMetadata::default(),
         };
 
         let polyphony = analyze_polyphony(&midi_data);
@@ -176,7 +885,9 @@ mod tests {
     #[test]
     fn test_analyze_polyphony_chord() {
         let midi_data = MidiData {
-            ticks_per_beat: 480,
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
             tempo_bpm: 120.0,
             events: vec![
                 MidiEvent::NoteOn {
@@ -184,41 +895,125 @@ mod tests {
                     channel: 0,
                     note: 60,
                     velocity: 100,
+                    track: 0,
+                    port: None,
                 },
                 MidiEvent::NoteOn {
                     ticks: 0,
                     channel: 0,
                     note: 64,
                     velocity: 100,
+                    track: 0,
+                    port: None,
                 },
                 MidiEvent::NoteOn {
                     ticks: 0,
                     channel: 0,
                     note: 67,
                     velocity: 100,
+                    track: 0,
+                    port: None,
                 },
                 MidiEvent::NoteOff {
                     ticks: 480,
                     channel: 0,
                     note: 60,
+                    track: 0,
+                    port: None,
                 },
                 MidiEvent::NoteOff {
                     ticks: 480,
                     channel: 0,
                     note: 64,
+                    track: 0,
+                    port: None,
                 },
                 MidiEvent::NoteOff {
                     ticks: 480,
                     channel: 0,
                     note: 67,
+                    track: 0,
+                    port: None,
                 },
             ],
+            metadata: MidiMetadata::default(),
         };
 
         let polyphony = analyze_polyphony(&midi_data);
         assert_eq!(polyphony.get(&0), Some(&3));
     }
 
+    #[test]
+    fn test_analyze_polyphony_counts_sustained_notes() {
+        let midi_data = MidiData {
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
+            tempo_bpm: 120.0,
+            events: vec![
+                MidiEvent::ControlChange {
+                    ticks: 0,
+                    channel: 0,
+                    controller: CC_SUSTAIN,
+                    value: 127,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::NoteOn {
+                    ticks: 0,
+                    channel: 0,
+                    note: 60,
+                    velocity: 100,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::NoteOff {
+                    ticks: 240,
+                    channel: 0,
+                    note: 60,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::NoteOn {
+                    ticks: 240,
+                    channel: 0,
+                    note: 64,
+                    velocity: 100,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::ControlChange {
+                    ticks: 480,
+                    channel: 0,
+                    controller: CC_SUSTAIN,
+                    value: 0,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::NoteOff {
+                    ticks: 480,
+                    channel: 0,
+                    note: 64,
+                    track: 0,
+                    port: None,
+                },
+                MidiEvent::NoteOn {
+                    ticks: 480,
+                    channel: 0,
+                    note: 67,
+                    velocity: 100,
+                    track: 0,
+                    port: None,
+                },
+            ],
+            metadata: MidiMetadata::default(),
+        };
+
+        // Note 60 rings under the pedal while 64 plays; after release only 67 sounds
+        let polyphony = analyze_polyphony(&midi_data);
+        assert_eq!(polyphony.get(&0), Some(&2));
+    }
+
     #[test]
     fn test_allocate_channels_simple() {
         let mut polyphony = HashMap::new();
@@ -254,4 +1049,125 @@ mod tests {
         // Should allocate 3 YM2151 channels
         assert_eq!(allocation.midi_to_ym2151.get(&0).unwrap().len(), 3);
     }
+
+    fn pinned(channels: &[u8]) -> ChannelAssignment {
+        ChannelAssignment {
+            ym2151_channels: channels.to_vec(),
+            ..ChannelAssignment::default()
+        }
+    }
+
+    #[test]
+    fn test_allocate_channels_keeps_pinned_drums_off_channel_0() {
+        let polyphony = HashMap::from([(0, 1), (9, 1)]);
+        let assignments = HashMap::from([(9, pinned(&[6]))]);
+
+        let allocation = allocate_channels_with_assignments(&polyphony, &assignments).unwrap();
+
+        assert_eq!(allocation.midi_to_ym2151[&9], [6]);
+        assert_eq!(allocation.midi_to_ym2151[&0], [0]);
+    }
+
+    #[test]
+    fn test_allocate_channels_rejects_conflicting_pins() {
+        let polyphony = HashMap::from([(0, 1), (1, 1)]);
+        let assignments = HashMap::from([(0, pinned(&[3])), (1, pinned(&[3]))]);
+
+        assert!(allocate_channels_with_assignments(&polyphony, &assignments).is_err());
+    }
+
+    #[test]
+    fn test_allocate_channels_rejects_max_voices_below_pins() {
+        let assignments = HashMap::from([(
+            0,
+            ChannelAssignment {
+                ym2151_channels: vec![0, 1],
+                max_voices: Some(1),
+                priority: 0,
+            },
+        )]);
+
+        assert!(
+            allocate_channels_with_assignments(&HashMap::from([(0, 2)]), &assignments).is_err()
+        );
+    }
+
+    #[test]
+    fn test_allocate_channels_errors_when_pins_take_every_channel() {
+        let polyphony = HashMap::from([(0, 8), (1, 1)]);
+        let assignments = HashMap::from([(0, pinned(&[0, 1, 2, 3, 4, 5, 6, 7]))]);
+
+        assert!(allocate_channels_with_assignments(&polyphony, &assignments).is_err());
+    }
+
+    #[test]
+    fn test_allocate_dynamic_voices_rejects_too_many_pins_and_reservations() {
+        let config = VoiceAllocation {
+            reserve: HashMap::from([(1, 4)]),
+            ..VoiceAllocation::default()
+        };
+        let assignments = HashMap::from([(0, pinned(&[0, 1, 2, 3, 4]))]);
+
+        assert!(allocate_dynamic_voices(&config, &assignments).is_err());
+    }
+
+    fn note_on_port(channel: u8, port: Option<u8>) -> MidiEvent {
+        MidiEvent::NoteOn {
+            ticks: 0,
+            channel,
+            note: 60,
+            velocity: 100,
+            track: 0,
+            port,
+        }
+    }
+
+    #[test]
+    fn test_assign_port_parts_single_port_unchanged() {
+        let midi_data = MidiData {
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
+            tempo_bpm: 120.0,
+            events: vec![note_on_port(0, Some(1)), note_on_port(1, Some(1))],
+            metadata: MidiMetadata::default(),
+        };
+        assert!(assign_port_parts(&midi_data).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_assign_port_parts_separates_ports() {
+        let midi_data = MidiData {
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
+            tempo_bpm: 120.0,
+            events: vec![
+                note_on_port(9, None),
+                note_on_port(9, Some(3)),
+                note_on_port(2, Some(1)),
+            ],
+            metadata: MidiMetadata::default(),
+        };
+        let parted = assign_port_parts(&midi_data).unwrap().unwrap();
+        let channels: Vec<_> = parted.events.iter().map(|e| e.channel()).collect();
+        // Ports 0 (no prefix), 1 and 3 become part blocks 0, 1 and 2
+        assert_eq!(channels, vec![Some(9), Some(41), Some(18)]);
+
+        let polyphony = analyze_polyphony(&parted);
+        assert_eq!(polyphony.len(), 3);
+    }
+
+    #[test]
+    fn test_assign_port_parts_too_many_ports() {
+        let midi_data = MidiData {
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
+            tempo_bpm: 120.0,
+            events: (0..17).map(|port| note_on_port(0, Some(port))).collect(),
+            metadata: MidiMetadata::default(),
+        };
+        assert!(assign_port_parts(&midi_data).is_err());
+    }
 }
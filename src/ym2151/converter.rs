@@ -3,21 +3,30 @@
 //! Converts MIDI events to YM2151 register write events.
 
 mod event_accumulator;
-mod pitch_effects;
-mod register_effects;
-mod register_fields;
+mod looping;
+mod metadata;
+pub(crate) mod pitch_effects;
+pub(crate) mod register_effects;
+pub(crate) mod register_fields;
 mod waveform;
 
 use crate::error::Result;
 use crate::midi::{ticks_to_seconds_with_tempo_map, MidiData};
 use crate::ym2151::{
-    allocate_channels, analyze_polyphony, apply_tone_to_channel, build_tempo_map,
-    initialize_channel_events, process_event, EventProcessorContext, NoteSegment, Ym2151Event,
-    Ym2151Log,
+    allocate_channels_with_assignments, allocate_dynamic_voices, analyze_noise_parts,
+    analyze_polyphony, apply_tone_to_channel, assign_port_parts, build_tempo_map,
+    hardware_lfo_events, hardware_vibrato_lfo_events, initialize_channel_events, process_event,
+    reserve_noise_voice, set_lfo_sensitivity, EventProcessorContext, NoteSegment,
+    Ym2151ChannelState, Ym2151Event, Ym2151Log, Ym2151LoopPoint,
 };
-use crate::ConversionOptions;
+use crate::{ConversionOptions, ModulationWheelMode};
 use event_accumulator::EventAccumulator;
-use pitch_effects::{append_delay_vibrato_events, append_portamento_events};
+use looping::{apply_fade_out, find_loop_ticks, last_pass, register_state_before, unroll_loop};
+use metadata::log_metadata;
+use pitch_effects::{
+    append_delay_vibrato_events, append_portamento_events, append_vibrato_events,
+    build_modulation_timelines, ModulationVibrato, PitchLfo,
+};
 use register_effects::{
     append_change_to_next_tone_events, append_pop_noise_envelope_events,
     append_register_lfo_events, build_register_state_cache,
@@ -39,13 +48,14 @@ use std::io::Write;
 ///
 /// # Example
 /// ```no_run
-/// use smf_to_ym2151log::midi::MidiData;
+/// use smf_to_ym2151log::midi::{MidiData, MidiMetadata, MidiTiming};
 /// use smf_to_ym2151log::ym2151::convert_to_ym2151_log;
 ///
 /// let midi_data = MidiData {
-///     ticks_per_beat: 480,
+///     timing: MidiTiming::Metrical { ticks_per_beat: 480 },
 ///     tempo_bpm: 120.0,
 ///     events: vec![],
+///     metadata: MidiMetadata::default(),
 /// };
 /// let log = convert_to_ym2151_log(&midi_data).unwrap();
 /// ```
@@ -58,7 +68,35 @@ pub fn convert_to_ym2151_log_with_options(
     midi_data: &MidiData,
     options: &ConversionOptions,
 ) -> Result<Ym2151Log> {
-    let ticks_per_beat = midi_data.ticks_per_beat;
+    // Unroll loop passes up front so every later stage sees a plain song;
+    // the loop then covers the last pass
+    let unrolled;
+    let (midi_data, loop_ticks) = match (&options.loop_options, find_loop_ticks(midi_data)) {
+        (Some(loop_options), Some(range))
+            if loop_options.count > 1 || loop_options.fade_out_seconds.is_some() =>
+        {
+            unrolled = unroll_loop(
+                midi_data,
+                range,
+                loop_options.count,
+                loop_options.fade_out_seconds.is_some(),
+            )?;
+            (&unrolled, Some(last_pass(range, loop_options.count)?))
+        }
+        (_, loop_ticks) => (midi_data, loop_ticks),
+    };
+
+    // Multi-port files get one block of 16 parts per port so ports never share a channel
+    let parted;
+    let midi_data = match assign_port_parts(midi_data)? {
+        Some(data) => {
+            parted = data;
+            &parted
+        }
+        None => midi_data,
+    };
+
+    let timing = midi_data.timing;
 
     let mut acc = EventAccumulator::new();
 
@@ -67,58 +105,36 @@ pub fn convert_to_ym2151_log_with_options(
     let last_tick = midi_data
         .events
         .iter()
-        .map(|event| match event {
-            crate::midi::MidiEvent::NoteOn { ticks, .. } => *ticks,
-            crate::midi::MidiEvent::NoteOff { ticks, .. } => *ticks,
-            crate::midi::MidiEvent::Tempo { ticks, .. } => *ticks,
-            crate::midi::MidiEvent::ProgramChange { ticks, .. } => *ticks,
-        })
+        .map(|event| event.ticks())
         .max()
         .unwrap_or(0);
 
-    // Initialize all channels at time 0
-    // Register 0x08 is the Key ON/OFF register
-    // Writing channel number turns off that channel
-    for ch in 0..8 {
-        acc.push(Ym2151Event {
-            time: 0.0,
-            addr: "0x08".to_string(),
-            data: format!("0x{:02X}", ch),
-        });
-    }
-
-    // Analyze polyphony requirements for each MIDI channel
-    let polyphony = analyze_polyphony(midi_data);
-
-    // Allocate YM2151 channels based on polyphony with drum channel priority
-    let mut allocation = allocate_channels(&polyphony);
+    // Allocate YM2151 channels based on polyphony with drum channel priority,
+    // or pick voices note by note from a dynamic pool
+    let assignments = &options.channel_assignment;
+    let mut allocation = match &options.voice_allocation {
+        Some(config) => allocate_dynamic_voices(config, assignments)?,
+        None => allocate_channels_with_assignments(&analyze_polyphony(midi_data), assignments)?,
+    };
+    reserve_noise_voice(
+        &mut allocation,
+        &analyze_noise_parts(midi_data, options),
+        assignments,
+    )?;
 
     // Collect all allocated YM2151 channels for initialization, sorted for deterministic output
-    let used_ym2151_channels: Vec<u8> = {
-        let mut set = HashSet::new();
-        for ym_channels in allocation.midi_to_ym2151.values() {
-            for &ym_ch in ym_channels {
-                set.insert(ym_ch);
-            }
-        }
-        let mut v: Vec<u8> = set.into_iter().collect();
-        v.sort_unstable();
-        v
-    };
+    let used_ym2151_channels = allocation.ym2151_channels();
 
-    // Initialize all used YM2151 channels with default parameters
-    for &ch in &used_ym2151_channels {
-        acc.extend(initialize_channel_events(ch, 0.0));
-    }
+    // Register shadow of the loaded tone per YM2151 channel
+    let mut ym2151_channel_states: HashMap<u8, Ym2151ChannelState> = HashMap::new();
+    acc.extend(initial_channel_events(
+        &used_ym2151_channels,
+        options,
+        0.0,
+        &mut ym2151_channel_states,
+    ));
 
-    // Apply initial tone (program 0) from attachment if available.
-    // This ensures the attachment tone takes effect even when the MIDI file
-    // does not contain an explicit Program Change event.
-    if let Some(initial_tone) = options.tones.get(&0) {
-        for &ch in &used_ym2151_channels {
-            acc.extend(apply_tone_to_channel(initial_tone, ch, 0.0));
-        }
-    }
+    let wheel_mode = options.modulation_wheel.as_ref().map(|wheel| wheel.mode);
 
     // Track the current program (tone) for each YM2151 channel
     let mut channel_programs: HashMap<u8, u8> = HashMap::new();
@@ -132,6 +148,7 @@ pub fn convert_to_ym2151_log_with_options(
 
     // Optional note tracking for vibrato/portamento
     let need_note_segments = options.delay_vibrato
+        || wheel_mode == Some(ModulationWheelMode::Software)
         || options.portamento
         || !options.software_lfo.is_empty()
         || options.pop_noise_envelope.is_some()
@@ -148,10 +165,13 @@ pub fn convert_to_ym2151_log_with_options(
     };
     let mut vibrato_segments: Vec<NoteSegment> = Vec::new();
 
+    // Controller state (pitch bend etc.) per MIDI channel
+    let mut midi_channel_states = HashMap::new();
+
     {
         // Create event processor context
         let mut ctx = EventProcessorContext {
-            ticks_per_beat,
+            timing,
             tempo_map: &tempo_map,
             allocation: &mut allocation,
             active_notes: &mut active_notes,
@@ -167,6 +187,9 @@ pub fn convert_to_ym2151_log_with_options(
             } else {
                 Some(&options.tones)
             },
+            midi_channel_states: &mut midi_channel_states,
+            ym2151_channel_states: &mut ym2151_channel_states,
+            options: Some(options),
         };
 
         for event in &midi_data.events {
@@ -177,7 +200,7 @@ pub fn convert_to_ym2151_log_with_options(
 
     if need_note_segments {
         if let Some(active_map) = vibrato_active_notes {
-            let end_time = ticks_to_seconds_with_tempo_map(last_tick, ticks_per_beat, &tempo_map);
+            let end_time = ticks_to_seconds_with_tempo_map(last_tick, timing, &tempo_map);
             for ((ym_ch, note), note_on) in active_map.into_iter() {
                 vibrato_segments.push(NoteSegment {
                     ym2151_channel: ym_ch,
@@ -187,17 +210,45 @@ pub fn convert_to_ym2151_log_with_options(
                     start_time: note_on.start_time,
                     end_time,
                     program: note_on.program,
+                    fixed_pitch: note_on.fixed_pitch,
+                    pitch_bends: note_on.pitch_bends,
                 });
             }
         }
     }
 
-    if options.delay_vibrato {
+    let modulation_vibrato = match &options.modulation_wheel {
+        Some(wheel) if wheel.mode == ModulationWheelMode::Software => Some(ModulationVibrato {
+            timelines: build_modulation_timelines(midi_data, &allocation, &tempo_map),
+            max_depth_cents: wheel.max_depth(),
+        }),
+        _ => None,
+    };
+
+    let pitch_lfo = PitchLfo::new(options);
+    let combined_vibrato = modulation_vibrato.is_some() || pitch_lfo.is_some();
+    if combined_vibrato {
+        // Delay vibrato (global or per program) is folded into the same pass
+        // so it adds to the wheel depth and pitch LFOs instead of writing KC/KF separately
+        let delayed_programs: HashSet<u8> = options
+            .program_attachments
+            .iter()
+            .filter(|pa| pa.delay_vibrato)
+            .map(|pa| pa.program_change)
+            .collect();
+        append_vibrato_events(
+            &vibrato_segments,
+            |segment| options.delay_vibrato || delayed_programs.contains(&segment.program),
+            modulation_vibrato.as_ref(),
+            pitch_lfo.as_ref(),
+            &mut acc,
+        );
+    } else if options.delay_vibrato {
         append_delay_vibrato_events(&vibrato_segments, &mut acc);
     }
 
     if options.portamento {
-        append_portamento_events(&vibrato_segments, &mut acc);
+        append_portamento_events(&vibrato_segments, pitch_lfo.as_ref(), &mut acc);
     }
 
     let need_pre_note_events = options.pop_noise_envelope.is_some();
@@ -263,12 +314,12 @@ pub fn convert_to_ym2151_log_with_options(
             _ => continue,
         };
 
-        if pa.delay_vibrato {
+        if pa.delay_vibrato && !combined_vibrato {
             append_delay_vibrato_events(program_segments, &mut acc);
         }
 
         if pa.portamento {
-            append_portamento_events(program_segments, &mut acc);
+            append_portamento_events(program_segments, pitch_lfo.as_ref(), &mut acc);
         }
 
         if !pa.software_lfo.is_empty() {
@@ -289,7 +340,7 @@ pub fn convert_to_ym2151_log_with_options(
         .iter()
         .any(|pa| pa.change_to_next_tone);
     if has_change_to_next_tone && !options.tones.is_empty() {
-        let song_end_time = ticks_to_seconds_with_tempo_map(last_tick, ticks_per_beat, &tempo_map);
+        let song_end_time = ticks_to_seconds_with_tempo_map(last_tick, timing, &tempo_map);
         append_change_to_next_tone_events(
             &options.program_attachments,
             &options.tones,
@@ -299,12 +350,95 @@ pub fn convert_to_ym2151_log_with_options(
         );
     }
 
+    // A faded-out song ends at the loop end, so it no longer loops
+    let fade_seconds = options
+        .loop_options
+        .as_ref()
+        .and_then(|loop_options| loop_options.fade_out_seconds);
+    let loop_point = loop_ticks.filter(|_| fade_seconds.is_none()).map(|range| {
+        let start_time = ticks_to_seconds_with_tempo_map(range.start, timing, &tempo_map);
+        Ym2151LoopPoint {
+            start_time,
+            end_time: ticks_to_seconds_with_tempo_map(range.end, timing, &tempo_map),
+            registers: register_state_before(acc.iter(), start_time),
+        }
+    });
+
+    let mut events = acc.into_vec();
+    if let (Some(range), Some(fade_seconds)) = (loop_ticks, fade_seconds) {
+        let fade_end = ticks_to_seconds_with_tempo_map(range.end, timing, &tempo_map);
+        events = apply_fade_out(events, (fade_end - fade_seconds).max(0.0), fade_end);
+    }
+
     Ok(Ym2151Log {
-        event_count: acc.iter().count(),
-        events: acc.into_vec(),
+        event_count: events.len(),
+        events,
+        loop_point,
+        metadata: log_metadata(midi_data, &tempo_map, last_tick),
     })
 }
 
+/// Register writes that reset the chip and load the starting tone
+///
+/// Every channel is keyed off, then each of `channels` gets the default
+/// parameters and, when the attachment defines program 0, that tone. This
+/// ensures the attachment tone takes effect even when the input has no
+/// explicit Program Change event. The chip LFO is set up when the modulation
+/// wheel or aftertouch drives hardware vibrato.
+pub(crate) fn initial_channel_events(
+    channels: &[u8],
+    options: &ConversionOptions,
+    time: f64,
+    states: &mut HashMap<u8, Ym2151ChannelState>,
+) -> Vec<Ym2151Event> {
+    // Register 0x08 is the Key ON/OFF register
+    // Writing channel number turns off that channel
+    let mut events: Vec<Ym2151Event> = (0..8)
+        .map(|ch| Ym2151Event {
+            time,
+            addr: "0x08".to_string(),
+            data: format!("0x{:02X}", ch),
+        })
+        .collect();
+
+    for &ch in channels {
+        let init_events = initialize_channel_events(ch, time);
+        states
+            .entry(ch)
+            .or_default()
+            .apply_tone_events(&init_events);
+        events.extend(init_events);
+    }
+
+    for &ch in channels {
+        let mut tone_events = options
+            .tones
+            .get(&0)
+            .map(|initial_tone| apply_tone_to_channel(initial_tone, ch, time))
+            .unwrap_or_default();
+        let state = states.entry(ch).or_default();
+        set_lfo_sensitivity(&mut tone_events, ch, state.pms_ams, options, 0, time);
+        state.apply_tone_events(&tone_events);
+        events.extend(tone_events);
+    }
+
+    // Hardware modulation wheel vibrato runs on the chip LFO; CC1 only sets PMS.
+    // Aftertouch vibrato works the same way with pressure setting PMS
+    let wheel_mode = options.modulation_wheel.as_ref().map(|wheel| wheel.mode);
+    let hardware_vibrato =
+        wheel_mode == Some(ModulationWheelMode::Hardware) || options.has_aftertouch_vibrato();
+    if channels.is_empty() {
+        return events;
+    }
+    if let Some(lfo) = &options.hardware_lfo {
+        events.extend(hardware_lfo_events(lfo, time));
+    } else if hardware_vibrato {
+        events.extend(hardware_vibrato_lfo_events(time));
+    }
+
+    events
+}
+
 /// Save YM2151 log to JSON file
 ///
 /// # Arguments
@@ -0,0 +1,369 @@
+//! Loop points
+//!
+//! Finds the loop range from loop markers, unrolls loop passes at the MIDI
+//! level (restoring the loop start state at each jump), snapshots the
+//! register state at the loop start and fades out the last pass.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::{Error, Result};
+use crate::midi::{MidiData, MidiEvent};
+use crate::ym2151::events::parse_hex_byte;
+use crate::ym2151::{attenuate_tl, carrier_operators, Ym2151Event, TL_MAX};
+
+/// Interval between fade-out level writes
+const FADE_STEP_SECONDS: f64 = 0.05;
+
+/// Loop range in ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct LoopTicks {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Find the loop range from the first loop start and the first loop end after it.
+///
+/// A loop end without a loop start loops from the beginning; a loop start
+/// without a loop end loops to the last event.
+pub(super) fn find_loop_ticks(midi_data: &MidiData) -> Option<LoopTicks> {
+    let start = midi_data.events.iter().find_map(|event| match event {
+        MidiEvent::LoopStart { ticks, .. } => Some(*ticks),
+        _ => None,
+    });
+    let end = midi_data.events.iter().find_map(|event| match event {
+        MidiEvent::LoopEnd { ticks, .. } if *ticks > start.unwrap_or(0) => Some(*ticks),
+        _ => None,
+    });
+    if start.is_none() && end.is_none() {
+        return None;
+    }
+    let start = start.unwrap_or(0);
+    let end = end.unwrap_or_else(|| {
+        midi_data
+            .events
+            .iter()
+            .map(MidiEvent::ticks)
+            .max()
+            .unwrap_or(0)
+    });
+    (end > start).then_some(LoopTicks { start, end })
+}
+
+/// Sounding notes by (port, channel, note): track of the latest note-on and count
+type SoundingNotes = BTreeMap<(Option<u8>, u8, u8), (u16, u32)>;
+
+/// Playback state a sequencer restores when it jumps back to the loop start
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum StateKey {
+    Tempo,
+    Program(Option<u8>, u8),
+    Controller(Option<u8>, u8, u8),
+    PitchBend(Option<u8>, u8),
+    PitchBendRange(Option<u8>, u8),
+}
+
+fn state_key(event: &MidiEvent) -> Option<StateKey> {
+    match *event {
+        MidiEvent::Tempo { .. } => Some(StateKey::Tempo),
+        MidiEvent::ProgramChange { channel, port, .. } => Some(StateKey::Program(port, channel)),
+        MidiEvent::ControlChange {
+            channel,
+            controller,
+            port,
+            ..
+        } => Some(StateKey::Controller(port, channel, controller)),
+        MidiEvent::PitchBend { channel, port, .. } => Some(StateKey::PitchBend(port, channel)),
+        MidiEvent::PitchBendRange { channel, port, .. } => {
+            Some(StateKey::PitchBendRange(port, channel))
+        }
+        _ => None,
+    }
+}
+
+/// `event` with its value reset to the one in effect before any such event.
+///
+/// Controllers have no common power-on value, so they get `None`.
+fn initial_state(event: &MidiEvent, tempo_bpm: f64) -> Option<MidiEvent> {
+    let mut event = event.clone();
+    match &mut event {
+        MidiEvent::Tempo { tempo_bpm: bpm, .. } => *bpm = tempo_bpm,
+        MidiEvent::ProgramChange { program, .. } => *program = 0,
+        MidiEvent::PitchBend { value, .. } => *value = 0,
+        MidiEvent::PitchBendRange {
+            semitones, cents, ..
+        } => {
+            *semitones = 2;
+            *cents = 0;
+        }
+        _ => return None,
+    }
+    Some(event)
+}
+
+/// Events that put every state the loop body changes back to its value at
+/// the loop start, at tick 0.
+///
+/// Controllers first set inside the body have no earlier value and are left
+/// as the body leaves them.
+fn loop_start_state(midi_data: &MidiData, range: LoopTicks) -> Vec<MidiEvent> {
+    let mut before: BTreeMap<StateKey, &MidiEvent> = BTreeMap::new();
+    let mut body: BTreeMap<StateKey, &MidiEvent> = BTreeMap::new();
+    for event in &midi_data.events {
+        let Some(key) = state_key(event) else {
+            continue;
+        };
+        if event.ticks() < range.start {
+            before.insert(key, event);
+        } else if event.ticks() < range.end {
+            body.entry(key).or_insert(event);
+        }
+    }
+    body.into_iter()
+        .filter_map(|(key, first)| match before.get(&key) {
+            Some(&event) => Some(event.clone()),
+            None => initial_state(first, midi_data.tempo_bpm),
+        })
+        .map(|mut event| {
+            *event.ticks_mut() = 0;
+            event
+        })
+        .collect()
+}
+
+/// Tick offset of loop pass `pass`, counting the first pass as 0
+fn pass_offset(range: LoopTicks, pass: u32) -> Result<u32> {
+    (range.end - range.start)
+        .checked_mul(pass)
+        .filter(|offset| range.end.checked_add(*offset).is_some())
+        .ok_or_else(|| {
+            Error::InvalidParameter(format!(
+                "loop pass {} ends beyond the last MIDI tick",
+                pass + 1
+            ))
+        })
+}
+
+/// Loop range of the last of `count` passes once the loop is unrolled
+pub(super) fn last_pass(range: LoopTicks, count: u32) -> Result<LoopTicks> {
+    let offset = pass_offset(range, count.max(1) - 1)?;
+    Ok(LoopTicks {
+        start: range.start + offset,
+        end: range.end + offset,
+    })
+}
+
+/// Repeat the loop body so it plays `count` times.
+///
+/// Like a sequencer jumping back, every sounding note is released at each
+/// jump, and the tempo, programs, controllers and pitch bends the body
+/// changed are set back to their loop start values. Events after the loop
+/// end follow the last pass unless `drop_tail` is set, in which case the
+/// song stops there with its sounding notes released.
+pub(super) fn unroll_loop(
+    midi_data: &MidiData,
+    range: LoopTicks,
+    count: u32,
+    drop_tail: bool,
+) -> Result<MidiData> {
+    let tail_offset = pass_offset(range, count.max(1) - 1)?;
+    let restore = loop_start_state(midi_data, range);
+    let mut sounding = SoundingNotes::new();
+    let mut events = Vec::new();
+
+    let push = |event: MidiEvent, sounding: &mut SoundingNotes, events: &mut Vec<MidiEvent>| {
+        match event {
+            MidiEvent::NoteOn {
+                channel,
+                note,
+                track,
+                port,
+                ..
+            } => {
+                let entry = sounding.entry((port, channel, note)).or_insert((track, 0));
+                *entry = (track, entry.1 + 1);
+            }
+            MidiEvent::NoteOff {
+                channel,
+                note,
+                port,
+                ..
+            } => {
+                if let Some((_, count)) = sounding.get_mut(&(port, channel, note)) {
+                    *count = count.saturating_sub(1);
+                }
+            }
+            _ => {}
+        }
+        events.push(event);
+    };
+    let release_all = |sounding: &mut SoundingNotes, ticks: u32, events: &mut Vec<MidiEvent>| {
+        for ((port, channel, note), (track, count)) in std::mem::take(sounding) {
+            for _ in 0..count {
+                events.push(MidiEvent::NoteOff {
+                    ticks,
+                    channel,
+                    note,
+                    track,
+                    port,
+                });
+            }
+        }
+    };
+
+    for event in midi_data.events.iter().filter(|e| e.ticks() < range.end) {
+        push(event.clone(), &mut sounding, &mut events);
+    }
+
+    for pass in 1..count.max(1) {
+        let offset = pass_offset(range, pass)?;
+        let jump_ticks = range.start + offset;
+        release_all(&mut sounding, jump_ticks, &mut events);
+        events.extend(restore.iter().map(|event| shift_ticks(event, jump_ticks)));
+        for event in midi_data.events.iter().filter(|e| {
+            (range.start..range.end).contains(&e.ticks())
+                && !matches!(e, MidiEvent::LoopStart { .. } | MidiEvent::LoopEnd { .. })
+        }) {
+            push(shift_ticks(event, offset), &mut sounding, &mut events);
+        }
+    }
+
+    if drop_tail {
+        release_all(&mut sounding, range.end + tail_offset, &mut events);
+    } else {
+        for event in midi_data.events.iter().filter(|e| e.ticks() >= range.end) {
+            if event.ticks().checked_add(tail_offset).is_none() {
+                return Err(Error::InvalidParameter(format!(
+                    "event at tick {} moves beyond the last MIDI tick after {count} loop passes",
+                    event.ticks()
+                )));
+            }
+            push(shift_ticks(event, tail_offset), &mut sounding, &mut events);
+        }
+    }
+
+    Ok(MidiData {
+        timing: midi_data.timing,
+        tempo_bpm: midi_data.tempo_bpm,
+        events,
+        metadata: midi_data.metadata.clone(),
+    })
+}
+
+fn shift_ticks(event: &MidiEvent, offset: u32) -> MidiEvent {
+    let mut event = event.clone();
+    *event.ticks_mut() += offset;
+    event
+}
+
+/// Last value written to every register before `time`, ordered by address.
+///
+/// Key on/off writes (0x08) are not state a player should restore.
+pub(super) fn register_state_before<'a>(
+    events: impl Iterator<Item = &'a Ym2151Event>,
+    time: f64,
+) -> Vec<Ym2151Event> {
+    let mut latest: BTreeMap<u8, &Ym2151Event> = BTreeMap::new();
+    for event in events.take_while(|e| e.time < time) {
+        if let Some(addr) = parse_hex_byte(&event.addr).filter(|&addr| addr != 0x08) {
+            latest.insert(addr, event);
+        }
+    }
+    latest
+        .into_values()
+        .map(|event| Ym2151Event {
+            time,
+            addr: event.addr.clone(),
+            data: event.data.clone(),
+        })
+        .collect()
+}
+
+/// Fade the carriers of every channel from full level at `fade_start` to
+/// silence at `fade_end`.
+///
+/// Carrier TL writes inside the fade are attenuated by the fade level, and
+/// extra TL writes are inserted every [`FADE_STEP_SECONDS`]. `events` must
+/// be in time order.
+pub(super) fn apply_fade_out(
+    events: Vec<Ym2151Event>,
+    fade_start: f64,
+    fade_end: f64,
+) -> Vec<Ym2151Event> {
+    let fade_length = (fade_end - fade_start).max(f64::EPSILON);
+    let attenuation_at =
+        |time: f64| TL_MAX as f64 * ((time - fade_start) / fade_length).clamp(0.0, 1.0);
+
+    // Fade level writes: every FADE_STEP_SECONDS, then exactly at the end
+    let step_count = (fade_length / FADE_STEP_SECONDS).ceil() as usize;
+    let mut step_times: Vec<f64> = (0..step_count)
+        .map(|step| fade_start + step as f64 * FADE_STEP_SECONDS)
+        .collect();
+    step_times.push(fade_end);
+    let mut pending_steps = step_times.into_iter().peekable();
+
+    // Unfaded register values and the carrier TL values actually written
+    let mut registers: HashMap<u8, u8> = HashMap::new();
+    let mut written_tl: HashMap<u8, u8> = HashMap::new();
+    let mut output = Vec::with_capacity(events.len());
+
+    let fade_step = |time: f64,
+                     registers: &HashMap<u8, u8>,
+                     written_tl: &mut HashMap<u8, u8>,
+                     output: &mut Vec<Ym2151Event>| {
+        let attenuation = attenuation_at(time);
+        for channel in 0..8u8 {
+            let Some(&rl_fb_con) = registers.get(&(0x20 + channel)) else {
+                continue;
+            };
+            for &operator in carrier_operators(rl_fb_con) {
+                let addr = 0x60 + channel + operator as u8 * 8;
+                let Some(&base) = registers.get(&addr) else {
+                    continue;
+                };
+                let tl = attenuate_tl(base, attenuation);
+                if written_tl.get(&addr) == Some(&tl) {
+                    continue;
+                }
+                written_tl.insert(addr, tl);
+                output.push(Ym2151Event {
+                    time,
+                    addr: format!("0x{:02X}", addr),
+                    data: format!("0x{:02X}", tl),
+                });
+            }
+        }
+    };
+
+    for mut event in events {
+        while let Some(step_time) = pending_steps.next_if(|&t| t <= event.time) {
+            fade_step(step_time, &registers, &mut written_tl, &mut output);
+        }
+
+        let (Some(addr), Some(data)) = (parse_hex_byte(&event.addr), parse_hex_byte(&event.data))
+        else {
+            output.push(event);
+            continue;
+        };
+        registers.insert(addr, data);
+        if (0x60..=0x7F).contains(&addr) {
+            let channel = addr & 0x07;
+            let operator = ((addr - 0x60) / 8) as usize;
+            let rl_fb_con = registers.get(&(0x20 + channel)).copied().unwrap_or(0);
+            let tl = if event.time >= fade_start && carrier_operators(rl_fb_con).contains(&operator)
+            {
+                attenuate_tl(data, attenuation_at(event.time))
+            } else {
+                data
+            };
+            written_tl.insert(addr, tl);
+            event.data = format!("0x{:02X}", tl);
+        }
+        output.push(event);
+    }
+
+    for step_time in pending_steps {
+        fade_step(step_time, &registers, &mut written_tl, &mut output);
+    }
+
+    output
+}
@@ -0,0 +1,135 @@
+//! Song metadata
+//!
+//! Converts the MIDI metadata (signatures, names and text) to seconds for the
+//! metadata section of the YM2151 log.
+
+use crate::midi::{ticks_to_seconds_with_tempo_map, MidiData, MidiTiming, TempoChange};
+use crate::ym2151::{Ym2151KeySignature, Ym2151Metadata, Ym2151Text, Ym2151TimeSignature};
+
+/// Metadata section for the log, or `None` when the file has no metadata
+pub(super) fn log_metadata(
+    midi_data: &MidiData,
+    tempo_map: &[TempoChange],
+    last_tick: u32,
+) -> Option<Ym2151Metadata> {
+    let metadata = &midi_data.metadata;
+    if metadata.is_empty() {
+        return None;
+    }
+    let seconds = |ticks: u32| ticks_to_seconds_with_tempo_map(ticks, midi_data.timing, tempo_map);
+
+    Some(Ym2151Metadata {
+        title: metadata.title.clone(),
+        copyright: metadata.copyright.clone(),
+        track_names: metadata.track_names.clone(),
+        bar_times: bar_ticks(midi_data, last_tick)
+            .into_iter()
+            .map(seconds)
+            .collect(),
+        time_signatures: metadata
+            .time_signatures
+            .iter()
+            .map(|signature| Ym2151TimeSignature {
+                time: seconds(signature.ticks),
+                numerator: signature.numerator,
+                denominator: signature.denominator,
+            })
+            .collect(),
+        key_signatures: metadata
+            .key_signatures
+            .iter()
+            .map(|signature| Ym2151KeySignature {
+                time: seconds(signature.ticks),
+                sharps: signature.sharps,
+                minor: signature.minor,
+            })
+            .collect(),
+        texts: metadata
+            .texts
+            .iter()
+            .map(|text| Ym2151Text {
+                time: seconds(text.ticks),
+                track: text.track,
+                kind: text.kind,
+                text: text.text.clone(),
+            })
+            .collect(),
+    })
+}
+
+/// Tick of every bar line up to `last_tick`
+///
+/// Bars are 4/4 until the first time signature. A time signature change
+/// starts a new bar even if the previous bar was not complete.
+fn bar_ticks(midi_data: &MidiData, last_tick: u32) -> Vec<u32> {
+    let MidiTiming::Metrical { ticks_per_beat } = midi_data.timing else {
+        return Vec::new();
+    };
+    let bar_length = |numerator: u8, denominator: u8| {
+        (ticks_per_beat as u32 * 4 * numerator as u32 / denominator.max(1) as u32).max(1)
+    };
+
+    let mut signatures = midi_data.metadata.time_signatures.iter().peekable();
+    let mut length = bar_length(4, 4);
+    let mut bars = Vec::new();
+    let mut tick = 0;
+    while tick <= last_tick {
+        while let Some(signature) = signatures.next_if(|s| s.ticks <= tick) {
+            length = bar_length(signature.numerator, signature.denominator);
+        }
+        bars.push(tick);
+        let next = tick + length;
+        tick = match signatures.peek() {
+            Some(signature) if signature.ticks < next => signature.ticks,
+            _ => next,
+        };
+    }
+    bars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::{MidiMetadata, TimeSignature};
+
+    fn time_signature(ticks: u32, numerator: u8, denominator: u8) -> TimeSignature {
+        TimeSignature {
+            ticks,
+            numerator,
+            denominator,
+            clocks_per_click: 24,
+            thirty_seconds_per_quarter: 8,
+        }
+    }
+
+    #[test]
+    fn test_bar_ticks_follow_time_signatures() {
+        let midi_data = MidiData {
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
+            tempo_bpm: 120.0,
+            events: vec![],
+            metadata: MidiMetadata {
+                time_signatures: vec![time_signature(0, 3, 4), time_signature(2880, 6, 8)],
+                ..MidiMetadata::default()
+            },
+        };
+
+        // Two bars of 3/4 (1440 ticks), then 6/8 (1440 ticks)
+        assert_eq!(bar_ticks(&midi_data, 5000), vec![0, 1440, 2880, 4320]);
+    }
+
+    #[test]
+    fn test_bar_ticks_default_to_four_four() {
+        let midi_data = MidiData {
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
+            tempo_bpm: 120.0,
+            events: vec![],
+            metadata: MidiMetadata::default(),
+        };
+        assert_eq!(bar_ticks(&midi_data, 3840), vec![0, 1920, 3840]);
+    }
+}
@@ -1,24 +1,219 @@
 //! Pitch-related effects
 //!
-//! Provides delay vibrato and portamento implementations for YM2151 conversion.
+//! Provides delay vibrato, modulation wheel vibrato, pitch LFO and portamento
+//! implementations for YM2151 conversion.
 
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::midi::{midi_note_to_frequency, midi_note_with_offset_to_kc_kf};
-use crate::ym2151::{NoteSegment, Ym2151Event};
+use crate::midi::{
+    midi_note_to_frequency, midi_note_with_offset_to_kc_kf, ticks_to_seconds_with_tempo_map,
+    MidiData, MidiEvent, TempoChange,
+};
+use crate::ym2151::{ChannelAllocation, NoteSegment, Ym2151Event, CC_MODULATION};
+use crate::{ConversionOptions, LfoTarget, RegisterLfoDefinition};
 
 use super::event_accumulator::EventAccumulator;
+use super::register_effects::{lfo_offset, next_edge_time};
 use super::waveform::triangle_wave;
 
 const DELAY_VIBRATO_DELAY_SECONDS: f64 = 0.2;
 const DELAY_VIBRATO_ATTACK_SECONDS: f64 = 0.3;
 const DELAY_VIBRATO_DEPTH_CENTS: f64 = 100.0;
 const DELAY_VIBRATO_RATE_HZ: f64 = 6.0;
-const VIBRATO_RELEASE_TAIL_SECONDS: f64 = 0.5;
+/// How long vibrato keeps running after a note is released
+pub(crate) const VIBRATO_RELEASE_TAIL_SECONDS: f64 = 0.5;
 const PORTAMENTO_TIME_SECONDS: f64 = 0.1;
+/// Samples at a pitch jump are evaluated this long after it, so rounding
+/// cannot land on the old side.
+const JUMP_EPSILON: f64 = 1e-9;
+
+/// Modulation wheel (CC1) positions over time on one YM2151 channel
+#[derive(Debug, Default)]
+pub(super) struct ModulationTimeline {
+    /// (time in seconds, CC1 value) in time order
+    points: Vec<(f64, u8)>,
+}
+
+impl ModulationTimeline {
+    fn value_at(&self, time: f64) -> u8 {
+        let idx = self
+            .points
+            .partition_point(|&(t, _)| t <= time + f64::EPSILON);
+        if idx == 0 {
+            0
+        } else {
+            self.points[idx - 1].1
+        }
+    }
+
+    fn is_active_during(&self, start: f64, stop: f64) -> bool {
+        self.value_at(start) > 0
+            || self
+                .points
+                .iter()
+                .any(|&(t, value)| t > start && t <= stop && value > 0)
+    }
+}
+
+/// Software vibrato whose depth follows the modulation wheel
+pub(super) struct ModulationVibrato {
+    /// CC1 timeline per YM2151 channel
+    pub timelines: HashMap<u8, ModulationTimeline>,
+    /// Vibrato depth in cents at CC1 = 127
+    pub max_depth_cents: f64,
+}
+
+/// Collect CC1 changes per YM2151 channel from the MIDI channels allocated to it
+///
+/// A dynamically allocated voice taking a new MIDI channel picks up that
+/// channel's wheel position at the handover.
+pub(super) fn build_modulation_timelines(
+    midi_data: &MidiData,
+    allocation: &ChannelAllocation,
+    tempo_map: &[TempoChange],
+) -> HashMap<u8, ModulationTimeline> {
+    let mut timelines: HashMap<u8, ModulationTimeline> = HashMap::new();
+    // (ticks, CC1 value) per MIDI channel, in tick order
+    let mut wheel_changes: HashMap<u8, Vec<(u32, u8)>> = HashMap::new();
+    for event in &midi_data.events {
+        let MidiEvent::ControlChange {
+            ticks,
+            channel,
+            controller: CC_MODULATION,
+            value,
+            ..
+        } = event
+        else {
+            continue;
+        };
+        wheel_changes
+            .entry(*channel)
+            .or_default()
+            .push((*ticks, *value));
+        let time = ticks_to_seconds_with_tempo_map(*ticks, midi_data.timing, tempo_map);
+        for ym_ch in allocation.ym2151_channels_at(*channel, *ticks) {
+            timelines
+                .entry(ym_ch)
+                .or_default()
+                .points
+                .push((time, *value));
+        }
+    }
+
+    if let Some(dynamic) = &allocation.dynamic {
+        for (&ym_ch, owners) in &dynamic.owners {
+            for &(ticks, channel) in owners {
+                let value = wheel_changes.get(&channel).map_or(0, |changes| {
+                    let idx = changes.partition_point(|&(t, _)| t <= ticks);
+                    idx.checked_sub(1).map_or(0, |idx| changes[idx].1)
+                });
+                let time = ticks_to_seconds_with_tempo_map(ticks, midi_data.timing, tempo_map);
+                timelines
+                    .entry(ym_ch)
+                    .or_default()
+                    .points
+                    .push((time, value));
+            }
+        }
+        for timeline in timelines.values_mut() {
+            timeline.points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        }
+    }
+    timelines
+}
+
+/// Software LFOs on the pitch, per program
+///
+/// Notes with a pitch LFO get their portamento glide from the vibrato pass,
+/// summed into the same KC/KF writes.
+pub(super) struct PitchLfo<'a> {
+    global: Vec<&'a RegisterLfoDefinition>,
+    programs: HashMap<u8, Vec<&'a RegisterLfoDefinition>>,
+    portamento: bool,
+    portamento_programs: HashSet<u8>,
+}
+
+impl<'a> PitchLfo<'a> {
+    /// Pitch LFOs of `options`, or `None` when no LFO targets the pitch
+    pub fn new(options: &'a ConversionOptions) -> Option<Self> {
+        let pitch_lfos = |defs: &'a [RegisterLfoDefinition]| -> Vec<&'a RegisterLfoDefinition> {
+            defs.iter()
+                .filter(|def| {
+                    def.target == LfoTarget::Pitch
+                        && def.rate_hz > 0.0
+                        && def.depth.abs() >= f64::EPSILON
+                })
+                .collect()
+        };
+        let global = pitch_lfos(&options.software_lfo);
+        let programs: HashMap<u8, Vec<&RegisterLfoDefinition>> = options
+            .program_attachments
+            .iter()
+            .map(|pa| (pa.program_change, pitch_lfos(&pa.software_lfo)))
+            .filter(|(_, defs)| !defs.is_empty())
+            .collect();
+        if global.is_empty() && programs.is_empty() {
+            return None;
+        }
+        Some(PitchLfo {
+            global,
+            programs,
+            portamento: options.portamento,
+            portamento_programs: options
+                .program_attachments
+                .iter()
+                .filter(|pa| pa.portamento)
+                .map(|pa| pa.program_change)
+                .collect(),
+        })
+    }
+
+    /// Pitch LFOs on notes of `program`, the global ones first
+    fn definitions(&self, program: u8) -> Vec<&'a RegisterLfoDefinition> {
+        let mut defs = self.global.clone();
+        defs.extend(self.programs.get(&program).into_iter().flatten());
+        defs
+    }
+
+    fn has_lfo(&self, segment: &NoteSegment) -> bool {
+        !self.global.is_empty() || self.programs.contains_key(&segment.program)
+    }
+
+    /// Whether `next` glides from `prev`, as the portamento pass would
+    fn glides(&self, prev: &NoteSegment, next: &NoteSegment) -> bool {
+        self.portamento
+            || (self.portamento_programs.contains(&next.program) && prev.program == next.program)
+    }
+}
+
+/// Pitch sources of one note, summed into its KC/KF writes
+struct SegmentPitch<'a> {
+    delayed: bool,
+    wheel: Option<(&'a ModulationTimeline, f64)>,
+    lfos: Vec<&'a RegisterLfoDefinition>,
+    /// Previous note the portamento glides from
+    glide_from: Option<u8>,
+}
 
 pub(super) fn append_delay_vibrato_events(segments: &[NoteSegment], events: &mut EventAccumulator) {
+    append_vibrato_events(segments, |_| true, None, None, events);
+}
+
+/// Append software vibrato for note segments
+///
+/// Segments for which `delayed` returns true get the fixed delay vibrato.
+/// With `wheel`, the modulation wheel depth is added on top, so a note can
+/// carry both without the two fighting over KC/KF. Pitch LFOs, the note's
+/// pitch bend and, for notes with a pitch LFO, the portamento glide are
+/// summed in the same way.
+pub(super) fn append_vibrato_events(
+    segments: &[NoteSegment],
+    delayed: impl Fn(&NoteSegment) -> bool,
+    wheel: Option<&ModulationVibrato>,
+    pitch_lfo: Option<&PitchLfo>,
+    events: &mut EventAccumulator,
+) {
     if segments.is_empty() {
         return;
     }
@@ -41,6 +236,9 @@ pub(super) fn append_delay_vibrato_events(segments: &[NoteSegment], events: &mut
 
     for segment_list in segments_by_channel.values() {
         for (idx, segment) in segment_list.iter().enumerate() {
+            if segment.fixed_pitch {
+                continue;
+            }
             let next_start = segment_list.get(idx + 1).map(|s| s.start_time);
             let natural_end = segment.end_time + VIBRATO_RELEASE_TAIL_SECONDS;
             let stop_time = match next_start {
@@ -48,12 +246,44 @@ pub(super) fn append_delay_vibrato_events(segments: &[NoteSegment], events: &mut
                 None => natural_end,
             };
 
-            append_vibrato_for_segment(segment, stop_time, events);
+            let wheel_timeline = wheel.and_then(|wheel| {
+                wheel
+                    .timelines
+                    .get(&segment.ym2151_channel)
+                    .filter(|timeline| timeline.is_active_during(segment.start_time, stop_time))
+                    .map(|timeline| (timeline, wheel.max_depth_cents))
+            });
+            let lfos = pitch_lfo.map_or_else(Vec::new, |lfo| lfo.definitions(segment.program));
+            let delayed = delayed(segment);
+            if !delayed && wheel_timeline.is_none() && lfos.is_empty() {
+                continue;
+            }
+            let glide_from = pitch_lfo
+                .filter(|_| !lfos.is_empty())
+                .zip(idx.checked_sub(1).map(|prev| segment_list[prev]))
+                .filter(|(lfo, prev)| !prev.fixed_pitch && lfo.glides(prev, segment))
+                .map(|(_, prev)| prev.note);
+
+            let pitch = SegmentPitch {
+                delayed,
+                wheel: wheel_timeline,
+                lfos,
+                glide_from,
+            };
+            append_vibrato_for_segment(segment, stop_time, &pitch, events);
         }
     }
 }
 
-pub(super) fn append_portamento_events(segments: &[NoteSegment], events: &mut EventAccumulator) {
+/// Append portamento glides between consecutive notes on a channel
+///
+/// Notes with a pitch LFO are left to the vibrato pass, which glides them
+/// together with the LFO.
+pub(super) fn append_portamento_events(
+    segments: &[NoteSegment],
+    pitch_lfo: Option<&PitchLfo>,
+    events: &mut EventAccumulator,
+) {
     if segments.is_empty() {
         return;
     }
@@ -78,30 +308,31 @@ pub(super) fn append_portamento_events(segments: &[NoteSegment], events: &mut Ev
         for pair in list.windows(2) {
             let prev = pair[0];
             let next = pair[1];
+            if prev.fixed_pitch
+                || next.fixed_pitch
+                || pitch_lfo.is_some_and(|lfo| lfo.has_lfo(next))
+            {
+                continue;
+            }
             let stop_time = (next.start_time + PORTAMENTO_TIME_SECONDS).min(next.end_time);
             if stop_time <= next.start_time {
                 continue;
             }
-            append_portamento_glide(
-                prev.note,
-                next.note,
-                next.ym2151_channel,
-                next.start_time,
-                stop_time,
-                events,
-            );
+            append_portamento_glide(prev.note, next, stop_time, events);
         }
     }
 }
 
+/// Glide `segment` from `prev_note` to its own note, keeping its pitch bend
 fn append_portamento_glide(
     prev_note: u8,
-    next_note: u8,
-    ym2151_channel: u8,
-    start_time: f64,
+    segment: &NoteSegment,
     stop_time: f64,
     events: &mut EventAccumulator,
 ) {
+    let next_note = segment.note;
+    let ym2151_channel = segment.ym2151_channel;
+    let start_time = segment.start_time;
     if prev_note == next_note {
         return;
     }
@@ -113,7 +344,10 @@ fn append_portamento_glide(
 
     while time <= stop_time + f64::EPSILON {
         let progress = ((time - start_time) / (stop_time - start_time)).clamp(0.0, 1.0);
-        let (kc, kf) = midi_note_with_offset_to_kc_kf(prev_note, delta_cents * progress);
+        let (kc, kf) = midi_note_with_offset_to_kc_kf(
+            prev_note,
+            delta_cents * progress + segment.pitch_bend_at(time),
+        );
         let values = (kc, kf);
 
         if Some(values) != last_values {
@@ -136,7 +370,8 @@ fn append_portamento_glide(
     // Always emit the target pitch at stop_time to ensure the portamento reaches the target note.
     // The loop above may stop just before stop_time when time_step doesn't evenly divide the
     // portamento duration, leaving the pitch slightly short of the target.
-    let (kc_end, kf_end) = midi_note_with_offset_to_kc_kf(prev_note, delta_cents);
+    let (kc_end, kf_end) =
+        midi_note_with_offset_to_kc_kf(prev_note, delta_cents + segment.pitch_bend_at(stop_time));
     if Some((kc_end, kf_end)) != last_values {
         events.push(Ym2151Event {
             time: stop_time,
@@ -154,9 +389,17 @@ fn append_portamento_glide(
 fn append_vibrato_for_segment(
     segment: &NoteSegment,
     stop_time: f64,
+    pitch: &SegmentPitch,
     events: &mut EventAccumulator,
 ) {
-    let vibrato_start = segment.start_time + DELAY_VIBRATO_DELAY_SECONDS;
+    let delay_start = segment.start_time + DELAY_VIBRATO_DELAY_SECONDS;
+    // The wheel, pitch LFOs and glides act from note-on; the delay vibrato
+    // keeps its own phase origin
+    let vibrato_start = if pitch.wheel.is_some() || !pitch.lfos.is_empty() {
+        segment.start_time
+    } else {
+        delay_start
+    };
     if stop_time <= vibrato_start {
         return;
     }
@@ -166,16 +409,75 @@ fn append_vibrato_for_segment(
         return;
     }
 
+    let glide_end = (segment.start_time + PORTAMENTO_TIME_SECONDS).min(segment.end_time);
+    let glide = pitch
+        .glide_from
+        .filter(|_| glide_end > segment.start_time)
+        .map(|from| ((from as f64 - segment.note as f64) * 100.0, glide_end));
+
+    // Pitch bend changes, LFO jumps and the end of the glide get samples of
+    // their own between the regular ones
+    let mut jumps: Vec<f64> = segment
+        .pitch_bends
+        .iter()
+        .map(|&(time, _)| time)
+        .chain(glide.map(|(_, end)| end))
+        .filter(|&time| time > vibrato_start && time <= stop_time)
+        .collect();
+    for def in &pitch.lfos {
+        let origin = pitch_lfo_origin(def, segment.start_time);
+        if origin > vibrato_start && origin <= stop_time {
+            jumps.push(origin);
+        }
+        let mut edge = next_edge_time(def, origin, vibrato_start.max(origin));
+        while edge <= stop_time {
+            jumps.push(edge);
+            edge = next_edge_time(def, origin, edge);
+        }
+    }
+    jumps.sort_by(f64::total_cmp);
+    let mut jumps = jumps.into_iter().peekable();
+
     let time_step = 1.0 / freq;
-    let mut time = vibrato_start;
+    let mut grid_time = vibrato_start;
     let mut last_values: Option<(u8, u8)> = None;
 
-    while time <= stop_time {
-        let elapsed_from_delay = time - vibrato_start;
-        let depth_ratio = (elapsed_from_delay / DELAY_VIBRATO_ATTACK_SECONDS).clamp(0.0, 1.0);
-        let phase = (elapsed_from_delay * DELAY_VIBRATO_RATE_HZ) % 1.0;
-        let waveform = triangle_wave(phase);
-        let offset_cents = DELAY_VIBRATO_DEPTH_CENTS * depth_ratio * waveform;
+    loop {
+        let (time, at_jump) = match jumps.peek() {
+            Some(&jump) if jump < grid_time - JUMP_EPSILON => {
+                jumps.next();
+                (jump, true)
+            }
+            _ => {
+                let time = grid_time;
+                grid_time += time_step;
+                let mut at_jump = false;
+                while jumps.next_if(|&jump| jump <= time + JUMP_EPSILON).is_some() {
+                    at_jump = true;
+                }
+                (time, at_jump)
+            }
+        };
+        if time > stop_time {
+            break;
+        }
+        let sample_time = if at_jump { time + JUMP_EPSILON } else { time };
+
+        let wheel_depth = pitch
+            .wheel
+            .map(|(timeline, max_depth)| max_depth * timeline.value_at(sample_time) as f64 / 127.0)
+            .unwrap_or(0.0);
+        let mut offset_cents =
+            vibrato_offset_cents(sample_time - segment.start_time, pitch.delayed, wheel_depth)
+                + segment.pitch_bend_at(sample_time);
+        for def in &pitch.lfos {
+            offset_cents += pitch_lfo_cents(def, segment.start_time, sample_time);
+        }
+        if let Some((glide_cents, end)) = glide {
+            let progress =
+                ((sample_time - segment.start_time) / (end - segment.start_time)).clamp(0.0, 1.0);
+            offset_cents += glide_cents * (1.0 - progress);
+        }
         let (kc, kf) = midi_note_with_offset_to_kc_kf(segment.note, offset_cents);
         let values = (kc, kf);
 
@@ -193,7 +495,42 @@ fn append_vibrato_for_segment(
 
             last_values = Some(values);
         }
+    }
+}
 
-        time += time_step;
+/// Time a pitch LFO starts on a note started at `note_start`
+///
+/// Without key-on sync the LFO runs from the start of the song.
+fn pitch_lfo_origin(def: &RegisterLfoDefinition, note_start: f64) -> f64 {
+    if def.key_on_sync {
+        note_start + def.delay_seconds
+    } else {
+        def.delay_seconds
+    }
+}
+
+/// Pitch offset in cents of a pitch LFO at `time` on a note started at `note_start`
+pub(crate) fn pitch_lfo_cents(def: &RegisterLfoDefinition, note_start: f64, time: f64) -> f64 {
+    let origin = pitch_lfo_origin(def, note_start);
+    if time < origin {
+        0.0
+    } else {
+        lfo_offset(def, time - origin)
     }
 }
+
+/// Vibrato pitch offset in cents `elapsed` seconds after note-on
+///
+/// With `delayed`, the fixed delay vibrato fades in after its delay and sets
+/// the phase origin; `wheel_depth_cents` is added on top of its depth.
+pub(crate) fn vibrato_offset_cents(elapsed: f64, delayed: bool, wheel_depth_cents: f64) -> f64 {
+    let delay_elapsed = elapsed - DELAY_VIBRATO_DELAY_SECONDS;
+    let (delayed_depth, phase_elapsed) = if delayed {
+        let depth_ratio = (delay_elapsed / DELAY_VIBRATO_ATTACK_SECONDS).clamp(0.0, 1.0);
+        (DELAY_VIBRATO_DEPTH_CENTS * depth_ratio, delay_elapsed)
+    } else {
+        (0.0, elapsed)
+    };
+    let phase = (phase_elapsed * DELAY_VIBRATO_RATE_HZ) % 1.0;
+    (delayed_depth + wheel_depth_cents) * triangle_wave(phase)
+}
@@ -5,12 +5,13 @@
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
 
+use crate::ym2151::events::parse_hex_byte;
 use crate::ym2151::{NoteSegment, ToneDefinition, Ym2151Event};
-use crate::{PopNoiseEnvelope, ProgramAttachment, RegisterLfoDefinition};
+use crate::{LfoTarget, PopNoiseEnvelope, ProgramAttachment, RegisterLfoDefinition};
 
 use super::event_accumulator::EventAccumulator;
 use super::register_fields::{get_register_fields, interpolate_fields, max_steps_for_fields};
-use super::waveform::lfo_waveform_value;
+use super::waveform::{lfo_max_slope, lfo_waveform_value, next_lfo_edge};
 
 pub(super) const RESTORE_BEFORE_NOTE_EPSILON: f64 = 1e-6;
 /// Small tolerance for time-loop termination conditions to absorb accumulated f64 rounding errors.
@@ -34,7 +35,10 @@ pub(super) fn append_register_lfo_events(
     });
 
     for segment in &ordered_segments {
-        for def in lfo_defs {
+        for def in lfo_defs
+            .iter()
+            .filter(|def| def.target == LfoTarget::Register)
+        {
             let Some(base_reg) = parse_hex_byte(&def.base_register) else {
                 continue;
             };
@@ -83,7 +87,10 @@ fn append_register_lfo_for_segment(
     // Use enough samples per period so consecutive values differ by at most 1 integer step.
     // A triangle wave with amplitude `depth` has a max slope of 4*depth per period,
     // so we need at least 4*depth samples to avoid stepping by more than 1.
-    let samples_per_period = (4.0 * def.depth.abs()).max(8.0).ceil();
+    // Jumps (square and sample-and-hold edges, ramp wraps) are not sampled through;
+    // they get a sample of their own exactly at the edge below.
+    let max_step = lfo_max_slope(def.waveform) * def.depth.abs();
+    let samples_per_period = max_step.max(8.0).ceil();
     let time_step = (1.0 / def.rate_hz.max(f64::EPSILON)) / samples_per_period;
     if !time_step.is_finite() || time_step <= 0.0 {
         return;
@@ -101,16 +108,7 @@ fn append_register_lfo_for_segment(
     if !def.key_on_sync && active_start > lfo_origin {
         let dist_from_grid = (active_start - lfo_origin) % time_step;
         if dist_from_grid > TIME_LOOP_EPSILON && dist_from_grid < time_step - TIME_LOOP_EPSILON {
-            let elapsed = active_start - lfo_origin;
-            let attack_ratio = if def.attack_seconds <= 0.0 {
-                1.0
-            } else {
-                (elapsed / def.attack_seconds).clamp(0.0, 1.0)
-            };
-            let phase = (elapsed * def.rate_hz) % 1.0;
-            let waveform_val = lfo_waveform_value(def.waveform, phase);
-            let offset = def.depth * attack_ratio * waveform_val;
-            let value = ((base_value as f64) + offset).round().clamp(0.0, 255.0) as u8;
+            let value = register_lfo_value(def, base_value, active_start - lfo_origin);
             events.push(Ym2151Event {
                 time: active_start,
                 addr: addr_str.clone(),
@@ -130,32 +128,60 @@ fn append_register_lfo_for_segment(
         lfo_origin + (n as f64) * time_step
     };
 
-    while time <= active_stop + f64::EPSILON {
-        let elapsed = (time - lfo_origin).max(0.0);
-        let attack_ratio = if def.attack_seconds <= 0.0 {
-            1.0
+    let mut next_edge = next_edge_time(def, lfo_origin, active_start);
+
+    while time.min(next_edge) <= active_stop + f64::EPSILON {
+        // Take whichever comes first: the next grid sample or the next waveform jump.
+        let grid_time = time;
+        let sample_time = if next_edge < grid_time - TIME_LOOP_EPSILON {
+            next_edge
         } else {
-            (elapsed / def.attack_seconds).clamp(0.0, 1.0)
+            time += time_step;
+            grid_time
         };
-
-        let phase = (elapsed * def.rate_hz) % 1.0;
-        let waveform = lfo_waveform_value(def.waveform, phase);
-        let offset = def.depth * attack_ratio * waveform;
-        let value = ((base_value as f64) + offset).round().clamp(0.0, 255.0) as u8;
+        let mut elapsed = (sample_time - lfo_origin).max(0.0);
+        if next_edge <= sample_time + TIME_LOOP_EPSILON {
+            // Evaluate just past the edge so rounding cannot land on its old side.
+            elapsed += TIME_LOOP_EPSILON;
+            next_edge = next_edge_time(def, lfo_origin, sample_time);
+        }
+        let value = register_lfo_value(def, base_value, elapsed);
 
         if Some(value) != last_value {
             events.push(Ym2151Event {
-                time,
+                time: sample_time,
                 addr: addr_str.clone(),
                 data: format!("0x{:02X}", value),
             });
             last_value = Some(value);
         }
-
-        time += time_step;
     }
 }
 
+/// Time of the LFO's first waveform jump after `time`, or infinity for smooth waveforms
+pub(super) fn next_edge_time(def: &RegisterLfoDefinition, lfo_origin: f64, time: f64) -> f64 {
+    let cycles = (time - lfo_origin).max(0.0) * def.rate_hz;
+    next_lfo_edge(def, cycles).map_or(f64::INFINITY, |edge| lfo_origin + edge / def.rate_hz)
+}
+
+/// Register value of a software LFO `elapsed` seconds after its origin
+pub(crate) fn register_lfo_value(def: &RegisterLfoDefinition, base_value: u8, elapsed: f64) -> u8 {
+    ((base_value as f64) + lfo_offset(def, elapsed))
+        .round()
+        .clamp(0.0, 255.0) as u8
+}
+
+/// Offset (in units of `depth`) of a software LFO `elapsed` seconds after its origin
+pub(crate) fn lfo_offset(def: &RegisterLfoDefinition, elapsed: f64) -> f64 {
+    let attack_ratio = if def.attack_seconds <= 0.0 {
+        1.0
+    } else {
+        (elapsed / def.attack_seconds).clamp(0.0, 1.0)
+    };
+    let cycles = elapsed * def.rate_hz;
+    def.depth * attack_ratio * lfo_waveform_value(def, cycles)
+}
+
 /// Inserts an event at the tail of its time bucket in the map.
 /// `counters` tracks the next sub_index per time bucket, giving O(1) insertion.
 /// Callers choose tail insertion to express "this event comes last within this timestamp".
@@ -305,6 +331,11 @@ pub(super) fn build_register_state_cache<'a>(
 
 impl RegisterStateCache {
     fn latest_value(&self, addr: u8, time: f64) -> Option<u8> {
+        self.latest_entry(addr, time).map(|(_, value)| value)
+    }
+
+    /// Latest `(time, value)` written to `addr` at or before `time`.
+    pub(super) fn latest_entry(&self, addr: u8, time: f64) -> Option<(f64, u8)> {
         let entries = self.by_addr.get(&addr)?;
         let mut lo = 0;
         let mut hi = entries.len();
@@ -319,7 +350,7 @@ impl RegisterStateCache {
         if lo == 0 {
             None
         } else {
-            Some(entries[lo - 1].1)
+            Some(entries[lo - 1])
         }
     }
 }
@@ -442,7 +473,7 @@ pub(super) fn append_change_to_next_tone_events(
     }
 }
 
-fn resolve_register_for_channel(base_register: u8, channel: u8) -> u8 {
+pub(crate) fn resolve_register_for_channel(base_register: u8, channel: u8) -> u8 {
     match base_register {
         0x20..=0x27 => 0x20 + channel,
         0x28..=0x2F => 0x28 + channel,
@@ -469,15 +500,3 @@ fn resolve_register_for_channel(base_register: u8, channel: u8) -> u8 {
 fn is_note_register(addr: u8) -> bool {
     matches!(addr, 0x08 | 0x28..=0x2F | 0x30..=0x37)
 }
-
-fn parse_hex_byte(value: &str) -> Option<u8> {
-    let trimmed = value.trim();
-    if let Some(hex) = trimmed
-        .strip_prefix("0x")
-        .or_else(|| trimmed.strip_prefix("0X"))
-    {
-        u8::from_str_radix(hex, 16).ok()
-    } else {
-        trimmed.parse::<u8>().ok()
-    }
-}
@@ -5,8 +5,10 @@
 //! This module defines those sub-fields so that linear interpolation can operate
 //! on each parameter independently, rather than blending the raw byte value.
 
+use crate::RegisterField;
+
 /// A single parameter packed into a YM2151 register byte.
-pub(super) struct RegisterFieldDef {
+pub(crate) struct RegisterFieldDef {
     /// Bitmask of this field's bits in their original register-byte position.
     pub mask: u8,
     /// Bit-position of this field's least-significant bit (right-shift amount).
@@ -23,6 +25,11 @@ impl RegisterFieldDef {
     pub fn max_value(&self) -> u8 {
         self.mask >> self.shift
     }
+
+    /// Replace this field's bits in a register byte with `value`.
+    pub fn insert(&self, byte: u8, value: u8) -> u8 {
+        (byte & !self.mask) | ((value << self.shift) & self.mask)
+    }
 }
 
 // ── static field tables ──────────────────────────────────────────────────────
@@ -120,6 +127,42 @@ static GENERIC_FIELDS: [RegisterFieldDef; 1] = [RegisterFieldDef {
 
 // ── public helpers ───────────────────────────────────────────────────────────
 
+/// Returns the channel-0 base register and bit-field of a named tone parameter.
+///
+/// Operator parameters use the M1 slot; add `operator * 8` for the others.
+pub(crate) fn named_register_field(field: RegisterField) -> (u8, &'static RegisterFieldDef) {
+    match field {
+        RegisterField::Con => (0x20, &RL_FB_CON_FIELDS[0]),
+        RegisterField::Fb => (0x20, &RL_FB_CON_FIELDS[1]),
+        RegisterField::Rl => (0x20, &RL_FB_CON_FIELDS[2]),
+        RegisterField::Ams => (0x38, &PMS_AMS_FIELDS[0]),
+        RegisterField::Pms => (0x38, &PMS_AMS_FIELDS[1]),
+        RegisterField::Mul => (0x40, &DT1_MUL_FIELDS[0]),
+        RegisterField::Dt1 => (0x40, &DT1_MUL_FIELDS[1]),
+        RegisterField::Tl => (0x60, &TL_FIELDS[0]),
+        RegisterField::Ar => (0x80, &KS_AR_FIELDS[0]),
+        RegisterField::Ks => (0x80, &KS_AR_FIELDS[1]),
+        RegisterField::D1r => (0xA0, &AMSEN_D1R_FIELDS[0]),
+        RegisterField::Amsen => (0xA0, &AMSEN_D1R_FIELDS[1]),
+        RegisterField::D2r => (0xC0, &DT2_D2R_FIELDS[0]),
+        RegisterField::Dt2 => (0xC0, &DT2_D2R_FIELDS[1]),
+        RegisterField::Rr => (0xE0, &D1L_RR_FIELDS[0]),
+        RegisterField::D1l => (0xE0, &D1L_RR_FIELDS[1]),
+    }
+}
+
+/// Channel-0 registers a field write touches: the channel register itself, or
+/// the slot of each listed operator (all four when `operators` is empty).
+pub(crate) fn field_registers(base_addr: u8, operators: &[u8]) -> Vec<u8> {
+    if base_addr < 0x40 {
+        vec![base_addr]
+    } else if operators.is_empty() {
+        (0..4).map(|op| base_addr + op * 8).collect()
+    } else {
+        operators.iter().map(|&op| base_addr + op * 8).collect()
+    }
+}
+
 /// Returns the bit-field definitions for the given YM2151 register address.
 pub(super) fn get_register_fields(addr: u8) -> &'static [RegisterFieldDef] {
     match addr {
@@ -248,11 +291,11 @@ mod tests {
 
         // Both should be near 7-8, not 15
         assert!(
-            rr_mid <= 8 && rr_mid >= 7,
+            (7..=8).contains(&rr_mid),
             "RR midpoint should be 7 or 8, got {rr_mid}"
         );
         assert!(
-            d1l_mid <= 8 && d1l_mid >= 7,
+            (7..=8).contains(&d1l_mid),
             "D1L midpoint should be 7 or 8, got {d1l_mid}"
         );
 
@@ -267,6 +310,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_named_register_field_insert_keeps_other_fields() {
+        let (base, field) = named_register_field(RegisterField::Ks);
+        assert_eq!(base, 0x80);
+        // AR=31 stays, KS becomes 2
+        assert_eq!(field.insert(0x1F, 2), 0x9F);
+        let (base, field) = named_register_field(RegisterField::Pms);
+        assert_eq!(base, 0x38);
+        assert_eq!(field.insert(0x03, 5), 0x53);
+    }
+
     #[test]
     fn test_max_steps_for_fields() {
         let fields = get_register_fields(0xE0); // D1L_RR
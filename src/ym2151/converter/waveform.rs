@@ -2,11 +2,59 @@
 //!
 //! Provides waveform generation helpers for LFO effects.
 
-use crate::LfoWaveform;
+use std::f64::consts::TAU;
 
-pub(super) fn lfo_waveform_value(waveform: LfoWaveform, phase: f64) -> f64 {
+use crate::{LfoWaveform, RegisterLfoDefinition};
+
+/// Edges closer than this many periods ahead count as already passed.
+const EDGE_EPSILON: f64 = 1e-9;
+
+/// Waveform value (-1.0 to 1.0) of a software LFO `cycles` periods after its origin
+pub(super) fn lfo_waveform_value(def: &RegisterLfoDefinition, cycles: f64) -> f64 {
+    let wrapped = cycles - cycles.floor();
+    match def.waveform {
+        LfoWaveform::Triangle => triangle_wave(cycles),
+        LfoWaveform::Sine => (TAU * wrapped).sin(),
+        LfoWaveform::RampUp => 2.0 * wrapped - 1.0,
+        LfoWaveform::RampDown => 1.0 - 2.0 * wrapped,
+        LfoWaveform::Square => {
+            if wrapped < def.duty {
+                1.0
+            } else {
+                -1.0
+            }
+        }
+        LfoWaveform::SampleAndHold => sample_and_hold(def.seed, cycles.floor() as i64),
+    }
+}
+
+/// Largest change of the waveform's continuous parts over one period, per unit of depth
+pub(super) fn lfo_max_slope(waveform: LfoWaveform) -> f64 {
     match waveform {
-        LfoWaveform::Triangle => triangle_wave(phase),
+        LfoWaveform::Triangle => 4.0,
+        LfoWaveform::Sine => TAU,
+        LfoWaveform::RampUp | LfoWaveform::RampDown => 2.0,
+        LfoWaveform::Square | LfoWaveform::SampleAndHold => 0.0,
+    }
+}
+
+/// Position (in periods) of the first jump strictly after `cycles`, if the waveform has jumps
+pub(super) fn next_lfo_edge(def: &RegisterLfoDefinition, cycles: f64) -> Option<f64> {
+    let cycles = cycles + EDGE_EPSILON;
+    let period_start = cycles.floor();
+    match def.waveform {
+        LfoWaveform::Triangle | LfoWaveform::Sine => None,
+        LfoWaveform::RampUp | LfoWaveform::RampDown | LfoWaveform::SampleAndHold => {
+            Some(period_start + 1.0)
+        }
+        LfoWaveform::Square => {
+            let fall = period_start + def.duty;
+            Some(if cycles < fall {
+                fall
+            } else {
+                period_start + 1.0
+            })
+        }
     }
 }
 
@@ -22,3 +70,16 @@ pub(super) fn triangle_wave(phase: f64) -> f64 {
         -1.0 + ((wrapped - 0.75) / 0.25)
     }
 }
+
+/// Level (-1.0 to 1.0) held during `period`, from a SplitMix64 hash of the seed and period
+fn sample_and_hold(seed: u64, period: i64) -> f64 {
+    let mut z = seed.wrapping_add(
+        (period as u64)
+            .wrapping_add(1)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15),
+    );
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
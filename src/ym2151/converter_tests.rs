@@ -5,26 +5,116 @@
 
 // Re-export items needed by test submodules
 pub use super::{convert_to_ym2151_log, convert_to_ym2151_log_with_options};
-pub use crate::midi::{midi_to_kc_kf, MidiData, MidiEvent};
+pub use crate::midi::{midi_to_kc_kf, MidiData, MidiEvent, MidiMetadata, MidiTiming};
 pub use crate::ym2151::{ToneDefinition, Ym2151Event};
 pub use crate::{
-    ConversionOptions, LfoWaveform, PopNoiseEnvelope, ProgramAttachment, RegisterLfoDefinition,
-    RegisterOverride,
+    ConversionOptions, LfoTarget, LfoWaveform, PopNoiseEnvelope, ProgramAttachment,
+    RegisterLfoDefinition, RegisterOverride,
 };
 
+fn note_on(ticks: u32, channel: u8, note: u8, velocity: u8) -> MidiEvent {
+    MidiEvent::NoteOn {
+        ticks,
+        channel,
+        note,
+        velocity,
+        track: 0,
+        port: None,
+    }
+}
+
+fn note_off(ticks: u32, channel: u8, note: u8) -> MidiEvent {
+    MidiEvent::NoteOff {
+        ticks,
+        channel,
+        note,
+        track: 0,
+        port: None,
+    }
+}
+
+/// At 120 BPM and 480 ticks per beat a second is 960 ticks
+fn midi_data(events: Vec<MidiEvent>) -> MidiData {
+    MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events,
+        metadata: MidiMetadata::default(),
+    }
+}
+
+fn options_from(json: &str) -> ConversionOptions {
+    ConversionOptions::from_attachment_bytes(Some(json.as_bytes())).unwrap()
+}
+
+fn convert(events: Vec<MidiEvent>, attachment: &str) -> crate::error::Result<Vec<Ym2151Event>> {
+    let options = ConversionOptions::from_attachment_bytes(Some(attachment.as_bytes()))?;
+    Ok(convert_to_ym2151_log_with_options(&midi_data(events), &options)?.events)
+}
+
+fn data_of(event: &Ym2151Event) -> u8 {
+    u8::from_str_radix(event.data.trim_start_matches("0x"), 16).unwrap()
+}
+
+/// (time, data) of every write to `addr`
+fn writes_to<'a>(events: &'a [Ym2151Event], addr: &str) -> Vec<(f64, &'a str)> {
+    events
+        .iter()
+        .filter(|e| e.addr == addr)
+        .map(|e| (e.time, e.data.as_str()))
+        .collect()
+}
+
+/// YM2151 channels keyed on at `time`, sorted
+fn key_ons_at(events: &[Ym2151Event], time: f64) -> Vec<u8> {
+    let mut channels: Vec<u8> = events
+        .iter()
+        .filter(|e| e.addr == "0x08" && e.time == time && data_of(e) & 0x78 != 0)
+        .map(|e| data_of(e) & 0x07)
+        .collect();
+    channels.sort_unstable();
+    channels
+}
+
+#[path = "converter_tests/aftertouch.rs"]
+mod aftertouch;
 #[path = "converter_tests/attachments.rs"]
 mod attachments;
 #[path = "converter_tests/basic.rs"]
 mod basic;
+#[path = "converter_tests/channel_assignment.rs"]
+mod channel_assignment;
 #[path = "converter_tests/channels.rs"]
 mod channels;
+#[path = "converter_tests/controller_mappings.rs"]
+mod controller_mappings;
+#[path = "converter_tests/drum_map.rs"]
+mod drum_map;
 #[path = "converter_tests/drums.rs"]
 mod drums;
 #[path = "converter_tests/effects.rs"]
 mod effects;
+#[path = "converter_tests/hardware_lfo.rs"]
+mod hardware_lfo;
 #[path = "converter_tests/lfo.rs"]
 mod lfo;
+#[path = "converter_tests/looping.rs"]
+mod looping;
+#[path = "converter_tests/modulation_wheel.rs"]
+mod modulation_wheel;
+#[path = "converter_tests/noise.rs"]
+mod noise;
+#[path = "converter_tests/pitch_bend.rs"]
+mod pitch_bend;
+#[path = "converter_tests/pitch_lfo.rs"]
+mod pitch_lfo;
 #[path = "converter_tests/portamento.rs"]
 mod portamento;
 #[path = "converter_tests/programs.rs"]
 mod programs;
+#[path = "converter_tests/velocity.rs"]
+mod velocity;
+#[path = "converter_tests/voice_allocation.rs"]
+mod voice_allocation;
@@ -0,0 +1,132 @@
+//! Aftertouch (channel and key pressure) routing tests for YM2151 converter
+use super::*;
+
+fn channel_pressure(ticks: u32, pressure: u8) -> MidiEvent {
+    MidiEvent::ChannelPressure {
+        ticks,
+        channel: 0,
+        pressure,
+        track: 0,
+        port: None,
+    }
+}
+
+/// YM2151 channel whose KC was set to `note` at note-on time `time`
+fn voice_of(events: &[Ym2151Event], note: u8, time: f64) -> u8 {
+    let kc = format!("0x{:02X}", midi_to_kc_kf(note).0);
+    events
+        .iter()
+        .find(|e| e.time == time && e.data == kc && (0x28..0x30).contains(&addr_of(e)))
+        .map(|e| addr_of(e) - 0x28)
+        .expect("KC write for the note")
+}
+
+fn addr_of(event: &Ym2151Event) -> u8 {
+    u8::from_str_radix(event.addr.trim_start_matches("0x"), 16).unwrap()
+}
+
+#[test]
+fn test_swell_raises_carrier_level_with_channel_pressure() {
+    let midi_data = midi_data(vec![
+        note_on(0, 0, 60, 127),
+        channel_pressure(480, 127),
+        note_off(960, 0, 60),
+    ]);
+    let options = options_from(
+        r#"[{ "ProgramChange": 0, "Aftertouch": { "Target": "swell", "Depth": 32 } }]"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    // The default tone's only audible carrier is M1 (0x60) at TL 0
+    let tl = writes_to(&result.events, "0x60");
+    assert!(tl.contains(&(0.0, "0x20")), "No pressure: {tl:?}");
+    assert!(tl.contains(&(0.5, "0x00")), "Full pressure: {tl:?}");
+}
+
+#[test]
+fn test_programs_without_routing_ignore_pressure() {
+    let midi_data = midi_data(vec![
+        note_on(0, 0, 60, 127),
+        channel_pressure(480, 127),
+        note_off(960, 0, 60),
+    ]);
+    let options = options_from(
+        r#"[{ "ProgramChange": 5, "Aftertouch": { "Target": "swell", "Depth": 32 } }]"#,
+    );
+
+    let with_routing = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+    let without = convert_to_ym2151_log(&midi_data).unwrap();
+    assert_eq!(with_routing.events, without.events);
+}
+
+#[test]
+fn test_poly_pressure_reaches_only_the_voice_holding_the_note() {
+    let midi_data = midi_data(vec![
+        note_on(0, 0, 60, 127),
+        note_on(0, 0, 64, 127),
+        MidiEvent::PolyPressure {
+            ticks: 480,
+            channel: 0,
+            note: 64,
+            pressure: 127,
+            track: 0,
+            port: None,
+        },
+        note_off(960, 0, 60),
+        note_off(960, 0, 64),
+    ]);
+    let options = options_from(r#"{ "Aftertouch": { "Target": "vibrato", "Depth": 5 } }"#);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    // The chip LFO is set up for the pressure vibrato
+    assert!(result.events.iter().any(|e| e.addr == "0x19"));
+
+    let pressed = voice_of(&result.events, 64, 0.0);
+    let other = voice_of(&result.events, 60, 0.0);
+    assert_ne!(pressed, other);
+    let pms_at = |ym_ch: u8| writes_to(&result.events, &format!("0x{:02X}", 0x38 + ym_ch));
+    assert!(
+        pms_at(pressed).contains(&(0.5, "0x50")),
+        "PMS 5 on the pressed voice: {:?}",
+        pms_at(pressed)
+    );
+    assert!(
+        pms_at(other).iter().all(|&(time, _)| time < 0.5),
+        "Other voice untouched: {:?}",
+        pms_at(other)
+    );
+}
+
+#[test]
+fn test_field_target_scales_over_range() {
+    let midi_data = midi_data(vec![
+        note_on(0, 0, 60, 127),
+        channel_pressure(480, 127),
+        note_off(960, 0, 60),
+    ]);
+    let options = options_from(
+        r#"{ "Aftertouch": { "Target": "field", "Field": "AR", "Operators": [3], "Range": [10, 31] } }"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    let ym_ch = voice_of(&result.events, 60, 0.0);
+    let ar = writes_to(&result.events, &format!("0x{:02X}", 0x98 + ym_ch));
+    let at_time = |time: f64| {
+        ar.iter()
+            .rfind(|&&(t, _)| t == time)
+            .map(|&(_, data)| u8::from_str_radix(data.trim_start_matches("0x"), 16).unwrap())
+    };
+    assert_eq!(at_time(0.0).map(|data| data & 0x1F), Some(10));
+    assert_eq!(at_time(0.5).map(|data| data & 0x1F), Some(31));
+}
+
+#[test]
+fn test_field_target_requires_field() {
+    let result = ConversionOptions::from_attachment_bytes(Some(
+        br#"[{ "ProgramChange": 0, "Aftertouch": { "Target": "field" } }]"#,
+    ));
+    assert!(result.is_err());
+}
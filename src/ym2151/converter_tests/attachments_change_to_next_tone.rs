@@ -7,7 +7,9 @@ fn test_change_to_next_tone_skips_kc_kf_key_on_registers() {
     // a real tone register (TL 0x60).  The KC/KF/key-on differences must NOT produce
     // interpolation events; only TL should be interpolated.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -15,13 +17,18 @@ fn test_change_to_next_tone_skips_kc_kf_key_on_registers() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 4800,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let make_tone = |tl: &str, key_on: &str, kc: &str, kf: &str| ToneDefinition {
@@ -148,7 +155,9 @@ fn test_change_to_next_tone_generates_interpolation_events() {
     // A 10-second song with program 0 and program 1 tones that differ in TL (0x60).
     // changeToNextTone should produce continuously changing register writes.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -156,14 +165,19 @@ fn test_change_to_next_tone_generates_interpolation_events() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             // Last event at tick 9600 = 10 seconds at 120 BPM
             MidiEvent::NoteOff {
                 ticks: 9600,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     // Program 0 tone: TL operator 0 = 0x10; Program 1 tone: TL = 0x30 (delta = 32)
@@ -233,7 +247,9 @@ fn test_change_to_next_tone_generates_interpolation_events() {
 fn test_change_to_next_tone_disabled_produces_no_extra_events() {
     // When change_to_next_tone is false, no interpolation events should be generated.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -241,13 +257,18 @@ fn test_change_to_next_tone_disabled_produces_no_extra_events() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 9600,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let tone0 = ToneDefinition {
@@ -305,7 +326,9 @@ fn test_change_to_next_tone_disabled_produces_no_extra_events() {
 fn test_change_to_next_tone_requires_both_tones() {
     // If tone N+1 is not defined, no interpolation events should be generated.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -313,13 +336,18 @@ fn test_change_to_next_tone_requires_both_tones() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 9600,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let tone0 = ToneDefinition {
@@ -385,7 +413,9 @@ fn test_change_to_next_tone_interpolates_fields_independently() {
     // Correct per-field midpoint (t=0.5): AR≈16, KS=1  → byte ≈ 0x50
     // Wrong raw-byte midpoint          : (0x1F+0x40)/2 → byte ≈ 0x30  (KS=0 — incorrect!)
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -393,14 +423,19 @@ fn test_change_to_next_tone_interpolates_fields_independently() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             // 10 seconds at 120 BPM
             MidiEvent::NoteOff {
                 ticks: 9600,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     // Both tones carry a KS_AR register entry for channel 0 operator 0 (0x80).
@@ -5,7 +5,9 @@ use super::*;
 fn test_program_attachment_delay_vibrato_applies_only_to_matching_program() {
     // Notes under program 0 should get vibrato; notes under program 1 should not.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             // Program 0 note (2 seconds long — long enough for vibrato to activate)
@@ -13,36 +15,49 @@ fn test_program_attachment_delay_vibrato_applies_only_to_matching_program() {
                 ticks: 0,
                 channel: 0,
                 program: 0,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 0,
                 channel: 0,
                 note: 69,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 1920, // 2 seconds at 120 BPM
                 channel: 0,
                 note: 69,
+                track: 0,
+                port: None,
             },
             // Program 1 note on a second channel
             MidiEvent::ProgramChange {
                 ticks: 1920,
                 channel: 1,
                 program: 1,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 1920,
                 channel: 1,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 3840, // another 2 seconds
                 channel: 1,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let options = ConversionOptions {
@@ -86,26 +101,35 @@ fn test_program_attachment_no_effects_entry_produces_no_extra_events() {
     // A ProgramAttachment with no effect-related fields enabled must
     // not crash and must not generate any vibrato/LFO/etc events.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::ProgramChange {
                 ticks: 0,
                 channel: 0,
                 program: 5,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 0,
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     // Attachment entry with no effects enabled (all flags remain at default)
@@ -137,26 +161,35 @@ fn test_program_attachment_no_effects_entry_produces_no_extra_events() {
 fn test_program_attachment_unmatched_program_produces_no_extra_events() {
     // An attachment for program 99 should do nothing when only program 0 is used.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::ProgramChange {
                 ticks: 0,
                 channel: 0,
                 program: 0,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 0,
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let options_with_attachment = ConversionOptions {
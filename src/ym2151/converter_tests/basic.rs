@@ -4,9 +4,12 @@ use super::*;
 #[test]
 fn test_convert_empty_midi() {
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -19,7 +22,9 @@ fn test_convert_empty_midi() {
 #[test]
 fn test_convert_single_note() {
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -27,13 +32,18 @@ fn test_convert_single_note() {
                 channel: 0,
                 note: 60, // Middle C
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -64,7 +74,9 @@ fn test_convert_single_note() {
 #[test]
 fn test_convert_tempo_change() {
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -72,17 +84,24 @@ fn test_convert_tempo_change() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::Tempo {
                 ticks: 240,
                 tempo_bpm: 60.0, // Half speed
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -102,7 +121,9 @@ fn test_convert_tempo_change() {
 #[test]
 fn test_convert_multiple_notes() {
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -110,24 +131,33 @@ fn test_convert_multiple_notes() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 240,
                 channel: 0,
                 note: 64,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 720,
                 channel: 0,
                 note: 64,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -141,14 +171,19 @@ fn test_convert_multiple_notes() {
 #[test]
 fn test_key_on_register_format() {
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![MidiEvent::NoteOn {
             ticks: 0,
             channel: 0,
             note: 60,
             velocity: 100,
+            track: 0,
+            port: None,
         }],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -167,7 +202,9 @@ fn test_key_on_register_format() {
 #[test]
 fn test_key_off_register_format() {
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -175,13 +212,18 @@ fn test_key_off_register_format() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -207,14 +249,19 @@ fn test_init_key_offs_precede_register_writes_at_time_zero() {
     // Note: a NoteOn at tick=0 also places a key-on (0x08) at t=0.0, but *after* register writes
     // — that ordering is intentional and tested separately.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![MidiEvent::NoteOn {
             ticks: 0,
             channel: 0,
             note: 60,
             velocity: 100,
+            track: 0,
+            port: None,
         }],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -241,3 +288,165 @@ fn test_init_key_offs_precede_register_writes_at_time_zero() {
         );
     }
 }
+
+#[test]
+fn test_register_writes_are_copied_in_midi_order() {
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events: vec![
+            MidiEvent::RegisterWrite {
+                ticks: 480,
+                addr: 0x0F,
+                data: 0x80,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOn {
+                ticks: 480,
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::RegisterWrite {
+                ticks: 480,
+                addr: 0x01,
+                data: 0x02,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOff {
+                ticks: 960,
+                channel: 0,
+                note: 60,
+                track: 0,
+                port: None,
+            },
+        ],
+        metadata: MidiMetadata::default(),
+    };
+
+    let result = convert_to_ym2151_log(&midi_data).unwrap();
+
+    let at_note: Vec<(&str, &str)> = result
+        .events
+        .iter()
+        .filter(|e| e.time == 0.5)
+        .map(|e| (e.addr.as_str(), e.data.as_str()))
+        .collect();
+    let (kc, kf) = midi_to_kc_kf(60);
+    let kc = format!("0x{:02X}", kc);
+    let kf = format!("0x{:02X}", kf);
+    assert_eq!(
+        at_note,
+        vec![
+            ("0x0F", "0x80"),
+            ("0x28", kc.as_str()),
+            ("0x30", kf.as_str()),
+            ("0x08", "0x78"),
+            ("0x01", "0x02"),
+        ]
+    );
+}
+
+#[test]
+fn test_timecode_timing_uses_wall_clock_times() {
+    // 30 fps x 100 ticks per frame = 3000 ticks per second
+    let midi_data = MidiData {
+        timing: MidiTiming::Timecode {
+            fps: crate::midi::SmpteFps::Fps30,
+            ticks_per_frame: 100,
+        },
+        tempo_bpm: 120.0,
+        events: vec![
+            MidiEvent::Tempo {
+                ticks: 0,
+                tempo_bpm: 60.0,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOn {
+                ticks: 4500,
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOff {
+                ticks: 6000,
+                channel: 0,
+                note: 60,
+                track: 0,
+                port: None,
+            },
+        ],
+        metadata: MidiMetadata::default(),
+    };
+
+    let result = convert_to_ym2151_log(&midi_data).unwrap();
+    let key_events: Vec<_> = result
+        .events
+        .iter()
+        .filter(|e| e.addr == "0x08" && e.time > 0.0)
+        .collect();
+    assert_eq!(key_events.len(), 2);
+    assert!((key_events[0].time - 1.5).abs() < 1e-9);
+    assert!((key_events[1].time - 2.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_metadata_passed_through_to_log() {
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events: vec![MidiEvent::NoteOn {
+            ticks: 1920,
+            channel: 0,
+            note: 60,
+            velocity: 100,
+            track: 0,
+            port: None,
+        }],
+        metadata: MidiMetadata {
+            title: Some("Song".to_string()),
+            texts: vec![crate::midi::TextEvent {
+                ticks: 960,
+                track: 0,
+                kind: crate::midi::TextKind::Lyric,
+                text: "la".to_string(),
+            }],
+            ..MidiMetadata::default()
+        },
+    };
+
+    let result = convert_to_ym2151_log(&midi_data).unwrap();
+    let metadata = result.metadata.expect("metadata section");
+    assert_eq!(metadata.title.as_deref(), Some("Song"));
+    assert!((metadata.texts[0].time - 1.0).abs() < 1e-9);
+    // 4/4 bars of 2 seconds at 120 BPM up to the last event
+    assert_eq!(metadata.bar_times, vec![0.0, 2.0]);
+}
+
+#[test]
+fn test_no_metadata_section_without_meta_events() {
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events: vec![],
+        metadata: MidiMetadata::default(),
+    };
+
+    let result = convert_to_ym2151_log(&midi_data).unwrap();
+    assert!(result.metadata.is_none());
+    let json = serde_json::to_string(&result).unwrap();
+    assert!(!json.contains("metadata"));
+}
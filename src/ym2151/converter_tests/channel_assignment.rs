@@ -0,0 +1,139 @@
+//! Explicit channel assignment tests for YM2151 converter
+use super::*;
+
+#[test]
+fn test_pinned_channels_play_on_their_ym2151_channels() {
+    let events = vec![
+        note_on(0, 0, 60, 100),
+        note_on(0, 0, 64, 100),
+        note_on(480, 1, 36, 100),
+        note_on(960, 9, 38, 100),
+        note_on(960, 9, 42, 100),
+    ];
+    let options = options_from(
+        r#"{ "ChannelAssignment": {
+            "1": { "Ym2151Channels": [1] },
+            "9": { "Ym2151Channels": [6, 7] }
+        } }"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    assert_eq!(key_ons_at(&result.events, 0.0), [0, 2]);
+    assert_eq!(key_ons_at(&result.events, 0.5), [1]);
+    assert_eq!(key_ons_at(&result.events, 1.0), [6, 7]);
+}
+
+#[test]
+fn test_max_voices_limits_a_channel() {
+    let events = vec![
+        note_on(0, 0, 60, 100),
+        note_on(0, 0, 64, 100),
+        note_on(0, 0, 67, 100),
+    ];
+    let options = options_from(r#"{ "ChannelAssignment": { "0": { "MaxVoices": 2 } } }"#);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let mut voices = key_ons_at(&result.events, 0.0);
+    voices.dedup();
+    assert_eq!(voices, [0, 1]);
+}
+
+#[test]
+fn test_priority_decides_who_gets_voices_first() {
+    // Channel 1 needs more voices, but channel 0 outranks it
+    let mut events: Vec<MidiEvent> = (0..6).map(|i| note_on(0, 1, 60 + i, 100)).collect();
+    events.extend((0..4).map(|i| note_on(480, 0, 60 + i, 100)));
+    let options = options_from(r#"{ "ChannelAssignment": { "0": { "Priority": 10 } } }"#);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    assert_eq!(key_ons_at(&result.events, 0.5), [0, 1, 2, 3]);
+}
+
+#[test]
+fn test_dynamic_voices_respect_pins_and_limits() {
+    let mut events: Vec<MidiEvent> = (0..8)
+        .map(|i| note_on(i * 10, 0, 60 + i as u8, 100))
+        .collect();
+    events.push(note_on(100, 1, 36, 100));
+    events.push(note_on(110, 1, 38, 100));
+    let options = options_from(
+        r#"{
+            "VoiceAllocation": {},
+            "ChannelAssignment": { "0": { "MaxVoices": 4 }, "1": { "Ym2151Channels": [7] } }
+        }"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let melody_voices: Vec<u8> = (0..8)
+        .flat_map(|i| key_ons_at(&result.events, i as f64 * 10.0 / 960.0))
+        .collect();
+    assert!(melody_voices.iter().all(|&voice| voice < 7));
+    assert_eq!(
+        melody_voices[4..],
+        melody_voices[..4],
+        "the fifth note onwards steals the channel's own oldest voice"
+    );
+    assert_eq!(key_ons_at(&result.events, 100.0 / 960.0), [7]);
+    assert_eq!(key_ons_at(&result.events, 110.0 / 960.0), [7]);
+}
+
+#[test]
+fn test_two_parts_pinned_to_one_channel_are_rejected() {
+    let result = ConversionOptions::from_attachment_bytes(Some(
+        br#"{ "ChannelAssignment": { "0": { "Ym2151Channels": [1] }, "2": { "Ym2151Channels": [1, 2] } } }"#,
+    ));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_pin_beyond_the_chip_is_rejected() {
+    let result = ConversionOptions::from_attachment_bytes(Some(
+        br#"{ "ChannelAssignment": { "0": { "Ym2151Channels": [8] } } }"#,
+    ));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_second_port_parts_are_assigned_and_mapped_by_part_number() {
+    // Channel 0 on port 1 is part 16
+    let events = vec![
+        note_on(0, 0, 60, 100),
+        MidiEvent::NoteOn {
+            ticks: 0,
+            channel: 0,
+            note: 64,
+            velocity: 100,
+            track: 1,
+            port: Some(1),
+        },
+        MidiEvent::ControlChange {
+            ticks: 480,
+            channel: 0,
+            controller: 74,
+            value: 127,
+            track: 1,
+            port: Some(1),
+        },
+    ];
+    let options = options_from(
+        r#"{
+          "ChannelAssignment": { "16": { "Ym2151Channels": [5] } },
+          "ControllerMappings": [{ "Source": { "Cc": 74 }, "Channel": 16, "Field": "FB" }]
+        }"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    assert_eq!(key_ons_at(&result.events, 0.0), [0, 5]);
+    let fb_writes: Vec<&str> = result
+        .events
+        .iter()
+        .filter(|e| e.time == 0.5)
+        .map(|e| e.addr.as_str())
+        .collect();
+    assert_eq!(fb_writes, ["0x25"]);
+}
@@ -5,7 +5,9 @@ use super::*;
 fn test_convert_multi_channel() {
     // Test with notes on different MIDI channels
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             // Channel 0: C (60)
@@ -14,6 +16,8 @@ fn test_convert_multi_channel() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             // Channel 1: E (64)
             MidiEvent::NoteOn {
@@ -21,6 +25,8 @@ fn test_convert_multi_channel() {
                 channel: 1,
                 note: 64,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             // Channel 2: G (67)
             MidiEvent::NoteOn {
@@ -28,24 +34,33 @@ fn test_convert_multi_channel() {
                 channel: 2,
                 note: 67,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             // Note offs at tick 480
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 1,
                 note: 64,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 2,
                 note: 67,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -107,7 +122,9 @@ fn test_convert_multi_channel() {
 fn test_convert_multi_channel_sequential() {
     // Test with notes on different channels played sequentially
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             // Channel 0 plays first
@@ -116,11 +133,15 @@ fn test_convert_multi_channel_sequential() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             // Channel 1 plays next
             MidiEvent::NoteOn {
@@ -128,13 +149,18 @@ fn test_convert_multi_channel_sequential() {
                 channel: 1,
                 note: 64,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 960,
                 channel: 1,
                 note: 64,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -168,3 +194,119 @@ fn test_convert_multi_channel_sequential() {
         ym_channels_with_notes
     );
 }
+
+#[test]
+fn test_sustain_pedal_extends_note_until_pedal_release() {
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events: vec![
+            MidiEvent::ControlChange {
+                ticks: 0,
+                channel: 0,
+                controller: 64,
+                value: 127,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOn {
+                ticks: 0,
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOff {
+                ticks: 240,
+                channel: 0,
+                note: 60,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::ControlChange {
+                ticks: 960,
+                channel: 0,
+                controller: 64,
+                value: 0,
+                track: 0,
+                port: None,
+            },
+        ],
+        metadata: MidiMetadata::default(),
+    };
+
+    let result = convert_to_ym2151_log(&midi_data).unwrap();
+
+    let key_offs: Vec<_> = result
+        .events
+        .iter()
+        .filter(|e| e.addr == "0x08" && e.data == "0x00" && e.time > 0.0)
+        .collect();
+    assert_eq!(key_offs.len(), 1);
+    assert!(
+        (key_offs[0].time - 1.0).abs() < 1e-9,
+        "Key-off should happen at pedal release, got {}",
+        key_offs[0].time
+    );
+}
+
+#[test]
+fn test_same_channel_on_two_ports_is_two_parts() {
+    let note = |port, off_ticks| {
+        [
+            MidiEvent::NoteOn {
+                ticks: 0,
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                track: port as u16,
+                port: Some(port),
+            },
+            MidiEvent::NoteOff {
+                ticks: off_ticks,
+                channel: 0,
+                note: 60,
+                track: port as u16,
+                port: Some(port),
+            },
+        ]
+    };
+    let mut events: Vec<MidiEvent> = note(0, 960).into_iter().chain(note(1, 480)).collect();
+    events.sort_by_key(MidiEvent::ticks);
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events,
+        metadata: MidiMetadata::default(),
+    };
+
+    let result = convert_to_ym2151_log(&midi_data).unwrap();
+
+    // Both parts sound on their own YM2151 channel
+    let key_ons: Vec<_> = result
+        .events
+        .iter()
+        .filter(|e| e.addr == "0x08" && e.data.starts_with("0x7"))
+        .map(|e| e.data.clone())
+        .collect();
+    assert_eq!(key_ons.len(), 2);
+    assert_ne!(key_ons[0], key_ons[1]);
+
+    // The port 1 note-off releases only its own voice
+    let key_offs_at = |time: f64| {
+        result
+            .events
+            .iter()
+            .filter(|e| {
+                e.addr == "0x08" && !e.data.starts_with("0x7") && (e.time - time).abs() < 1e-9
+            })
+            .count()
+    };
+    assert_eq!(key_offs_at(0.5), 1);
+    assert_eq!(key_offs_at(1.0), 1);
+}
@@ -0,0 +1,225 @@
+//! Controller (CC/NRPN) to register-field mapping tests for YM2151 converter
+use super::*;
+use crate::{ControllerMapping, ControllerSource, RegisterField};
+
+fn note_with(controllers: Vec<MidiEvent>) -> MidiData {
+    let mut events = vec![
+        MidiEvent::NoteOn {
+            ticks: 0,
+            channel: 0,
+            note: 60,
+            velocity: 100,
+            track: 0,
+            port: None,
+        },
+        MidiEvent::NoteOff {
+            ticks: 1920,
+            channel: 0,
+            note: 60,
+            track: 0,
+            port: None,
+        },
+    ];
+    events.extend(controllers);
+    events.sort_by_key(MidiEvent::ticks);
+    MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events,
+        metadata: MidiMetadata::default(),
+    }
+}
+
+fn writes_after_init<'a>(result: &'a crate::ym2151::Ym2151Log, addr: &str) -> Vec<(f64, &'a str)> {
+    result
+        .events
+        .iter()
+        .filter(|e| e.addr == addr && e.time > 0.0)
+        .map(|e| (e.time, e.data.as_str()))
+        .collect()
+}
+
+#[test]
+fn test_cc_maps_to_modulator_tl_range() {
+    let json = br#"{
+      "ControllerMappings": [
+        { "Source": { "Cc": 74 }, "Field": "TL", "Operators": [0, 1], "Range": [40, 10] }
+      ]
+    }"#;
+    let options = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+    let cc74 = |ticks, value| MidiEvent::ControlChange {
+        ticks,
+        channel: 0,
+        controller: 74,
+        value,
+        track: 0,
+        port: None,
+    };
+    let midi_data = note_with(vec![cc74(480, 0), cc74(960, 127)]);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    // CC 0 -> 40 (0x28), CC 127 -> 10 (0x0A) on M1 and M2 only
+    assert_eq!(
+        writes_after_init(&result, "0x60"),
+        vec![(0.5, "0x28"), (1.0, "0x0A")]
+    );
+    assert_eq!(
+        writes_after_init(&result, "0x68"),
+        vec![(0.5, "0x28"), (1.0, "0x0A")]
+    );
+    assert!(writes_after_init(&result, "0x70").is_empty());
+}
+
+#[test]
+fn test_nrpn_maps_to_packed_field_keeping_neighbours() {
+    let options = ConversionOptions {
+        controller_mappings: vec![
+            ControllerMapping {
+                source: ControllerSource::Nrpn(0x10),
+                channel: None,
+                field: RegisterField::Ks,
+                operators: vec![0],
+                range: None,
+            },
+            ControllerMapping {
+                source: ControllerSource::Cc(20),
+                channel: None,
+                field: RegisterField::Ar,
+                operators: vec![0],
+                range: None,
+            },
+        ],
+        ..ConversionOptions::default()
+    };
+    let midi_data = note_with(vec![
+        MidiEvent::Nrpn {
+            ticks: 480,
+            channel: 0,
+            parameter: 0x10,
+            value: 0x3FFF,
+            track: 0,
+            port: None,
+        },
+        MidiEvent::ControlChange {
+            ticks: 960,
+            channel: 0,
+            controller: 20,
+            value: 0,
+            track: 0,
+            port: None,
+        },
+    ]);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    // Init KS/AR is 0x1F: KS=3 keeps AR=31, then AR=0 keeps KS=3
+    assert_eq!(
+        writes_after_init(&result, "0x80"),
+        vec![(0.5, "0xDF"), (1.0, "0xC0")]
+    );
+}
+
+#[test]
+fn test_mapping_channel_filter() {
+    let options = ConversionOptions {
+        controller_mappings: vec![ControllerMapping {
+            source: ControllerSource::Cc(74),
+            channel: Some(1),
+            field: RegisterField::Fb,
+            operators: vec![],
+            range: None,
+        }],
+        ..ConversionOptions::default()
+    };
+    let midi_data = note_with(vec![MidiEvent::ControlChange {
+        ticks: 480,
+        channel: 0,
+        controller: 74,
+        value: 127,
+        track: 0,
+        port: None,
+    }]);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+    assert!(writes_after_init(&result, "0x20").is_empty());
+}
+
+#[test]
+fn test_carrier_tl_mapping_combines_with_channel_volume() {
+    let json = br#"{
+      "ControllerMappings": [
+        { "Source": { "Cc": 74 }, "Field": "TL", "Operators": [3], "Range": [0, 40] }
+      ]
+    }"#;
+    let options = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+    let cc = |ticks, controller, value| MidiEvent::ControlChange {
+        ticks,
+        channel: 0,
+        controller,
+        value,
+        track: 0,
+        port: None,
+    };
+    // CC7 = 64 attenuates carriers by 16 TL steps
+    let midi_data = note_with(vec![cc(0, 7, 64), cc(480, 74, 64), cc(960, 7, 127)]);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    // Mapped TL 20 plus 16 steps of volume, then the mapped TL alone at full volume
+    assert_eq!(
+        writes_after_init(&result, "0x78"),
+        vec![(0.5, "0x24"), (1.0, "0x14")]
+    );
+}
+
+#[test]
+fn test_fb_mapping_survives_a_later_pan() {
+    let json = br#"{
+      "ControllerMappings": [{ "Source": { "Cc": 74 }, "Field": "FB" }]
+    }"#;
+    let options = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+    let cc = |ticks, controller, value| MidiEvent::ControlChange {
+        ticks,
+        channel: 0,
+        controller,
+        value,
+        track: 0,
+        port: None,
+    };
+    let midi_data = note_with(vec![cc(480, 74, 127), cc(960, 10, 0)]);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    // FB 7 is kept when the pan rewrites the speaker bits
+    let writes = writes_after_init(&result, "0x20");
+    assert_eq!(writes.len(), 2);
+    for (time, data) in writes {
+        let data = u8::from_str_radix(data.trim_start_matches("0x"), 16).unwrap();
+        assert_eq!(data & 0x38, 0x38, "FB lost at {time}");
+    }
+}
+
+#[test]
+fn test_con_mapping_changes_which_operators_volume_levels() {
+    let json = br#"{
+      "ControllerMappings": [{ "Source": { "Cc": 75 }, "Field": "CON", "Range": [0, 0] }]
+    }"#;
+    let options = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+    let cc = |ticks, controller, value| MidiEvent::ControlChange {
+        ticks,
+        channel: 0,
+        controller,
+        value,
+        track: 0,
+        port: None,
+    };
+    let midi_data = note_with(vec![cc(480, 75, 127), cc(960, 7, 64)]);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    // With CON 0 only C2 is a carrier, so the volume change leaves M1 alone
+    assert!(writes_after_init(&result, "0x60").is_empty());
+}
@@ -0,0 +1,135 @@
+//! Drum map tests for YM2151 converter
+use super::*;
+
+fn writes_at<'a>(events: &'a [Ym2151Event], time: f64, addr: &str) -> Vec<&'a str> {
+    events
+        .iter()
+        .filter(|e| e.time == time && e.addr == addr)
+        .map(|e| e.data.as_str())
+        .collect()
+}
+
+/// Key-on writes at `time`
+fn key_on_writes_at(events: &[Ym2151Event], time: f64) -> Vec<&str> {
+    writes_at(events, time, "0x08")
+        .into_iter()
+        .filter(|data| u8::from_str_radix(&data[2..], 16).unwrap() & 0x78 != 0)
+        .collect()
+}
+
+const KICK_TONE: &str = r#"{ "events": [{ "time": 0, "addr": "0x20", "data": "0xC4" }] }"#;
+const SNARE_TONE: &str = r#"{ "events": [{ "time": 0, "addr": "0x20", "data": "0xFA" }] }"#;
+
+#[test]
+fn test_drum_note_plays_at_fixed_pitch() {
+    let events = vec![note_on(0, 9, 36, 100), note_off(240, 9, 36)];
+
+    let result = convert(events, r#"{ "DrumMap": { "36": { "Kc": 16, "Kf": 8 } } }"#).unwrap();
+
+    assert_eq!(writes_at(&result, 0.0, "0x28").last(), Some(&"0x10"));
+    assert_eq!(writes_at(&result, 0.0, "0x30").last(), Some(&"0x08"));
+}
+
+#[test]
+fn test_unmapped_drum_note_keeps_its_pitch() {
+    let events = vec![note_on(0, 9, 60, 100)];
+
+    let result = convert(events, r#"{ "DrumMap": { "36": { "Kc": 16 } } }"#).unwrap();
+
+    let (kc, _) = midi_to_kc_kf(60);
+    assert_eq!(
+        writes_at(&result, 0.0, "0x28").last(),
+        Some(&format!("0x{kc:02X}").as_str())
+    );
+}
+
+#[test]
+fn test_drum_tones_load_per_note() {
+    let events = vec![
+        note_on(480, 9, 36, 100),
+        note_off(600, 9, 36),
+        note_on(960, 9, 36, 100),
+        note_off(1080, 9, 36),
+        note_on(1440, 9, 38, 100),
+        note_off(1560, 9, 38),
+        note_on(1920, 9, 40, 100),
+    ];
+    let attachment = format!(
+        r#"{{ "DrumMap": {{ "36": {{ "Tone": {KICK_TONE} }}, "38": {{ "Tone": {SNARE_TONE} }} }} }}"#
+    );
+
+    let result = convert(events, &attachment).unwrap();
+
+    assert_eq!(writes_at(&result, 0.5, "0x20"), ["0xC4"]);
+    assert!(
+        writes_at(&result, 1.0, "0x20").is_empty(),
+        "the kick tone is still loaded"
+    );
+    assert_eq!(writes_at(&result, 1.5, "0x20"), ["0xFA"]);
+    let restored = writes_at(&result, 2.0, "0x20");
+    assert_eq!(
+        restored.len(),
+        1,
+        "an unmapped note restores the program tone"
+    );
+    assert_eq!(restored, writes_at(&result, 0.0, "0x20"));
+}
+
+#[test]
+fn test_drum_key_on_mask_selects_operators() {
+    let events = vec![note_on(0, 9, 42, 100)];
+
+    let result = convert(events, r#"{ "DrumMap": { "42": { "KeyOnMask": 8 } } }"#).unwrap();
+
+    assert_eq!(key_on_writes_at(&result, 0.0), ["0x40"]);
+}
+
+#[test]
+fn test_drum_voice_group_shares_one_voice() {
+    let events = vec![
+        note_on(0, 9, 36, 100),
+        note_on(0, 9, 38, 100),
+        note_on(0, 9, 42, 100),
+        note_off(240, 9, 36),
+        note_off(240, 9, 38),
+        note_off(240, 9, 42),
+        note_on(480, 9, 36, 100),
+        note_on(480, 9, 46, 100),
+    ];
+
+    let result = convert(
+        events,
+        r#"{ "DrumMap": { "42": { "Group": 1 }, "46": { "Group": 1 } } }"#,
+    )
+    .unwrap();
+
+    let closed_hat = key_on_writes_at(&result, 0.0)[2];
+    let open_hat = key_on_writes_at(&result, 0.5)[1];
+    assert_eq!(open_hat, closed_hat);
+}
+
+#[test]
+fn test_pitch_bend_leaves_fixed_pitch_drums_alone() {
+    let events = vec![
+        note_on(0, 9, 36, 100),
+        MidiEvent::PitchBend {
+            ticks: 240,
+            channel: 9,
+            value: 4096,
+            track: 0,
+            port: None,
+        },
+    ];
+
+    let result = convert(events, r#"{ "DrumMap": { "36": { "Kc": 16 } } }"#).unwrap();
+
+    assert!(writes_at(&result, 0.25, "0x28").is_empty());
+}
+
+#[test]
+fn test_drum_map_rejects_empty_key_on_mask() {
+    let result = ConversionOptions::from_attachment_bytes(Some(
+        br#"{ "DrumMap": { "42": { "KeyOnMask": 0 } } }"#,
+    ));
+    assert!(result.is_err());
+}
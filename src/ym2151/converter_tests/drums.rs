@@ -5,7 +5,9 @@ use super::*;
 fn test_convert_drum_channel_note_on_channel_0() {
     // Test that MIDI channel 9 (drum) maps to YM2151 channel 0
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -13,13 +15,18 @@ fn test_convert_drum_channel_note_on_channel_0() {
                 channel: 9, // Drum channel
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 9,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -50,7 +57,9 @@ fn test_convert_drum_channel_note_on_channel_0() {
 fn test_convert_drum_and_regular_channels_together() {
     // Test with both drum channel and regular channels
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             // Drum channel (MIDI 9) at same tick
@@ -59,6 +68,8 @@ fn test_convert_drum_and_regular_channels_together() {
                 channel: 9,
                 note: 36, // Bass drum
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             // Regular channel (MIDI 0) at same tick
             MidiEvent::NoteOn {
@@ -66,6 +77,8 @@ fn test_convert_drum_and_regular_channels_together() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             // Regular channel (MIDI 1) at same tick
             MidiEvent::NoteOn {
@@ -73,23 +86,32 @@ fn test_convert_drum_and_regular_channels_together() {
                 channel: 1,
                 note: 64,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 9,
                 note: 36,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 1,
                 note: 64,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -4,7 +4,9 @@ use super::*;
 #[test]
 fn test_delay_vibrato_generates_additional_pitch_events() {
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -12,13 +14,18 @@ fn test_delay_vibrato_generates_additional_pitch_events() {
                 channel: 0,
                 note: 69, // A4 (440 Hz)
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 1920, // 2 seconds at 120 BPM
                 channel: 0,
                 note: 69,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let options = ConversionOptions {
@@ -57,7 +64,9 @@ fn test_pop_noise_envelope_adds_pre_note_overrides() {
     // time as the key-on for note 2 (t=0.5s).  PopNoiseEnvelope should move
     // that key-off to apply_time so the envelope decays before note 2 starts.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -65,24 +74,33 @@ fn test_pop_noise_envelope_adds_pre_note_overrides() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480, // ends at t=0.5s — same tick as note 2 start (back-to-back)
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 480,
                 channel: 0,
                 note: 64,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 960,
                 channel: 0,
                 note: 64,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let options = ConversionOptions {
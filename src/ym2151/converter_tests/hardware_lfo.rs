@@ -0,0 +1,87 @@
+//! Hardware LFO tests for YM2151 converter
+use super::*;
+
+#[test]
+fn test_hardware_lfo_is_programmed_once_at_start() {
+    let events = convert(
+        vec![note_on(0, 0, 60, 100), note_on(480, 0, 60, 100)],
+        r#"{ "HardwareLfo": { "Rate": 200, "Waveform": "square", "Pmd": 20, "Amd": 10 } }"#,
+    )
+    .unwrap();
+
+    assert_eq!(writes_to(&events, "0x18"), [(0.0, "0xC8")]);
+    assert_eq!(writes_to(&events, "0x1B"), [(0.0, "0x01")]);
+    assert_eq!(writes_to(&events, "0x19"), [(0.0, "0x0A"), (0.0, "0x94")]);
+    assert!(writes_to(&events, "0x01").is_empty());
+}
+
+#[test]
+fn test_hardware_lfo_replaces_modulation_wheel_lfo() {
+    let events = convert(
+        vec![note_on(0, 0, 60, 100)],
+        r#"{
+            "ModulationWheel": { "Mode": "hardware" },
+            "HardwareLfo": { "Rate": 100, "Pmd": 127 }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(writes_to(&events, "0x18"), [(0.0, "0x64")]);
+}
+
+#[test]
+fn test_lfo_is_reset_before_each_key_on() {
+    let events = convert(
+        vec![note_on(0, 0, 60, 100)],
+        r#"{ "HardwareLfo": { "Rate": 200, "Pmd": 20, "ResetOnKeyOn": true } }"#,
+    )
+    .unwrap();
+
+    let reset: Vec<&str> = events
+        .iter()
+        .filter(|e| e.addr == "0x01")
+        .map(|e| e.data.as_str())
+        .collect();
+    assert_eq!(reset, ["0x02", "0x00"]);
+    let reset_end = events.iter().rposition(|e| e.addr == "0x01").unwrap();
+    let key_on = events
+        .iter()
+        .rposition(|e| e.addr == "0x08" && e.data == "0x78")
+        .unwrap();
+    assert_eq!(reset_end + 1, key_on);
+}
+
+#[test]
+fn test_program_lfo_sensitivity_is_written_with_its_tone() {
+    let events = convert(
+        vec![
+            note_on(0, 0, 60, 100),
+            MidiEvent::ProgramChange {
+                ticks: 480,
+                channel: 0,
+                program: 1,
+                track: 0,
+                port: None,
+            },
+        ],
+        r#"[
+            { "ProgramChange": 0, "Pms": 3, "Ams": 1 },
+            { "ProgramChange": 1, "Pms": 5 }
+        ]"#,
+    )
+    .unwrap();
+
+    let sensitivity = writes_to(&events, "0x38");
+    assert_eq!(sensitivity.first(), Some(&(0.0, "0x00")));
+    assert!(sensitivity.contains(&(0.0, "0x31")));
+    assert_eq!(sensitivity.last().map(|(time, _)| *time), Some(0.5));
+    let last = u8::from_str_radix(&sensitivity.last().unwrap().1[2..], 16).unwrap();
+    assert_eq!(last & 0x70, 0x50);
+}
+
+#[test]
+fn test_lfo_sensitivity_out_of_range_is_rejected() {
+    let result =
+        ConversionOptions::from_attachment_bytes(Some(br#"[{ "ProgramChange": 0, "Pms": 8 }]"#));
+    assert!(result.is_err());
+}
@@ -1,12 +1,15 @@
 //! Software LFO tests for YM2151 converter
 use super::*;
+use crate::ym2151::parse_hex_byte;
 
 #[test]
 fn test_register_lfo_triangle_wave_smooth_transitions() {
     // Verify that the triangle LFO produces intermediate values (not just top/center/bottom)
     // and that consecutive register values differ by at most 1.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -14,23 +17,31 @@ fn test_register_lfo_triangle_wave_smooth_transitions() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 1920, // 2 seconds at 120 BPM
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let options = ConversionOptions {
         software_lfo: vec![RegisterLfoDefinition {
+            target: LfoTarget::Register,
             base_register: "0x60".to_string(),
             depth: 6.0,
             rate_hz: 4.0,
             delay_seconds: 0.0,
             attack_seconds: 0.0,
             waveform: LfoWaveform::Triangle,
+            duty: 0.5,
+            seed: 0,
             key_on_sync: true,
         }],
         ..ConversionOptions::default()
@@ -80,7 +91,9 @@ fn test_register_lfo_triangle_wave_smooth_transitions() {
 #[test]
 fn test_register_lfo_modulates_tone_register() {
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -88,34 +101,46 @@ fn test_register_lfo_modulates_tone_register() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 240,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 0,
                 channel: 1,
                 note: 64,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 1,
                 note: 64,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let options = ConversionOptions {
         software_lfo: vec![RegisterLfoDefinition {
+            target: LfoTarget::Register,
             base_register: "0x60".to_string(),
             depth: 4.0,
             rate_hz: 2.0,
             delay_seconds: 0.0,
             attack_seconds: 0.0,
             waveform: LfoWaveform::Triangle,
+            duty: 0.5,
+            seed: 0,
             key_on_sync: true,
         }],
         ..ConversionOptions::default()
@@ -153,7 +178,9 @@ fn test_register_lfo_key_on_sync_false_generates_smooth_events_across_notes() {
     // phase were reset it would equal base_value+0 (same as the song start value).
     // The default TL for 0x60 (channel 0, operator 0) is 0x00, so values are 4 vs 0.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -161,24 +188,33 @@ fn test_register_lfo_key_on_sync_false_generates_smooth_events_across_notes() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 240, // 0.25 seconds at 120 BPM
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 240,
                 channel: 0,
                 note: 64,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480, // 0.5 seconds at 120 BPM
                 channel: 0,
                 note: 64,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     // Use key_on_sync=false so LFO runs continuously.
@@ -186,12 +222,15 @@ fn test_register_lfo_key_on_sync_false_generates_smooth_events_across_notes() {
     // A key_on_sync=true reset restarts at phase=0 (offset=0), giving a different value.
     let options = ConversionOptions {
         software_lfo: vec![RegisterLfoDefinition {
+            target: LfoTarget::Register,
             base_register: "0x60".to_string(),
             depth: 4.0,
             rate_hz: 1.0,
             delay_seconds: 0.0,
             attack_seconds: 0.0,
             waveform: LfoWaveform::Triangle,
+            duty: 0.5,
+            seed: 0,
             key_on_sync: false,
         }],
         ..ConversionOptions::default()
@@ -270,7 +309,9 @@ fn test_register_lfo_key_on_sync_false_emits_at_segment_start_with_gap() {
     // which falls between grid points 0.1875 and 0.25. The fix ensures an event
     // is emitted exactly at 0.2083s rather than waiting until 0.25s.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -278,34 +319,46 @@ fn test_register_lfo_key_on_sync_false_emits_at_segment_start_with_gap() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 100, // ~0.1042s
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 200, // ~0.2083s (between grid points 0.1875 and 0.25)
                 channel: 0,
                 note: 64,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 400, // ~0.4167s
                 channel: 0,
                 note: 64,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let options = ConversionOptions {
         software_lfo: vec![RegisterLfoDefinition {
+            target: LfoTarget::Register,
             base_register: "0x60".to_string(),
             depth: 4.0,
             rate_hz: 1.0,
             delay_seconds: 0.0,
             attack_seconds: 0.0,
             waveform: LfoWaveform::Triangle,
+            duty: 0.5,
+            seed: 0,
             key_on_sync: false,
         }],
         ..ConversionOptions::default()
@@ -383,3 +436,150 @@ fn test_register_lfo_key_on_sync_false_deserializes() {
         "key_on_sync should be false when KeyOnSync: false is specified"
     );
 }
+
+/// (time, value) of the LFO's 0x60 writes for one two-second note under `waveform`
+/// around the tone's TL of 0x20
+fn lfo_writes(waveform: LfoWaveform, duty: f64, seed: u64) -> Vec<(f64, u8)> {
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events: vec![
+            MidiEvent::NoteOn {
+                ticks: 0,
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOff {
+                ticks: 1920,
+                channel: 0,
+                note: 60,
+                track: 0,
+                port: None,
+            },
+        ],
+        metadata: MidiMetadata::default(),
+    };
+    let mut options = ConversionOptions::from_attachment_bytes(Some(
+        br#"{ "Tones": { "0": { "events": [{ "time": 0, "addr": "0x60", "data": "0x20" }] } } }"#,
+    ))
+    .unwrap();
+    options.software_lfo = vec![RegisterLfoDefinition {
+        target: LfoTarget::Register,
+        base_register: "0x60".to_string(),
+        depth: 6.0,
+        rate_hz: 2.0,
+        delay_seconds: 0.0,
+        attack_seconds: 0.0,
+        waveform,
+        duty,
+        seed,
+        key_on_sync: true,
+    }];
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+    let writes: Vec<(f64, u8)> = result
+        .events
+        .iter()
+        .filter(|e| e.addr == "0x60" && e.time < 2.0)
+        .map(|e| (e.time, parse_hex_byte(&e.data).unwrap()))
+        .collect();
+    let tone_write = writes.iter().position(|&w| w == (0.0, 0x20)).unwrap();
+    writes[tone_write + 1..].to_vec()
+}
+
+#[test]
+fn test_register_lfo_square_jumps_at_duty_edges() {
+    let lfo = lfo_writes(LfoWaveform::Square, 0.25, 0);
+    let (high, low) = (0x20 + 6, 0x20 - 6);
+
+    // Only the jumps are written, exactly at 0.125s (duty) and 0.5s (period) edges
+    let expected: Vec<(f64, u8)> = (0..4)
+        .flat_map(|period| {
+            let start = period as f64 * 0.5;
+            [(start, high), (start + 0.125, low)]
+        })
+        .collect();
+    assert_eq!(lfo.len(), expected.len(), "writes: {lfo:?}");
+    for ((time, value), (expected_time, expected_value)) in lfo.iter().zip(&expected) {
+        assert!((time - expected_time).abs() < 1e-6, "writes: {lfo:?}");
+        assert_eq!(value, expected_value);
+    }
+}
+
+#[test]
+fn test_register_lfo_sample_and_hold_steps_once_per_period() {
+    let writes = lfo_writes(LfoWaveform::SampleAndHold, 0.5, 7);
+
+    for (time, value) in &writes {
+        let periods = time * 2.0;
+        assert!(
+            (periods - periods.round()).abs() < 1e-6,
+            "a held level only changes at a period start, got a write at {time}"
+        );
+        assert!(value.abs_diff(0x20) <= 6);
+    }
+    assert!(writes.len() > 1, "the level should change between periods");
+
+    // The sequence repeats for a seed and differs for another
+    assert_eq!(writes, lfo_writes(LfoWaveform::SampleAndHold, 0.5, 7));
+    assert_ne!(writes, lfo_writes(LfoWaveform::SampleAndHold, 0.5, 8));
+}
+
+#[test]
+fn test_register_lfo_ramp_wraps_in_one_write() {
+    let lfo = lfo_writes(LfoWaveform::RampDown, 0.5, 0);
+
+    for window in lfo.windows(2) {
+        let ((_, previous), (time, value)) = (window[0], window[1]);
+        if value > previous {
+            // The wrap from the bottom back to the top is a single write at the period start
+            let periods = time * 2.0;
+            assert!((periods - periods.round()).abs() < 1e-6);
+            assert!(value - previous >= 11);
+        } else {
+            assert_eq!(previous - value, 1, "the ramp falls one step at a time");
+        }
+    }
+}
+
+#[test]
+fn test_register_lfo_sine_transitions_are_smooth() {
+    let writes = lfo_writes(LfoWaveform::Sine, 0.5, 0);
+    let values: Vec<u8> = writes.iter().map(|&(_, value)| value).collect();
+
+    assert!(values.len() > 12);
+    for window in values.windows(2) {
+        assert!(window[0].abs_diff(window[1]) <= 1);
+    }
+}
+
+#[test]
+fn test_register_lfo_waveforms_from_attachment() {
+    let options = ConversionOptions::from_attachment_bytes(Some(
+        br#"{ "SoftwareLfo": [{ "BaseRegister": "0x60", "RateHz": 4.0, "Waveform": "square", "Duty": 0.2 },
+                              { "BaseRegister": "0x68", "RateHz": 1.0, "Waveform": "sample_and_hold", "Seed": 3 },
+                              { "BaseRegister": "0x70", "RateHz": 1.0, "Waveform": "ramp_up" }] }"#,
+    ))
+    .unwrap();
+    let waveforms: Vec<LfoWaveform> = options.software_lfo.iter().map(|d| d.waveform).collect();
+    assert_eq!(
+        waveforms,
+        [
+            LfoWaveform::Square,
+            LfoWaveform::SampleAndHold,
+            LfoWaveform::RampUp
+        ]
+    );
+    assert_eq!(options.software_lfo[0].duty, 0.2);
+    assert_eq!(options.software_lfo[1].seed, 3);
+
+    let result = ConversionOptions::from_attachment_bytes(Some(
+        br#"[{ "ProgramChange": 0, "SoftwareLfo": [{ "BaseRegister": "0x60", "RateHz": 4.0, "Waveform": "square", "Duty": 1.0 }] }]"#,
+    ));
+    assert!(result.is_err(), "a duty of 1.0 never goes low");
+}
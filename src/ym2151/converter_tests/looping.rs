@@ -0,0 +1,196 @@
+//! Loop point and loop unrolling tests for YM2151 converter
+use super::*;
+use crate::LoopOptions;
+
+/// Intro note, loop body from 0.5 s to 1.0 s, then a tail note
+fn looped_midi_data() -> MidiData {
+    let note = |ticks: u32, note: u8| {
+        [
+            MidiEvent::NoteOn {
+                ticks,
+                channel: 0,
+                note,
+                velocity: 100,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOff {
+                ticks: ticks + 240,
+                channel: 0,
+                note,
+                track: 0,
+                port: None,
+            },
+        ]
+    };
+    let mut events = vec![
+        MidiEvent::LoopStart {
+            ticks: 480,
+            track: 0,
+            port: None,
+        },
+        MidiEvent::LoopEnd {
+            ticks: 960,
+            track: 0,
+            port: None,
+        },
+    ];
+    events.extend(note(0, 60));
+    events.extend(note(480, 64));
+    events.extend(note(960, 67));
+    events.sort_by_key(MidiEvent::ticks);
+    MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events,
+        metadata: MidiMetadata::default(),
+    }
+}
+
+fn loop_options(count: u32, fade_out_seconds: Option<f64>) -> ConversionOptions {
+    ConversionOptions {
+        loop_options: Some(LoopOptions {
+            count,
+            fade_out_seconds,
+        }),
+        ..ConversionOptions::default()
+    }
+}
+
+fn key_on_times(events: &[Ym2151Event]) -> Vec<f64> {
+    events
+        .iter()
+        .filter(|e| e.addr == "0x08" && e.data == "0x78")
+        .map(|e| e.time)
+        .collect()
+}
+
+#[test]
+fn test_loop_point_records_times_and_registers() {
+    let result = convert_to_ym2151_log(&looped_midi_data()).unwrap();
+    let loop_point = result
+        .loop_point
+        .expect("Loop markers should set a loop point");
+
+    assert!((loop_point.start_time - 0.5).abs() < 1e-9);
+    assert!((loop_point.end_time - 1.0).abs() < 1e-9);
+    assert!(
+        loop_point.registers.iter().any(|e| e.addr == "0x28"),
+        "Loop start state should include the last KC write"
+    );
+    assert!(
+        loop_point.registers.iter().all(|e| e.addr != "0x08"),
+        "Key on/off writes are not part of the register state"
+    );
+    assert!(loop_point
+        .registers
+        .iter()
+        .all(|e| (e.time - 0.5).abs() < 1e-9));
+}
+
+#[test]
+fn test_no_loop_markers_has_no_loop_point() {
+    let mut midi_data = looped_midi_data();
+    midi_data
+        .events
+        .retain(|e| !matches!(e, MidiEvent::LoopStart { .. } | MidiEvent::LoopEnd { .. }));
+    let result = convert_to_ym2151_log(&midi_data).unwrap();
+
+    assert!(result.loop_point.is_none());
+    let json = serde_json::to_string(&result).unwrap();
+    assert!(!json.contains("loop_point"));
+}
+
+#[test]
+fn test_loop_count_unrolls_loop_body() {
+    let result =
+        convert_to_ym2151_log_with_options(&looped_midi_data(), &loop_options(3, None)).unwrap();
+    let times = key_on_times(&result.events);
+
+    assert_eq!(times.len(), 5, "Intro, three loop passes and the tail");
+    for (time, expected) in times.iter().zip([0.0, 0.5, 1.0, 1.5, 2.0]) {
+        assert!((time - expected).abs() < 1e-9, "Key on at {}", time);
+    }
+    // The loop point moves to the last pass
+    let loop_point = result.loop_point.unwrap();
+    assert!((loop_point.start_time - 1.5).abs() < 1e-9);
+    assert!((loop_point.end_time - 2.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_loop_jump_restores_loop_start_tempo() {
+    let mut midi_data = looped_midi_data();
+    midi_data.events.push(MidiEvent::Tempo {
+        ticks: 720,
+        tempo_bpm: 60.0,
+        track: 0,
+        port: None,
+    });
+    midi_data.events.sort_by_key(MidiEvent::ticks);
+    let result = convert_to_ym2151_log_with_options(&midi_data, &loop_options(2, None)).unwrap();
+    let times = key_on_times(&result.events);
+
+    // Each pass plays 240 ticks at 120 BPM, then 240 ticks at 60 BPM
+    assert_eq!(times.len(), 4);
+    for (time, expected) in times.iter().zip([0.0, 0.5, 1.25, 2.0]) {
+        assert!((time - expected).abs() < 1e-9, "Key on at {}", time);
+    }
+}
+
+#[test]
+fn test_loop_jump_restores_loop_start_pitch_bend() {
+    let mut midi_data = looped_midi_data();
+    midi_data.events.push(MidiEvent::PitchBend {
+        ticks: 600,
+        channel: 0,
+        value: 8191,
+        track: 0,
+        port: None,
+    });
+    midi_data.events.sort_by_key(MidiEvent::ticks);
+    let result = convert_to_ym2151_log_with_options(&midi_data, &loop_options(2, None)).unwrap();
+    let kc_at = |time: f64| {
+        result
+            .events
+            .iter()
+            .rfind(|e| e.addr == "0x28" && (e.time - time).abs() < 1e-9)
+            .map(|e| e.data.clone())
+            .unwrap()
+    };
+
+    assert_eq!(kc_at(1.0), kc_at(0.5), "The second pass starts unbent");
+}
+
+#[test]
+fn test_loop_count_overflowing_ticks_is_rejected() {
+    let result =
+        convert_to_ym2151_log_with_options(&looped_midi_data(), &loop_options(u32::MAX, None));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_loop_fade_out_silences_last_pass() {
+    let result =
+        convert_to_ym2151_log_with_options(&looped_midi_data(), &loop_options(2, Some(0.4)))
+            .unwrap();
+    let times = key_on_times(&result.events);
+    assert_eq!(times.len(), 3, "The tail is dropped when fading out");
+    assert!(
+        result.loop_point.is_none(),
+        "A faded-out song does not loop"
+    );
+
+    // Default tone uses CON 7 with only M1 audible
+    let last_tl = result.events.iter().rfind(|e| e.addr == "0x60").unwrap();
+    assert!((last_tl.time - 1.5).abs() < 1e-9);
+    assert_eq!(last_tl.data, "0x7F");
+
+    let fading = result
+        .events
+        .iter()
+        .filter(|e| e.addr == "0x60" && e.time > 1.1 && e.time < 1.5)
+        .count();
+    assert!(fading > 1, "Fade should step the carrier level down");
+}
@@ -0,0 +1,162 @@
+//! Modulation wheel (CC1) vibrato tests for YM2151 converter
+use super::*;
+use crate::{ModulationWheel, ModulationWheelMode};
+
+/// A4 held for 2 seconds with the wheel raised at 1 second
+fn wheel_midi_data(extra: Vec<MidiEvent>) -> MidiData {
+    let mut events = vec![
+        MidiEvent::NoteOn {
+            ticks: 0,
+            channel: 0,
+            note: 69,
+            velocity: 100,
+            track: 0,
+            port: None,
+        },
+        MidiEvent::ControlChange {
+            ticks: 960,
+            channel: 0,
+            controller: 1,
+            value: 64,
+            track: 0,
+            port: None,
+        },
+        MidiEvent::NoteOff {
+            ticks: 1920,
+            channel: 0,
+            note: 69,
+            track: 0,
+            port: None,
+        },
+    ];
+    events.extend(extra);
+    events.sort_by_key(MidiEvent::ticks);
+    MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events,
+        metadata: MidiMetadata::default(),
+    }
+}
+
+fn wheel_options(mode: ModulationWheelMode) -> ConversionOptions {
+    ConversionOptions {
+        modulation_wheel: Some(ModulationWheel {
+            mode,
+            max_depth: None,
+        }),
+        ..ConversionOptions::default()
+    }
+}
+
+#[test]
+fn test_software_wheel_vibrato_starts_with_cc1() {
+    let options = wheel_options(ModulationWheelMode::Software);
+    let result = convert_to_ym2151_log_with_options(&wheel_midi_data(vec![]), &options).unwrap();
+
+    let pitch_writes = |from: f64, to: f64| {
+        result
+            .events
+            .iter()
+            .filter(|e| (e.addr == "0x28" || e.addr == "0x30") && e.time > from && e.time < to)
+            .count()
+    };
+    assert_eq!(
+        pitch_writes(0.0, 1.0),
+        0,
+        "No vibrato before the wheel moves"
+    );
+    assert!(pitch_writes(1.0, 2.0) > 0, "Vibrato once CC1 is raised");
+    // Hardware LFO stays untouched in software mode
+    assert!(!result.events.iter().any(|e| e.addr == "0x19"));
+}
+
+#[test]
+fn test_software_wheel_depth_scales_with_max_depth() {
+    let span = |max_depth: f64| {
+        let options = ConversionOptions {
+            modulation_wheel: Some(ModulationWheel {
+                mode: ModulationWheelMode::Software,
+                max_depth: Some(max_depth),
+            }),
+            ..ConversionOptions::default()
+        };
+        let result =
+            convert_to_ym2151_log_with_options(&wheel_midi_data(vec![]), &options).unwrap();
+        let kcs: Vec<u8> = result
+            .events
+            .iter()
+            .filter(|e| e.addr == "0x28" && e.time > 1.0)
+            .map(|e| u8::from_str_radix(e.data.trim_start_matches("0x"), 16).unwrap())
+            .collect();
+        kcs.iter().max().unwrap() - kcs.iter().min().unwrap()
+    };
+    assert!(span(400.0) > span(100.0));
+}
+
+#[test]
+fn test_hardware_wheel_sets_pms_and_survives_program_change() {
+    let options = wheel_options(ModulationWheelMode::Hardware);
+    let midi_data = wheel_midi_data(vec![MidiEvent::ProgramChange {
+        ticks: 1440,
+        channel: 0,
+        program: 1,
+        track: 0,
+        port: None,
+    }]);
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    // Global LFO setup with full PMD
+    let pmd = result.events.iter().find(|e| e.addr == "0x19").unwrap();
+    assert_eq!((pmd.time, pmd.data.as_str()), (0.0, "0xFF"));
+
+    // CC1 = 64 of max PMS 7 -> PMS 4; the reloaded tone keeps it
+    let pms_writes: Vec<_> = result
+        .events
+        .iter()
+        .filter(|e| e.addr == "0x38" && e.time > 0.0)
+        .map(|e| (e.time, e.data.as_str()))
+        .collect();
+    assert_eq!(pms_writes, vec![(1.0, "0x40"), (1.5, "0x40")]);
+
+    // No software pitch writes after the note-on
+    assert!(!result
+        .events
+        .iter()
+        .any(|e| e.addr == "0x28" && e.time > 0.0));
+}
+
+#[test]
+fn test_software_wheel_vibrato_keeps_pitch_bend() {
+    // Bend up a whole tone before the wheel moves
+    let bend = MidiEvent::PitchBend {
+        ticks: 480,
+        channel: 0,
+        value: 8191,
+        track: 0,
+        port: None,
+    };
+    let options = wheel_options(ModulationWheelMode::Software);
+    let result =
+        convert_to_ym2151_log_with_options(&wheel_midi_data(vec![bend]), &options).unwrap();
+
+    let mut pitch = (0, 0);
+    let mut vibrato_pitches = Vec::new();
+    for event in &result.events {
+        match event.addr.as_str() {
+            "0x28" => pitch.0 = u8::from_str_radix(&event.data[2..], 16).unwrap(),
+            "0x30" => pitch.1 = u8::from_str_radix(&event.data[2..], 16).unwrap(),
+            _ => continue,
+        }
+        if event.time > 1.0 && event.time < 2.0 {
+            vibrato_pitches.push(pitch);
+        }
+    }
+    assert!(!vibrato_pitches.is_empty());
+    assert!(
+        vibrato_pitches.iter().all(|&p| p >= midi_to_kc_kf(70)),
+        "vibrato should swing around the bent note, got {vibrato_pitches:?}"
+    );
+}
@@ -0,0 +1,116 @@
+//! Noise generator tests for YM2151 converter
+use super::*;
+
+fn noise_writes_at(events: &[Ym2151Event], time: f64) -> Vec<&str> {
+    events
+        .iter()
+        .filter(|e| e.time == time && e.addr == "0x0F")
+        .map(|e| e.data.as_str())
+        .collect()
+}
+
+/// A full eight-note chord on MIDI channel 0 plus a snare hit
+fn chord_and_snare() -> Vec<MidiEvent> {
+    let mut events: Vec<MidiEvent> = (0..8).map(|i| note_on(0, 0, 60 + i, 100)).collect();
+    events.push(note_on(480, 9, 38, 100));
+    events
+}
+
+#[test]
+fn test_noise_drum_plays_on_channel_7() {
+    let events = convert(
+        chord_and_snare(),
+        r#"{ "DrumMap": { "38": { "Noise": 20 } } }"#,
+    )
+    .unwrap();
+
+    assert!(
+        !key_ons_at(&events, 0.0).contains(&7),
+        "channel 7 is kept for noise"
+    );
+    assert_eq!(key_ons_at(&events, 0.5), [7]);
+    assert_eq!(noise_writes_at(&events, 0.5), ["0x94"]);
+    let noise = events.iter().position(|e| e.addr == "0x0F").unwrap();
+    let key_on = events
+        .iter()
+        .position(|e| e.time == 0.5 && e.addr == "0x08")
+        .unwrap();
+    assert!(noise < key_on, "NE must be set before the key-on");
+}
+
+#[test]
+fn test_noise_program_plays_on_channel_7() {
+    let events = vec![
+        note_on(0, 0, 60, 100),
+        note_off(240, 0, 60),
+        MidiEvent::ProgramChange {
+            ticks: 480,
+            channel: 0,
+            program: 1,
+            track: 0,
+            port: None,
+        },
+        note_on(480, 0, 60, 100),
+    ];
+
+    let events = convert(events, r#"[{ "ProgramChange": 1, "Noise": 5 }]"#).unwrap();
+
+    assert_ne!(key_ons_at(&events, 0.0), [7]);
+    assert_eq!(key_ons_at(&events, 0.5), [7]);
+    assert_eq!(noise_writes_at(&events, 0.5), ["0x85"]);
+}
+
+#[test]
+fn test_noise_is_switched_off_for_a_tonal_note() {
+    let events = vec![
+        note_on(0, 9, 38, 100),
+        note_off(240, 9, 38),
+        note_on(480, 9, 36, 100),
+    ];
+
+    let events = convert(
+        events,
+        r#"{
+            "DrumMap": { "38": { "Noise": 20 } },
+            "ChannelAssignment": { "9": { "Ym2151Channels": [7] } }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(noise_writes_at(&events, 0.0), ["0x94"]);
+    assert_eq!(key_ons_at(&events, 0.5), [7]);
+    assert_eq!(noise_writes_at(&events, 0.5), ["0x00"]);
+}
+
+#[test]
+fn test_noise_with_dynamic_voices() {
+    let events = convert(
+        chord_and_snare(),
+        r#"{ "VoiceAllocation": {}, "DrumMap": { "38": { "Noise": 3 } } }"#,
+    )
+    .unwrap();
+
+    assert!(!key_ons_at(&events, 0.0).contains(&7));
+    assert_eq!(key_ons_at(&events, 0.5), [7]);
+    assert_eq!(noise_writes_at(&events, 0.5), ["0x83"]);
+}
+
+#[test]
+fn test_channel_7_pinned_to_a_tonal_part_conflicts_with_noise() {
+    let result = convert(
+        chord_and_snare(),
+        r#"{
+            "DrumMap": { "38": { "Noise": 20 } },
+            "ChannelAssignment": { "0": { "Ym2151Channels": [7] } }
+        }"#,
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_noise_frequency_out_of_range_is_rejected() {
+    let result = ConversionOptions::from_attachment_bytes(Some(
+        br#"{ "DrumMap": { "38": { "Noise": 32 } } }"#,
+    ));
+    assert!(result.is_err());
+}
@@ -0,0 +1,112 @@
+//! Pitch bend tests for YM2151 converter
+use super::*;
+
+#[test]
+fn test_pitch_bend_emits_kc_kf_for_sounding_note() {
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events: vec![
+            MidiEvent::NoteOn {
+                ticks: 0,
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::PitchBend {
+                ticks: 240,
+                channel: 0,
+                value: 4096, // +1 semitone with the default ±2 range
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOff {
+                ticks: 480,
+                channel: 0,
+                note: 60,
+                track: 0,
+                port: None,
+            },
+        ],
+        metadata: MidiMetadata::default(),
+    };
+
+    let result = convert_to_ym2151_log(&midi_data).unwrap();
+
+    let (expected_kc, _) = midi_to_kc_kf(61);
+    let bent_kc: Vec<_> = result
+        .events
+        .iter()
+        .filter(|e| e.addr == "0x28" && (e.time - 0.25).abs() < 1e-9)
+        .collect();
+    assert_eq!(bent_kc.len(), 1, "Pitch bend should write KC once");
+    assert_eq!(bent_kc[0].data, format!("0x{:02X}", expected_kc));
+}
+
+#[test]
+fn test_pitch_bend_only_affects_its_midi_channel() {
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events: vec![
+            MidiEvent::NoteOn {
+                ticks: 0,
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOn {
+                ticks: 0,
+                channel: 1,
+                note: 64,
+                velocity: 100,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::PitchBend {
+                ticks: 240,
+                channel: 1,
+                value: -8192,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOff {
+                ticks: 480,
+                channel: 0,
+                note: 60,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOff {
+                ticks: 480,
+                channel: 1,
+                note: 64,
+                track: 0,
+                port: None,
+            },
+        ],
+        metadata: MidiMetadata::default(),
+    };
+
+    let result = convert_to_ym2151_log(&midi_data).unwrap();
+
+    // MIDI ch 0 -> YM ch 0, MIDI ch 1 -> YM ch 1
+    let bend_writes: Vec<_> = result
+        .events
+        .iter()
+        .filter(|e| (e.time - 0.25).abs() < 1e-9)
+        .collect();
+    assert_eq!(bend_writes.len(), 2);
+    assert_eq!(bend_writes[0].addr, "0x29");
+    assert_eq!(bend_writes[1].addr, "0x31");
+    let (expected_kc, _) = midi_to_kc_kf(62);
+    assert_eq!(bend_writes[0].data, format!("0x{:02X}", expected_kc));
+}
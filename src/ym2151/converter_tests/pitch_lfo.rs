@@ -0,0 +1,188 @@
+//! Pitch-target software LFO tests for YM2151 converter
+use super::*;
+use crate::midi::midi_note_with_offset_to_kc_kf;
+
+/// (KC, KF) of YM2151 channel 0 once every write up to `time` is issued
+fn pitch_at(events: &[Ym2151Event], time: f64) -> (u8, u8) {
+    let mut pitch = (0, 0);
+    for event in events.iter().filter(|e| e.time <= time + 1e-9) {
+        match event.addr.as_str() {
+            "0x28" => pitch.0 = data_of(event),
+            "0x30" => pitch.1 = data_of(event),
+            _ => {}
+        }
+    }
+    pitch
+}
+
+/// Times of the KC writes on YM2151 channel 0 after note-on
+fn kc_write_times(events: &[Ym2151Event]) -> Vec<f64> {
+    events
+        .iter()
+        .filter(|e| e.addr == "0x28" && e.time > 0.0)
+        .map(|e| e.time)
+        .collect()
+}
+
+#[test]
+fn test_pitch_lfo_square_swings_around_the_note_in_cents() {
+    let events = vec![note_on(0, 0, 60, 100), note_off(1920, 0, 60)];
+    let options = options_from(
+        r#"{ "SoftwareLfo": [{ "Target": "pitch", "Depth": 100, "RateHz": 2.0, "Waveform": "square" }] }"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    // ±100 cents is a semitone either side, switching every quarter second
+    assert_eq!(pitch_at(&result.events, 0.1), midi_to_kc_kf(61));
+    assert_eq!(pitch_at(&result.events, 0.3), midi_to_kc_kf(59));
+    assert_eq!(pitch_at(&result.events, 0.6), midi_to_kc_kf(61));
+    for time in kc_write_times(&result.events) {
+        let quarters = time * 4.0;
+        assert!(
+            (quarters - quarters.round()).abs() < 1e-6,
+            "square jumps are written only at their edges, got a write at {time}"
+        );
+    }
+}
+
+#[test]
+fn test_pitch_lfo_waits_for_its_delay_and_restarts_on_each_note() {
+    let events = vec![
+        note_on(0, 0, 60, 100),
+        note_off(960, 0, 60),
+        note_on(1440, 0, 60, 100),
+        note_off(2400, 0, 60),
+    ];
+    let options = options_from(
+        r#"{ "SoftwareLfo": [{ "Target": "pitch", "Depth": 100, "RateHz": 2.0, "DelaySeconds": 0.5, "Waveform": "ramp_up" }] }"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let times = kc_write_times(&result.events);
+    assert!(
+        times.iter().all(|&t| !(t > 0.0 && t < 0.5)),
+        "nothing moves before the delay: {times:?}"
+    );
+    assert!(times.iter().all(|&t| !(t > 1.5 && t < 2.0)));
+    // A ramp starts at its bottom when the delay ends
+    assert_eq!(pitch_at(&result.events, 0.5), midi_to_kc_kf(59));
+    assert_eq!(pitch_at(&result.events, 2.0), midi_to_kc_kf(59));
+}
+
+#[test]
+fn test_pitch_lfo_keeps_the_pitch_bend() {
+    let events = vec![
+        note_on(0, 0, 60, 100),
+        MidiEvent::PitchBend {
+            ticks: 96,
+            channel: 0,
+            value: 4096,
+            track: 0,
+            port: None,
+        },
+        note_off(1920, 0, 60),
+    ];
+    let options = options_from(
+        r#"{ "SoftwareLfo": [{ "Target": "pitch", "Depth": 50, "RateHz": 2.0, "Waveform": "square" }] }"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    // A bend of +100 cents at 0.1s lands on top of the LFO's +50 rather than replacing it
+    assert_eq!(
+        pitch_at(&result.events, 0.1),
+        midi_note_with_offset_to_kc_kf(60, 150.0)
+    );
+    assert_eq!(
+        pitch_at(&result.events, 0.3),
+        midi_note_with_offset_to_kc_kf(60, 50.0)
+    );
+}
+
+#[test]
+fn test_pitch_lfo_rides_on_the_portamento_glide() {
+    let events = vec![
+        note_on(0, 0, 60, 100),
+        note_off(480, 0, 60),
+        note_on(480, 0, 72, 100),
+        note_off(1440, 0, 72),
+    ];
+    let options = options_from(
+        r#"{
+            "Portamento": true,
+            "SoftwareLfo": [{ "Target": "pitch", "Depth": 50, "RateHz": 2.0, "Waveform": "square" }]
+        }"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    // The glide starts from the previous note and ends on the new one, plus the LFO
+    let glide: Vec<(u8, u8)> = result
+        .events
+        .iter()
+        .filter(|e| e.addr == "0x28" && e.time > 0.5 && e.time <= 0.6 + 1e-9)
+        .map(|e| pitch_at(&result.events, e.time))
+        .collect();
+    assert!(glide.len() > 2, "the glide should take several steps");
+    assert!(glide.windows(2).all(|w| w[0] <= w[1]), "glide: {glide:?}");
+    assert_eq!(
+        pitch_at(&result.events, 0.5 + 1e-6),
+        midi_note_with_offset_to_kc_kf(60, 50.0)
+    );
+    assert_eq!(
+        pitch_at(&result.events, 0.6),
+        midi_note_with_offset_to_kc_kf(72, 50.0)
+    );
+    assert_eq!(
+        pitch_at(&result.events, 0.8),
+        midi_note_with_offset_to_kc_kf(72, -50.0)
+    );
+}
+
+#[test]
+fn test_pitch_lfo_per_program() {
+    let events = vec![
+        note_on(0, 0, 60, 100),
+        note_off(480, 0, 60),
+        MidiEvent::ProgramChange {
+            ticks: 960,
+            channel: 0,
+            program: 1,
+            track: 0,
+            port: None,
+        },
+        note_on(960, 0, 60, 100),
+        note_off(1440, 0, 60),
+    ];
+    let options = options_from(
+        r#"[{ "ProgramChange": 1, "SoftwareLfo": [{ "Target": "pitch", "Depth": 100, "RateHz": 4.0, "Waveform": "sine" }] }]"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let times = kc_write_times(&result.events);
+    assert!(
+        times.iter().all(|&t| t >= 1.0),
+        "program 0 has no pitch LFO: {times:?}"
+    );
+    assert!(times.iter().filter(|&&t| t > 1.0).count() > 4);
+    // A quarter period into a 4 Hz sine is its peak, give or take a sample
+    let peak = pitch_at(&result.events, 1.0625);
+    assert!(peak > midi_note_with_offset_to_kc_kf(60, 95.0) && peak <= midi_to_kc_kf(61));
+}
+
+#[test]
+fn test_pitch_lfo_target_from_attachment() {
+    let options = options_from(
+        r#"{ "SoftwareLfo": [{ "Target": "pitch", "Depth": 20, "RateHz": 5.0 }, { "BaseRegister": "0x60", "RateHz": 5.0 }] }"#,
+    );
+    let targets: Vec<LfoTarget> = options.software_lfo.iter().map(|d| d.target).collect();
+    assert_eq!(targets, [LfoTarget::Pitch, LfoTarget::Register]);
+
+    let result = ConversionOptions::from_attachment_bytes(Some(
+        br#"{ "SoftwareLfo": [{ "Depth": 4, "RateHz": 5.0 }] }"#,
+    ));
+    assert!(result.is_err(), "a register LFO needs its register");
+}
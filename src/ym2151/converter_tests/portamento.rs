@@ -4,7 +4,9 @@ use super::*;
 #[test]
 fn test_portamento_generates_pitch_glide_events() {
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -12,24 +14,33 @@ fn test_portamento_generates_pitch_glide_events() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 480,
                 channel: 0,
                 note: 67,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 960,
                 channel: 0,
                 note: 67,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let options = ConversionOptions {
@@ -117,7 +128,9 @@ fn test_portamento_one_octave_reaches_target() {
     // Previously, the loop's time_step didn't align with stop_time, leaving the portamento
     // stuck just below the target note.
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -125,24 +138,33 @@ fn test_portamento_one_octave_reaches_target() {
                 channel: 0,
                 note: 60, // C4
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 480,
                 channel: 0,
                 note: 72, // C5 (one octave up)
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 960,
                 channel: 0,
                 note: 72,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let options = ConversionOptions {
@@ -185,3 +207,35 @@ fn test_portamento_one_octave_reaches_target() {
         "1-octave portamento must reach the target KF (C5) at the end of the glide"
     );
 }
+
+#[test]
+fn test_portamento_glide_keeps_the_pitch_bend() {
+    let midi_data = midi_data(vec![
+        MidiEvent::PitchBend {
+            ticks: 0,
+            channel: 0,
+            value: 4096, // +1 semitone with the default ±2 range
+            track: 0,
+            port: None,
+        },
+        note_on(0, 0, 60, 100),
+        note_off(480, 0, 60),
+        note_on(480, 0, 62, 100),
+        note_off(960, 0, 62),
+    ]);
+    let options = ConversionOptions {
+        portamento: true,
+        ..ConversionOptions::default()
+    };
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    // The glide ends at the bent target, not at the note itself
+    let (kc_target, _) = midi_to_kc_kf(63);
+    let last_glide_kc = result
+        .events
+        .iter()
+        .rfind(|e| e.addr == "0x28" && e.time > 0.5 && e.time <= 0.6 + 1e-9)
+        .expect("Portamento should glide to the second note");
+    assert_eq!(last_glide_kc.data, format!("0x{:02X}", kc_target));
+}
@@ -5,7 +5,9 @@ use super::*;
 fn test_convert_program_change() {
     // Test that program change events trigger tone changes
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             // Program change at the start
@@ -13,6 +15,8 @@ fn test_convert_program_change() {
                 ticks: 0,
                 channel: 0,
                 program: 42,
+                track: 0,
+                port: None,
             },
             // Play a note
             MidiEvent::NoteOn {
@@ -20,13 +24,18 @@ fn test_convert_program_change() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -55,7 +64,9 @@ fn test_convert_program_change() {
 fn test_convert_program_change_unused_channel() {
     // Program change on a channel that has no notes should be ignored
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             // Program change on channel 5
@@ -63,6 +74,8 @@ fn test_convert_program_change_unused_channel() {
                 ticks: 0,
                 channel: 5,
                 program: 10,
+                track: 0,
+                port: None,
             },
             // But only channel 0 plays a note
             MidiEvent::NoteOn {
@@ -70,13 +83,18 @@ fn test_convert_program_change_unused_channel() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -90,26 +108,35 @@ fn test_convert_program_change_unused_channel() {
 fn test_convert_program_change_with_attachment_tone() {
     // Program change should use tone definitions supplied via attachment JSON
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::ProgramChange {
                 ticks: 0,
                 channel: 0,
                 program: 99,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 0,
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let mut options = ConversionOptions::default();
@@ -141,43 +168,58 @@ fn test_convert_program_change_with_attachment_tone() {
 fn test_convert_multiple_program_changes() {
     // Test multiple program changes on the same channel
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::ProgramChange {
                 ticks: 0,
                 channel: 0,
                 program: 10,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 0,
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 240,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             // Change to a different program
             MidiEvent::ProgramChange {
                 ticks: 240,
                 channel: 0,
                 program: 20,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 480,
                 channel: 0,
                 note: 64,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 720,
                 channel: 0,
                 note: 64,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data).unwrap();
@@ -209,3 +251,113 @@ fn test_convert_multiple_program_changes() {
         "Should have tone change at later time"
     );
 }
+
+#[test]
+fn test_pan_survives_program_change() {
+    let mut options = ConversionOptions::default();
+    options.tones.insert(
+        5,
+        ToneDefinition {
+            events: vec![Ym2151Event {
+                time: 0.0,
+                addr: "0x20".to_string(),
+                data: "0xDC".to_string(), // RL=both, FB=3, CON=4
+            }],
+            ..ToneDefinition::default()
+        },
+    );
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events: vec![
+            // Hard left, then a tone change that writes RL=both
+            MidiEvent::ControlChange {
+                ticks: 0,
+                channel: 0,
+                controller: 10,
+                value: 0,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::ProgramChange {
+                ticks: 480,
+                channel: 0,
+                program: 5,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOn {
+                ticks: 480,
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOff {
+                ticks: 960,
+                channel: 0,
+                note: 60,
+                track: 0,
+                port: None,
+            },
+        ],
+        metadata: MidiMetadata::default(),
+    };
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    let rl_writes: Vec<_> = result
+        .events
+        .iter()
+        .filter(|e| e.addr == "0x20")
+        .map(|e| (e.time, e.data.as_str()))
+        .collect();
+    // Init (0xC7), pan left keeps FB/CON (0x47), tone keeps the pan (0x5C)
+    assert_eq!(rl_writes, vec![(0.0, "0xC7"), (0.0, "0x47"), (0.5, "0x5C")]);
+}
+
+#[test]
+fn test_pan_threshold_option() {
+    let json = br#"{ "PanThreshold": 8 }"#;
+    let options = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events: vec![
+            MidiEvent::ControlChange {
+                ticks: 0,
+                channel: 0,
+                controller: 10,
+                value: 80,
+                track: 0,
+                port: None,
+            },
+            MidiEvent::NoteOn {
+                ticks: 0,
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                track: 0,
+                port: None,
+            },
+        ],
+        metadata: MidiMetadata::default(),
+    };
+
+    // 80 is within the default threshold but beyond 64 + 8: right only
+    let default_result = convert_to_ym2151_log(&midi_data).unwrap();
+    assert!(!default_result
+        .events
+        .iter()
+        .any(|e| e.addr == "0x20" && e.data == "0x87"));
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+    assert!(result
+        .events
+        .iter()
+        .any(|e| e.addr == "0x20" && e.data == "0x87"));
+}
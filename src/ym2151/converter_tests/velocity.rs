@@ -0,0 +1,161 @@
+//! Velocity sensitivity tests for YM2151 converter
+use super::*;
+
+fn con4_tone() -> ToneDefinition {
+    let reg = |addr: &str, data: &str| Ym2151Event {
+        time: 0.0,
+        addr: addr.to_string(),
+        data: data.to_string(),
+    };
+    ToneDefinition {
+        events: vec![
+            reg("0x20", "0xC4"), // CON=4: C1 and C2 are carriers
+            reg("0x60", "0x20"), // M1
+            reg("0x68", "0x30"), // M2
+            reg("0x70", "0x10"), // C1
+            reg("0x78", "0x08"), // C2
+        ],
+        ..ToneDefinition::default()
+    }
+}
+
+fn three_notes(velocities: [u8; 3]) -> MidiData {
+    let mut events = Vec::new();
+    for (i, velocity) in velocities.into_iter().enumerate() {
+        let ticks = i as u32 * 480;
+        events.push(MidiEvent::NoteOn {
+            ticks,
+            channel: 0,
+            note: 60,
+            velocity,
+            track: 0,
+            port: None,
+        });
+        events.push(MidiEvent::NoteOff {
+            ticks: ticks + 240,
+            channel: 0,
+            note: 60,
+            track: 0,
+            port: None,
+        });
+    }
+    MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events,
+        metadata: MidiMetadata::default(),
+    }
+}
+
+fn tl_writes_after_init(log: &crate::ym2151::Ym2151Log) -> Vec<(f64, String, String)> {
+    log.events
+        .iter()
+        .filter(|e| e.time > 0.0 && (e.addr.starts_with("0x6") || e.addr.starts_with("0x7")))
+        .map(|e| (e.time, e.addr.clone(), e.data.clone()))
+        .collect()
+}
+
+#[test]
+fn test_velocity_scales_carrier_tl_from_tone_base() {
+    let json = br#"[
+      {
+        "ProgramChange": 0,
+        "VelocitySensitivity": { "Curve": "linear", "Depth": 40 }
+      }
+    ]"#;
+    let mut options = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+    options.tones.insert(0, con4_tone());
+
+    let result =
+        convert_to_ym2151_log_with_options(&three_notes([127, 64, 127]), &options).unwrap();
+
+    // Velocity 127 plays at the tone's own level.
+    // Velocity 64 attenuates both carriers by round(40 * 63/127) = 20 steps,
+    // then the third note restores the tone's own levels instead of stacking.
+    assert_eq!(
+        tl_writes_after_init(&result),
+        vec![
+            (0.5, "0x70".to_string(), "0x24".to_string()),
+            (0.5, "0x78".to_string(), "0x1C".to_string()),
+            (1.0, "0x70".to_string(), "0x10".to_string()),
+            (1.0, "0x78".to_string(), "0x08".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_velocity_tl_written_before_key_on() {
+    let options = ConversionOptions {
+        velocity_sensitivity: Some(crate::VelocitySensitivity {
+            curve: crate::VelocityCurve::Linear,
+            depth: 40.0,
+        }),
+        ..ConversionOptions::default()
+    };
+
+    let result = convert_to_ym2151_log_with_options(&three_notes([1, 1, 1]), &options).unwrap();
+
+    let first_note: Vec<_> = result
+        .events
+        .iter()
+        .filter(|e| e.time == 0.0)
+        .skip_while(|e| e.addr != "0x28")
+        .collect();
+    assert_eq!(first_note.last().unwrap().addr, "0x08");
+    assert!(
+        first_note.iter().any(|e| e.addr.starts_with("0x6")),
+        "Default tone (CON=7) carriers should be attenuated before key-on"
+    );
+}
+
+#[test]
+fn test_no_velocity_sensitivity_keeps_output_unchanged() {
+    let result = convert_to_ym2151_log(&three_notes([127, 10, 127])).unwrap();
+    assert!(tl_writes_after_init(&result).is_empty());
+}
+
+#[test]
+fn test_volume_combines_with_velocity_in_db() {
+    let json = br#"[
+      {
+        "ProgramChange": 0,
+        "VelocitySensitivity": { "Curve": "linear", "Depth": 40 }
+      }
+    ]"#;
+    let mut options = ConversionOptions::from_attachment_bytes(Some(json)).unwrap();
+    options.tones.insert(0, con4_tone());
+
+    let mut midi_data = three_notes([127, 64, 127]);
+    // CC7 = 64 (16 TL steps) before the first note
+    midi_data.events.insert(
+        0,
+        MidiEvent::ControlChange {
+            ticks: 0,
+            channel: 0,
+            controller: 7,
+            value: 64,
+            track: 0,
+            port: None,
+        },
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    // Ignoring the silent init and tone writes, volume attenuates every note; velocity adds 20 more steps to the second
+    let tl: Vec<_> = result
+        .events
+        .iter()
+        .filter(|e| e.addr == "0x70" && e.data != "0x10" && e.data != "0x7F")
+        .map(|e| (e.time, e.data.clone()))
+        .collect();
+    assert_eq!(
+        tl,
+        vec![
+            (0.0, "0x20".to_string()),
+            (0.5, "0x34".to_string()),
+            (1.0, "0x20".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,183 @@
+//! Dynamic voice allocation tests for YM2151 converter
+use super::*;
+
+/// YM2151 channels keyed off at `time`, in write order
+fn key_offs_at(events: &[Ym2151Event], time: f64) -> Vec<u8> {
+    events
+        .iter()
+        .filter(|e| e.addr == "0x08" && e.time == time && data_of(e) & 0x78 == 0)
+        .map(|e| data_of(e) & 0x07)
+        .collect()
+}
+
+/// Eight notes on MIDI channel 0, one every 10 ticks, never released
+fn full_chip(velocities: [u8; 8]) -> Vec<MidiEvent> {
+    velocities
+        .iter()
+        .enumerate()
+        .map(|(i, &velocity)| note_on(i as u32 * 10, 0, 60 + i as u8, velocity))
+        .collect()
+}
+
+#[test]
+fn test_busy_sections_of_different_parts_share_all_voices() {
+    let mut events = Vec::new();
+    for (channel, start) in [(0u8, 0u32), (1, 960)] {
+        for i in 0..6 {
+            events.push(note_on(start, channel, 60 + i, 100));
+        }
+        for i in 0..6 {
+            events.push(note_off(start + 480, channel, 60 + i));
+        }
+    }
+    let options = options_from(r#"{ "VoiceAllocation": {} }"#);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let mut second_chord = key_ons_at(&result.events, 1.0);
+    second_chord.dedup();
+    assert_eq!(
+        second_chord.len(),
+        6,
+        "each note of the second part needs its own voice"
+    );
+    assert!(
+        key_offs_at(&result.events, 1.0).is_empty(),
+        "nothing to steal"
+    );
+}
+
+#[test]
+fn test_oldest_note_is_stolen_by_default() {
+    let mut events = full_chip([100; 8]);
+    events.push(note_on(100, 0, 72, 100));
+    let options = options_from(r#"{ "VoiceAllocation": { "Stealing": "oldest" } }"#);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let time = 100.0 / 960.0;
+    let first_voice = key_ons_at(&result.events, 0.0)[0];
+    assert_eq!(key_offs_at(&result.events, time), [first_voice]);
+    assert_eq!(key_ons_at(&result.events, time), [first_voice]);
+}
+
+#[test]
+fn test_quietest_note_is_stolen() {
+    let mut events = full_chip([100, 100, 100, 20, 100, 100, 100, 100]);
+    events.push(note_on(100, 0, 72, 100));
+    let options = options_from(r#"{ "VoiceAllocation": { "Stealing": "quietest" } }"#);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let quiet_voice = key_ons_at(&result.events, 30.0 / 960.0)[0];
+    assert_eq!(key_ons_at(&result.events, 100.0 / 960.0), [quiet_voice]);
+}
+
+#[test]
+fn test_released_first_steals_a_pedal_held_note() {
+    let mut events = vec![MidiEvent::ControlChange {
+        ticks: 0,
+        channel: 0,
+        controller: 64,
+        value: 127,
+        track: 0,
+        port: None,
+    }];
+    events.extend(full_chip([100; 8]));
+    events.push(note_off(90, 0, 65));
+    events.push(note_on(100, 0, 72, 100));
+    let options = options_from(r#"{ "VoiceAllocation": { "Stealing": "released_first" } }"#);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let held_voice = key_ons_at(&result.events, 50.0 / 960.0)[0];
+    let time = 100.0 / 960.0;
+    assert_eq!(key_offs_at(&result.events, time), [held_voice]);
+    assert_eq!(key_ons_at(&result.events, time), [held_voice]);
+}
+
+#[test]
+fn test_lowest_priority_never_steals_from_higher_priority() {
+    let mut events: Vec<MidiEvent> = (0..8)
+        .map(|i| note_on(i * 10, if i < 4 { 0 } else { 1 }, 60 + i as u8, 100))
+        .collect();
+    // Channel 1 steals its own oldest voice; channel 2 may not steal at all
+    events.push(note_on(100, 1, 72, 100));
+    events.push(note_on(110, 2, 74, 100));
+    let options = options_from(
+        r#"{
+            "VoiceAllocation": { "Stealing": "lowest_priority" },
+            "ChannelAssignment": { "0": { "Priority": 10 }, "1": { "Priority": 5 }, "2": { "Priority": 1 } }
+        }"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let oldest_channel_1_voice = key_ons_at(&result.events, 40.0 / 960.0)[0];
+    assert_eq!(
+        key_ons_at(&result.events, 100.0 / 960.0),
+        [oldest_channel_1_voice]
+    );
+    assert!(key_ons_at(&result.events, 110.0 / 960.0).is_empty());
+    assert!(key_offs_at(&result.events, 110.0 / 960.0).is_empty());
+}
+
+#[test]
+fn test_reserved_voices_are_kept_for_their_channel() {
+    let mut events = full_chip([100; 8]);
+    events.push(note_on(100, 9, 36, 100));
+    let options = options_from(r#"{ "VoiceAllocation": { "Reserve": { "9": 2 } } }"#);
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let melody_voices: Vec<u8> = (0..8)
+        .flat_map(|i| key_ons_at(&result.events, i as f64 * 10.0 / 960.0))
+        .collect();
+    assert!(melody_voices.iter().all(|&voice| voice >= 2));
+    assert_eq!(key_ons_at(&result.events, 100.0 / 960.0), [0]);
+}
+
+#[test]
+fn test_voice_taken_by_another_program_loads_its_tone() {
+    let events = vec![
+        note_on(0, 0, 60, 100),
+        note_off(240, 0, 60),
+        MidiEvent::ProgramChange {
+            ticks: 480,
+            channel: 1,
+            program: 1,
+            track: 0,
+            port: None,
+        },
+        note_on(480, 1, 64, 100),
+    ];
+    let options = options_from(
+        r#"{
+            "VoiceAllocation": {},
+            "Tones": { "1": { "events": [{ "time": 0, "addr": "0x20", "data": "0xFD" }] } }
+        }"#,
+    );
+
+    let result = convert_to_ym2151_log_with_options(&midi_data(events), &options).unwrap();
+
+    let voice = key_ons_at(&result.events, 0.5)[0];
+    let tone_write = result
+        .events
+        .iter()
+        .position(|e| e.time == 0.5 && e.addr == format!("0x{:02X}", 0x20 + voice));
+    let key_on = result
+        .events
+        .iter()
+        .position(|e| e.time == 0.5 && e.addr == "0x08");
+    assert!(tone_write.is_some(), "program 1 tone should be loaded");
+    assert!(tone_write < key_on);
+    assert_eq!(result.events[tone_write.unwrap()].data, "0xFD");
+}
+
+#[test]
+fn test_reserving_more_than_eight_voices_is_rejected() {
+    let result = ConversionOptions::from_attachment_bytes(Some(
+        br#"{ "VoiceAllocation": { "Reserve": { "0": 5, "9": 4 } } }"#,
+    ));
+    assert!(result.is_err());
+}
@@ -3,10 +3,21 @@
 //! This module handles the processing of individual MIDI events
 //! and converts them to YM2151 register write events.
 
-use crate::midi::{midi_to_kc_kf, ticks_to_seconds_with_tempo_map, MidiEvent, TempoChange};
+use crate::midi::{
+    midi_note_with_offset_to_kc_kf, midi_to_kc_kf, ticks_to_seconds_with_tempo_map, MidiEvent,
+    MidiTiming, TempoChange,
+};
+use crate::ym2151::converter::register_fields::{field_registers, named_register_field};
+use crate::ym2151::events::parse_hex_byte;
 use crate::ym2151::{
-    apply_tone_to_channel, default_tone_events, load_tone_for_program, ChannelAllocation,
-    ToneDefinition, Ym2151Event,
+    apply_tone_to_channel, attenuate_tl, carrier_operators, controller_gain_attenuation,
+    default_tone_events, lfo_reset_events, load_tone_for_program, pan_rl_bits,
+    velocity_attenuation, ChannelAllocation, ToneDefinition, Ym2151Event, DEFAULT_PAN_THRESHOLD,
+    NOISE_CHANNEL, RL_MASK, TL_STEP_DB,
+};
+use crate::{
+    AftertouchRouting, AftertouchTarget, ControllerSource, ConversionOptions, DrumNote,
+    ModulationWheelMode, RegisterField,
 };
 use std::collections::{BTreeMap, HashMap, HashSet};
 
@@ -17,6 +28,10 @@ pub struct NoteOnInfo {
     pub start_time: f64,
     /// MIDI program number active on the channel when this note started
     pub program: u8,
+    /// Played at a fixed pitch from the drum map; pitch effects leave it alone
+    pub fixed_pitch: bool,
+    /// (time, cents) of the channel's pitch bend from note-on while the note sounds
+    pub pitch_bends: Vec<(f64, f64)>,
 }
 
 /// Captures a full note span on a specific YM2151 channel
@@ -30,12 +45,355 @@ pub struct NoteSegment {
     pub end_time: f64,
     /// MIDI program number that was active when this note started
     pub program: u8,
+    /// Played at a fixed pitch from the drum map; pitch effects leave it alone
+    pub fixed_pitch: bool,
+    /// (time, cents) of the channel's pitch bend from note-on while the note sounded
+    pub pitch_bends: Vec<(f64, f64)>,
+}
+
+impl NoteSegment {
+    /// Pitch bend in cents in effect at `time`
+    pub fn pitch_bend_at(&self, time: f64) -> f64 {
+        self.pitch_bends
+            .iter()
+            .take_while(|&&(t, _)| t <= time + f64::EPSILON)
+            .last()
+            .map_or(0.0, |&(_, cents)| cents)
+    }
+}
+
+/// Default pitch bend sensitivity in cents (GM: ±2 semitones)
+pub const DEFAULT_PITCH_BEND_RANGE_CENTS: f64 = 200.0;
+
+/// Channel volume controller number
+pub const CC_VOLUME: u8 = 7;
+/// Modulation wheel controller number
+pub const CC_MODULATION: u8 = 1;
+/// Pan controller number
+pub const CC_PAN: u8 = 10;
+/// Expression controller number
+pub const CC_EXPRESSION: u8 = 11;
+
+/// Damper (sustain) pedal controller number
+pub const CC_SUSTAIN: u8 = 64;
+/// Sostenuto pedal controller number
+pub const CC_SOSTENUTO: u8 = 66;
+
+/// PMS bits of the PMS/AMS register (0x38-0x3F)
+const PMS_MASK: u8 = 0x70;
+/// AMS bits of the PMS/AMS register (0x38-0x3F)
+const AMS_MASK: u8 = 0x03;
+/// Noise enable (NE) and noise frequency (NFRQ) register
+const NOISE_REGISTER: u8 = 0x0F;
+/// NE bit of the noise register
+const NOISE_ENABLE: u8 = 0x80;
+
+/// Largest 14-bit NRPN data entry value
+const NRPN_VALUE_MAX: f64 = 16383.0;
+
+/// Controller state tracked per MIDI channel
+#[derive(Debug, Clone)]
+pub struct MidiChannelState {
+    /// Current pitch bend value (-8192 to 8191, 0 = center)
+    pub pitch_bend: i16,
+    /// Pitch bend sensitivity in cents (set via RPN 0)
+    pub pitch_bend_range_cents: f64,
+    /// Channel volume (CC7), 127 = tone level
+    pub volume: u8,
+    /// Expression (CC11), 127 = tone level
+    pub expression: u8,
+    /// Pan (CC10); `None` keeps the speaker bits the tone sets
+    pub pan: Option<u8>,
+    /// Modulation wheel (CC1); `None` until the first CC1
+    pub modulation: Option<u8>,
+    /// Channel pressure (channel aftertouch)
+    pub channel_pressure: u8,
+    /// Latest program change
+    pub program: u8,
+    /// Damper pedal (CC64) is pressed
+    pub sustain: bool,
+    /// Sostenuto pedal (CC66) is pressed
+    pub sostenuto: bool,
+    /// Voices (YM2151 channel, MIDI note) that were sounding when sostenuto was pressed
+    pub sostenuto_notes: HashSet<(u8, u8)>,
+    /// Voices (YM2151 channel, MIDI note) whose note-off is deferred by a pedal
+    pub held_notes: Vec<(u8, u8)>,
+    /// YM2151 channel each drum map voice group last played on
+    pub drum_groups: HashMap<u8, u8>,
+}
+
+impl Default for MidiChannelState {
+    fn default() -> Self {
+        Self {
+            pitch_bend: 0,
+            pitch_bend_range_cents: DEFAULT_PITCH_BEND_RANGE_CENTS,
+            volume: 127,
+            expression: 127,
+            pan: None,
+            modulation: None,
+            channel_pressure: 0,
+            program: 0,
+            sustain: false,
+            sostenuto: false,
+            sostenuto_notes: HashSet::new(),
+            held_notes: Vec::new(),
+            drum_groups: HashMap::new(),
+        }
+    }
+}
+
+impl MidiChannelState {
+    /// Current pitch bend offset in cents
+    pub fn pitch_bend_cents(&self) -> f64 {
+        pitch_bend_to_cents(self.pitch_bend, self.pitch_bend_range_cents)
+    }
+
+    /// Carrier attenuation (in TL steps) from channel volume and expression
+    pub fn gain_attenuation(&self) -> f64 {
+        controller_gain_attenuation(self.volume) + controller_gain_attenuation(self.expression)
+    }
+
+    /// Whether a released voice must keep sounding because of a pedal
+    fn is_pedal_held(&self, voice: (u8, u8)) -> bool {
+        self.sustain || self.sostenuto_notes.contains(&voice)
+    }
+}
+
+/// Register shadow for one YM2151 channel
+///
+/// Keeps the values written by the current tone so that level changes
+/// (velocity, volume) are always computed from the tone's own TL instead of
+/// accumulating on top of previous notes.
+#[derive(Debug, Clone, Default)]
+pub struct Ym2151ChannelState {
+    /// RL_FB_CONNECT (0x20-0x27) as currently written to the chip
+    pub rl_fb_con: u8,
+    /// PMS/AMS (0x38-0x3F) as currently written to the chip
+    pub pms_ams: u8,
+    /// Tone TL per operator (register order M1, M2, C1, C2)
+    pub base_tl: [u8; 4],
+    /// TL currently written to the chip per operator
+    pub current_tl: [u8; 4],
+    /// Velocity attenuation (in TL steps) of the latest note-on
+    pub velocity_attenuation: f64,
+    /// Key pressure (poly aftertouch) of the latest note, reset at note-on
+    pub key_pressure: u8,
+    /// Drum note whose drum map tone is loaded in place of the program tone
+    pub drum_tone: Option<u8>,
+    /// Other registers of this channel as last written, by address
+    pub registers: HashMap<u8, u8>,
+}
+
+impl Ym2151ChannelState {
+    /// Update the shadow from tone register writes targeting this channel
+    pub fn apply_tone_events(&mut self, events: &[Ym2151Event]) {
+        for event in events {
+            let (Some(addr), Some(data)) =
+                (parse_hex_byte(&event.addr), parse_hex_byte(&event.data))
+            else {
+                continue;
+            };
+            match addr {
+                0x20..=0x27 => self.rl_fb_con = data,
+                0x38..=0x3F => self.pms_ams = data,
+                0x60..=0x7F => {
+                    let operator = ((addr - 0x60) / 8) as usize;
+                    self.base_tl[operator] = data;
+                    self.current_tl[operator] = data;
+                }
+                _ => {
+                    self.registers.insert(addr, data);
+                }
+            }
+        }
+    }
+
+    /// Shadow of a per-channel register by its channel-0 address
+    fn shadow_mut(&mut self, base_addr: u8) -> &mut u8 {
+        match base_addr {
+            0x20 => &mut self.rl_fb_con,
+            0x38 => &mut self.pms_ams,
+            _ => unreachable!("register 0x{base_addr:02X} is not shadowed"),
+        }
+    }
+
+    /// Value of a register of this channel as currently written to the chip
+    fn register(&self, addr: u8) -> u8 {
+        match addr {
+            0x20..=0x27 => self.rl_fb_con,
+            0x38..=0x3F => self.pms_ams,
+            0x60..=0x7F => self.current_tl[((addr - 0x60) / 8) as usize],
+            _ => self.registers.get(&addr).copied().unwrap_or(0),
+        }
+    }
+
+    /// Connection algorithm (CON) of the current tone
+    pub fn con(&self) -> u8 {
+        self.rl_fb_con & 0x07
+    }
+}
+
+/// RL bits for the pan of a MIDI channel, if pan was set
+fn channel_rl_bits(channel: u8, ctx: &EventProcessorContext) -> Option<u8> {
+    let pan = ctx.midi_channel_states.get(&channel)?.pan?;
+    let threshold = ctx
+        .options
+        .map(ConversionOptions::pan_threshold)
+        .unwrap_or(DEFAULT_PAN_THRESHOLD);
+    Some(pan_rl_bits(pan, threshold))
+}
+
+/// PMS bits for the modulation wheel of a MIDI channel in hardware vibrato mode
+fn channel_pms_bits(channel: u8, ctx: &EventProcessorContext) -> Option<u8> {
+    let wheel = ctx.options?.modulation_wheel.as_ref()?;
+    if wheel.mode != ModulationWheelMode::Hardware {
+        return None;
+    }
+    let modulation = ctx.midi_channel_states.get(&channel)?.modulation?;
+    let pms = (wheel.max_depth().clamp(0.0, 7.0) * modulation as f64 / 127.0).round() as u8;
+    Some(pms << 4)
+}
+
+/// Aftertouch routing of the program playing on a YM2151 channel
+fn voice_aftertouch<'a>(
+    ym2151_channel: u8,
+    ctx: &EventProcessorContext<'a>,
+) -> Option<&'a AftertouchRouting> {
+    let program = *ctx.channel_programs.get(&ym2151_channel).unwrap_or(&0);
+    ctx.options?.aftertouch_for(program)
+}
+
+/// Pressure of a voice: the larger of its MIDI channel's pressure and its key pressure
+fn voice_pressure(channel: u8, ym2151_channel: u8, ctx: &EventProcessorContext) -> u8 {
+    let channel_pressure = ctx
+        .midi_channel_states
+        .get(&channel)
+        .map_or(0, |state| state.channel_pressure);
+    let key_pressure = ctx
+        .ym2151_channel_states
+        .get(&ym2151_channel)
+        .map_or(0, |state| state.key_pressure);
+    channel_pressure.max(key_pressure)
+}
+
+/// PMS bits of a voice: the deeper of the modulation wheel and aftertouch vibrato
+fn voice_pms_bits(channel: u8, ym2151_channel: u8, ctx: &EventProcessorContext) -> Option<u8> {
+    let pressure_pms = voice_aftertouch(ym2151_channel, ctx)
+        .filter(|routing| routing.target == AftertouchTarget::Vibrato)
+        .map(|routing| {
+            let pressure = voice_pressure(channel, ym2151_channel, ctx);
+            let pms = (routing.depth().clamp(0.0, 7.0) * pressure as f64 / 127.0).round() as u8;
+            pms << 4
+        });
+    channel_pms_bits(channel, ctx).max(pressure_pms)
+}
+
+/// Set the `mask` bits of a per-channel register on one YM2151 channel,
+/// if its value changes
+fn voice_register_bits_event(
+    time_seconds: f64,
+    ym2151_channel: u8,
+    base_addr: u8,
+    mask: u8,
+    bits: u8,
+    ctx: &mut EventProcessorContext,
+) -> Option<Ym2151Event> {
+    let state = ctx.ym2151_channel_states.entry(ym2151_channel).or_default();
+    let shadow = state.shadow_mut(base_addr);
+    let data = (*shadow & !mask) | bits;
+    if data == *shadow {
+        return None;
+    }
+    *shadow = data;
+    Some(Ym2151Event {
+        time: time_seconds,
+        addr: format!("0x{:02X}", base_addr + ym2151_channel),
+        data: format!("0x{:02X}", data),
+    })
+}
+
+/// Set the `mask` bits of a per-channel register on every YM2151 channel of
+/// MIDI `channel`, writing only the channels whose value changes
+fn channel_register_bits_events(
+    ticks: u32,
+    channel: u8,
+    base_addr: u8,
+    mask: u8,
+    bits: u8,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let Some(ym_channels) = ctx.allocation.midi_to_ym2151.get(&channel).cloned() else {
+        return Vec::new();
+    };
+    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+
+    ym_channels
+        .into_iter()
+        .filter_map(|ym2151_channel| {
+            voice_register_bits_event(time_seconds, ym2151_channel, base_addr, mask, bits, ctx)
+        })
+        .collect()
+}
+
+/// Replace the `mask` bits of every tone write to `addr`
+fn override_tone_bits(tone_events: &mut [Ym2151Event], addr: u8, mask: u8, bits: u8) {
+    for event in tone_events
+        .iter_mut()
+        .filter(|e| parse_hex_byte(&e.addr) == Some(addr))
+    {
+        if let Some(data) = parse_hex_byte(&event.data) {
+            event.data = format!("0x{:02X}", (data & !mask) | bits);
+        }
+    }
+}
+
+/// Apply a program's hardware LFO sensitivity to tone writes for a YM2151 channel
+///
+/// The PMS/AMS bits the program's attachment entry sets replace those of the
+/// tone's PMS/AMS write; without one, a write on top of `current` (the
+/// register as last written) is added.
+pub(crate) fn set_lfo_sensitivity(
+    tone_events: &mut Vec<Ym2151Event>,
+    ym2151_channel: u8,
+    current: u8,
+    options: &ConversionOptions,
+    program: u8,
+    time_seconds: f64,
+) {
+    let (pms, ams) = options.lfo_sensitivity_for(program);
+    let mask = pms.map_or(0, |_| PMS_MASK) | ams.map_or(0, |_| AMS_MASK);
+    if mask == 0 {
+        return;
+    }
+    let bits = (pms.unwrap_or(0) << 4) | ams.unwrap_or(0);
+    let addr = 0x38 + ym2151_channel;
+    if tone_events
+        .iter()
+        .any(|e| parse_hex_byte(&e.addr) == Some(addr))
+    {
+        override_tone_bits(tone_events, addr, mask, bits);
+    } else {
+        tone_events.push(Ym2151Event {
+            time: time_seconds,
+            addr: format!("0x{:02X}", addr),
+            data: format!("0x{:02X}", (current & !mask) | bits),
+        });
+    }
+}
+
+/// Convert a signed pitch bend value to a cent offset for the given bend range
+///
+/// Positive and negative halves are scaled separately so that both extremes
+/// (-8192 and 8191) reach the full bend range.
+fn pitch_bend_to_cents(value: i16, range_cents: f64) -> f64 {
+    let full_scale = if value >= 0 { 8191.0 } else { 8192.0 };
+    value as f64 / full_scale * range_cents
 }
 
 /// Context for processing MIDI events
 pub struct EventProcessorContext<'a> {
-    /// Ticks per beat from MIDI file
-    pub ticks_per_beat: u16,
+    /// Tick timing model from MIDI file
+    pub timing: MidiTiming,
     /// Tempo map for timing conversion
     pub tempo_map: &'a [TempoChange],
     /// Channel allocation mapping
@@ -50,6 +408,12 @@ pub struct EventProcessorContext<'a> {
     pub vibrato_completed_notes: Option<&'a mut Vec<NoteSegment>>,
     /// Optional tone definitions provided via attachment JSON
     pub attachment_tones: Option<&'a HashMap<u8, ToneDefinition>>,
+    /// Controller state (pitch bend etc.) per MIDI channel
+    pub midi_channel_states: &'a mut HashMap<u8, MidiChannelState>,
+    /// Register shadow of the loaded tone per YM2151 channel
+    pub ym2151_channel_states: &'a mut HashMap<u8, Ym2151ChannelState>,
+    /// Optional conversion options (per-program velocity sensitivity etc.)
+    pub options: Option<&'a ConversionOptions>,
 }
 
 /// Process a Note On MIDI event
@@ -80,25 +444,80 @@ pub fn process_note_on(
         return events;
     }
 
-    // Get allocated YM2151 channel(s) for this MIDI channel
-    let Some(ym_channels) = ctx.allocation.midi_to_ym2151.get(&channel) else {
-        return events;
+    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+    let options = ctx.options;
+    let drum = options.and_then(|options| options.drum_note(channel, note));
+    let noise = note_noise(channel, note, ctx);
+    let voice = if ctx.allocation.dynamic.is_some() {
+        take_dynamic_voice(
+            channel,
+            note,
+            velocity,
+            ticks,
+            time_seconds,
+            ctx,
+            &mut events,
+        )
+    } else if noise.is_some() {
+        Some(NOISE_CHANNEL)
+    } else {
+        drum.and_then(|drum| drum.group)
+            .and_then(|group| drum_group_voice(channel, group, ctx))
+            .or_else(|| round_robin_voice(channel, ctx))
     };
-    if ym_channels.is_empty() {
+    let Some(ym2151_channel) = voice else {
         return events;
+    };
+    if let Some(group) = drum.and_then(|drum| drum.group) {
+        ctx.midi_channel_states
+            .entry(channel)
+            .or_default()
+            .drum_groups
+            .insert(group, ym2151_channel);
     }
 
-    // Use round-robin voice allocation for polyphony
-    let voice_index = ctx.allocation.current_voice.entry(channel).or_insert(0);
-    let ym2151_channel = ym_channels[*voice_index % ym_channels.len()];
-    *voice_index = (*voice_index + 1) % ym_channels.len();
+    // A pedal-held voice taken over by this note is no longer held
+    if let Some(state) = ctx.midi_channel_states.get_mut(&channel) {
+        let mut taken_over = Vec::new();
+        state.held_notes.retain(|&(ym_ch, held_note)| {
+            if ym_ch == ym2151_channel {
+                taken_over.push(held_note);
+                false
+            } else {
+                true
+            }
+        });
+        for held_note in taken_over {
+            finish_note(ym2151_channel, held_note, ticks, time_seconds, ctx);
+        }
+    }
+
+    events.extend(note_tone_events(
+        channel,
+        note,
+        ym2151_channel,
+        time_seconds,
+        ctx,
+    ));
+    if ym2151_channel == NOISE_CHANNEL {
+        events.extend(noise_register_event(noise, time_seconds, ctx));
+    }
 
-    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.ticks_per_beat, ctx.tempo_map);
-    let (kc, kf) = midi_to_kc_kf(note);
+    let bend_cents = ctx
+        .midi_channel_states
+        .get(&channel)
+        .map(MidiChannelState::pitch_bend_cents)
+        .unwrap_or(0.0);
+    let fixed_pitch = drum.and_then(DrumNote::fixed_pitch);
+    let (kc, kf) = match fixed_pitch {
+        Some(pitch) => pitch,
+        None if bend_cents == 0.0 => midi_to_kc_kf(note),
+        None => midi_note_with_offset_to_kc_kf(note, bend_cents),
+    };
 
     // Use BTreeMap to make intra-timestamp ordering explicit:
-    // KC (sub_index=0) and KF (sub_index=1) must precede key-ON (sub_index=2)
-    // so the pitch registers are set before the note is triggered.
+    // KC (sub_index=0) and KF (sub_index=1), then carrier levels, must precede
+    // key-ON (last sub_index) so the registers are set before the note is triggered.
     // Relying on Vec push order + stable sort would make this intent implicit and fragile.
     let time_bits = time_seconds.to_bits();
     let mut ordered: BTreeMap<(u64, u64), Ym2151Event> = BTreeMap::new();
@@ -123,13 +542,47 @@ pub fn process_note_on(
         },
     );
 
-    // Key ON last (after pitch registers are set)
+    // Carrier levels from velocity, volume and expression (sub_index 2..)
+    let program = *ctx.channel_programs.get(&ym2151_channel).unwrap_or(&0);
+    let note_attenuation = ctx
+        .options
+        .and_then(|options| options.velocity_sensitivity_for(program))
+        .map(|sensitivity| velocity_attenuation(velocity, sensitivity))
+        .unwrap_or(0.0);
+    let state = ctx.ym2151_channel_states.entry(ym2151_channel).or_default();
+    state.velocity_attenuation = note_attenuation;
+    state.key_pressure = 0;
+    let mut sub_index = 2;
+    for event in carrier_level_events(channel, ym2151_channel, time_seconds, ctx) {
+        ordered.insert((time_bits, sub_index), event);
+        sub_index += 1;
+    }
+
+    // Aftertouch targets follow the new key's pressure
+    for event in pressure_events(channel, ym2151_channel, time_seconds, ctx) {
+        ordered.insert((time_bits, sub_index), event);
+        sub_index += 1;
+    }
+
+    // Restart the chip LFO so the note's vibrato starts from its first phase
+    if options
+        .and_then(|options| options.hardware_lfo.as_ref())
+        .is_some_and(|lfo| lfo.reset_on_key_on)
+    {
+        for event in lfo_reset_events(time_seconds) {
+            ordered.insert((time_bits, sub_index), event);
+            sub_index += 1;
+        }
+    }
+
+    // Key ON last (after pitch and level registers are set)
+    let key_on_mask = drum.and_then(|drum| drum.key_on_mask).unwrap_or(0x0F);
     ordered.insert(
-        (time_bits, 2),
+        (time_bits, sub_index),
         Ym2151Event {
             time: time_seconds,
             addr: "0x08".to_string(),
-            data: format!("0x{:02X}", 0x78 | ym2151_channel),
+            data: format!("0x{:02X}", (key_on_mask << 3) | ym2151_channel),
         },
     );
 
@@ -146,6 +599,8 @@ pub fn process_note_on(
                 start_tick: ticks,
                 start_time: time_seconds,
                 program,
+                fixed_pitch: fixed_pitch.is_some(),
+                pitch_bends: vec![(time_seconds, bend_cents)],
             },
         );
     }
@@ -153,9 +608,336 @@ pub fn process_note_on(
     events
 }
 
+/// Pick the next of the YM2151 channels allocated to MIDI `channel`
+///
+/// Round-robin over the allocated channels, skipping voices that are still
+/// sounding (including pedal-held notes) while a free one exists. A channel
+/// kept for noise is only used when the MIDI channel has no other.
+fn round_robin_voice(channel: u8, ctx: &mut EventProcessorContext) -> Option<u8> {
+    let allocated = ctx.allocation.midi_to_ym2151.get(&channel)?;
+    let tonal: Vec<u8> = allocated
+        .iter()
+        .copied()
+        .filter(|&ym_ch| !(ctx.allocation.noise && ym_ch == NOISE_CHANNEL))
+        .collect();
+    let ym_channels = if tonal.is_empty() { allocated } else { &tonal };
+    if ym_channels.is_empty() {
+        return None;
+    }
+
+    let voice_count = ym_channels.len();
+    let voice_index = ctx.allocation.current_voice.entry(channel).or_insert(0);
+    let start = *voice_index % voice_count;
+    let free_offset = (0..voice_count)
+        .find(|offset| {
+            let candidate = ym_channels[(start + offset) % voice_count];
+            !ctx.active_notes
+                .iter()
+                .any(|(ym_ch, _)| *ym_ch == candidate)
+        })
+        .unwrap_or(0);
+    *voice_index = (start + free_offset + 1) % voice_count;
+    Some(ym_channels[(start + free_offset) % voice_count])
+}
+
+/// Noise frequency of a note-on, if it plays with the noise generator
+///
+/// Only when the allocation keeps YM2151 channel 7 for noise notes.
+fn note_noise(channel: u8, note: u8, ctx: &EventProcessorContext) -> Option<u8> {
+    if !ctx.allocation.noise {
+        return None;
+    }
+    let program = ctx
+        .midi_channel_states
+        .get(&channel)
+        .map_or(0, |state| state.program);
+    ctx.options?.noise_for(channel, program, note)
+}
+
+/// Switch the noise generator on (with `noise` as NFRQ) or off for the next
+/// note on YM2151 channel 7, if it changes
+///
+/// NE stays on after a noise note so its release still sounds; the next
+/// tonal note on the channel switches it off.
+fn noise_register_event(
+    noise: Option<u8>,
+    time_seconds: f64,
+    ctx: &mut EventProcessorContext,
+) -> Option<Ym2151Event> {
+    if !ctx.allocation.noise {
+        return None;
+    }
+    let data = noise.map_or(0, |frequency| NOISE_ENABLE | frequency);
+    let registers = &mut ctx
+        .ym2151_channel_states
+        .entry(NOISE_CHANNEL)
+        .or_default()
+        .registers;
+    if registers.get(&NOISE_REGISTER).copied().unwrap_or(0) == data {
+        return None;
+    }
+    registers.insert(NOISE_REGISTER, data);
+    Some(Ym2151Event {
+        time: time_seconds,
+        addr: format!("0x{:02X}", NOISE_REGISTER),
+        data: format!("0x{:02X}", data),
+    })
+}
+
+/// YM2151 channel for a drum map voice group of MIDI `channel`
+///
+/// With static allocation each group keeps one of the channel's voices;
+/// with dynamic allocation a group reuses the voice it last played on while
+/// the channel still owns it.
+fn drum_group_voice(channel: u8, group: u8, ctx: &EventProcessorContext) -> Option<u8> {
+    let ym_channels = ctx.allocation.midi_to_ym2151.get(&channel)?;
+    if ctx.allocation.dynamic.is_none() {
+        return ym_channels
+            .get(group as usize % ym_channels.len().max(1))
+            .copied();
+    }
+    let last_voice = *ctx
+        .midi_channel_states
+        .get(&channel)?
+        .drum_groups
+        .get(&group)?;
+    ym_channels.contains(&last_voice).then_some(last_voice)
+}
+
+/// Pick a voice from the dynamic pool for a note-on on MIDI `channel`
+///
+/// A noise note takes YM2151 channel 7 and a drum note of a voice group
+/// takes the group's voice if the channel still owns it. A stolen voice is
+/// keyed off first, and a voice changing hands is brought in line with its
+/// new MIDI channel. Those writes go to `events`. Returns `None` when the
+/// stealing policy leaves the note unplayed.
+fn take_dynamic_voice(
+    channel: u8,
+    note: u8,
+    velocity: u8,
+    ticks: u32,
+    time_seconds: f64,
+    ctx: &mut EventProcessorContext,
+    events: &mut Vec<Ym2151Event>,
+) -> Option<u8> {
+    let forced_voice = note_noise(channel, note, ctx)
+        .map(|_| NOISE_CHANNEL)
+        .or_else(|| {
+            ctx.options
+                .and_then(|options| options.drum_note(channel, note))
+                .and_then(|drum| drum.group)
+                .and_then(|group| drum_group_voice(channel, group, ctx))
+        });
+    let ym2151_channel = match forced_voice {
+        Some(ym2151_channel) => ym2151_channel,
+        None => {
+            let dynamic = ctx.allocation.dynamic.as_ref()?;
+            let sounding: HashSet<u8> = ctx.active_notes.iter().map(|&(ym_ch, _)| ym_ch).collect();
+            let held: HashSet<u8> = ctx
+                .midi_channel_states
+                .values()
+                .flat_map(|state| state.held_notes.iter().map(|&(ym_ch, _)| ym_ch))
+                .collect();
+            let states = &*ctx.midi_channel_states;
+            dynamic.pick(channel, ctx.allocation.noise, &sounding, &held, |voice| {
+                let gain_attenuation = states
+                    .get(&voice.channel)
+                    .map_or(0.0, MidiChannelState::gain_attenuation);
+                voice.velocity as f64 * 10f64.powf(-gain_attenuation * TL_STEP_DB / 20.0)
+            })?
+        }
+    };
+
+    // Steal: key off whatever still sounds on the voice, pedal-held or not
+    let stolen: Vec<u8> = ctx
+        .active_notes
+        .iter()
+        .filter(|&&(ym_ch, _)| ym_ch == ym2151_channel)
+        .map(|&(_, stolen_note)| stolen_note)
+        .collect();
+    for state in ctx.midi_channel_states.values_mut() {
+        state
+            .held_notes
+            .retain(|&(ym_ch, _)| ym_ch != ym2151_channel);
+    }
+    for stolen_note in stolen {
+        events.push(release_voice(
+            ym2151_channel,
+            stolen_note,
+            ticks,
+            time_seconds,
+            ctx,
+        ));
+    }
+
+    let dynamic = ctx.allocation.dynamic.as_mut()?;
+    if let Some(previous_owner) = dynamic.start(ym2151_channel, channel, note, velocity, ticks) {
+        if let Some(owned) =
+            previous_owner.and_then(|owner| ctx.allocation.midi_to_ym2151.get_mut(&owner))
+        {
+            owned.retain(|&ym_ch| ym_ch != ym2151_channel);
+        }
+        ctx.allocation
+            .midi_to_ym2151
+            .entry(channel)
+            .or_default()
+            .push(ym2151_channel);
+        events.extend(hand_over_voice(channel, ym2151_channel, time_seconds, ctx));
+    }
+    Some(ym2151_channel)
+}
+
+/// Bring a voice taken over by MIDI `channel` in line with it
+///
+/// The channel's program is loaded if the voice plays another one;
+/// otherwise only its pan and vibrato depth are set.
+fn hand_over_voice(
+    channel: u8,
+    ym2151_channel: u8,
+    time_seconds: f64,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let program = ctx
+        .midi_channel_states
+        .get(&channel)
+        .map_or(0, |state| state.program);
+    if ctx
+        .channel_programs
+        .get(&ym2151_channel)
+        .copied()
+        .unwrap_or(0)
+        != program
+    {
+        return program_tone_events(channel, ym2151_channel, program, time_seconds, ctx);
+    }
+
+    let mut events = Vec::new();
+    if let Some(rl) = channel_rl_bits(channel, ctx) {
+        events.extend(voice_register_bits_event(
+            time_seconds,
+            ym2151_channel,
+            0x20,
+            RL_MASK,
+            rl,
+            ctx,
+        ));
+    }
+    if let Some(pms) = voice_pms_bits(channel, ym2151_channel, ctx) {
+        events.extend(voice_register_bits_event(
+            time_seconds,
+            ym2151_channel,
+            0x38,
+            PMS_MASK,
+            pms,
+            ctx,
+        ));
+    }
+    events
+}
+
+/// Compute carrier TL writes for `ym2151_channel` played by MIDI `channel`
+///
+/// Each carrier starts from the tone's base TL, attenuated by the latest
+/// note's velocity, by the MIDI channel's volume and expression and, for
+/// programs that route aftertouch to a swell, by the missing pressure. Only
+/// registers whose value changes are written, so files without velocity
+/// sensitivity or volume controllers produce no extra writes.
+fn carrier_level_events(
+    channel: u8,
+    ym2151_channel: u8,
+    time_seconds: f64,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let gain_attenuation = ctx
+        .midi_channel_states
+        .get(&channel)
+        .map(MidiChannelState::gain_attenuation)
+        .unwrap_or(0.0);
+    let swell_attenuation = match voice_aftertouch(ym2151_channel, ctx) {
+        Some(routing) if routing.target == AftertouchTarget::Swell => {
+            let pressure = voice_pressure(channel, ym2151_channel, ctx);
+            routing.depth().max(0.0) * (1.0 - pressure as f64 / 127.0)
+        }
+        _ => 0.0,
+    };
+
+    let Some(state) = ctx.ym2151_channel_states.get_mut(&ym2151_channel) else {
+        return Vec::new();
+    };
+    let attenuation = state.velocity_attenuation + gain_attenuation + swell_attenuation;
+
+    let mut events = Vec::new();
+    for &operator in carrier_operators(state.con()) {
+        let tl = attenuate_tl(state.base_tl[operator], attenuation);
+        if tl == state.current_tl[operator] {
+            continue;
+        }
+        state.current_tl[operator] = tl;
+        events.push(Ym2151Event {
+            time: time_seconds,
+            addr: format!("0x{:02X}", 0x60 + ym2151_channel + (operator as u8) * 8),
+            data: format!("0x{:02X}", tl),
+        });
+    }
+    events
+}
+
+/// Mark a voice as finished: drop it from the active set and record its note span
+fn finish_note(
+    ym2151_channel: u8,
+    note: u8,
+    ticks: u32,
+    time_seconds: f64,
+    ctx: &mut EventProcessorContext,
+) {
+    ctx.active_notes.remove(&(ym2151_channel, note));
+    if let Some(dynamic) = ctx.allocation.dynamic.as_mut() {
+        dynamic.release(ym2151_channel, note, ticks);
+    }
+    for state in ctx.midi_channel_states.values_mut() {
+        state.sostenuto_notes.remove(&(ym2151_channel, note));
+    }
+    if let (Some(active_map), Some(completed)) = (
+        ctx.vibrato_active_notes.as_deref_mut(),
+        ctx.vibrato_completed_notes.as_deref_mut(),
+    ) {
+        if let Some(note_on) = active_map.remove(&(ym2151_channel, note)) {
+            completed.push(NoteSegment {
+                ym2151_channel,
+                note,
+                start_tick: note_on.start_tick,
+                end_tick: ticks,
+                start_time: note_on.start_time,
+                end_time: time_seconds,
+                program: note_on.program,
+                fixed_pitch: note_on.fixed_pitch,
+                pitch_bends: note_on.pitch_bends,
+            });
+        }
+    }
+}
+
+/// Key off a voice and mark it as finished
+fn release_voice(
+    ym2151_channel: u8,
+    note: u8,
+    ticks: u32,
+    time_seconds: f64,
+    ctx: &mut EventProcessorContext,
+) -> Ym2151Event {
+    finish_note(ym2151_channel, note, ticks, time_seconds, ctx);
+    Ym2151Event {
+        time: time_seconds,
+        addr: "0x08".to_string(),
+        data: format!("0x{:02X}", ym2151_channel),
+    }
+}
+
 /// Process a Note Off MIDI event
 ///
 /// Converts a MIDI Note Off event to a YM2151 Key OFF register write.
+/// While the damper pedal is down, or the note was caught by sostenuto,
+/// the key-off is deferred until the pedal is released.
 ///
 /// # Arguments
 /// * `ticks` - MIDI tick time
@@ -178,42 +960,304 @@ pub fn process_note_off(
         return events;
     };
 
-    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.ticks_per_beat, ctx.tempo_map);
+    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+    let state = ctx.midi_channel_states.entry(channel).or_default();
 
-    // Find which YM2151 channel has this note active and turn it off
-    for &ym2151_channel in ym_channels {
-        if ctx.active_notes.contains(&(ym2151_channel, note)) {
-            // Key OFF
-            events.push(Ym2151Event {
-                time: time_seconds,
-                addr: "0x08".to_string(),
-                data: format!("0x{:02X}", ym2151_channel),
-            });
+    // Find which YM2151 channel has this note active (and not already released)
+    let Some(ym2151_channel) = ym_channels.iter().copied().find(|&ym_ch| {
+        ctx.active_notes.contains(&(ym_ch, note)) && !state.held_notes.contains(&(ym_ch, note))
+    }) else {
+        return events;
+    };
+
+    if state.is_pedal_held((ym2151_channel, note)) {
+        state.held_notes.push((ym2151_channel, note));
+        return events;
+    }
+
+    // Key OFF (only one voice)
+    events.push(release_voice(
+        ym2151_channel,
+        note,
+        ticks,
+        time_seconds,
+        ctx,
+    ));
+
+    events
+}
 
-            ctx.active_notes.remove(&(ym2151_channel, note));
-            if let (Some(active_map), Some(completed)) = (
-                ctx.vibrato_active_notes.as_deref_mut(),
-                ctx.vibrato_completed_notes.as_deref_mut(),
-            ) {
-                if let Some(note_on) = active_map.remove(&(ym2151_channel, note)) {
-                    completed.push(NoteSegment {
+/// Process a Control Change MIDI event
+///
+/// Handles channel volume (CC7) and expression (CC11), which re-level the
+/// carriers of every YM2151 channel assigned to the MIDI channel at once,
+/// pan (CC10), which sets their speaker bits while keeping FB/CON, the
+/// modulation wheel (CC1), which sets their PMS in hardware vibrato mode,
+/// and the damper (CC64) and sostenuto (CC66) pedals. Releasing a pedal
+/// sends the key-offs that were deferred while it was held. Attachment
+/// controller mappings are applied here as well (see
+/// [`mapped_field_events`]).
+///
+/// # Arguments
+/// * `ticks` - MIDI tick time
+/// * `channel` - MIDI channel
+/// * `controller` - Controller number
+/// * `value` - Controller value (0-127)
+/// * `ctx` - Event processor context
+///
+/// # Returns
+/// Vector of YM2151 register write events
+pub fn process_control_change(
+    ticks: u32,
+    channel: u8,
+    controller: u8,
+    value: u8,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let mut events = mapped_field_events(
+        ticks,
+        channel,
+        ControllerSource::Cc(controller),
+        value as f64 / 127.0,
+        ctx,
+    );
+    let pressed = value >= 64;
+    events.extend(match controller {
+        CC_VOLUME | CC_EXPRESSION => {
+            let state = ctx.midi_channel_states.entry(channel).or_default();
+            if controller == CC_VOLUME {
+                state.volume = value;
+            } else {
+                state.expression = value;
+            }
+            let ym_channels = ctx
+                .allocation
+                .midi_to_ym2151
+                .get(&channel)
+                .cloned()
+                .unwrap_or_default();
+            let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+            ym_channels
+                .into_iter()
+                .flat_map(|ym2151_channel| {
+                    carrier_level_events(channel, ym2151_channel, time_seconds, ctx)
+                })
+                .collect()
+        }
+        CC_PAN => {
+            ctx.midi_channel_states.entry(channel).or_default().pan = Some(value);
+            match channel_rl_bits(channel, ctx) {
+                Some(rl) => channel_register_bits_events(ticks, channel, 0x20, RL_MASK, rl, ctx),
+                None => Vec::new(),
+            }
+        }
+        CC_MODULATION => {
+            ctx.midi_channel_states
+                .entry(channel)
+                .or_default()
+                .modulation = Some(value);
+            let ym_channels = ctx
+                .allocation
+                .midi_to_ym2151
+                .get(&channel)
+                .cloned()
+                .unwrap_or_default();
+            let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+            ym_channels
+                .into_iter()
+                .filter_map(|ym2151_channel| {
+                    let pms = voice_pms_bits(channel, ym2151_channel, ctx)?;
+                    voice_register_bits_event(
+                        time_seconds,
                         ym2151_channel,
-                        note,
-                        start_tick: note_on.start_tick,
-                        end_tick: ticks,
-                        start_time: note_on.start_time,
-                        end_time: time_seconds,
-                        program: note_on.program,
-                    });
-                }
+                        0x38,
+                        PMS_MASK,
+                        pms,
+                        ctx,
+                    )
+                })
+                .collect()
+        }
+        CC_SUSTAIN => {
+            let state = ctx.midi_channel_states.entry(channel).or_default();
+            state.sustain = pressed;
+            release_unheld_notes(ticks, channel, ctx)
+        }
+        CC_SOSTENUTO => {
+            let sounding: HashSet<(u8, u8)> = ctx
+                .allocation
+                .midi_to_ym2151
+                .get(&channel)
+                .map(|ym_channels| {
+                    ctx.active_notes
+                        .iter()
+                        .filter(|(ym_ch, _)| ym_channels.contains(ym_ch))
+                        .copied()
+                        .collect()
+                })
+                .unwrap_or_default();
+            let state = ctx.midi_channel_states.entry(channel).or_default();
+            if pressed && !state.sostenuto {
+                // Only the notes sounding at the moment of pressing are caught
+                state.sostenuto_notes = sounding;
+            } else if !pressed {
+                state.sostenuto_notes.clear();
             }
-            break; // Only turn off one voice
+            state.sostenuto = pressed;
+            release_unheld_notes(ticks, channel, ctx)
         }
+        _ => Vec::new(),
+    });
+    events
+}
+
+/// Apply attachment controller mappings for a CC or NRPN on MIDI `channel`
+///
+/// `position` is the controller value scaled to 0.0-1.0. Mapped fields go
+/// through the channel's register shadow, so later pan, volume and velocity
+/// writes build on them. A mapped TL becomes the operator's base TL, so the
+/// velocity, volume and swell attenuation of carriers is applied on top of
+/// it, now and at later note-ons; a mapped CON re-levels the operators that
+/// become carriers or modulators.
+fn mapped_field_events(
+    ticks: u32,
+    channel: u8,
+    source: ControllerSource,
+    position: f64,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let Some(options) = ctx.options else {
+        return Vec::new();
+    };
+    let mappings: Vec<_> = options
+        .controller_mappings
+        .iter()
+        .filter(|mapping| {
+            mapping.source == source && mapping.channel.is_none_or(|ch| ch == channel)
+        })
+        .collect();
+    if mappings.is_empty() {
+        return Vec::new();
     }
 
+    let ym_channels = ctx
+        .allocation
+        .midi_to_ym2151
+        .get(&channel)
+        .cloned()
+        .unwrap_or_default();
+    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+    let mut events = Vec::new();
+    for ym2151_channel in ym_channels {
+        let state = ctx.ym2151_channel_states.entry(ym2151_channel).or_default();
+        let mut relevel = false;
+        for mapping in &mappings {
+            let (base_addr, field) = named_register_field(mapping.field);
+            let [from, to] = mapping.range.unwrap_or([0, field.max_value()]);
+            let value = (from as f64 + (to as f64 - from as f64) * position)
+                .round()
+                .clamp(0.0, field.max_value() as f64) as u8;
+            if mapping.field == RegisterField::Tl {
+                let operators: Vec<usize> = if mapping.operators.is_empty() {
+                    (0..4).collect()
+                } else {
+                    mapping.operators.iter().map(|&op| op as usize).collect()
+                };
+                for operator in operators {
+                    state.base_tl[operator] = value;
+                }
+                relevel = true;
+                continue;
+            }
+            for slot in field_registers(base_addr, &mapping.operators) {
+                let addr = slot + ym2151_channel;
+                let current = state.register(addr);
+                let data = field.insert(current, value);
+                if data == current {
+                    continue;
+                }
+                let event = Ym2151Event {
+                    time: time_seconds,
+                    addr: format!("0x{:02X}", addr),
+                    data: format!("0x{:02X}", data),
+                };
+                state.apply_tone_events(std::slice::from_ref(&event));
+                events.push(event);
+                relevel |= mapping.field == RegisterField::Con;
+            }
+        }
+        if !relevel {
+            continue;
+        }
+        // Modulators play their base TL as is; carriers are re-levelled below
+        let carriers = carrier_operators(state.con());
+        for operator in 0..4 {
+            let tl = state.base_tl[operator];
+            if carriers.contains(&operator) || tl == state.current_tl[operator] {
+                continue;
+            }
+            state.current_tl[operator] = tl;
+            events.push(Ym2151Event {
+                time: time_seconds,
+                addr: format!("0x{:02X}", 0x60 + ym2151_channel + (operator as u8) * 8),
+                data: format!("0x{:02X}", tl),
+            });
+        }
+        events.extend(carrier_level_events(
+            channel,
+            ym2151_channel,
+            time_seconds,
+            ctx,
+        ));
+    }
     events
 }
 
+/// Process an NRPN data entry
+///
+/// NRPNs only drive attachment controller mappings (see
+/// [`mapped_field_events`]).
+pub fn process_nrpn(
+    ticks: u32,
+    channel: u8,
+    parameter: u16,
+    value: u16,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    mapped_field_events(
+        ticks,
+        channel,
+        ControllerSource::Nrpn(parameter),
+        value as f64 / NRPN_VALUE_MAX,
+        ctx,
+    )
+}
+
+/// Key off every deferred voice on `channel` that no pedal holds anymore
+fn release_unheld_notes(
+    ticks: u32,
+    channel: u8,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let Some(state) = ctx.midi_channel_states.get_mut(&channel) else {
+        return Vec::new();
+    };
+    let (still_held, released): (Vec<_>, Vec<_>) = std::mem::take(&mut state.held_notes)
+        .into_iter()
+        .partition(|&voice| state.is_pedal_held(voice));
+    state.held_notes = still_held;
+
+    if released.is_empty() {
+        return Vec::new();
+    }
+
+    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+    released
+        .into_iter()
+        .map(|(ym2151_channel, note)| release_voice(ym2151_channel, note, ticks, time_seconds, ctx))
+        .collect()
+}
+
 /// Process a Program Change MIDI event
 ///
 /// Converts a MIDI Program Change event to YM2151 tone register writes.
@@ -233,42 +1277,395 @@ pub fn process_program_change(
     program: u8,
     ctx: &mut EventProcessorContext,
 ) -> Vec<Ym2151Event> {
-    let mut events = Vec::new();
+    ctx.midi_channel_states.entry(channel).or_default().program = program;
 
     // Get allocated YM2151 channel(s) for this MIDI channel
-    let Some(ym_channels) = ctx.allocation.midi_to_ym2151.get(&channel) else {
-        return events;
+    let Some(ym_channels) = ctx.allocation.midi_to_ym2151.get(&channel).cloned() else {
+        return Vec::new();
     };
 
-    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.ticks_per_beat, ctx.tempo_map);
+    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
 
     // Apply program change to all allocated YM2151 channels for this MIDI channel
-    for &ym2151_channel in ym_channels {
-        let load_or_default = || match load_tone_for_program(program) {
-            Ok(Some(tone)) => apply_tone_to_channel(&tone, ym2151_channel, time_seconds),
-            Ok(None) | Err(_) => default_tone_events(ym2151_channel, time_seconds),
-        };
+    ym_channels
+        .into_iter()
+        .flat_map(|ym2151_channel| {
+            program_tone_events(channel, ym2151_channel, program, time_seconds, ctx)
+        })
+        .collect()
+}
+
+/// Load the tone of `program` onto a YM2151 channel played by MIDI `channel`
+fn program_tone_events(
+    channel: u8,
+    ym2151_channel: u8,
+    program: u8,
+    time_seconds: f64,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let load_or_default = || match load_tone_for_program(program) {
+        Ok(Some(tone)) => apply_tone_to_channel(&tone, ym2151_channel, time_seconds),
+        Ok(None) | Err(_) => default_tone_events(ym2151_channel, time_seconds),
+    };
+
+    // Prefer tone definitions supplied via attachment JSON, fallback to file or default tone
+    let mut tone_events = if let Some(tone_map) = ctx.attachment_tones {
+        tone_map
+            .get(&program)
+            .map(|tone| apply_tone_to_channel(tone, ym2151_channel, time_seconds))
+            .unwrap_or_else(load_or_default)
+    } else {
+        load_or_default()
+    };
+
+    // Update the channel's current program
+    ctx.channel_programs.insert(ym2151_channel, program);
+
+    if let Some(options) = ctx.options {
+        let current = ctx
+            .ym2151_channel_states
+            .get(&ym2151_channel)
+            .map_or(0, |state| state.pms_ams);
+        set_lfo_sensitivity(
+            &mut tone_events,
+            ym2151_channel,
+            current,
+            options,
+            program,
+            time_seconds,
+        );
+    }
+    fit_tone_events(channel, ym2151_channel, &mut tone_events, ctx);
+    ctx.ym2151_channel_states
+        .entry(ym2151_channel)
+        .or_default()
+        .drum_tone = None;
+    tone_events
+}
+
+/// Fit tone writes for a YM2151 channel to MIDI `channel` and track them
+///
+/// The channel's pan and vibrato depth are kept: the tone only decides
+/// FB/CON and AMS. The tone's levels are recorded so note-on scaling starts
+/// from them.
+fn fit_tone_events(
+    channel: u8,
+    ym2151_channel: u8,
+    tone_events: &mut [Ym2151Event],
+    ctx: &mut EventProcessorContext,
+) {
+    if let Some(rl) = channel_rl_bits(channel, ctx) {
+        override_tone_bits(tone_events, 0x20 + ym2151_channel, RL_MASK, rl);
+    }
+    if let Some(pms) = voice_pms_bits(channel, ym2151_channel, ctx) {
+        override_tone_bits(tone_events, 0x38 + ym2151_channel, PMS_MASK, pms);
+    }
+    ctx.ym2151_channel_states
+        .entry(ym2151_channel)
+        .or_default()
+        .apply_tone_events(tone_events);
+}
+
+/// Tone writes a note-on needs before it is keyed on
+///
+/// A drum map note with a tone loads it unless the voice already has it; any
+/// other note restores its channel's program tone on a voice that was left
+/// with a drum tone.
+fn note_tone_events(
+    channel: u8,
+    note: u8,
+    ym2151_channel: u8,
+    time_seconds: f64,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let options = ctx.options;
+    let tone = options
+        .and_then(|options| options.drum_note(channel, note))
+        .and_then(|drum| drum.tone.as_ref());
+    let loaded = ctx
+        .ym2151_channel_states
+        .get(&ym2151_channel)
+        .and_then(|state| state.drum_tone);
+
+    match tone {
+        Some(tone) if loaded != Some(note) => {
+            let mut tone_events = apply_tone_to_channel(tone, ym2151_channel, time_seconds);
+            fit_tone_events(channel, ym2151_channel, &mut tone_events, ctx);
+            ctx.ym2151_channel_states
+                .entry(ym2151_channel)
+                .or_default()
+                .drum_tone = Some(note);
+            tone_events
+        }
+        None if loaded.is_some() => {
+            let program = ctx
+                .midi_channel_states
+                .get(&channel)
+                .map_or(0, |state| state.program);
+            program_tone_events(channel, ym2151_channel, program, time_seconds, ctx)
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Process a Channel Pressure (channel aftertouch) MIDI event
+///
+/// Updates the pressure of every voice of the MIDI channel and drives the
+/// aftertouch target of each voice's program, if any.
+///
+/// # Arguments
+/// * `ticks` - MIDI tick time
+/// * `channel` - MIDI channel
+/// * `pressure` - Pressure (0-127)
+/// * `ctx` - Event processor context
+///
+/// # Returns
+/// Vector of YM2151 register write events
+pub fn process_channel_pressure(
+    ticks: u32,
+    channel: u8,
+    pressure: u8,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    ctx.midi_channel_states
+        .entry(channel)
+        .or_default()
+        .channel_pressure = pressure;
+    let ym_channels = ctx
+        .allocation
+        .midi_to_ym2151
+        .get(&channel)
+        .cloned()
+        .unwrap_or_default();
+    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+    ym_channels
+        .into_iter()
+        .flat_map(|ym2151_channel| pressure_events(channel, ym2151_channel, time_seconds, ctx))
+        .collect()
+}
+
+/// Process a Polyphonic Key Pressure (poly aftertouch) MIDI event
+///
+/// Only the YM2151 channel sounding `note` for the MIDI channel is affected;
+/// pressure for a note that is not sounding is ignored.
+///
+/// # Arguments
+/// * `ticks` - MIDI tick time
+/// * `channel` - MIDI channel
+/// * `note` - MIDI note number
+/// * `pressure` - Pressure (0-127)
+/// * `ctx` - Event processor context
+///
+/// # Returns
+/// Vector of YM2151 register write events
+pub fn process_poly_pressure(
+    ticks: u32,
+    channel: u8,
+    note: u8,
+    pressure: u8,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let Some(ym2151_channel) =
+        ctx.allocation
+            .midi_to_ym2151
+            .get(&channel)
+            .and_then(|ym_channels| {
+                ym_channels
+                    .iter()
+                    .copied()
+                    .find(|&ym_ch| ctx.active_notes.contains(&(ym_ch, note)))
+            })
+    else {
+        return Vec::new();
+    };
+    ctx.ym2151_channel_states
+        .entry(ym2151_channel)
+        .or_default()
+        .key_pressure = pressure;
+    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+    pressure_events(channel, ym2151_channel, time_seconds, ctx)
+}
 
-        // Prefer tone definitions supplied via attachment JSON, fallback to file or default tone
-        let tone_events = if let Some(tone_map) = ctx.attachment_tones {
-            tone_map
-                .get(&program)
-                .map(|tone| apply_tone_to_channel(tone, ym2151_channel, time_seconds))
-                .unwrap_or_else(load_or_default)
-        } else {
-            load_or_default()
-        };
+/// Register writes that bring a voice's aftertouch target up to its current pressure
+fn pressure_events(
+    channel: u8,
+    ym2151_channel: u8,
+    time_seconds: f64,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let Some(routing) = voice_aftertouch(ym2151_channel, ctx) else {
+        return Vec::new();
+    };
+    match routing.target {
+        AftertouchTarget::Swell => carrier_level_events(channel, ym2151_channel, time_seconds, ctx),
+        AftertouchTarget::Vibrato => voice_pms_bits(channel, ym2151_channel, ctx)
+            .and_then(|pms| {
+                voice_register_bits_event(time_seconds, ym2151_channel, 0x38, PMS_MASK, pms, ctx)
+            })
+            .into_iter()
+            .collect(),
+        AftertouchTarget::Field => {
+            let Some(field) = routing.field else {
+                return Vec::new();
+            };
+            let pressure = voice_pressure(channel, ym2151_channel, ctx);
+            let (base_addr, def) = named_register_field(field);
+            let [from, to] = routing.range.unwrap_or([0, def.max_value()]);
+            let value = (from as f64 + (to as f64 - from as f64) * pressure as f64 / 127.0)
+                .round()
+                .clamp(0.0, def.max_value() as f64) as u8;
 
-        // Add the tone change events
-        events.extend(tone_events);
+            let state = ctx.ym2151_channel_states.entry(ym2151_channel).or_default();
+            let mut events = Vec::new();
+            for slot in field_registers(base_addr, &routing.operators) {
+                let addr = slot + ym2151_channel;
+                let current = state.register(addr);
+                let data = def.insert(current, value);
+                if data == current {
+                    continue;
+                }
+                let event = Ym2151Event {
+                    time: time_seconds,
+                    addr: format!("0x{:02X}", addr),
+                    data: format!("0x{:02X}", data),
+                };
+                state.apply_tone_events(std::slice::from_ref(&event));
+                events.push(event);
+            }
+            events
+        }
+    }
+}
 
-        // Update the channel's current program
-        ctx.channel_programs.insert(ym2151_channel, program);
+/// Process a Pitch Bend MIDI event
+///
+/// Re-tunes every sounding voice of the MIDI channel by writing KC/KF
+/// for the bent pitch. Voices whose KC/KF do not change are skipped.
+///
+/// # Arguments
+/// * `ticks` - MIDI tick time
+/// * `channel` - MIDI channel
+/// * `value` - Signed pitch bend value (-8192 to 8191)
+/// * `ctx` - Event processor context
+///
+/// # Returns
+/// Vector of YM2151 register write events
+pub fn process_pitch_bend(
+    ticks: u32,
+    channel: u8,
+    value: i16,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let mut events = Vec::new();
+
+    let state = ctx.midi_channel_states.entry(channel).or_default();
+    let previous_cents = state.pitch_bend_cents();
+    state.pitch_bend = value;
+    let bend_cents = state.pitch_bend_cents();
+
+    let Some(ym_channels) = ctx.allocation.midi_to_ym2151.get(&channel) else {
+        return events;
+    };
+
+    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+    let options = ctx.options;
+    let fixed_pitch = |note: u8| {
+        options
+            .and_then(|options| options.drum_note(channel, note))
+            .and_then(DrumNote::fixed_pitch)
+            .is_some()
+    };
+
+    for &ym2151_channel in ym_channels {
+        let mut notes: Vec<u8> = ctx
+            .active_notes
+            .iter()
+            .filter(|(ym_ch, note)| *ym_ch == ym2151_channel && !fixed_pitch(*note))
+            .map(|(_, note)| *note)
+            .collect();
+        notes.sort_unstable();
+
+        for note in notes {
+            if let Some(note_on) = ctx
+                .vibrato_active_notes
+                .as_deref_mut()
+                .and_then(|active_map| active_map.get_mut(&(ym2151_channel, note)))
+            {
+                note_on.pitch_bends.push((time_seconds, bend_cents));
+            }
+            let (kc, kf) = midi_note_with_offset_to_kc_kf(note, bend_cents);
+            if (kc, kf) == midi_note_with_offset_to_kc_kf(note, previous_cents) {
+                continue;
+            }
+            events.push(Ym2151Event {
+                time: time_seconds,
+                addr: format!("0x{:02X}", 0x28 + ym2151_channel),
+                data: format!("0x{:02X}", kc),
+            });
+            events.push(Ym2151Event {
+                time: time_seconds,
+                addr: format!("0x{:02X}", 0x30 + ym2151_channel),
+                data: format!("0x{:02X}", kf),
+            });
+        }
     }
 
     events
 }
 
+/// Process a Pitch Bend Range (RPN 0) event
+///
+/// Updates the bend sensitivity used by subsequent pitch bend events.
+/// No register writes are produced.
+///
+/// # Arguments
+/// * `channel` - MIDI channel
+/// * `semitones` - Bend range in semitones (RPN 0 data entry MSB)
+/// * `cents` - Additional bend range in cents (RPN 0 data entry LSB)
+/// * `ctx` - Event processor context
+pub fn process_pitch_bend_range(
+    channel: u8,
+    semitones: u8,
+    cents: u8,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let state = ctx.midi_channel_states.entry(channel).or_default();
+    state.pitch_bend_range_cents = semitones as f64 * 100.0 + cents as f64;
+    Vec::new()
+}
+
+/// Process a raw register write (from a register write SysEx)
+///
+/// The write is copied to the log unchanged. Writes to shadowed channel
+/// registers (RL_FB_CONNECT, PMS/AMS, TL) also update the shadow so later
+/// pan, modulation and level changes build on the poked value.
+///
+/// # Arguments
+/// * `ticks` - MIDI tick time
+/// * `addr` - YM2151 register address
+/// * `data` - Value to write
+/// * `ctx` - Event processor context
+pub fn process_register_write(
+    ticks: u32,
+    addr: u8,
+    data: u8,
+    ctx: &mut EventProcessorContext,
+) -> Vec<Ym2151Event> {
+    let time_seconds = ticks_to_seconds_with_tempo_map(ticks, ctx.timing, ctx.tempo_map);
+    let event = Ym2151Event {
+        time: time_seconds,
+        addr: format!("0x{:02X}", addr),
+        data: format!("0x{:02X}", data),
+    };
+    if addr >= 0x20 {
+        ctx.ym2151_channel_states
+            .entry(addr & 0x07)
+            .or_default()
+            .apply_tone_events(std::slice::from_ref(&event));
+    }
+    vec![event]
+}
+
 /// Process a single MIDI event
 ///
 /// Dispatches to the appropriate handler based on event type.
@@ -303,7 +1700,60 @@ pub fn process_event(event: &MidiEvent, ctx: &mut EventProcessorContext) -> Vec<
             ticks,
             channel,
             program,
+            ..
         } => process_program_change(*ticks, *channel, *program, ctx),
+
+        MidiEvent::ControlChange {
+            ticks,
+            channel,
+            controller,
+            value,
+            ..
+        } => process_control_change(*ticks, *channel, *controller, *value, ctx),
+
+        MidiEvent::ChannelPressure {
+            ticks,
+            channel,
+            pressure,
+            ..
+        } => process_channel_pressure(*ticks, *channel, *pressure, ctx),
+
+        MidiEvent::PolyPressure {
+            ticks,
+            channel,
+            note,
+            pressure,
+            ..
+        } => process_poly_pressure(*ticks, *channel, *note, *pressure, ctx),
+
+        MidiEvent::PitchBend {
+            ticks,
+            channel,
+            value,
+            ..
+        } => process_pitch_bend(*ticks, *channel, *value, ctx),
+
+        MidiEvent::PitchBendRange {
+            channel,
+            semitones,
+            cents,
+            ..
+        } => process_pitch_bend_range(*channel, *semitones, *cents, ctx),
+
+        MidiEvent::Nrpn {
+            ticks,
+            channel,
+            parameter,
+            value,
+            ..
+        } => process_nrpn(*ticks, *channel, *parameter, *value, ctx),
+
+        MidiEvent::RegisterWrite {
+            ticks, addr, data, ..
+        } => process_register_write(*ticks, *addr, *data, ctx),
+
+        // Loop points are handled by the converter
+        MidiEvent::LoopStart { .. } | MidiEvent::LoopEnd { .. } => Vec::new(),
     }
 }
 
@@ -10,9 +10,11 @@ fn create_test_context<'a>(
     allocation: &'a mut ChannelAllocation,
     active_notes: &'a mut HashSet<(u8, u8)>,
     channel_programs: &'a mut HashMap<u8, u8>,
+    midi_channel_states: &'a mut HashMap<u8, MidiChannelState>,
+    ym2151_channel_states: &'a mut HashMap<u8, Ym2151ChannelState>,
 ) -> EventProcessorContext<'a> {
     EventProcessorContext {
-        ticks_per_beat,
+        timing: MidiTiming::Metrical { ticks_per_beat },
         tempo_map,
         allocation,
         active_notes,
@@ -20,6 +22,9 @@ fn create_test_context<'a>(
         vibrato_active_notes: None,
         vibrato_completed_notes: None,
         attachment_tones: None,
+        midi_channel_states,
+        ym2151_channel_states,
+        options: None,
     }
 }
 
@@ -33,6 +38,8 @@ fn test_process_note_on_basic() {
     let mut allocation = allocate_channels(&polyphony);
     let mut active_notes = HashSet::new();
     let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
 
     let mut ctx = create_test_context(
         480,
@@ -40,6 +47,8 @@ fn test_process_note_on_basic() {
         &mut allocation,
         &mut active_notes,
         &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
     );
 
     let events = process_note_on(0, 0, 60, 100, &mut ctx);
@@ -70,6 +79,8 @@ fn test_process_note_on_zero_velocity() {
     let mut allocation = allocate_channels(&polyphony);
     let mut active_notes = HashSet::new();
     let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
 
     let mut ctx = create_test_context(
         480,
@@ -77,6 +88,8 @@ fn test_process_note_on_zero_velocity() {
         &mut allocation,
         &mut active_notes,
         &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
     );
 
     let events = process_note_on(0, 0, 60, 0, &mut ctx);
@@ -96,6 +109,8 @@ fn test_process_note_on_no_allocation() {
     let mut allocation = allocate_channels(&polyphony);
     let mut active_notes = HashSet::new();
     let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
 
     let mut ctx = create_test_context(
         480,
@@ -103,6 +118,8 @@ fn test_process_note_on_no_allocation() {
         &mut allocation,
         &mut active_notes,
         &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
     );
 
     let events = process_note_on(0, 0, 60, 100, &mut ctx);
@@ -121,6 +138,8 @@ fn test_process_note_off_basic() {
     let mut allocation = allocate_channels(&polyphony);
     let mut active_notes = HashSet::new();
     let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
 
     // First, send a note on
     {
@@ -130,6 +149,8 @@ fn test_process_note_off_basic() {
             &mut allocation,
             &mut active_notes,
             &mut channel_programs,
+            &mut midi_channel_states,
+            &mut ym2151_channel_states,
         );
         process_note_on(0, 0, 60, 100, &mut ctx);
     }
@@ -141,6 +162,8 @@ fn test_process_note_off_basic() {
         &mut allocation,
         &mut active_notes,
         &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
     );
 
     let events = process_note_off(480, 0, 60, &mut ctx);
@@ -162,6 +185,8 @@ fn test_process_note_off_no_active_note() {
     let mut allocation = allocate_channels(&polyphony);
     let mut active_notes = HashSet::new();
     let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
 
     let mut ctx = create_test_context(
         480,
@@ -169,6 +194,8 @@ fn test_process_note_off_no_active_note() {
         &mut allocation,
         &mut active_notes,
         &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
     );
 
     // Note off without note on
@@ -188,6 +215,8 @@ fn test_process_program_change_basic() {
     let mut allocation = allocate_channels(&polyphony);
     let mut active_notes = HashSet::new();
     let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
 
     let mut ctx = create_test_context(
         480,
@@ -195,6 +224,8 @@ fn test_process_program_change_basic() {
         &mut allocation,
         &mut active_notes,
         &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
     );
 
     let events = process_program_change(0, 0, 42, &mut ctx);
@@ -216,6 +247,8 @@ fn test_process_program_change_no_allocation() {
     let mut allocation = allocate_channels(&polyphony);
     let mut active_notes = HashSet::new();
     let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
 
     let mut ctx = create_test_context(
         480,
@@ -223,6 +256,8 @@ fn test_process_program_change_no_allocation() {
         &mut allocation,
         &mut active_notes,
         &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
     );
 
     let events = process_program_change(0, 0, 42, &mut ctx);
@@ -238,6 +273,8 @@ fn test_process_event_tempo() {
     let mut allocation = allocate_channels(&polyphony);
     let mut active_notes = HashSet::new();
     let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
 
     let mut ctx = create_test_context(
         480,
@@ -245,11 +282,15 @@ fn test_process_event_tempo() {
         &mut allocation,
         &mut active_notes,
         &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
     );
 
     let event = MidiEvent::Tempo {
         ticks: 0,
         tempo_bpm: 140.0,
+        track: 0,
+        port: None,
     };
 
     let events = process_event(&event, &mut ctx);
@@ -268,6 +309,8 @@ fn test_process_event_note_on() {
     let mut allocation = allocate_channels(&polyphony);
     let mut active_notes = HashSet::new();
     let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
 
     let mut ctx = create_test_context(
         480,
@@ -275,6 +318,8 @@ fn test_process_event_note_on() {
         &mut allocation,
         &mut active_notes,
         &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
     );
 
     let event = MidiEvent::NoteOn {
@@ -282,6 +327,8 @@ fn test_process_event_note_on() {
         channel: 0,
         note: 60,
         velocity: 100,
+        track: 0,
+        port: None,
     };
 
     let events = process_event(&event, &mut ctx);
@@ -289,3 +336,281 @@ fn test_process_event_note_on() {
     // Should produce 3 events: KC, KF, Key ON
     assert_eq!(events.len(), 3);
 }
+
+#[test]
+fn test_process_pitch_bend_retunes_active_note() {
+    let tempo_map = vec![TempoChange {
+        tick: 0,
+        tempo_bpm: 120.0,
+    }];
+    let polyphony = [(0u8, 1usize)].into_iter().collect();
+    let mut allocation = allocate_channels(&polyphony);
+    let mut active_notes = HashSet::new();
+    let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
+
+    let mut ctx = create_test_context(
+        480,
+        &tempo_map,
+        &mut allocation,
+        &mut active_notes,
+        &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
+    );
+
+    process_note_on(0, 0, 60, 100, &mut ctx);
+
+    // Full upward bend with the default ±2 semitone range = D4 (MIDI 62)
+    let events = process_pitch_bend(240, 0, 8191, &mut ctx);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].addr, "0x28");
+    assert_eq!(events[1].addr, "0x30");
+    let (expected_kc, _) = midi_to_kc_kf(62);
+    assert_eq!(events[0].data, format!("0x{:02X}", expected_kc));
+    assert!((events[0].time - 0.25).abs() < 1e-9);
+
+    // Re-sending the same bend value produces no redundant writes
+    let events = process_pitch_bend(300, 0, 8191, &mut ctx);
+    assert!(events.is_empty());
+}
+
+#[test]
+fn test_process_pitch_bend_uses_rpn_range_and_applies_to_next_note_on() {
+    let tempo_map = vec![TempoChange {
+        tick: 0,
+        tempo_bpm: 120.0,
+    }];
+    let polyphony = [(0u8, 1usize)].into_iter().collect();
+    let mut allocation = allocate_channels(&polyphony);
+    let mut active_notes = HashSet::new();
+    let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
+
+    let mut ctx = create_test_context(
+        480,
+        &tempo_map,
+        &mut allocation,
+        &mut active_notes,
+        &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
+    );
+
+    // 12 semitone range, bend down by half the range = -6 semitones
+    process_event(
+        &MidiEvent::PitchBendRange {
+            ticks: 0,
+            channel: 0,
+            semitones: 12,
+            cents: 0,
+            track: 0,
+            port: None,
+        },
+        &mut ctx,
+    );
+    let events = process_event(
+        &MidiEvent::PitchBend {
+            ticks: 0,
+            channel: 0,
+            value: -4096,
+            track: 0,
+            port: None,
+        },
+        &mut ctx,
+    );
+    // No sounding note yet, so nothing to retune
+    assert!(events.is_empty());
+
+    // A note started while bent sounds at the bent pitch
+    let events = process_note_on(0, 0, 66, 100, &mut ctx);
+    let (expected_kc, expected_kf) = midi_to_kc_kf(60);
+    assert_eq!(events[0].data, format!("0x{:02X}", expected_kc));
+    assert_eq!(events[1].data, format!("0x{:02X}", expected_kf));
+}
+
+#[test]
+fn test_sustain_pedal_defers_key_off_until_release() {
+    let tempo_map = vec![TempoChange {
+        tick: 0,
+        tempo_bpm: 120.0,
+    }];
+    let polyphony = [(0u8, 2usize)].into_iter().collect();
+    let mut allocation = allocate_channels(&polyphony);
+    let mut active_notes = HashSet::new();
+    let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
+
+    let mut ctx = create_test_context(
+        480,
+        &tempo_map,
+        &mut allocation,
+        &mut active_notes,
+        &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
+    );
+
+    process_control_change(0, 0, CC_SUSTAIN, 127, &mut ctx);
+    process_note_on(0, 0, 60, 100, &mut ctx);
+
+    // Note off while the pedal is down produces no key-off
+    let events = process_note_off(240, 0, 60, &mut ctx);
+    assert!(events.is_empty());
+    assert!(ctx.active_notes.contains(&(0, 60)));
+
+    // The held voice is still busy, so the next note takes the other voice
+    let events = process_note_on(240, 0, 64, 100, &mut ctx);
+    assert_eq!(events[2].data, "0x79");
+
+    // Releasing the pedal keys off only the released note
+    let events = process_control_change(480, 0, CC_SUSTAIN, 0, &mut ctx);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].addr, "0x08");
+    assert_eq!(events[0].data, "0x00");
+    assert!((events[0].time - 0.5).abs() < 1e-9);
+    assert!(!ctx.active_notes.contains(&(0, 60)));
+    assert!(ctx.active_notes.contains(&(1, 64)));
+}
+
+#[test]
+fn test_sostenuto_holds_only_notes_sounding_when_pressed() {
+    let tempo_map = vec![TempoChange {
+        tick: 0,
+        tempo_bpm: 120.0,
+    }];
+    let polyphony = [(0u8, 2usize)].into_iter().collect();
+    let mut allocation = allocate_channels(&polyphony);
+    let mut active_notes = HashSet::new();
+    let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
+
+    let mut ctx = create_test_context(
+        480,
+        &tempo_map,
+        &mut allocation,
+        &mut active_notes,
+        &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
+    );
+
+    process_note_on(0, 0, 48, 100, &mut ctx);
+    process_control_change(10, 0, CC_SOSTENUTO, 127, &mut ctx);
+    process_note_on(20, 0, 72, 100, &mut ctx);
+
+    // The note played after pressing sostenuto is released normally
+    let events = process_note_off(100, 0, 72, &mut ctx);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].data, "0x01");
+
+    // The caught note is held until sostenuto is released
+    let events = process_note_off(100, 0, 48, &mut ctx);
+    assert!(events.is_empty());
+
+    let events = process_control_change(200, 0, CC_SOSTENUTO, 0, &mut ctx);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].data, "0x00");
+}
+
+#[test]
+fn test_volume_and_expression_relevel_carriers_mid_note() {
+    let tempo_map = vec![TempoChange {
+        tick: 0,
+        tempo_bpm: 120.0,
+    }];
+    let polyphony = [(0u8, 2usize)].into_iter().collect();
+    let mut allocation = allocate_channels(&polyphony);
+    let mut active_notes = HashSet::new();
+    let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
+    // CON=0 tone: only C2 (0x78 + channel) is a carrier
+    for ym_ch in 0..2 {
+        let state: &mut Ym2151ChannelState = ym2151_channel_states.entry(ym_ch).or_default();
+        state.base_tl = [0x20, 0x20, 0x20, 0x04];
+        state.current_tl = state.base_tl;
+    }
+
+    let mut ctx = create_test_context(
+        480,
+        &tempo_map,
+        &mut allocation,
+        &mut active_notes,
+        &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
+    );
+
+    process_note_on(0, 0, 60, 100, &mut ctx);
+
+    // CC7 = 64 is -11.9 dB: 16 TL steps, written to every voice at the CC time
+    let events = process_control_change(240, 0, CC_VOLUME, 64, &mut ctx);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].addr, "0x78");
+    assert_eq!(events[0].data, "0x14");
+    assert_eq!(events[1].addr, "0x79");
+    assert!((events[0].time - 0.25).abs() < 1e-9);
+
+    // Expression adds its own attenuation on top of volume
+    let events = process_control_change(480, 0, CC_EXPRESSION, 64, &mut ctx);
+    assert_eq!(events[0].data, "0x24");
+
+    // Unchanged levels produce no writes
+    let events = process_control_change(480, 0, CC_EXPRESSION, 64, &mut ctx);
+    assert!(events.is_empty());
+
+    // Back to full level restores the tone's own TL
+    process_control_change(720, 0, CC_VOLUME, 127, &mut ctx);
+    let events = process_control_change(720, 0, CC_EXPRESSION, 127, &mut ctx);
+    assert_eq!(events[0].data, "0x04");
+    assert_eq!(ctx.ym2151_channel_states[&0].current_tl[3], 0x04);
+}
+
+#[test]
+fn test_pan_sets_rl_bits_of_every_voice() {
+    let tempo_map = vec![TempoChange {
+        tick: 0,
+        tempo_bpm: 120.0,
+    }];
+    let polyphony = [(0u8, 2usize)].into_iter().collect();
+    let mut allocation = allocate_channels(&polyphony);
+    let mut active_notes = HashSet::new();
+    let mut channel_programs = HashMap::new();
+    let mut midi_channel_states = HashMap::new();
+    let mut ym2151_channel_states = HashMap::new();
+    for ym_ch in 0..2 {
+        ym2151_channel_states.insert(
+            ym_ch,
+            Ym2151ChannelState {
+                rl_fb_con: 0xDC,
+                ..Ym2151ChannelState::default()
+            },
+        );
+    }
+
+    let mut ctx = create_test_context(
+        480,
+        &tempo_map,
+        &mut allocation,
+        &mut active_notes,
+        &mut channel_programs,
+        &mut midi_channel_states,
+        &mut ym2151_channel_states,
+    );
+
+    let events = process_control_change(0, 0, CC_PAN, 127, &mut ctx);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].addr, "0x20");
+    assert_eq!(events[0].data, "0x9C");
+    assert_eq!(events[1].addr, "0x21");
+
+    // Same side again writes nothing; center restores both speakers
+    assert!(process_control_change(0, 0, CC_PAN, 120, &mut ctx).is_empty());
+    let events = process_control_change(0, 0, CC_PAN, 64, &mut ctx);
+    assert_eq!(events[0].data, "0xDC");
+}
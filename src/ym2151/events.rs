@@ -2,6 +2,8 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::midi::TextKind;
+
 /// Represents a YM2151 register write event
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Ym2151Event {
@@ -20,4 +22,87 @@ pub struct Ym2151Log {
     pub event_count: usize,
     /// List of YM2151 register write events
     pub events: Vec<Ym2151Event>,
+    /// Loop point, when the song has loop markers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_point: Option<Ym2151LoopPoint>,
+    /// Song metadata, when the MIDI file has any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Ym2151Metadata>,
+}
+
+/// Loop point of a YM2151 log
+///
+/// A player that reaches `end_time` keys off all channels, restores
+/// `registers` and continues from `start_time`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Ym2151LoopPoint {
+    /// Loop start time in seconds
+    pub start_time: f64,
+    /// Loop end time in seconds (end of the last pass when the loop is unrolled)
+    pub end_time: f64,
+    /// Last value written to each register before `start_time`, by address
+    /// (key on/off writes excluded)
+    pub registers: Vec<Ym2151Event>,
+}
+
+/// Song metadata passed through from the MIDI file, timed in seconds
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Ym2151Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copyright: Option<String>,
+    /// Track names indexed by track; `None` for unnamed tracks
+    #[serde(default)]
+    pub track_names: Vec<Option<String>>,
+    /// Start time of every bar up to the end of the song (metrical files only)
+    #[serde(default)]
+    pub bar_times: Vec<f64>,
+    #[serde(default)]
+    pub time_signatures: Vec<Ym2151TimeSignature>,
+    #[serde(default)]
+    pub key_signatures: Vec<Ym2151KeySignature>,
+    /// Text, copyright, track name and lyric events
+    #[serde(default)]
+    pub texts: Vec<Ym2151Text>,
+}
+
+/// Time signature change
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Ym2151TimeSignature {
+    pub time: f64,
+    pub numerator: u8,
+    /// Note value of one beat (4 = quarter note)
+    pub denominator: u8,
+}
+
+/// Key signature change
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Ym2151KeySignature {
+    pub time: f64,
+    /// Sharps (positive) or flats (negative)
+    pub sharps: i8,
+    pub minor: bool,
+}
+
+/// Text meta event
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Ym2151Text {
+    pub time: f64,
+    pub track: u16,
+    pub kind: TextKind,
+    pub text: String,
+}
+
+/// Parse a register address or data string ("0x4E", "0X4E" or decimal "78")
+pub(crate) fn parse_hex_byte(value: &str) -> Option<u8> {
+    let trimmed = value.trim();
+    if let Some(hex) = trimmed
+        .strip_prefix("0x")
+        .or_else(|| trimmed.strip_prefix("0X"))
+    {
+        u8::from_str_radix(hex, 16).ok()
+    } else {
+        trimmed.parse::<u8>().ok()
+    }
 }
@@ -3,6 +3,7 @@
 //! Provides initialization sequences for YM2151 channels.
 
 use crate::ym2151::Ym2151Event;
+use crate::HardwareLfo;
 
 /// Generate initialization events for a YM2151 channel
 ///
@@ -98,6 +99,91 @@ pub fn initialize_channel_events(channel: u8, time: f64) -> Vec<Ym2151Event> {
     events
 }
 
+/// LFO frequency (LFRQ) for modulation wheel vibrato, roughly 6 Hz
+pub const VIBRATO_LFRQ: u8 = 0xCD;
+
+/// Generate the global LFO setup for hardware modulation wheel vibrato
+///
+/// Sets a triangle LFO at vibrato rate with full phase modulation depth (PMD),
+/// so each channel's depth is controlled only by its PMS bits.
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::ym2151::hardware_vibrato_lfo_events;
+/// let events = hardware_vibrato_lfo_events(0.0);
+/// assert_eq!(events.last().unwrap().addr, "0x19");
+/// ```
+pub fn hardware_vibrato_lfo_events(time: f64) -> Vec<Ym2151Event> {
+    vec![
+        // LFRQ: LFO frequency
+        Ym2151Event {
+            time,
+            addr: "0x18".to_string(),
+            data: format!("0x{:02X}", VIBRATO_LFRQ),
+        },
+        // CT/W: LFO waveform 2 = triangle
+        Ym2151Event {
+            time,
+            addr: "0x1B".to_string(),
+            data: "0x02".to_string(),
+        },
+        // PMD/AMD: bit 7 selects PMD, 0x7F = maximum depth
+        Ym2151Event {
+            time,
+            addr: "0x19".to_string(),
+            data: "0xFF".to_string(),
+        },
+    ]
+}
+
+/// Generate the global LFO setup from an attachment's `HardwareLfo`
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::ym2151::hardware_lfo_events;
+/// use smf_to_ym2151log::HardwareLfo;
+///
+/// let lfo = HardwareLfo { rate: 200, pmd: 20, ..HardwareLfo::default() };
+/// let events = hardware_lfo_events(&lfo, 0.0);
+/// assert_eq!(events[0].data, "0xC8");
+/// ```
+pub fn hardware_lfo_events(lfo: &HardwareLfo, time: f64) -> Vec<Ym2151Event> {
+    let write = |addr: u8, data: u8| Ym2151Event {
+        time,
+        addr: format!("0x{:02X}", addr),
+        data: format!("0x{:02X}", data),
+    };
+    vec![
+        // LFRQ: LFO frequency
+        write(0x18, lfo.rate),
+        // CT/W: LFO waveform
+        write(0x1B, lfo.waveform.register_value()),
+        // PMD/AMD: bit 7 clear selects AMD, set selects PMD
+        write(0x19, lfo.amd & 0x7F),
+        write(0x19, 0x80 | (lfo.pmd & 0x7F)),
+    ]
+}
+
+/// Restart the LFO: pulse bit 1 (LFO reset) of test register 0x01
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::ym2151::lfo_reset_events;
+/// let events = lfo_reset_events(0.0);
+/// assert_eq!(events[0].data, "0x02");
+/// assert_eq!(events[1].data, "0x00");
+/// ```
+pub fn lfo_reset_events(time: f64) -> Vec<Ym2151Event> {
+    ["0x02", "0x00"]
+        .into_iter()
+        .map(|data| Ym2151Event {
+            time,
+            addr: "0x01".to_string(),
+            data: data.to_string(),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -154,4 +240,29 @@ mod tests {
         assert_eq!(tl_events[2].data, "0x7F");
         assert_eq!(tl_events[3].data, "0x7F");
     }
+
+    #[test]
+    fn test_hardware_lfo_events_registers() {
+        let lfo = HardwareLfo {
+            rate: 0x40,
+            waveform: crate::HardwareLfoWaveform::Noise,
+            pmd: 0x7F,
+            amd: 0x10,
+            reset_on_key_on: false,
+        };
+        let events = hardware_lfo_events(&lfo, 0.0);
+        let writes: Vec<(&str, &str)> = events
+            .iter()
+            .map(|e| (e.addr.as_str(), e.data.as_str()))
+            .collect();
+        assert_eq!(
+            writes,
+            [
+                ("0x18", "0x40"),
+                ("0x1B", "0x03"),
+                ("0x19", "0x10"),
+                ("0x19", "0xFF")
+            ]
+        );
+    }
 }
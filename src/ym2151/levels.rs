@@ -0,0 +1,159 @@
+//! Carrier output levels and panning
+//!
+//! Determines which operators are carriers for a connection algorithm,
+//! computes Total Level (TL) values applied on top of a tone's own levels,
+//! and maps MIDI pan to the speaker enable bits of RL_FB_CONNECT.
+
+use crate::{VelocityCurve, VelocitySensitivity};
+
+/// TL value that silences an operator
+pub const TL_MAX: u8 = 0x7F;
+
+/// Attenuation of one TL step in decibels
+pub const TL_STEP_DB: f64 = 0.75;
+
+/// Operator indices (register order M1, M2, C1, C2) that output sound for a CON value
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::ym2151::carrier_operators;
+/// assert_eq!(carrier_operators(0), &[3]); // serial: only C2 is a carrier
+/// assert_eq!(carrier_operators(7), &[0, 1, 2, 3]); // additive: all operators
+/// ```
+pub fn carrier_operators(con: u8) -> &'static [usize] {
+    match con & 0x07 {
+        0..=3 => &[3],
+        4 => &[2, 3],
+        5 | 6 => &[1, 2, 3],
+        _ => &[0, 1, 2, 3],
+    }
+}
+
+/// TL attenuation (in TL steps) for a note-on velocity
+pub fn velocity_attenuation(velocity: u8, sensitivity: &VelocitySensitivity) -> f64 {
+    let normalized = (velocity.min(127) as f64) / 127.0;
+    let level = match sensitivity.curve {
+        VelocityCurve::Linear => normalized,
+        VelocityCurve::Quadratic => normalized * normalized,
+        VelocityCurve::SquareRoot => normalized.sqrt(),
+    };
+    sensitivity.depth.max(0.0) * (1.0 - level)
+}
+
+/// TL attenuation (in TL steps) for a channel volume (CC7) or expression (CC11) value
+///
+/// Uses the GM recommended gain curve of 40·log10(value/127) dB, so volume and
+/// expression combine by adding their attenuations. A value of 0 silences the channel.
+pub fn controller_gain_attenuation(value: u8) -> f64 {
+    if value == 0 {
+        return TL_MAX as f64;
+    }
+    let gain_db = 40.0 * (value.min(127) as f64 / 127.0).log10();
+    -gain_db / TL_STEP_DB
+}
+
+/// Left speaker enable bit of RL_FB_CONNECT (0x20-0x27)
+pub const RL_LEFT: u8 = 0x40;
+/// Right speaker enable bit of RL_FB_CONNECT (0x20-0x27)
+pub const RL_RIGHT: u8 = 0x80;
+/// Both speaker enable bits of RL_FB_CONNECT
+pub const RL_MASK: u8 = RL_LEFT | RL_RIGHT;
+
+/// Default distance from center (64) at which MIDI pan becomes hard left/right
+pub const DEFAULT_PAN_THRESHOLD: u8 = 32;
+
+/// RL bits of RL_FB_CONNECT for a MIDI pan (CC10) value
+///
+/// Pan values at least `threshold` below center play on the left speaker only,
+/// values at least `threshold` above center on the right speaker only, and
+/// everything in between on both.
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::ym2151::{pan_rl_bits, RL_LEFT, RL_MASK, RL_RIGHT};
+/// assert_eq!(pan_rl_bits(0, 32), RL_LEFT);
+/// assert_eq!(pan_rl_bits(64, 32), RL_MASK);
+/// assert_eq!(pan_rl_bits(127, 32), RL_RIGHT);
+/// ```
+pub fn pan_rl_bits(pan: u8, threshold: u8) -> u8 {
+    let threshold = threshold.clamp(1, 63);
+    if pan.saturating_add(threshold) <= 64 {
+        RL_LEFT
+    } else if pan >= 64 + threshold {
+        RL_RIGHT
+    } else {
+        RL_MASK
+    }
+}
+
+/// Add an attenuation (in TL steps) to a base TL, clamped to the register range
+pub fn attenuate_tl(base_tl: u8, attenuation: f64) -> u8 {
+    ((base_tl & TL_MAX) as f64 + attenuation)
+        .round()
+        .clamp(0.0, TL_MAX as f64) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_carrier_operators_by_algorithm() {
+        assert_eq!(carrier_operators(3), &[3]);
+        assert_eq!(carrier_operators(4), &[2, 3]);
+        assert_eq!(carrier_operators(5), &[1, 2, 3]);
+        assert_eq!(carrier_operators(6), &[1, 2, 3]);
+        // Only the CON bits are considered
+        assert_eq!(carrier_operators(0xC7), &[0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_velocity_attenuation_curves() {
+        let linear = VelocitySensitivity {
+            curve: VelocityCurve::Linear,
+            depth: 40.0,
+        };
+        assert_eq!(velocity_attenuation(127, &linear), 0.0);
+        assert!((velocity_attenuation(0, &linear) - 40.0).abs() < 1e-9);
+
+        let quadratic = VelocitySensitivity {
+            curve: VelocityCurve::Quadratic,
+            ..linear.clone()
+        };
+        let square_root = VelocitySensitivity {
+            curve: VelocityCurve::SquareRoot,
+            ..linear.clone()
+        };
+        let mid_linear = velocity_attenuation(64, &linear);
+        assert!(velocity_attenuation(64, &quadratic) > mid_linear);
+        assert!(velocity_attenuation(64, &square_root) < mid_linear);
+    }
+
+    #[test]
+    fn test_controller_gain_attenuation_is_db_accurate() {
+        assert_eq!(controller_gain_attenuation(127), 0.0);
+        // 64/127 is about -11.9 dB, i.e. 16 TL steps of 0.75 dB
+        let half = controller_gain_attenuation(64);
+        assert!((half * TL_STEP_DB - 11.91).abs() < 0.01, "got {half}");
+        assert_eq!(attenuate_tl(0, half), 16);
+        assert_eq!(attenuate_tl(0, controller_gain_attenuation(0)), TL_MAX);
+    }
+
+    #[test]
+    fn test_pan_rl_bits_threshold() {
+        assert_eq!(pan_rl_bits(32, 32), RL_LEFT);
+        assert_eq!(pan_rl_bits(33, 32), RL_MASK);
+        assert_eq!(pan_rl_bits(95, 32), RL_MASK);
+        assert_eq!(pan_rl_bits(96, 32), RL_RIGHT);
+        // A small threshold pans everything but the center hard
+        assert_eq!(pan_rl_bits(63, 1), RL_LEFT);
+        assert_eq!(pan_rl_bits(64, 1), RL_MASK);
+        assert_eq!(pan_rl_bits(65, 1), RL_RIGHT);
+    }
+
+    #[test]
+    fn test_attenuate_tl_clamps() {
+        assert_eq!(attenuate_tl(0x10, 4.4), 0x14);
+        assert_eq!(attenuate_tl(0x7A, 20.0), TL_MAX);
+    }
+}
@@ -7,7 +7,9 @@ pub mod converter;
 pub mod event_processor;
 pub mod events;
 pub mod init;
+pub mod levels;
 pub mod note_table;
+pub mod streaming;
 pub mod tempo_map;
 pub mod tone;
 
@@ -16,6 +18,8 @@ pub use converter::*;
 pub use event_processor::*;
 pub use events::*;
 pub use init::*;
+pub use levels::*;
 pub use note_table::*;
+pub use streaming::*;
 pub use tempo_map::*;
 pub use tone::*;
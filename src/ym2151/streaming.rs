@@ -0,0 +1,432 @@
+//! Streaming YM2151 conversion
+//!
+//! Converts live MIDI input one message at a time. The converter keeps the
+//! same channel allocation, tone, controller and active-note state that
+//! [`EventProcessorContext`] borrows during file conversion, so each message
+//! is turned into the register writes to issue right away.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::error::{Error, Result};
+use crate::midi::{midi_note_with_offset_to_kc_kf, MidiEvent, MidiTiming, SmpteFps};
+use crate::ym2151::converter::initial_channel_events;
+use crate::ym2151::converter::pitch_effects::{
+    pitch_lfo_cents, vibrato_offset_cents, VIBRATO_RELEASE_TAIL_SECONDS,
+};
+use crate::ym2151::converter::register_effects::{
+    register_lfo_value, resolve_register_for_channel,
+};
+use crate::ym2151::events::parse_hex_byte;
+use crate::ym2151::{
+    allocate_channels_with_assignments, allocate_dynamic_voices, is_drum_channel, process_event,
+    reserve_noise_voice, ChannelAllocation, EventProcessorContext, MidiChannelState, NoteOnInfo,
+    NoteSegment, Ym2151ChannelState, Ym2151Event,
+};
+use crate::{
+    ConversionOptions, LfoTarget, ModulationWheelMode, ProgramAttachment, RegisterLfoDefinition,
+};
+
+/// Stream ticks are milliseconds since the first message (25 fps × 40 ticks)
+const STREAM_TIMING: MidiTiming = MidiTiming::Timecode {
+    fps: SmpteFps::Fps25,
+    ticks_per_frame: 40,
+};
+const STREAM_TICKS_PER_SECOND: f64 = 1000.0;
+
+/// The note a YM2151 channel is currently playing, for time-based effects
+struct Voice {
+    midi_channel: Option<u8>,
+    note: u8,
+    start_time: f64,
+    program: u8,
+    /// Drum map note at a fixed pitch, which gets no vibrato
+    fixed_pitch: bool,
+    /// False once the note is released and only its vibrato tail is running
+    sounding: bool,
+}
+
+/// Stateful converter for live MIDI input
+///
+/// Push one timestamped [`MidiEvent`] at a time with [`push`](Self::push)
+/// and issue the returned register writes immediately; call
+/// [`tick`](Self::tick) periodically so delay vibrato, software modulation
+/// wheel vibrato and software register and pitch LFOs advance between messages.
+/// Times are seconds on the caller's clock and must never go backwards;
+/// event tick positions are ignored. The first call also returns the writes
+/// that initialize the chip.
+///
+/// Voices are allocated up front from the polyphony declared per MIDI
+/// channel, as the file converter does after measuring it. Effects that need
+/// to see later notes (portamento, the pop-noise envelope, change-to-next
+/// tone) and controller mappings are only applied by the file converter.
+///
+/// # Example
+/// ```
+/// use smf_to_ym2151log::midi::MidiEvent;
+/// use smf_to_ym2151log::ym2151::StreamingConverter;
+/// use smf_to_ym2151log::ConversionOptions;
+/// use std::collections::HashMap;
+///
+/// let mut converter =
+///     StreamingConverter::new(&HashMap::from([(0, 2)]), ConversionOptions::default()).unwrap();
+/// let note_on = MidiEvent::NoteOn {
+///     ticks: 0,
+///     channel: 0,
+///     note: 60,
+///     velocity: 100,
+///     track: 0,
+///     port: None,
+/// };
+/// let writes = converter.push(0.5, &note_on).unwrap();
+/// assert_eq!(writes.last().unwrap().addr, "0x08");
+/// assert!(converter.tick(0.6).unwrap().is_empty());
+/// ```
+pub struct StreamingConverter {
+    options: ConversionOptions,
+    allocation: ChannelAllocation,
+    active_notes: HashSet<(u8, u8)>,
+    channel_programs: HashMap<u8, u8>,
+    /// Start of every sounding note, keyed by (YM2151 channel, note)
+    note_starts: HashMap<(u8, u8), NoteOnInfo>,
+    /// Released notes whose vibrato tail may still be running
+    released_notes: Vec<NoteSegment>,
+    midi_channel_states: HashMap<u8, MidiChannelState>,
+    ym2151_channel_states: HashMap<u8, Ym2151ChannelState>,
+    /// Last value written to each register by MIDI messages
+    base_registers: HashMap<u8, u8>,
+    /// Last value written to each register, including time-based effects
+    registers: HashMap<u8, u8>,
+    /// Caller time of the first message; stream time counts from here
+    start_time: Option<f64>,
+    last_time: f64,
+}
+
+impl StreamingConverter {
+    /// Create a converter allocating voices for `polyphony` (MIDI channel to
+    /// voice count), with drum channel priority as in
+    /// [`allocate_channels_with_assignments`]
+    ///
+    /// With a `VoiceAllocation` in `options`, voices are picked per note
+    /// instead and `polyphony` is not used.
+    ///
+    /// # Errors
+    /// Returns an error if the channel assignment in `options` conflicts
+    pub fn new(polyphony: &HashMap<u8, usize>, options: ConversionOptions) -> Result<Self> {
+        let assignments = &options.channel_assignment;
+        let mut allocation = match &options.voice_allocation {
+            Some(config) => allocate_dynamic_voices(config, assignments)?,
+            None => allocate_channels_with_assignments(polyphony, assignments)?,
+        };
+        // Without a song to scan, any channel whose notes may need noise
+        // gets the noise channel
+        let noise_parts: BTreeSet<u8> = polyphony
+            .keys()
+            .copied()
+            .filter(|&channel| {
+                options
+                    .program_attachments
+                    .iter()
+                    .any(|pa| pa.noise.is_some())
+                    || (is_drum_channel(channel)
+                        && options.drum_map.values().any(|drum| drum.noise.is_some()))
+            })
+            .collect();
+        reserve_noise_voice(&mut allocation, &noise_parts, assignments)?;
+        Ok(StreamingConverter {
+            options,
+            allocation,
+            active_notes: HashSet::new(),
+            channel_programs: HashMap::new(),
+            note_starts: HashMap::new(),
+            released_notes: Vec::new(),
+            midi_channel_states: HashMap::new(),
+            ym2151_channel_states: HashMap::new(),
+            base_registers: HashMap::new(),
+            registers: HashMap::new(),
+            start_time: None,
+            last_time: 0.0,
+        })
+    }
+
+    /// Channel allocation used for incoming MIDI channels
+    pub fn allocation(&self) -> &ChannelAllocation {
+        &self.allocation
+    }
+
+    /// Convert one MIDI message received at `now` (seconds)
+    ///
+    /// Returns the register writes to issue now, all stamped with `now`.
+    ///
+    /// # Errors
+    /// Returns an error if `now` is not finite or is earlier than the
+    /// previous call
+    pub fn push(&mut self, now: f64, event: &MidiEvent) -> Result<Vec<Ym2151Event>> {
+        let mut events = self.advance_to(now)?;
+        let stream_time = now - self.start_time.unwrap_or(now);
+
+        let mut event = event.clone();
+        *event.ticks_mut() = (stream_time * STREAM_TICKS_PER_SECOND).round() as u32;
+
+        let mut completed = Vec::new();
+        let writes = {
+            let mut ctx = EventProcessorContext {
+                timing: STREAM_TIMING,
+                tempo_map: &[],
+                allocation: &mut self.allocation,
+                active_notes: &mut self.active_notes,
+                channel_programs: &mut self.channel_programs,
+                vibrato_active_notes: Some(&mut self.note_starts),
+                vibrato_completed_notes: Some(&mut completed),
+                attachment_tones: if self.options.tones.is_empty() {
+                    None
+                } else {
+                    Some(&self.options.tones)
+                },
+                midi_channel_states: &mut self.midi_channel_states,
+                ym2151_channel_states: &mut self.ym2151_channel_states,
+                options: Some(&self.options),
+            };
+            process_event(&event, &mut ctx)
+        };
+        self.released_notes.extend(completed);
+
+        for mut write in writes {
+            write.time = now;
+            if let (Some(addr), Some(data)) =
+                (parse_hex_byte(&write.addr), parse_hex_byte(&write.data))
+            {
+                self.base_registers.insert(addr, data);
+                self.registers.insert(addr, data);
+            }
+            events.push(write);
+        }
+        Ok(events)
+    }
+
+    /// Advance time-based effects to `now` (seconds)
+    ///
+    /// Returns the KC/KF writes of vibrato and the register writes of
+    /// software LFOs whose value changed since they were last written.
+    /// Call it at a steady rate, e.g. every few milliseconds.
+    ///
+    /// # Errors
+    /// Returns an error if `now` is not finite or is earlier than the
+    /// previous call
+    pub fn tick(&mut self, now: f64) -> Result<Vec<Ym2151Event>> {
+        let mut events = self.advance_to(now)?;
+        let stream_time = now - self.start_time.unwrap_or(now);
+
+        self.released_notes
+            .retain(|segment| stream_time <= segment.end_time + VIBRATO_RELEASE_TAIL_SECONDS);
+
+        for (ym2151_channel, voice) in self.voices() {
+            let attachment = self.attachment_for(voice.program);
+            let mut writes: Vec<(u8, u8)> = Vec::new();
+
+            if let Some(offset_cents) = self.vibrato_cents(&voice, attachment, stream_time) {
+                let (kc, kf) = midi_note_with_offset_to_kc_kf(voice.note, offset_cents);
+                writes.push((0x28 + ym2151_channel, kc));
+                writes.push((0x30 + ym2151_channel, kf));
+            }
+
+            if voice.sounding {
+                let lfo_defs = self
+                    .lfo_definitions(attachment)
+                    .filter(|def| def.target == LfoTarget::Register);
+                for def in lfo_defs {
+                    if def.rate_hz <= 0.0 || def.depth.abs() < f64::EPSILON {
+                        continue;
+                    }
+                    let Some(base_reg) = parse_hex_byte(&def.base_register) else {
+                        continue;
+                    };
+                    let addr = resolve_register_for_channel(base_reg, ym2151_channel);
+                    let Some(&base_value) = self.base_registers.get(&addr) else {
+                        continue;
+                    };
+                    // Without key-on sync the LFO runs from the start of the stream
+                    let origin = if def.key_on_sync {
+                        voice.start_time + def.delay_seconds
+                    } else {
+                        def.delay_seconds
+                    };
+                    if stream_time < origin {
+                        continue;
+                    }
+                    writes.push((
+                        addr,
+                        register_lfo_value(def, base_value, stream_time - origin),
+                    ));
+                }
+            }
+
+            for (addr, data) in writes {
+                if self.registers.insert(addr, data) == Some(data) {
+                    continue;
+                }
+                events.push(Ym2151Event {
+                    time: now,
+                    addr: format!("0x{:02X}", addr),
+                    data: format!("0x{:02X}", data),
+                });
+            }
+        }
+        Ok(events)
+    }
+
+    /// Check `now` against the previous call; the first call initializes the chip
+    fn advance_to(&mut self, now: f64) -> Result<Vec<Ym2151Event>> {
+        if !now.is_finite() {
+            return Err(Error::InvalidParameter(format!(
+                "stream time {now} is not a finite number of seconds"
+            )));
+        }
+        if self.start_time.is_some() {
+            if now < self.last_time {
+                return Err(Error::InvalidParameter(format!(
+                    "stream time {now} is earlier than the previous call at {}",
+                    self.last_time
+                )));
+            }
+            self.last_time = now;
+            return Ok(Vec::new());
+        }
+
+        self.start_time = Some(now);
+        self.last_time = now;
+        let channels = self.allocation.ym2151_channels();
+        for &ch in &channels {
+            self.channel_programs.insert(ch, 0);
+        }
+        let events = initial_channel_events(
+            &channels,
+            &self.options,
+            now,
+            &mut self.ym2151_channel_states,
+        );
+        for event in &events {
+            if let (Some(addr), Some(data)) =
+                (parse_hex_byte(&event.addr), parse_hex_byte(&event.data))
+            {
+                self.base_registers.insert(addr, data);
+                self.registers.insert(addr, data);
+            }
+        }
+        Ok(events)
+    }
+
+    /// Latest note per YM2151 channel: a sounding note wins over a released one
+    fn voices(&self) -> BTreeMap<u8, Voice> {
+        let mut voices: BTreeMap<u8, Voice> = BTreeMap::new();
+        let mut consider = |ym2151_channel: u8, voice: Voice| {
+            let replace = voices.get(&ym2151_channel).is_none_or(|current| {
+                (voice.sounding, voice.start_time) > (current.sounding, current.start_time)
+            });
+            if replace {
+                voices.insert(ym2151_channel, voice);
+            }
+        };
+        for segment in &self.released_notes {
+            consider(
+                segment.ym2151_channel,
+                Voice {
+                    midi_channel: self.midi_channel_of(segment.ym2151_channel),
+                    note: segment.note,
+                    start_time: segment.start_time,
+                    program: segment.program,
+                    fixed_pitch: segment.fixed_pitch,
+                    sounding: false,
+                },
+            );
+        }
+        for (&(ym2151_channel, note), note_on) in &self.note_starts {
+            consider(
+                ym2151_channel,
+                Voice {
+                    midi_channel: self.midi_channel_of(ym2151_channel),
+                    note,
+                    start_time: note_on.start_time,
+                    program: note_on.program,
+                    fixed_pitch: note_on.fixed_pitch,
+                    sounding: true,
+                },
+            );
+        }
+        voices
+    }
+
+    /// Pitch offset of a voice in cents at `stream_time`, or `None` when it
+    /// has no software vibrato or pitch LFO
+    ///
+    /// The channel's pitch bend is included so vibrato writes keep the bend.
+    fn vibrato_cents(
+        &self,
+        voice: &Voice,
+        attachment: Option<&ProgramAttachment>,
+        stream_time: f64,
+    ) -> Option<f64> {
+        if voice.fixed_pitch {
+            return None;
+        }
+        let delayed = self.options.delay_vibrato || attachment.is_some_and(|pa| pa.delay_vibrato);
+        let state = voice
+            .midi_channel
+            .and_then(|channel| self.midi_channel_states.get(&channel));
+        let wheel_depth = match &self.options.modulation_wheel {
+            Some(wheel) if wheel.mode == ModulationWheelMode::Software => {
+                let value = state.and_then(|state| state.modulation).unwrap_or(0);
+                Some(wheel.max_depth() * value as f64 / 127.0)
+            }
+            _ => None,
+        };
+        let pitch_lfos: Vec<&RegisterLfoDefinition> = self
+            .lfo_definitions(attachment)
+            .filter(|def| def.target == LfoTarget::Pitch && def.rate_hz > 0.0)
+            .collect();
+        if !delayed && wheel_depth.is_none() && pitch_lfos.is_empty() {
+            return None;
+        }
+        let elapsed = stream_time - voice.start_time;
+        let bend_cents = state.map(MidiChannelState::pitch_bend_cents).unwrap_or(0.0);
+        let lfo_cents: f64 = pitch_lfos
+            .iter()
+            .map(|def| pitch_lfo_cents(def, voice.start_time, stream_time))
+            .sum();
+        Some(
+            vibrato_offset_cents(elapsed, delayed, wheel_depth.unwrap_or(0.0))
+                + bend_cents
+                + lfo_cents,
+        )
+    }
+
+    /// Software LFOs on a voice: the global ones, then its program's
+    fn lfo_definitions<'a>(
+        &'a self,
+        attachment: Option<&'a ProgramAttachment>,
+    ) -> impl Iterator<Item = &'a RegisterLfoDefinition> {
+        self.options
+            .software_lfo
+            .iter()
+            .chain(attachment.into_iter().flat_map(|pa| &pa.software_lfo))
+    }
+
+    fn attachment_for(&self, program: u8) -> Option<&ProgramAttachment> {
+        self.options
+            .program_attachments
+            .iter()
+            .find(|pa| pa.program_change == program)
+    }
+
+    fn midi_channel_of(&self, ym2151_channel: u8) -> Option<u8> {
+        self.allocation
+            .midi_to_ym2151
+            .iter()
+            .filter(|(_, ym_channels)| ym_channels.contains(&ym2151_channel))
+            .map(|(&channel, _)| channel)
+            .min()
+    }
+}
+
+#[cfg(test)]
+#[path = "streaming_tests.rs"]
+mod tests;
@@ -0,0 +1,256 @@
+//! Tests for the streaming converter
+use super::*;
+use crate::midi::{MidiData, MidiMetadata};
+use crate::ym2151::convert_to_ym2151_log_with_options;
+use crate::{LfoTarget, LfoWaveform, RegisterLfoDefinition};
+
+fn note_on(channel: u8, note: u8) -> MidiEvent {
+    MidiEvent::NoteOn {
+        ticks: 0,
+        channel,
+        note,
+        velocity: 100,
+        track: 0,
+        port: None,
+    }
+}
+
+fn note_off(channel: u8, note: u8) -> MidiEvent {
+    MidiEvent::NoteOff {
+        ticks: 0,
+        channel,
+        note,
+        track: 0,
+        port: None,
+    }
+}
+
+fn writes(events: &[Ym2151Event]) -> Vec<(&str, &str)> {
+    events
+        .iter()
+        .map(|e| (e.addr.as_str(), e.data.as_str()))
+        .collect()
+}
+
+#[test]
+fn test_stream_matches_file_conversion() {
+    // (seconds, event); at 120 BPM and 480 ticks per beat a second is 960 ticks
+    let stream = vec![
+        (0.0, note_on(0, 60)),
+        (0.0, note_on(0, 64)),
+        (
+            0.25,
+            MidiEvent::ControlChange {
+                ticks: 0,
+                channel: 0,
+                controller: 7,
+                value: 64,
+                track: 0,
+                port: None,
+            },
+        ),
+        (
+            0.5,
+            MidiEvent::PitchBend {
+                ticks: 0,
+                channel: 0,
+                value: 4096,
+                track: 0,
+                port: None,
+            },
+        ),
+        (1.0, note_off(0, 60)),
+        (1.0, note_off(0, 64)),
+        (1.5, note_on(0, 67)),
+        (2.0, note_off(0, 67)),
+    ];
+    let midi_data = MidiData {
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
+        tempo_bpm: 120.0,
+        events: stream
+            .iter()
+            .map(|(seconds, event)| {
+                let mut event = event.clone();
+                *event.ticks_mut() = (seconds * 960.0) as u32;
+                event
+            })
+            .collect(),
+        metadata: MidiMetadata::default(),
+    };
+    let options = ConversionOptions::default();
+    let log = convert_to_ym2151_log_with_options(&midi_data, &options).unwrap();
+
+    let mut converter = StreamingConverter::new(&HashMap::from([(0, 2)]), options).unwrap();
+    let mut streamed = Vec::new();
+    for (seconds, event) in &stream {
+        let events = converter.push(*seconds, event).unwrap();
+        assert!(events.iter().all(|e| e.time == *seconds));
+        streamed.extend(events);
+    }
+
+    assert_eq!(writes(&streamed), writes(&log.events));
+}
+
+#[test]
+fn test_stream_first_call_initializes_chip() {
+    let mut converter =
+        StreamingConverter::new(&HashMap::from([(0, 1)]), ConversionOptions::default()).unwrap();
+
+    let events = converter.tick(3.0).unwrap();
+    let key_offs: Vec<&str> = events
+        .iter()
+        .filter(|e| e.addr == "0x08")
+        .map(|e| e.data.as_str())
+        .collect();
+    assert_eq!(
+        key_offs,
+        ["0x00", "0x01", "0x02", "0x03", "0x04", "0x05", "0x06", "0x07"]
+    );
+    assert!(events.iter().all(|e| e.time == 3.0));
+    assert!(events.iter().any(|e| e.addr == "0x20"));
+
+    // Later calls only return what changed
+    assert!(converter.tick(3.1).unwrap().is_empty());
+}
+
+#[test]
+fn test_stream_allocates_voices_round_robin() {
+    let mut converter =
+        StreamingConverter::new(&HashMap::from([(0, 2)]), ConversionOptions::default()).unwrap();
+
+    let first = converter.push(0.0, &note_on(0, 60)).unwrap();
+    let second = converter.push(0.1, &note_on(0, 64)).unwrap();
+    assert_eq!(first.last().unwrap().data, "0x78");
+    assert_eq!(writes(&second).last(), Some(&("0x08", "0x79")));
+
+    let release = converter.push(0.2, &note_off(0, 64)).unwrap();
+    assert_eq!(writes(&release), [("0x08", "0x01")]);
+    assert_eq!(release[0].time, 0.2);
+}
+
+#[test]
+fn test_stream_rejects_time_going_backwards() {
+    let mut converter =
+        StreamingConverter::new(&HashMap::from([(0, 1)]), ConversionOptions::default()).unwrap();
+    converter.push(1.0, &note_on(0, 60)).unwrap();
+
+    assert!(converter.push(0.5, &note_off(0, 60)).is_err());
+    assert!(converter.tick(f64::NAN).is_err());
+    assert!(converter.push(1.0, &note_off(0, 60)).is_ok());
+}
+
+#[test]
+fn test_stream_tick_delay_vibrato() {
+    let options = ConversionOptions {
+        delay_vibrato: true,
+        ..ConversionOptions::default()
+    };
+    let mut converter = StreamingConverter::new(&HashMap::from([(0, 1)]), options).unwrap();
+    converter.push(10.0, &note_on(0, 69)).unwrap();
+
+    // Nothing moves before the vibrato delay
+    assert!(converter.tick(10.1).unwrap().is_empty());
+
+    let events = converter.tick(10.35).unwrap();
+    assert!(
+        !events.is_empty(),
+        "vibrato should bend the pitch after its delay"
+    );
+    assert!(events
+        .iter()
+        .all(|e| (e.addr == "0x28" || e.addr == "0x30") && e.time == 10.35));
+
+    // The vibrato stops once the release tail is over
+    converter.push(11.0, &note_off(0, 69)).unwrap();
+    converter.tick(11.9).unwrap();
+    assert!(converter.tick(12.5).unwrap().is_empty());
+}
+
+#[test]
+fn test_stream_tick_software_lfo() {
+    let options = ConversionOptions {
+        software_lfo: vec![RegisterLfoDefinition {
+            target: LfoTarget::Register,
+            base_register: "0x60".to_string(),
+            depth: 6.0,
+            rate_hz: 4.0,
+            delay_seconds: 0.0,
+            attack_seconds: 0.0,
+            waveform: LfoWaveform::Triangle,
+            duty: 0.5,
+            seed: 0,
+            key_on_sync: true,
+        }],
+        ..ConversionOptions::default()
+    };
+    let mut converter = StreamingConverter::new(&HashMap::from([(0, 1)]), options).unwrap();
+    let init = converter.push(0.0, &note_on(0, 60)).unwrap();
+    let base = init
+        .iter()
+        .rev()
+        .find(|e| e.addr == "0x60")
+        .and_then(|e| parse_hex_byte(&e.data))
+        .unwrap();
+
+    // A quarter period into a 4 Hz triangle is its peak
+    let events = converter.tick(0.0625).unwrap();
+    assert_eq!(
+        writes(&events),
+        [("0x60", format!("0x{:02X}", base + 6).as_str())]
+    );
+
+    // After note-off the register keeps its last value
+    converter.push(0.1, &note_off(0, 60)).unwrap();
+    assert!(converter.tick(0.2).unwrap().is_empty());
+}
+
+#[test]
+fn test_stream_tick_pitch_lfo_adds_to_pitch_bend() {
+    let options = ConversionOptions {
+        software_lfo: vec![RegisterLfoDefinition {
+            target: LfoTarget::Pitch,
+            base_register: String::new(),
+            depth: 100.0,
+            rate_hz: 2.0,
+            delay_seconds: 0.0,
+            attack_seconds: 0.0,
+            waveform: LfoWaveform::Square,
+            duty: 0.5,
+            seed: 0,
+            key_on_sync: true,
+        }],
+        ..ConversionOptions::default()
+    };
+    let mut converter = StreamingConverter::new(&HashMap::from([(0, 1)]), options).unwrap();
+    converter.push(0.0, &note_on(0, 60)).unwrap();
+    converter
+        .push(
+            0.05,
+            &MidiEvent::PitchBend {
+                ticks: 0,
+                channel: 0,
+                value: 4096,
+                track: 0,
+                port: None,
+            },
+        )
+        .unwrap();
+
+    // Square high (+100 cents) on top of a +100 cent bend; KF stays 0
+    let (kc, _) = crate::midi::midi_to_kc_kf(62);
+    let events = converter.tick(0.1).unwrap();
+    assert_eq!(
+        writes(&events),
+        [("0x28", format!("0x{:02X}", kc).as_str())]
+    );
+
+    // Half a period later the square is low, a semitone under the bent note
+    let (kc, _) = crate::midi::midi_to_kc_kf(60);
+    let events = converter.tick(0.3).unwrap();
+    assert_eq!(
+        writes(&events),
+        [("0x28", format!("0x{:02X}", kc).as_str())]
+    );
+}
@@ -17,15 +17,21 @@ use crate::midi::{MidiData, MidiEvent, TempoChange};
 ///
 /// # Example
 /// ```
-/// use smf_to_ym2151log::midi::{MidiData, MidiEvent};
+/// use smf_to_ym2151log::midi::{MidiData, MidiEvent, MidiMetadata, MidiTiming};
 /// use smf_to_ym2151log::ym2151::build_tempo_map;
 ///
 /// let midi_data = MidiData {
-///     ticks_per_beat: 480,
+///     timing: MidiTiming::Metrical { ticks_per_beat: 480 },
 ///     tempo_bpm: 120.0,
 ///     events: vec![
-///         MidiEvent::Tempo { ticks: 480, tempo_bpm: 140.0 },
+///         MidiEvent::Tempo {
+///             ticks: 480,
+///             tempo_bpm: 140.0,
+///             track: 0,
+///             port: None,
+///         },
 ///     ],
+///     metadata: MidiMetadata::default(),
 /// };
 /// let tempo_map = build_tempo_map(&midi_data);
 /// assert_eq!(tempo_map.len(), 2);
@@ -39,7 +45,10 @@ pub fn build_tempo_map(midi_data: &MidiData) -> Vec<TempoChange> {
     }];
 
     for event in &midi_data.events {
-        if let MidiEvent::Tempo { ticks, tempo_bpm } = event {
+        if let MidiEvent::Tempo {
+            ticks, tempo_bpm, ..
+        } = event
+        {
             // Only add if it's different from the current tempo
             // or if it's the first explicit tempo event at tick 0
             if tempo_map.is_empty()
@@ -64,13 +73,17 @@ pub fn build_tempo_map(midi_data: &MidiData) -> Vec<TempoChange> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::midi::{MidiMetadata, MidiTiming};
 
     #[test]
     fn test_build_tempo_map_empty_events() {
         let midi_data = MidiData {
-            ticks_per_beat: 480,
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
             tempo_bpm: 120.0,
             events: vec![],
+            metadata: MidiMetadata::default(),
         };
 
         let tempo_map = build_tempo_map(&midi_data);
@@ -83,12 +96,17 @@ mod tests {
     #[test]
     fn test_build_tempo_map_single_change() {
         let midi_data = MidiData {
-            ticks_per_beat: 480,
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
             tempo_bpm: 120.0,
             events: vec![MidiEvent::Tempo {
                 ticks: 480,
                 tempo_bpm: 140.0,
+                track: 0,
+                port: None,
             }],
+            metadata: MidiMetadata::default(),
         };
 
         let tempo_map = build_tempo_map(&midi_data);
@@ -103,18 +121,25 @@ mod tests {
     #[test]
     fn test_build_tempo_map_multiple_changes() {
         let midi_data = MidiData {
-            ticks_per_beat: 480,
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
             tempo_bpm: 100.0,
             events: vec![
                 MidiEvent::Tempo {
                     ticks: 240,
                     tempo_bpm: 120.0,
+                    track: 0,
+                    port: None,
                 },
                 MidiEvent::Tempo {
                     ticks: 480,
                     tempo_bpm: 140.0,
+                    track: 0,
+                    port: None,
                 },
             ],
+            metadata: MidiMetadata::default(),
         };
 
         let tempo_map = build_tempo_map(&midi_data);
@@ -131,7 +156,9 @@ mod tests {
     #[test]
     fn test_build_tempo_map_ignores_non_tempo_events() {
         let midi_data = MidiData {
-            ticks_per_beat: 480,
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
             tempo_bpm: 120.0,
             events: vec![
                 MidiEvent::NoteOn {
@@ -139,17 +166,24 @@ mod tests {
                     channel: 0,
                     note: 60,
                     velocity: 100,
+                    track: 0,
+                    port: None,
                 },
                 MidiEvent::Tempo {
                     ticks: 480,
                     tempo_bpm: 140.0,
+                    track: 0,
+                    port: None,
                 },
                 MidiEvent::NoteOff {
                     ticks: 960,
                     channel: 0,
                     note: 60,
+                    track: 0,
+                    port: None,
                 },
             ],
+            metadata: MidiMetadata::default(),
         };
 
         let tempo_map = build_tempo_map(&midi_data);
@@ -162,18 +196,25 @@ mod tests {
     #[test]
     fn test_build_tempo_map_deduplicates_same_tick() {
         let midi_data = MidiData {
-            ticks_per_beat: 480,
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
             tempo_bpm: 120.0,
             events: vec![
                 MidiEvent::Tempo {
                     ticks: 480,
                     tempo_bpm: 130.0,
+                    track: 0,
+                    port: None,
                 },
                 MidiEvent::Tempo {
                     ticks: 480,
                     tempo_bpm: 140.0,
+                    track: 0,
+                    port: None,
                 },
             ],
+            metadata: MidiMetadata::default(),
         };
 
         let tempo_map = build_tempo_map(&midi_data);
@@ -187,12 +228,17 @@ mod tests {
     #[test]
     fn test_build_tempo_map_override_initial_tempo() {
         let midi_data = MidiData {
-            ticks_per_beat: 480,
+            timing: MidiTiming::Metrical {
+                ticks_per_beat: 480,
+            },
             tempo_bpm: 120.0,
             events: vec![MidiEvent::Tempo {
                 ticks: 0,
                 tempo_bpm: 140.0,
+                track: 0,
+                port: None,
             }],
+            metadata: MidiMetadata::default(),
         };
 
         let tempo_map = build_tempo_map(&midi_data);
@@ -18,7 +18,7 @@ fn test_end_to_end_simple_melody() {
     let midi_data = parse_midi_file(midi_path).expect("Failed to parse MIDI file");
 
     // Verify Pass A output
-    assert_eq!(midi_data.ticks_per_beat, 480);
+    assert_eq!(midi_data.timing.ticks_per_beat(), Some(480));
     assert_eq!(midi_data.tempo_bpm, 120.0);
     assert!(!midi_data.events.is_empty());
 
@@ -86,7 +86,10 @@ fn test_end_to_end_tempo_change() {
         .events
         .iter()
         .filter_map(|e| {
-            if let MidiEvent::Tempo { ticks, tempo_bpm } = e {
+            if let MidiEvent::Tempo {
+                ticks, tempo_bpm, ..
+            } = e
+            {
                 Some((*ticks, *tempo_bpm))
             } else {
                 None
@@ -1,6 +1,9 @@
 //! Integration tests for MIDI parsing (Pass A)
 
-use smf_to_ym2151log::midi::{parse_midi_file, save_midi_events_json, MidiEvent};
+use smf_to_ym2151log::midi::{
+    parse_midi_file, parse_midi_from_bytes, save_midi_events_json, write_midi_to_bytes, MidiEvent,
+    SmfFormat,
+};
 use std::fs;
 
 #[test]
@@ -18,7 +21,7 @@ fn test_parse_simple_melody() {
     let midi_data = result.unwrap();
 
     // Check metadata
-    assert_eq!(midi_data.ticks_per_beat, 480);
+    assert_eq!(midi_data.timing.ticks_per_beat(), Some(480));
     assert_eq!(midi_data.tempo_bpm, 120.0);
 
     // Check events
@@ -45,6 +48,7 @@ fn test_parse_simple_melody() {
         note,
         velocity,
         channel,
+        ..
     } = note_ons[0]
     {
         assert_eq!(*ticks, 0);
@@ -180,16 +184,7 @@ fn test_events_are_sorted_by_ticks() {
     let midi_data = parse_midi_file(midi_path).expect("Failed to parse MIDI file");
 
     // Verify events are sorted by ticks
-    let ticks: Vec<u32> = midi_data
-        .events
-        .iter()
-        .map(|e| match e {
-            MidiEvent::NoteOn { ticks, .. } => *ticks,
-            MidiEvent::NoteOff { ticks, .. } => *ticks,
-            MidiEvent::Tempo { ticks, .. } => *ticks,
-            MidiEvent::ProgramChange { ticks, .. } => *ticks,
-        })
-        .collect();
+    let ticks: Vec<u32> = midi_data.events.iter().map(MidiEvent::ticks).collect();
 
     // Check that each tick is >= the previous tick
     for i in 1..ticks.len() {
@@ -227,3 +222,22 @@ fn test_parse_nonexistent_file() {
     let result = parse_midi_file("nonexistent_file.mid");
     assert!(result.is_err(), "Should fail for nonexistent file");
 }
+
+#[test]
+fn test_write_round_trip_of_test_files() {
+    for name in [
+        "simple_melody",
+        "tempo_change",
+        "multi_track",
+        "multi_channel",
+        "program_change",
+    ] {
+        let midi_data = parse_midi_file(&format!("tests/test_data/{name}.mid")).unwrap();
+
+        let bytes = write_midi_to_bytes(&midi_data, SmfFormat::MultiTrack).unwrap();
+        let reparsed = parse_midi_from_bytes(&bytes).unwrap();
+
+        assert_eq!(reparsed.events, midi_data.events, "{name}");
+        assert_eq!(reparsed.metadata, midi_data.metadata, "{name}");
+    }
+}
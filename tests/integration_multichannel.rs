@@ -24,7 +24,7 @@ fn test_parse_midi_from_bytes() {
     let midi_data = result.unwrap();
 
     // Verify metadata
-    assert_eq!(midi_data.ticks_per_beat, 480);
+    assert_eq!(midi_data.timing.ticks_per_beat(), Some(480));
     assert_eq!(midi_data.tempo_bpm, 120.0);
 
     // Verify we got events
@@ -46,7 +46,7 @@ fn test_parse_multi_channel() {
     let midi_data = result.unwrap();
 
     // Check metadata
-    assert_eq!(midi_data.ticks_per_beat, 480);
+    assert_eq!(midi_data.timing.ticks_per_beat(), Some(480));
     assert_eq!(midi_data.tempo_bpm, 120.0);
 
     // Should have 6 events (3 note on, 3 note off)
@@ -162,19 +162,23 @@ fn test_end_to_end_multi_channel() {
 #[test]
 fn test_tempo_change_timing_accuracy() {
     use smf_to_ym2151log::midi::{
-        ticks_to_seconds_with_tempo_map, MidiData, MidiEvent, TempoChange,
+        ticks_to_seconds_with_tempo_map, MidiData, MidiEvent, MidiMetadata, MidiTiming, TempoChange,
     };
     use smf_to_ym2151log::ym2151::convert_to_ym2151_log;
 
     // Create a test MIDI file with tempo change
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             // Tempo starts at 120 BPM
             MidiEvent::Tempo {
                 ticks: 0,
                 tempo_bpm: 120.0,
+                track: 0,
+                port: None,
             },
             // First note at tick 0
             MidiEvent::NoteOn {
@@ -182,17 +186,23 @@ fn test_tempo_change_timing_accuracy() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             // First note off at tick 480 (1 beat at 120 BPM = 0.5 seconds)
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
             // Tempo changes to 60 BPM at tick 480
             MidiEvent::Tempo {
                 ticks: 480,
                 tempo_bpm: 60.0,
+                track: 0,
+                port: None,
             },
             // Second note at tick 480
             MidiEvent::NoteOn {
@@ -200,14 +210,19 @@ fn test_tempo_change_timing_accuracy() {
                 channel: 0,
                 note: 62,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             // Second note off at tick 960 (1 beat at 60 BPM = 1.0 second after tempo change)
             MidiEvent::NoteOff {
                 ticks: 960,
                 channel: 0,
                 note: 62,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     // Convert to YM2151 log
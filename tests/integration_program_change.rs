@@ -50,31 +50,40 @@ fn test_tone_loading_nonexistent() {
 
 #[test]
 fn test_end_to_end_program_change() {
-    use smf_to_ym2151log::midi::{MidiData, MidiEvent};
+    use smf_to_ym2151log::midi::{MidiData, MidiEvent, MidiMetadata, MidiTiming};
     use smf_to_ym2151log::ym2151::convert_to_ym2151_log;
 
     // Create MIDI data with program change
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::ProgramChange {
                 ticks: 0,
                 channel: 0,
                 program: 0, // Use program 0 which has a tone file
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOn {
                 ticks: 0,
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log(&midi_data);
@@ -119,6 +128,7 @@ fn test_parse_program_change_midi() {
         ticks,
         channel,
         program,
+        ..
     } = program_events[0]
     {
         assert_eq!(*ticks, 0);
@@ -131,6 +141,7 @@ fn test_parse_program_change_midi() {
         ticks: _,
         channel,
         program,
+        ..
     } = program_events[1]
     {
         assert_eq!(*channel, 0);
@@ -196,7 +207,7 @@ fn test_end_to_end_program_change_with_file() {
 /// the MIDI file contains no explicit Program Change event.
 #[test]
 fn test_attachment_tone_applied_without_program_change_event() {
-    use smf_to_ym2151log::midi::{MidiData, MidiEvent};
+    use smf_to_ym2151log::midi::{MidiData, MidiEvent, MidiMetadata, MidiTiming};
     use smf_to_ym2151log::ym2151::convert_to_ym2151_log_with_options;
     use smf_to_ym2151log::ConversionOptions;
 
@@ -220,7 +231,9 @@ fn test_attachment_tone_applied_without_program_change_event() {
 
     // MIDI data without any Program Change event
     let midi_data = MidiData {
-        ticks_per_beat: 480,
+        timing: MidiTiming::Metrical {
+            ticks_per_beat: 480,
+        },
         tempo_bpm: 120.0,
         events: vec![
             MidiEvent::NoteOn {
@@ -228,13 +241,18 @@ fn test_attachment_tone_applied_without_program_change_event() {
                 channel: 0,
                 note: 60,
                 velocity: 100,
+                track: 0,
+                port: None,
             },
             MidiEvent::NoteOff {
                 ticks: 480,
                 channel: 0,
                 note: 60,
+                track: 0,
+                port: None,
             },
         ],
+        metadata: MidiMetadata::default(),
     };
 
     let result = convert_to_ym2151_log_with_options(&midi_data, &options);